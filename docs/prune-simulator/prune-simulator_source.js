@@ -3,24 +3,55 @@ function gettext(val) { return val; };
 
 Ext.onReady(function() {
     const COLORS = {
+	'keep-within': 'pink',
 	'keep-last': 'orange',
 	'keep-hourly': 'purple',
 	'keep-daily': 'yellow',
 	'keep-weekly': 'green',
 	'keep-monthly': 'blue',
 	'keep-yearly': 'red',
+	'keep-last-verified': 'gray',
 	'all zero': 'white',
     };
     const TEXT_COLORS = {
+	'keep-within': 'black',
 	'keep-last': 'black',
 	'keep-hourly': 'white',
 	'keep-daily': 'black',
 	'keep-weekly': 'white',
 	'keep-monthly': 'white',
 	'keep-yearly': 'white',
+	'keep-last-verified': 'white',
 	'all zero': 'black',
     };
 
+    // same units as the time spans of the backup server, returns milliseconds
+    const TIME_SPAN_UNITS = {
+	s: 1, sec: 1, second: 1, seconds: 1,
+	m: 60, min: 60, minute: 60, minutes: 60,
+	h: 3600, hour: 3600, hours: 3600,
+	d: 86400, day: 86400, days: 86400,
+	w: 604800, week: 604800, weeks: 604800,
+	M: 2630016, month: 2630016, months: 2630016,
+	y: 31557600, year: 31557600, years: 31557600,
+    };
+    const parseTimeSpan = function(value) {
+	if (!value) {
+	    return undefined;
+	}
+	let seconds = 0;
+	let rest = value.trim();
+	while (rest.length) {
+	    let match = /^(\d+)\s*([a-zA-Z]+)\s*/.exec(rest);
+	    if (!match || !TIME_SPAN_UNITS[match[2]]) {
+		return null;
+	    }
+	    seconds += Number(match[1]) * TIME_SPAN_UNITS[match[2]];
+	    rest = rest.slice(match[0].length);
+	}
+	return seconds * 1000;
+    };
+
     Ext.define('PBS.prunesimulator.Documentation', {
 	extend: 'Ext.Panel',
 	alias: 'widget.prunesimulatorDocumentation',
@@ -102,6 +133,10 @@ Ext.onReady(function() {
 		name: 'keepName',
 		type: 'string',
 	    },
+	    {
+		name: 'failed',
+		type: 'boolean',
+	    },
 	],
     });
 
@@ -123,6 +158,9 @@ Ext.onReady(function() {
 		    dataIndex: 'backuptime',
 		    renderer: function(value, metaData, { data }) {
 			let text = Ext.Date.format(value, 'Y-m-d H:i:s');
+			if (data.failed) {
+			    text += ' (verify failed)';
+			}
 			if (data.mark !== 'keep') {
 			    return `<div style="text-decoration: line-through;">${text}</div>`;
 			}
@@ -209,6 +247,9 @@ Ext.onReady(function() {
 			html += '<tr><td>';
 
 			let text = Ext.Date.format(backup.data.backuptime, 'H:i');
+			if (backup.data.failed) {
+			    text += ' !';
+			}
 			if (backup.data.mark === 'remove') {
 			    html += `<span class="strikethrough">${text}</span>`;
 			} else {
@@ -286,6 +327,19 @@ Ext.onReady(function() {
 	},
     });
 
+    Ext.define('PBS.PruneSimulatorTimeSpanInput', {
+	extend: 'Ext.form.field.Text',
+	alias: 'widget.prunesimulatorTimeSpanInput',
+
+	allowBlank: true,
+	fieldGroup: 'keep',
+	emptyText: 'e.g. 14d, 1y 6M',
+
+	validator: function(value) {
+	    return parseTimeSpan(value) !== null || 'invalid time span';
+	},
+    });
+
     Ext.define('PBS.PruneSimulatorPanel', {
 	extend: 'Ext.panel.Panel',
 	alias: 'widget.prunesimulatorPanel',
@@ -398,6 +452,7 @@ Ext.onReady(function() {
 			hours,
 			minutes,
 			params.numberOfWeeks,
+			Number(params.failEvery),
 		    );
 
 		    me.pruneSelect(backups, params);
@@ -418,6 +473,7 @@ Ext.onReady(function() {
 		view.pruneStore.getData().items.forEach(function(item) {
 		    backups.push({
 			backuptime: item.data.backuptime,
+			failed: item.data.failed,
 		    });
 		});
 
@@ -426,8 +482,8 @@ Ext.onReady(function() {
 		view.pruneStore.setData(backups);
 	    },
 
-	    // backups are sorted descending by date
-	    populateFromSchedule: function(weekdays, hours, minutes, weekCount) {
+	    // backups are sorted descending by date, every `failEvery`-th one fails verification
+	    populateFromSchedule: function(weekdays, hours, minutes, weekCount, failEvery) {
 		const me = this;
 
 		let weekdayFlags = ['sun', 'mon', 'tue', 'wed', 'thu', 'fri', 'sat']
@@ -458,7 +514,8 @@ Ext.onReady(function() {
 			timesOnSingleDay.forEach(function(time) {
 			    const backuptime = Ext.Date.subtract(new Date(time), Ext.Date.DAY, i);
 			    if (backuptime <= vmDate) {
-				backups.push({ backuptime: backuptime });
+				let failed = failEvery > 0 && (backups.length + 1) % failEvery === 0;
+				backups.push({ backuptime: backuptime, failed: failed });
 			    }
 			});
 		    }
@@ -514,12 +571,16 @@ Ext.onReady(function() {
 	    pruneSelect: function(backups, keepParams) {
 		let me = this;
 
+		let now = me.getViewModel().get('now');
+		let keepWithin = parseTimeSpan(keepParams['keep-within']);
+
+		// keep-last-verified alone is not allowed, it only keeps an additional backup
 		if (Number(keepParams['keep-last']) +
 		    Number(keepParams['keep-hourly']) +
 		    Number(keepParams['keep-daily']) +
 		    Number(keepParams['keep-weekly']) +
 		    Number(keepParams['keep-monthly']) +
-		    Number(keepParams['keep-yearly']) === 0) {
+		    Number(keepParams['keep-yearly']) === 0 && !keepWithin) {
 		    backups.forEach(function(backup) {
 			backup.mark = 'keep';
 			backup.keepName = 'keep-all';
@@ -528,6 +589,16 @@ Ext.onReady(function() {
 		    return;
 		}
 
+		if (keepWithin) {
+		    let cutoff = now.getTime() - keepWithin;
+		    backups.forEach(function(backup) {
+			if (backup.backuptime.getTime() >= cutoff) {
+			    backup.mark = 'keep';
+			    backup.keepName = 'keep-within';
+			}
+		    });
+		}
+
 		me.pruneMark(backups, keepParams['keep-last'], 'keep-last', function(backup) {
 		    return backup.backuptime;
 		});
@@ -548,6 +619,32 @@ Ext.onReady(function() {
 		    return Ext.Date.format(backup.backuptime, 'Y');
 		});
 
+		let oldAge = parseTimeSpan(keepParams['old-age']);
+		let maxOld = keepParams['max-old'];
+		if (oldAge && maxOld !== undefined && maxOld !== '') {
+		    let cutoff = now.getTime() - oldAge;
+		    let kept = 0;
+		    backups.forEach(function(backup) {
+			if (backup.backuptime.getTime() >= cutoff || backup.mark !== 'keep') {
+			    return;
+			}
+			if (kept < Number(maxOld)) {
+			    kept++;
+			} else {
+			    backup.mark = 'remove';
+			}
+		    });
+		}
+
+		if (keepParams['keep-last-verified']) {
+		    let lastVerified = backups.find(backup => !backup.failed);
+		    if (lastVerified && lastVerified.mark !== 'keep') {
+			lastVerified.mark = 'keep';
+			lastVerified.keepName = 'keep-last-verified';
+			lastVerified.keepCount = undefined;
+		    }
+		}
+
 		backups.forEach(function(backup) {
 		    backup.mark = backup.mark || 'remove';
 		});
@@ -565,7 +662,7 @@ Ext.onReady(function() {
 		    `background-color: ${COLORS[name]}; color: ${TEXT_COLORS[name]};`;
 
 		for (const field of view.query('[isFormField]')) {
-		    if (field.fieldGroup !== 'keep') {
+		    if (field.fieldGroup !== 'keep' || !COLORS[field.name]) {
 			continue;
 		    }
 		    if (useColors) {
@@ -583,6 +680,11 @@ Ext.onReady(function() {
 	},
 
 	keepItems: [
+	    {
+		xtype: 'prunesimulatorTimeSpanInput',
+		name: 'keep-within',
+		fieldLabel: 'keep-within',
+	    },
 	    {
 		xtype: 'prunesimulatorKeepInput',
 		name: 'keep-last',
@@ -616,6 +718,23 @@ Ext.onReady(function() {
 		name: 'keep-yearly',
 		fieldLabel: 'keep-yearly',
 	    },
+	    {
+		xtype: 'prunesimulatorTimeSpanInput',
+		name: 'old-age',
+		fieldLabel: 'old-age',
+	    },
+	    {
+		xtype: 'prunesimulatorKeepInput',
+		name: 'max-old',
+		fieldLabel: 'max-old',
+		minValue: 0,
+	    },
+	    {
+		xtype: 'checkbox',
+		name: 'keep-last-verified',
+		fieldLabel: 'keep-last-verified',
+		fieldGroup: 'keep',
+	    },
 	],
 
 	initComponent: function() {
@@ -705,6 +824,14 @@ Ext.onReady(function() {
 					    value: '0/6:00',
 					    fieldLabel: 'Schedule',
 					},
+					{
+					    xtype: 'numberfield',
+					    name: 'failEvery',
+					    fieldLabel: 'Failed Verify',
+					    emptyText: 'every N-th backup',
+					    allowBlank: true,
+					    minValue: 1,
+					},
 				    ],
 				},
 				{
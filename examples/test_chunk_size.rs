@@ -5,10 +5,10 @@ extern crate proxmox_backup;
 use anyhow::Error;
 use std::io::{Read, Write};
 
-use pbs_datastore::Chunker;
+use pbs_datastore::{BuzHashChunker, Chunker};
 
 struct ChunkWriter {
-    chunker: Chunker,
+    chunker: BuzHashChunker,
     last_chunk: usize,
     chunk_offset: usize,
 
@@ -23,7 +23,7 @@ struct ChunkWriter {
 impl ChunkWriter {
     fn new(chunk_size: usize) -> Self {
         ChunkWriter {
-            chunker: Chunker::new(chunk_size),
+            chunker: BuzHashChunker::new(chunk_size),
             last_chunk: 0,
             chunk_offset: 0,
             chunk_count: 0,
@@ -5,14 +5,15 @@ use anyhow::{bail, format_err, Error};
 use serde::{Deserialize, Serialize};
 
 use proxmox_schema::{
-    api, const_regex, ApiStringFormat, ApiType, ArraySchema, EnumEntry, IntegerSchema, ReturnType,
-    Schema, StringSchema, Updater, UpdaterType,
+    api, const_regex, ApiStringFormat, ApiType, ArraySchema, BooleanSchema, EnumEntry,
+    IntegerSchema, ReturnType, Schema, StringSchema, Updater, UpdaterType,
 };
 
 use crate::{
-    Authid, CryptMode, Fingerprint, MaintenanceMode, Userid, DATASTORE_NOTIFY_STRING_SCHEMA,
-    GC_SCHEDULE_SCHEMA, PROXMOX_SAFE_ID_FORMAT, PRUNE_SCHEDULE_SCHEMA, SHA256_HEX_REGEX,
-    SINGLE_LINE_COMMENT_SCHEMA, UPID,
+    Authid, CryptMode, Fingerprint, MaintenanceMode, QuotaStatus, Userid,
+    CHUNK_USAGE_SCHEDULE_SCHEMA, DATASTORE_NOTIFY_STRING_SCHEMA, GC_SCHEDULE_SCHEMA,
+    PROXMOX_SAFE_ID_FORMAT, PRUNE_SCHEDULE_SCHEMA, S3_BUCKET_NAME_SCHEMA, S3_CLIENT_ID_SCHEMA,
+    SHA256_HEX_REGEX, SINGLE_LINE_COMMENT_SCHEMA, UPID,
 };
 
 const_regex! {
@@ -35,6 +36,10 @@ const_regex! {
     pub GROUP_OR_SNAPSHOT_PATH_REGEX = concat!(r"^", GROUP_OR_SNAPSHOT_PATH_REGEX_STR!(), r"$");
 
     pub DATASTORE_MAP_REGEX = concat!(r"(:?", PROXMOX_SAFE_ID_REGEX_STR!(), r"=)?", PROXMOX_SAFE_ID_REGEX_STR!());
+
+    pub FILESYSTEM_UUID_REGEX = r"^[0-9a-fA-F][0-9a-fA-F\-]{3,63}$";
+
+    pub ZSTD_DICTIONARY_ID_REGEX = r"^[0-9a-f]{8}$";
 }
 
 pub const CHUNK_DIGEST_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&SHA256_HEX_REGEX);
@@ -44,6 +49,24 @@ pub const DIR_NAME_SCHEMA: Schema = StringSchema::new("Directory name")
     .max_length(4096)
     .schema();
 
+pub const CHUNK_SHARD_ARRAY_SCHEMA: Schema =
+    ArraySchema::new("Array of chunk shard directories.", &DIR_NAME_SCHEMA).schema();
+
+pub const CHUNK_SHARD_LIST_SCHEMA: Schema = StringSchema::new(
+    "A list of additional directories to store chunks in, comma separated. \
+    The chunks are distributed over the datastore path and these directories by their digest.",
+)
+.format(&ApiStringFormat::PropertyString(&CHUNK_SHARD_ARRAY_SCHEMA))
+.schema();
+
+pub const FILESYSTEM_UUID_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&FILESYSTEM_UUID_REGEX);
+
+pub const BACKING_DEVICE_SCHEMA: Schema =
+    StringSchema::new("Filesystem UUID of the device backing a removable datastore.")
+        .format(&FILESYSTEM_UUID_FORMAT)
+        .schema();
+
 pub const BACKUP_ARCHIVE_NAME_SCHEMA: Schema = StringSchema::new("Backup archive name.")
     .format(&PROXMOX_SAFE_ID_FORMAT)
     .schema();
@@ -157,6 +180,37 @@ pub const PRUNE_SCHEMA_KEEP_YEARLY: Schema =
         .minimum(1)
         .schema();
 
+pub const PRUNE_SCHEMA_KEEP_WITHIN: Schema =
+    StringSchema::new("Keep all backups younger than this time span (for example '14d').")
+        .format(&ApiStringFormat::VerifyFn(proxmox_time::verify_time_span))
+        .schema();
+
+pub const PRUNE_SCHEMA_OLD_AGE: Schema =
+    StringSchema::new("Age from which backups are limited by 'max-old' (for example '1y').")
+        .format(&ApiStringFormat::VerifyFn(proxmox_time::verify_time_span))
+        .schema();
+
+pub const PRUNE_SCHEMA_MAX_OLD: Schema =
+    IntegerSchema::new("Maximum number of backups older than 'old-age' to keep.")
+        .minimum(0)
+        .schema();
+
+pub const PRUNE_SCHEMA_SKIP_FAILED_VERIFY: Schema = BooleanSchema::new(
+    "Backups with a failed verification do not use up a slot of the keep options.",
+)
+.schema();
+
+pub const PRUNE_SCHEMA_KEEP_LAST_VERIFIED: Schema =
+    BooleanSchema::new("Always keep the newest backup with a successful verification.").schema();
+
+pub const GC_MAX_RUNTIME_SCHEMA: Schema = IntegerSchema::new(
+    "Maximum runtime of the garbage collection mark phase in minutes. \
+    An unfinished garbage collection is continued by the next run. Forces access time based \
+    marking, ignored for datastores with S3 backend.",
+)
+.minimum(1)
+.schema();
+
 #[api]
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -169,6 +223,18 @@ pub enum ChunkOrder {
     Inode,
 }
 
+#[api]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Content defined chunking algorithm used to split dynamic archives
+pub enum ChunkerType {
+    /// Rolling Buzhash over a 64 byte window (casync compatible)
+    #[default]
+    Buzhash,
+    /// Gear hash based FastCDC with normalized chunking
+    Fastcdc,
+}
+
 #[api]
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -200,12 +266,68 @@ pub enum DatastoreFSyncLevel {
     Filesystem,
 }
 
+#[api]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// How garbage collection marks the chunks still in use.
+pub enum GcMarkMode {
+    /// Update the access time of every referenced chunk, the sweep phase then removes all chunks
+    /// with an access time older than the start of the garbage collection.
+    #[default]
+    Atime,
+    /// Collect the digests of all referenced chunks in an in-memory bitmap, the sweep phase only
+    /// removes chunks which are neither marked nor recently accessed by a backup. This avoids
+    /// writing the metadata of every chunk, at the cost of memory (2 to 4 bytes per chunk).
+    Bitmap,
+}
+
+pub const BACKUP_RESUME_TIMEOUT_SCHEMA: Schema = IntegerSchema::new(
+    "Keep the data of interrupted backups for this many minutes, so that the client can resume \
+    the backup. Interrupted backups are removed immediately if not set.",
+)
+.minimum(1)
+.schema();
+
+pub const COMPRESSION_LEVEL_SCHEMA: Schema = IntegerSchema::new("zstd compression level.")
+    .minimum(1)
+    .maximum(19)
+    .default(1)
+    .schema();
+
+pub const ZSTD_DICTIONARY_ID_SCHEMA: Schema =
+    StringSchema::new("ID of a trained zstd dictionary stored in the datastore.")
+        .format(&ApiStringFormat::Pattern(&ZSTD_DICTIONARY_ID_REGEX))
+        .schema();
+
+pub const ZSTD_DICTIONARY_SIZE_SCHEMA: Schema =
+    IntegerSchema::new("Maximum size of a trained zstd dictionary in bytes.")
+        .minimum(4 * 1024)
+        .maximum(1024 * 1024)
+        .default(112 * 1024)
+        .schema();
+
 #[api(
     properties: {
         "chunk-order": {
             type: ChunkOrder,
             optional: true,
         },
+        "gc-mark-mode": {
+            type: GcMarkMode,
+            optional: true,
+        },
+        "backup-resume-timeout": {
+            schema: BACKUP_RESUME_TIMEOUT_SCHEMA,
+            optional: true,
+        },
+        "compression-level": {
+            schema: COMPRESSION_LEVEL_SCHEMA,
+            optional: true,
+        },
+        "zstd-dictionary": {
+            schema: ZSTD_DICTIONARY_ID_SCHEMA,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Default)]
@@ -215,6 +337,12 @@ pub struct DatastoreTuning {
     /// Iterate chunks in this order
     pub chunk_order: Option<ChunkOrder>,
     pub sync_level: Option<DatastoreFSyncLevel>,
+    pub gc_mark_mode: Option<GcMarkMode>,
+    pub backup_resume_timeout: Option<u64>,
+    /// Compression level suggested to clients for new chunks and blobs
+    pub compression_level: Option<i32>,
+    /// Dictionary suggested to clients for compressing unencrypted chunks and blobs
+    pub zstd_dictionary: Option<String>,
 }
 
 pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore tuning options")
@@ -223,6 +351,128 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
     ))
     .schema();
 
+#[api]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Backend type used to store the datastore contents
+pub enum DatastoreBackendType {
+    /// Local filesystem below the datastore path
+    #[default]
+    Filesystem,
+    /// S3 compatible object store, the datastore path is used as local cache
+    S3,
+}
+
+#[api(
+    properties: {
+        type: {
+            type: DatastoreBackendType,
+            optional: true,
+        },
+        client: {
+            schema: S3_CLIENT_ID_SCHEMA,
+            optional: true,
+        },
+        bucket: {
+            schema: S3_BUCKET_NAME_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Datastore backend configuration
+pub struct DatastoreBackendConfig {
+    /// Backend type, defaults to the local filesystem
+    #[serde(rename = "type")]
+    pub ty: Option<DatastoreBackendType>,
+    /// S3 client used to access the bucket (required for type 's3')
+    pub client: Option<String>,
+    /// Bucket to store the contents in (required for type 's3')
+    pub bucket: Option<String>,
+}
+
+pub const DATASTORE_BACKEND_CONFIG_STRING_SCHEMA: Schema =
+    StringSchema::new("Datastore backend configuration")
+        .format(&ApiStringFormat::PropertyString(
+            &DatastoreBackendConfig::API_SCHEMA,
+        ))
+        .schema();
+
+impl DatastoreBackendConfig {
+    /// Parses and checks the backend configuration property string.
+    pub fn parse(value: Option<&str>) -> Result<Self, Error> {
+        let config =
+            Self::deserialize(Self::API_SCHEMA.parse_property_string(value.unwrap_or(""))?)?;
+
+        if config.ty.unwrap_or_default() == DatastoreBackendType::S3
+            && (config.client.is_none() || config.bucket.is_none())
+        {
+            bail!("backend type 's3' requires both 'client' and 'bucket' to be set");
+        }
+
+        Ok(config)
+    }
+}
+
+#[api]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// How the datastore key used for at-rest encryption is sealed
+pub enum DatastoreKeySealing {
+    /// Sealed with a passphrase, the datastore needs to be unlocked after every boot
+    #[default]
+    Passphrase,
+    /// Sealed with a key file, which is read when opening the datastore
+    Keyfile,
+}
+
+#[api(
+    properties: {
+        sealing: {
+            type: DatastoreKeySealing,
+            optional: true,
+        },
+        keyfile: {
+            type: String,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Datastore at-rest encryption configuration
+pub struct DatastoreEncryptionConfig {
+    /// How the datastore key is sealed, defaults to a passphrase
+    pub sealing: Option<DatastoreKeySealing>,
+    /// Path of the key file sealing the datastore key (required for sealing 'keyfile')
+    pub keyfile: Option<String>,
+}
+
+pub const DATASTORE_ENCRYPTION_CONFIG_STRING_SCHEMA: Schema =
+    StringSchema::new("Datastore at-rest encryption configuration")
+        .format(&ApiStringFormat::PropertyString(
+            &DatastoreEncryptionConfig::API_SCHEMA,
+        ))
+        .schema();
+
+impl DatastoreEncryptionConfig {
+    /// Parses and checks the encryption configuration property string.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let config = Self::deserialize(Self::API_SCHEMA.parse_property_string(value)?)?;
+
+        match (config.sealing.unwrap_or_default(), &config.keyfile) {
+            (DatastoreKeySealing::Keyfile, None) => {
+                bail!("sealing 'keyfile' requires 'keyfile' to be set");
+            }
+            (DatastoreKeySealing::Passphrase, Some(_)) => {
+                bail!("'keyfile' is only allowed with sealing 'keyfile'");
+            }
+            _ => Ok(config),
+        }
+    }
+}
+
 #[api(
     properties: {
         name: {
@@ -247,10 +497,18 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
             optional: true,
             schema: GC_SCHEDULE_SCHEMA,
         },
+        "gc-max-runtime": {
+            optional: true,
+            schema: GC_MAX_RUNTIME_SCHEMA,
+        },
         "prune-schedule": {
             optional: true,
             schema: PRUNE_SCHEDULE_SCHEMA,
         },
+        "chunk-usage-schedule": {
+            optional: true,
+            schema: CHUNK_USAGE_SCHEDULE_SCHEMA,
+        },
         keep: {
             type: crate::KeepOptions,
         },
@@ -268,6 +526,22 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
             format: &ApiStringFormat::PropertyString(&MaintenanceMode::API_SCHEMA),
             type: String,
         },
+        backend: {
+            optional: true,
+            schema: DATASTORE_BACKEND_CONFIG_STRING_SCHEMA,
+        },
+        "backing-device": {
+            optional: true,
+            schema: BACKING_DEVICE_SCHEMA,
+        },
+        "chunk-shards": {
+            optional: true,
+            schema: CHUNK_SHARD_LIST_SCHEMA,
+        },
+        encryption: {
+            optional: true,
+            schema: DATASTORE_ENCRYPTION_CONFIG_STRING_SCHEMA,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
@@ -286,9 +560,15 @@ pub struct DataStoreConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub gc_schedule: Option<String>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gc_max_runtime: Option<u64>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub prune_schedule: Option<String>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_usage_schedule: Option<String>,
+
     #[serde(flatten)]
     pub keep: crate::KeepOptions,
 
@@ -311,6 +591,26 @@ pub struct DataStoreConfig {
     /// Maintenance mode, type is either 'offline' or 'read-only', message should be enclosed in "
     #[serde(skip_serializing_if = "Option::is_none")]
     pub maintenance_mode: Option<String>,
+
+    /// Backend used to store the datastore contents, cannot be changed after creation
+    #[updater(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+
+    /// The device of a removable datastore, mounted on `path` when available
+    #[updater(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backing_device: Option<String>,
+
+    /// Additional directories to store chunks in, can only be extended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_shards: Option<String>,
+
+    /// At-rest encryption of the chunks and snapshot files, set up with
+    /// `proxmox-backup-manager datastore encryption-init`
+    #[updater(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<String>,
 }
 
 impl DataStoreConfig {
@@ -320,13 +620,34 @@ impl DataStoreConfig {
             path,
             comment: None,
             gc_schedule: None,
+            gc_max_runtime: None,
             prune_schedule: None,
+            chunk_usage_schedule: None,
             keep: Default::default(),
             verify_new: None,
             notify_user: None,
             notify: None,
             tuning: None,
             maintenance_mode: None,
+            backend: None,
+            backing_device: None,
+            chunk_shards: None,
+            encryption: None,
+        }
+    }
+
+    /// Returns whether the datastore lives on a removable device.
+    pub fn is_removable(&self) -> bool {
+        self.backing_device.is_some()
+    }
+
+    /// The additional chunk shard directories.
+    pub fn chunk_shards(&self) -> Result<Vec<String>, Error> {
+        match self.chunk_shards.as_deref() {
+            Some(shards) => Ok(Vec::<String>::deserialize(
+                CHUNK_SHARD_ARRAY_SCHEMA.parse_property_string(shards)?,
+            )?),
+            None => Ok(Vec::new()),
         }
     }
 
@@ -1118,6 +1439,13 @@ pub struct SnapshotListItem {
             type: Authid,
             optional: true,
         },
+        quotas: {
+            type: Array,
+            optional: true,
+            items: {
+                type: QuotaStatus,
+            },
+        },
     },
 )]
 #[derive(Serialize, Deserialize)]
@@ -1138,6 +1466,9 @@ pub struct GroupListItem {
     /// The first line from group "notes"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    /// Quotas configured for this group, with current usage
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub quotas: Vec<QuotaStatus>,
 }
 
 #[api()]
@@ -1215,12 +1546,29 @@ pub struct TypeCounts {
     pub snapshots: u64,
 }
 
+#[api]
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Marking progress of an unfinished garbage collection.
+pub struct GarbageCollectionCheckpoint {
+    /// Start time of the first mark phase, used as cutoff when resuming.
+    pub phase1_start_time: i64,
+    /// Last processed index file, relative to the datastore base path. Index files are
+    /// processed in sorted order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_index: Option<String>,
+}
+
 #[api(
     properties: {
         "upid": {
             optional: true,
             type: UPID,
         },
+        checkpoint: {
+            optional: true,
+            type: GarbageCollectionCheckpoint,
+        },
     },
 )]
 #[derive(Clone, Default, Serialize, Deserialize, PartialEq)]
@@ -1248,6 +1596,9 @@ pub struct GarbageCollectionStatus {
     pub removed_bad: usize,
     /// Number of chunks still marked as .bad after garbage collection.
     pub still_bad: usize,
+    /// Set while the garbage collection is unfinished, it is resumed from here on the next run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<GarbageCollectionCheckpoint>,
 }
 
 #[api(
@@ -1260,6 +1611,13 @@ pub struct GarbageCollectionStatus {
             type: Counts,
             optional: true,
         },
+        quotas: {
+            type: Array,
+            optional: true,
+            items: {
+                type: QuotaStatus,
+            },
+        },
     },
 )]
 #[derive(Serialize, Deserialize)]
@@ -1278,6 +1636,156 @@ pub struct DataStoreStatus {
     /// Group/Snapshot counts
     #[serde(skip_serializing_if = "Option::is_none")]
     pub counts: Option<Counts>,
+    /// Quotas configured on this datastore, with current usage
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub quotas: Vec<QuotaStatus>,
+}
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Deduplicated chunk usage of a snapshot, backup group or namespace.
+///
+/// Sizes are the sizes of the chunks as stored on disk (or in the bucket), after compression.
+pub struct ChunkUsage {
+    /// Sum of the chunks referenced only here.
+    pub unique_bytes: u64,
+    /// Sum of the chunks also referenced somewhere else.
+    pub shared_bytes: u64,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        backup: {
+            type: BackupDir,
+        },
+        usage: {
+            type: ChunkUsage,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Chunk usage of a backup snapshot.
+pub struct SnapshotChunkUsage {
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    #[serde(flatten)]
+    pub backup: BackupDir,
+    #[serde(flatten)]
+    pub usage: ChunkUsage,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        backup: {
+            type: BackupGroup,
+        },
+        usage: {
+            type: ChunkUsage,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Chunk usage of a backup group, chunks are unique if only referenced by snapshots of the group.
+pub struct GroupChunkUsage {
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    #[serde(flatten)]
+    pub backup: BackupGroup,
+    #[serde(flatten)]
+    pub usage: ChunkUsage,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+        },
+        usage: {
+            type: ChunkUsage,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Chunk usage of the groups directly contained in a namespace (sub-namespaces are not
+/// included).
+pub struct NamespaceChunkUsage {
+    pub ns: BackupNamespace,
+    #[serde(flatten)]
+    pub usage: ChunkUsage,
+}
+
+#[api(
+    properties: {
+        upid: {
+            optional: true,
+            type: UPID,
+        },
+        namespaces: {
+            type: Array,
+            items: { type: NamespaceChunkUsage },
+        },
+        groups: {
+            type: Array,
+            items: { type: GroupChunkUsage },
+        },
+        snapshots: {
+            type: Array,
+            items: { type: SnapshotChunkUsage },
+        },
+        quotas: {
+            type: Array,
+            optional: true,
+            items: { type: QuotaStatus },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Result of the last chunk usage calculation of a datastore.
+pub struct DataStoreChunkUsage {
+    /// The UPID of the task which calculated the usage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    /// Time the calculation started (epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<i64>,
+    /// Time the calculation finished (epoch).
+    pub time: i64,
+    /// Usage per namespace.
+    pub namespaces: Vec<NamespaceChunkUsage>,
+    /// Usage per backup group.
+    pub groups: Vec<GroupChunkUsage>,
+    /// Usage per snapshot.
+    pub snapshots: Vec<SnapshotChunkUsage>,
+    /// Usage of the quotas configured for the datastore.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quotas: Vec<QuotaStatus>,
+}
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// Mount status of a datastore.
+pub enum DataStoreMountStatus {
+    /// The backing device of the removable datastore is mounted.
+    Mounted,
+    /// The backing device of the removable datastore is not mounted.
+    NotMounted,
+    /// The datastore is not removable.
+    #[default]
+    NonRemovable,
 }
 
 #[api(
@@ -1327,10 +1835,13 @@ pub struct DataStoreStatusListItem {
     /// Status of last GC
     #[serde(skip_serializing_if = "Option::is_none")]
     pub gc_status: Option<GarbageCollectionStatus>,
+    /// Mount status, unavailable removable datastores are not mounted
+    #[serde(default)]
+    pub mount_status: DataStoreMountStatus,
 }
 
 impl DataStoreStatusListItem {
-    pub fn empty(store: &str, err: Option<String>) -> Self {
+    pub fn empty(store: &str, err: Option<String>, mount_status: DataStoreMountStatus) -> Self {
         DataStoreStatusListItem {
             store: store.to_owned(),
             total: -1,
@@ -1342,6 +1853,7 @@ impl DataStoreStatusListItem {
             estimated_full_date: None,
             error: err,
             gc_status: None,
+            mount_status,
         }
     }
 }
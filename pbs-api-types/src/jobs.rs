@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 use proxmox_schema::*;
 
 use crate::{
-    Authid, BackupNamespace, BackupType, RateLimitConfig, Userid, BACKUP_GROUP_SCHEMA,
+    Authid, BackupNamespace, BackupType, HumanByte, RateLimitConfig, Userid, BACKUP_GROUP_SCHEMA,
     BACKUP_NAMESPACE_SCHEMA, DATASTORE_SCHEMA, DRIVE_NAME_SCHEMA, MEDIA_POOL_NAME_SCHEMA,
     NS_MAX_DEPTH_REDUCED_SCHEMA, PROXMOX_SAFE_ID_FORMAT, REMOTE_ID_SCHEMA,
     SINGLE_LINE_COMMENT_SCHEMA,
@@ -42,6 +42,14 @@ pub const GC_SCHEDULE_SCHEMA: Schema =
         .type_text("<calendar-event>")
         .schema();
 
+pub const CHUNK_USAGE_SCHEDULE_SCHEMA: Schema =
+    StringSchema::new("Run chunk usage calculation at specified schedule.")
+        .format(&ApiStringFormat::VerifyFn(
+            proxmox_time::verify_calendar_event,
+        ))
+        .type_text("<calendar-event>")
+        .schema();
+
 pub const PRUNE_SCHEDULE_SCHEMA: Schema = StringSchema::new("Run prune job at specified schedule.")
     .format(&ApiStringFormat::VerifyFn(
         proxmox_time::verify_calendar_event,
@@ -165,6 +173,40 @@ pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
         .minimum(0)
         .schema();
 
+pub const VERIFY_READ_THREADS_SCHEMA: Schema =
+    IntegerSchema::new("Number of threads reading chunks from the datastore.")
+        .minimum(1)
+        .maximum(32)
+        .default(1)
+        .schema();
+
+pub const VERIFY_DECODE_THREADS_SCHEMA: Schema =
+    IntegerSchema::new("Number of threads decoding and checking chunks.")
+        .minimum(1)
+        .maximum(32)
+        .default(4)
+        .schema();
+
+pub const VERIFY_SAMPLE_PERCENT_SCHEMA: Schema = NumberSchema::new(
+    "Only verify this percentage of the chunks per run, rotating through the datastore.",
+)
+.minimum(0.01)
+.maximum(100.0)
+.schema();
+
+#[api]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// I/O priority of the threads reading chunks.
+pub enum VerifyIoPriority {
+    /// Highest priority of the best-effort class.
+    High,
+    /// Lowest priority of the best-effort class.
+    Low,
+    /// Idle class, only read when no other process uses the disk.
+    Idle,
+}
+
 #[api(
     properties: {
         id: {
@@ -197,6 +239,46 @@ pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
             optional: true,
             schema: crate::NS_MAX_DEPTH_SCHEMA,
         },
+        "read-threads": {
+            optional: true,
+            schema: VERIFY_READ_THREADS_SCHEMA,
+        },
+        "decode-threads": {
+            optional: true,
+            schema: VERIFY_DECODE_THREADS_SCHEMA,
+        },
+        "io-priority": {
+            optional: true,
+            type: VerifyIoPriority,
+        },
+        "read-rate": {
+            optional: true,
+            type: HumanByte,
+        },
+        "repair-remote": {
+            optional: true,
+            schema: REMOTE_ID_SCHEMA,
+        },
+        "repair-store": {
+            optional: true,
+            schema: DATASTORE_SCHEMA,
+        },
+        "repair-ns": {
+            optional: true,
+            schema: BACKUP_NAMESPACE_SCHEMA,
+        },
+        "repair-drive": {
+            optional: true,
+            schema: DRIVE_NAME_SCHEMA,
+        },
+        "sample-percent": {
+            optional: true,
+            schema: VERIFY_SAMPLE_PERCENT_SCHEMA,
+        },
+        "sample-budget": {
+            optional: true,
+            type: HumanByte,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Updater)]
@@ -227,6 +309,34 @@ pub struct VerificationJobConfig {
     /// how deep the verify should go from the `ns` level downwards. Passing 0 verifies only the
     /// snapshots on the same level as the passed `ns`, or the datastore root if none.
     pub max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decode_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// I/O priority of the reading threads, unchanged if not set
+    pub io_priority: Option<VerifyIoPriority>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// maximum rate of reading chunks, unlimited if not set
+    pub read_rate: Option<HumanByte>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// remote to fetch good copies of corrupt chunks from
+    pub repair_remote: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// datastore on the repair remote
+    pub repair_store: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    /// namespace on the repair remote corresponding to `ns`, the remote root if not set
+    pub repair_ns: Option<BackupNamespace>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// tape drive to read good copies of corrupt chunks from, located via the media catalogs
+    pub repair_drive: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// percentage of all chunks to verify per run in sampling mode
+    pub sample_percent: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// maximum amount of chunk data to read per run in sampling mode
+    pub sample_budget: Option<HumanByte>,
 }
 
 impl VerificationJobConfig {
@@ -236,6 +346,16 @@ impl VerificationJobConfig {
             None => vec!["datastore", &self.store],
         }
     }
+
+    /// Whether corrupt chunks are repaired from a remote or from tape.
+    pub fn has_repair_source(&self) -> bool {
+        self.repair_remote.is_some() || self.repair_drive.is_some()
+    }
+
+    /// Whether the job only verifies a sample of the chunks per run.
+    pub fn is_sampling(&self) -> bool {
+        self.sample_percent.is_some() || self.sample_budget.is_some()
+    }
 }
 
 #[api(
@@ -444,6 +564,27 @@ pub const GROUP_FILTER_SCHEMA: Schema = StringSchema::new(
 pub const GROUP_FILTER_LIST_SCHEMA: Schema =
     ArraySchema::new("List of group filters.", &GROUP_FILTER_SCHEMA).schema();
 
+#[api]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Direction of a sync job
+pub enum SyncDirection {
+    /// Pull snapshots from the remote into the local datastore
+    #[default]
+    Pull,
+    /// Push snapshots from the local datastore to the remote
+    Push,
+}
+
+impl std::fmt::Display for SyncDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncDirection::Pull => f.write_str("pull"),
+            SyncDirection::Push => f.write_str("push"),
+        }
+    }
+}
+
 #[api(
     properties: {
         id: {
@@ -493,11 +634,25 @@ pub const GROUP_FILTER_LIST_SCHEMA: Schema =
             schema: GROUP_FILTER_LIST_SCHEMA,
             optional: true,
         },
+        "sync-direction": {
+            type: SyncDirection,
+            optional: true,
+        },
+        "run-on-mount": {
+            description: "Run this job when the removable datastore 'store' gets mounted.",
+            optional: true,
+            default: false,
+            type: bool,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 /// Sync Job
+///
+/// For pull jobs, `store`/`ns` denote the local target and `remote-store`/`remote-ns` the source.
+/// For push jobs the roles are swapped: the local `store`/`ns` is the source, and `owner` denotes
+/// the local user whose privileges are used to read it.
 pub struct SyncJobConfig {
     #[updater(skip)]
     pub id: String,
@@ -522,6 +677,10 @@ pub struct SyncJobConfig {
     pub group_filter: Option<Vec<GroupFilter>>,
     #[serde(flatten)]
     pub limit: RateLimitConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_direction: Option<SyncDirection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_on_mount: Option<bool>,
 }
 
 impl SyncJobConfig {
@@ -531,6 +690,10 @@ impl SyncJobConfig {
             None => vec!["datastore", &self.store],
         }
     }
+
+    pub fn sync_direction(&self) -> SyncDirection {
+        self.sync_direction.unwrap_or_default()
+    }
 }
 
 #[api(
@@ -581,6 +744,26 @@ pub struct SyncJobStatus {
             schema: crate::PRUNE_SCHEMA_KEEP_YEARLY,
             optional: true,
         },
+        "keep-within": {
+            schema: crate::PRUNE_SCHEMA_KEEP_WITHIN,
+            optional: true,
+        },
+        "old-age": {
+            schema: crate::PRUNE_SCHEMA_OLD_AGE,
+            optional: true,
+        },
+        "max-old": {
+            schema: crate::PRUNE_SCHEMA_MAX_OLD,
+            optional: true,
+        },
+        "keep-last-verified": {
+            schema: crate::PRUNE_SCHEMA_KEEP_LAST_VERIFIED,
+            optional: true,
+        },
+        "skip-failed-verify": {
+            schema: crate::PRUNE_SCHEMA_SKIP_FAILED_VERIFY,
+            optional: true,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Default, Updater, Clone, PartialEq)]
@@ -599,9 +782,21 @@ pub struct KeepOptions {
     pub keep_monthly: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_yearly: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_within: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_age: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_old: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_last_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_failed_verify: Option<bool>,
 }
 
 impl KeepOptions {
+    /// Whether any option limits the kept snapshots, `keep-last-verified` only keeps an
+    /// additional snapshot and does not count.
     pub fn keeps_something(&self) -> bool {
         self.keep_last.unwrap_or(0)
             + self.keep_hourly.unwrap_or(0)
@@ -610,6 +805,12 @@ impl KeepOptions {
             + self.keep_monthly.unwrap_or(0)
             + self.keep_yearly.unwrap_or(0)
             > 0
+            || self.keep_within.is_some()
+    }
+
+    /// Whether the verification state of the snapshots is required to apply these options.
+    pub fn needs_verify_state(&self) -> bool {
+        self.keep_last_verified.unwrap_or(false) || self.skip_failed_verify.unwrap_or(false)
     }
 }
 
@@ -85,6 +85,9 @@ pub use maintenance::*;
 mod network;
 pub use network::*;
 
+mod quota;
+pub use quota::*;
+
 #[macro_use]
 mod userid;
 pub use userid::Authid;
@@ -114,6 +117,9 @@ pub use ldap::*;
 mod remote;
 pub use remote::*;
 
+mod s3;
+pub use s3::*;
+
 mod tape;
 pub use tape::*;
 
@@ -22,14 +22,15 @@ pub const MAINTENANCE_MESSAGE_SCHEMA: Schema =
 pub enum Operation {
     /// for any read operation like backup restore or RRD metric collection
     Read,
-    /// for any write/delete operation, like backup create or GC
+    /// for any write operation, like backup create or sync
     Write,
+    /// for any operation only removing data, like prune, forget or GC
+    Delete,
     /// for any purely logical operation on the in-memory state of the datastore, e.g., to check if
     /// some mutex could be locked (e.g., GC already running?)
     ///
     /// NOTE: one must *not* do any IO operations when only helding this Op state
     Lookup,
-    // GarbageCollect or Delete?
 }
 
 #[api]
@@ -37,17 +38,16 @@ pub enum Operation {
 #[serde(rename_all = "kebab-case")]
 /// Maintenance type.
 pub enum MaintenanceType {
-    // TODO:
-    //  - Add "unmounting" once we got pluggable datastores
-    //  - Add "GarbageCollection" or "DeleteOnly" as type and track GC (or all deletes) as separate
-    //    operation, so that one can enable a mode where nothing new can be added but stuff can be
-    //    cleaned
     /// Only read operations are allowed on the datastore.
     ReadOnly,
+    /// Only read and delete operations (prune, forget and GC) are allowed on the datastore.
+    DeleteOnly,
     /// Neither read nor write operations are allowed on the datastore.
     Offline,
     /// The datastore is being deleted.
     Delete,
+    /// The removable datastore is being unmounted.
+    Unmount,
 }
 serde_plain::derive_display_from_serialize!(MaintenanceType);
 serde_plain::derive_fromstr_from_deserialize!(MaintenanceType);
@@ -67,7 +67,7 @@ serde_plain::derive_fromstr_from_deserialize!(MaintenanceType);
 #[derive(Deserialize, Serialize)]
 /// Maintenance mode
 pub struct MaintenanceMode {
-    /// Type of maintenance ("read-only" or "offline").
+    /// Type of maintenance ("read-only", "delete-only" or "offline").
     #[serde(rename = "type")]
     ty: MaintenanceType,
 
@@ -80,6 +80,8 @@ impl MaintenanceMode {
     pub fn check(&self, operation: Option<Operation>) -> Result<(), Error> {
         if self.ty == MaintenanceType::Delete {
             bail!("datastore is being deleted");
+        } else if self.ty == MaintenanceType::Unmount {
+            bail!("datastore is being unmounted");
         }
 
         let message = percent_encoding::percent_decode_str(self.message.as_deref().unwrap_or(""))
@@ -91,9 +93,13 @@ impl MaintenanceMode {
         } else if self.ty == MaintenanceType::Offline {
             bail!("offline maintenance mode: {}", message);
         } else if self.ty == MaintenanceType::ReadOnly {
-            if let Some(Operation::Write) = operation {
+            if let Some(Operation::Write | Operation::Delete) = operation {
                 bail!("read-only maintenance mode: {}", message);
             }
+        } else if self.ty == MaintenanceType::DeleteOnly {
+            if let Some(Operation::Write) = operation {
+                bail!("delete-only maintenance mode: {}", message);
+            }
         }
         Ok(())
     }
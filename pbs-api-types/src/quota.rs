@@ -0,0 +1,177 @@
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::*;
+
+use crate::{
+    BackupGroup, BackupNamespace, HumanByte, BACKUP_GROUP_SCHEMA, DATASTORE_SCHEMA,
+    PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
+};
+
+pub const QUOTA_ID_SCHEMA: Schema = StringSchema::new("Quota ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(32)
+    .schema();
+
+#[api(
+    properties: {
+        id: {
+            schema: QUOTA_ID_SCHEMA,
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        group: {
+            schema: BACKUP_GROUP_SCHEMA,
+            optional: true,
+        },
+        "max-logical-size": {
+            type: HumanByte,
+            optional: true,
+        },
+        "max-unique-size": {
+            type: HumanByte,
+            optional: true,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Updater, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Storage quota for a namespace (including its sub-namespaces) or a single backup group.
+pub struct QuotaConfig {
+    /// Unique ID to address this quota
+    #[updater(skip)]
+    pub id: String,
+
+    pub store: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+
+    /// Limit the quota to a single backup group in the namespace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+
+    /// Maximum sum of the archive sizes of all snapshots.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_logical_size: Option<HumanByte>,
+
+    /// Maximum sum of the stored sizes of the distinct chunks referenced by all snapshots.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_unique_size: Option<HumanByte>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl QuotaConfig {
+    pub fn acl_path(&self) -> Vec<&str> {
+        match &self.ns {
+            Some(ns) => ns.acl_path(&self.store),
+            None => vec!["datastore", &self.store],
+        }
+    }
+
+    /// The backup group this quota is limited to, if any.
+    pub fn backup_group(&self) -> Result<Option<BackupGroup>, Error> {
+        self.group.as_deref().map(str::parse).transpose()
+    }
+
+    /// Check whether a new snapshot in `group` is subject to this quota.
+    pub fn applies_to(&self, store: &str, ns: &BackupNamespace, group: &BackupGroup) -> bool {
+        if self.store != store {
+            return false;
+        }
+
+        let quota_ns = self.ns.clone().unwrap_or_default();
+
+        match self.backup_group() {
+            Ok(Some(quota_group)) => quota_ns == *ns && quota_group == *group,
+            Ok(None) => quota_ns.contains(ns).is_some(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Space used by the snapshots covered by a quota.
+pub struct QuotaUsage {
+    /// Sum of the archive sizes of all snapshots (bytes).
+    pub logical_size: u64,
+    /// Sum of the stored sizes of the distinct chunks referenced by all snapshots (bytes). Only calculated if a
+    /// unique size limit is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unique_size: Option<u64>,
+}
+
+#[api(
+    properties: {
+        config: {
+            type: QuotaConfig,
+        },
+        usage: {
+            type: QuotaUsage,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Quota configuration and current usage.
+pub struct QuotaStatus {
+    #[serde(flatten)]
+    pub config: QuotaConfig,
+    #[serde(flatten)]
+    pub usage: QuotaUsage,
+}
+
+#[test]
+fn test_quota_applies_to() -> Result<(), Error> {
+    let group: BackupGroup = "vm/100".parse()?;
+    let other_group: BackupGroup = "vm/101".parse()?;
+    let ns = BackupNamespace::new("a/b")?;
+
+    let mut quota = QuotaConfig {
+        id: "test".to_string(),
+        store: "store1".to_string(),
+        ns: None,
+        group: None,
+        max_logical_size: None,
+        max_unique_size: None,
+        comment: None,
+    };
+
+    // a quota on the root namespace covers the whole datastore, but only that one
+    assert!(quota.applies_to("store1", &BackupNamespace::root(), &group));
+    assert!(quota.applies_to("store1", &ns, &group));
+    assert!(!quota.applies_to("store2", &ns, &group));
+
+    // namespace quotas include sub-namespaces, but not parents or siblings
+    quota.ns = Some(BackupNamespace::new("a")?);
+    assert!(quota.applies_to("store1", &ns, &group));
+    assert!(quota.applies_to("store1", &BackupNamespace::new("a")?, &group));
+    assert!(!quota.applies_to("store1", &BackupNamespace::root(), &group));
+    assert!(!quota.applies_to("store1", &BackupNamespace::new("c/a")?, &group));
+
+    // group quotas only cover the group in exactly that namespace
+    quota.group = Some("vm/100".to_string());
+    assert!(!quota.applies_to("store1", &ns, &group));
+    assert!(quota.applies_to("store1", &BackupNamespace::new("a")?, &group));
+    assert!(!quota.applies_to("store1", &BackupNamespace::new("a")?, &other_group));
+
+    // an invalid group never applies
+    quota.group = Some("invalid".to_string());
+    assert!(!quota.applies_to("store1", &BackupNamespace::new("a")?, &group));
+
+    Ok(())
+}
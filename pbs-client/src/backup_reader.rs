@@ -1,6 +1,6 @@
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::os::unix::fs::OpenOptionsExt;
 use std::sync::Arc;
 
@@ -11,20 +11,28 @@ use pbs_api_types::{BackupDir, BackupNamespace};
 use pbs_datastore::data_blob::DataBlob;
 use pbs_datastore::data_blob_reader::DataBlobReader;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::file_formats::{DictDataBlobHeader, DICT_COMPR_BLOB_MAGIC_1_0};
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::zstd_dictionary::{lookup_dictionary, register_dictionary, ZstdDictionary};
 use pbs_datastore::{BackupManifest, PROXMOX_BACKUP_READER_PROTOCOL_ID_V1};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::sha::sha256;
 
 use super::{H2Client, HttpClient};
 
+/// Name to register the zstd dictionaries of a remote datastore with, unique per server.
+pub(crate) fn dictionary_store(client: &HttpClient, datastore: &str) -> String {
+    format!("{}:{}/{}", client.server(), client.port(), datastore)
+}
+
 /// Backup Reader
 pub struct BackupReader {
     h2: H2Client,
     abort: AbortHandle,
     crypt_config: Option<Arc<CryptConfig>>,
+    dictionary_store: String,
 }
 
 impl Drop for BackupReader {
@@ -34,11 +42,17 @@ impl Drop for BackupReader {
 }
 
 impl BackupReader {
-    fn new(h2: H2Client, abort: AbortHandle, crypt_config: Option<Arc<CryptConfig>>) -> Arc<Self> {
+    fn new(
+        h2: H2Client,
+        abort: AbortHandle,
+        crypt_config: Option<Arc<CryptConfig>>,
+        dictionary_store: String,
+    ) -> Arc<Self> {
         Arc::new(Self {
             h2,
             abort,
             crypt_config,
+            dictionary_store,
         })
     }
 
@@ -76,7 +90,9 @@ impl BackupReader {
             .start_h2_connection(req, String::from(PROXMOX_BACKUP_READER_PROTOCOL_ID_V1!()))
             .await?;
 
-        Ok(BackupReader::new(h2, abort, crypt_config))
+        let dictionary_store = dictionary_store(&client, datastore);
+
+        Ok(BackupReader::new(h2, abort, crypt_config, dictionary_store))
     }
 
     /// Execute a GET request
@@ -119,6 +135,33 @@ impl BackupReader {
         self.h2.download(path, Some(param), output).await
     }
 
+    /// The name the zstd dictionaries of the datastore are registered with, see
+    /// [`DataBlob::decode_for_store`].
+    pub fn dictionary_store(&self) -> &str {
+        &self.dictionary_store
+    }
+
+    /// Download and register the zstd dictionary with `id`, unless already registered.
+    ///
+    /// Needed to decode chunks and blobs compressed with a dictionary.
+    pub async fn download_zstd_dictionary(&self, id: u32) -> Result<Arc<ZstdDictionary>, Error> {
+        if let Some(dictionary) = lookup_dictionary(&self.dictionary_store, id) {
+            return Ok(dictionary);
+        }
+
+        let mut data = Vec::new();
+        let param = json!({ "id": format!("{id:08x}") });
+        self.h2
+            .download("zstd_dictionary", Some(param), &mut data)
+            .await?;
+
+        let dictionary = ZstdDictionary::new(data);
+        if dictionary.id() != id {
+            bail!("downloaded zstd dictionary {id:08x} has wrong ID");
+        }
+        register_dictionary(&self.dictionary_store, dictionary)
+    }
+
     pub fn force_close(self) {
         self.abort.abort();
     }
@@ -160,9 +203,16 @@ impl BackupReader {
         let (csum, size) = sha256(&mut tmpfile)?;
         manifest.verify_file(name, &csum, size)?;
 
+        tmpfile.seek(SeekFrom::Start(0))?;
+        let mut head = [0u8; std::mem::size_of::<DictDataBlobHeader>()];
+        if tmpfile.read_exact(&mut head).is_ok() && head[..8] == DICT_COMPR_BLOB_MAGIC_1_0 {
+            let id = u32::from_le_bytes(head[12..].try_into().unwrap());
+            self.download_zstd_dictionary(id).await?;
+        }
+
         tmpfile.seek(SeekFrom::Start(0))?;
 
-        DataBlobReader::new(tmpfile, self.crypt_config.clone())
+        DataBlobReader::new_for_store(tmpfile, self.crypt_config.clone(), &self.dictionary_store)
     }
 
     /// Download dynamic index file
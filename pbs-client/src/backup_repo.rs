@@ -7,7 +7,7 @@ use pbs_api_types::{Authid, Userid, BACKUP_REPO_URL_REGEX, IP_V6_REGEX};
 /// Reference remote backup locations
 ///
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BackupRepository {
     /// The user name used for Authentication
     auth_id: Option<Authid>,
@@ -1,34 +1,43 @@
 use std::collections::HashSet;
 use std::future::Future;
 use std::os::unix::fs::OpenOptionsExt;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
 use futures::future::{self, AbortHandle, Either, FutureExt, TryFutureExt};
 use futures::stream::{Stream, StreamExt, TryStreamExt};
+use hex::FromHex;
 use serde_json::{json, Value};
 use tokio::io::AsyncReadExt;
 use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 
 use pbs_api_types::{BackupDir, BackupNamespace, HumanByte};
-use pbs_datastore::data_blob::{ChunkInfo, DataBlob, DataChunkBuilder};
+use pbs_datastore::chunker::ChunkerConfig;
+use pbs_datastore::data_blob::{ChunkInfo, Compression, DataBlob, DataChunkBuilder};
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{ArchiveType, BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_datastore::zstd_dictionary::{lookup_dictionary, register_dictionary, ZstdDictionary};
 use pbs_datastore::{CATALOG_NAME, PROXMOX_BACKUP_PROTOCOL_ID_V1};
 use pbs_tools::crypt_config::CryptConfig;
 
+use super::inject_reused_chunks::{InjectChunks, InjectReusedChunks, InjectedChunksInfo};
 use super::merge_known_chunks::{MergeKnownChunks, MergedChunkInfo};
 
+use super::backup_reader::dictionary_store;
 use super::{H2Client, HttpClient};
 
 pub struct BackupWriter {
     h2: H2Client,
     abort: AbortHandle,
     crypt_config: Option<Arc<CryptConfig>>,
+    /// Archives kept by the server from the interrupted backup this session resumes
+    resume_archives: HashSet<String>,
+    /// Name the zstd dictionaries of the datastore are registered with
+    dictionary_store: String,
 }
 
 impl Drop for BackupWriter {
@@ -47,8 +56,15 @@ pub struct BackupStats {
 pub struct UploadOptions {
     pub previous_manifest: Option<Arc<BackupManifest>>,
     pub compress: bool,
+    /// Compression level and dictionary, the defaults are used if unset
+    pub compression: Option<Arc<Compression>>,
     pub encrypt: bool,
     pub fixed_size: Option<u64>,
+    /// Chunker used to split a dynamic archive, recorded in its index
+    ///
+    /// The default chunker is not sent, so that servers without chunker selection still accept
+    /// the archive.
+    pub chunker: Option<ChunkerConfig>,
 }
 
 struct UploadStats {
@@ -61,15 +77,26 @@ struct UploadStats {
     csum: [u8; 32],
 }
 
+/// Number of new chunks checked with a single `known_chunks` request.
+const KNOWN_CHUNKS_QUERY_SIZE: usize = 256;
+
 type UploadQueueSender = mpsc::Sender<(MergedChunkInfo, Option<h2::client::ResponseFuture>)>;
 type UploadResultReceiver = oneshot::Receiver<Result<(), Error>>;
 
 impl BackupWriter {
-    fn new(h2: H2Client, abort: AbortHandle, crypt_config: Option<Arc<CryptConfig>>) -> Arc<Self> {
+    fn new(
+        h2: H2Client,
+        abort: AbortHandle,
+        crypt_config: Option<Arc<CryptConfig>>,
+        resume_archives: HashSet<String>,
+        dictionary_store: String,
+    ) -> Arc<Self> {
         Arc::new(Self {
             h2,
             abort,
             crypt_config,
+            resume_archives,
+            dictionary_store,
         })
     }
 
@@ -83,6 +110,7 @@ impl BackupWriter {
         backup: &BackupDir,
         debug: bool,
         benchmark: bool,
+        resume: bool,
     ) -> Result<Arc<BackupWriter>, Error> {
         let mut param = json!({
             "backup-type": backup.ty(),
@@ -96,6 +124,10 @@ impl BackupWriter {
         if !ns.is_root() {
             param["ns"] = serde_json::to_value(ns)?;
         }
+        // only sent if requested, older servers do not know the parameter
+        if resume {
+            param["resume"] = true.into();
+        }
 
         let req = HttpClient::request_builder(
             client.server(),
@@ -110,7 +142,39 @@ impl BackupWriter {
             .start_h2_connection(req, String::from(PROXMOX_BACKUP_PROTOCOL_ID_V1!()))
             .await?;
 
-        Ok(BackupWriter::new(h2, abort, crypt_config))
+        let mut resume_archives = HashSet::new();
+        if resume {
+            let info = h2.get("resume", None).await?;
+            if let Some(files) = info["files"].as_array() {
+                // completed indexes, blobs have no checksum
+                resume_archives.extend(files.iter().filter_map(|file| {
+                    file["csum"].as_str()?;
+                    file["filename"].as_str().map(String::from)
+                }));
+            }
+            if let Some(archives) = info["archives"].as_array() {
+                resume_archives.extend(
+                    archives
+                        .iter()
+                        .filter_map(|archive| archive["archive-name"].as_str().map(String::from)),
+                );
+            }
+        }
+
+        let dictionary_store = dictionary_store(&client, datastore);
+
+        Ok(BackupWriter::new(
+            h2,
+            abort,
+            crypt_config,
+            resume_archives,
+            dictionary_store,
+        ))
+    }
+
+    /// Whether this session resumes an interrupted backup kept by the server.
+    pub fn is_resuming(&self) -> bool {
+        !self.resume_archives.is_empty()
     }
 
     pub async fn get(&self, path: &str, param: Option<Value>) -> Result<Value, Error> {
@@ -210,11 +274,19 @@ impl BackupWriter {
         file_name: &str,
         options: UploadOptions,
     ) -> Result<BackupStats, Error> {
+        let default_compression = Compression::default();
+        let compression = options.compress.then(|| {
+            options
+                .compression
+                .as_deref()
+                .unwrap_or(&default_compression)
+        });
+
         let blob = match (options.encrypt, &self.crypt_config) {
-            (false, _) => DataBlob::encode(&data, None, options.compress)?,
+            (false, _) => DataBlob::encode_with_compression(&data, None, compression)?,
             (true, None) => bail!("requested encryption without a crypt config"),
             (true, Some(crypt_config)) => {
-                DataBlob::encode(&data, Some(crypt_config), options.compress)?
+                DataBlob::encode_with_compression(&data, Some(crypt_config), compression)?
             }
         };
 
@@ -258,11 +330,16 @@ impl BackupWriter {
             .await
     }
 
+    /// Upload a chunk stream as index `archive_name`.
+    ///
+    /// Chunks reused from the previous backup are received via `injections` and appended to the
+    /// index at their offset. They need to be part of the previous index of the same archive.
     pub async fn upload_stream(
         &self,
         archive_name: &str,
-        stream: impl Stream<Item = Result<bytes::BytesMut, Error>>,
+        stream: impl Stream<Item = Result<bytes::BytesMut, Error>> + Unpin,
         options: UploadOptions,
+        injections: Option<std::sync::mpsc::Receiver<InjectChunks>>,
     ) -> Result<BackupStats, Error> {
         let known_chunks = Arc::new(Mutex::new(HashSet::new()));
 
@@ -271,6 +348,9 @@ impl BackupWriter {
             param["size"] = size.into();
             "fixed"
         } else {
+            if let Some(chunker) = &options.chunker {
+                add_chunker_param(&mut param, chunker);
+            }
             "dynamic"
         };
 
@@ -312,6 +392,16 @@ impl BackupWriter {
             }
         }
 
+        if self.resume_archives.contains(archive_name) {
+            // try, but ignore errors
+            if let Err(err) = self
+                .download_resume_index(archive_name, known_chunks.clone())
+                .await
+            {
+                eprintln!("Error downloading index of interrupted backup: {}", err);
+            }
+        }
+
         let wid = self
             .h2
             .post(&index_path, Some(param))
@@ -322,7 +412,7 @@ impl BackupWriter {
         let upload_stats = Self::upload_chunk_info_stream(
             self.h2.clone(),
             wid,
-            stream,
+            InjectReusedChunks::new(stream, injections),
             prefix,
             known_chunks.clone(),
             if options.encrypt {
@@ -331,6 +421,7 @@ impl BackupWriter {
                 None
             },
             options.compress,
+            options.compression.clone(),
         )
         .await?;
 
@@ -402,6 +493,63 @@ impl BackupWriter {
         })
     }
 
+    /// Upload an index whose chunks are already encoded, e.g. when pushing an existing snapshot
+    /// to another server.
+    ///
+    /// New chunks in `stream` get uploaded, known ones are only appended to the index. `csum` and
+    /// `size` are the checksum and size of the source index, which the server verifies on close.
+    pub async fn upload_index_chunk_info(
+        &self,
+        archive_name: &str,
+        stream: impl Stream<Item = Result<MergedChunkInfo, Error>>,
+        csum: [u8; 32],
+        size: u64,
+        options: UploadOptions,
+    ) -> Result<BackupStats, Error> {
+        let mut param = json!({ "archive-name": archive_name });
+        let prefix = if let Some(size) = options.fixed_size {
+            param["size"] = size.into();
+            "fixed"
+        } else {
+            if let Some(chunker) = &options.chunker {
+                add_chunker_param(&mut param, chunker);
+            }
+            "dynamic"
+        };
+
+        let index_path = format!("{}_index", prefix);
+        let close_path = format!("{}_close", prefix);
+
+        let wid = self
+            .h2
+            .post(&index_path, Some(param))
+            .await?
+            .as_u64()
+            .unwrap();
+
+        let chunk_count = Arc::new(AtomicUsize::new(0));
+        let chunk_count2 = chunk_count.clone();
+
+        let stream = stream.inspect_ok(move |merged_chunk_info| {
+            let count = match merged_chunk_info {
+                MergedChunkInfo::Known(list) => list.len(),
+                MergedChunkInfo::New(_) => 1,
+            };
+            chunk_count.fetch_add(count, Ordering::SeqCst);
+        });
+
+        Self::upload_merged_chunk_stream(self.h2.clone(), wid, prefix, stream).await?;
+
+        let param = json!({
+            "wid": wid,
+            "chunk-count": chunk_count2.load(Ordering::SeqCst),
+            "size": size,
+            "csum": hex::encode(csum),
+        });
+        let _value = self.h2.post(&close_path, Some(param)).await?;
+        Ok(BackupStats { size, csum })
+    }
+
     fn response_queue() -> (
         mpsc::Sender<h2::client::ResponseFuture>,
         oneshot::Receiver<Result<(), Error>>,
@@ -585,6 +733,67 @@ impl BackupWriter {
         Ok(index)
     }
 
+    /// Download the index of `archive_name` kept from the interrupted backup and add its chunks
+    /// to `known_chunks`, so that they are not uploaded again.
+    ///
+    /// The server registers the chunks for this session when sending the index.
+    pub async fn download_resume_index(
+        &self,
+        archive_name: &str,
+        known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    ) -> Result<(), Error> {
+        let mut tmpfile = std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .custom_flags(libc::O_TMPFILE)
+            .open("/tmp")?;
+
+        let param = json!({ "archive-name": archive_name });
+        self.h2
+            .download("resume_index", Some(param), &mut tmpfile)
+            .await?;
+
+        let index: Box<dyn IndexFile> = match ArchiveType::from_path(archive_name)? {
+            ArchiveType::FixedIndex => Box::new(FixedIndexReader::new(tmpfile)?),
+            ArchiveType::DynamicIndex => Box::new(DynamicIndexReader::new(tmpfile)?),
+            ArchiveType::Blob => bail!("no index for archive '{}'", archive_name),
+        };
+
+        let mut known_chunks = known_chunks.lock().unwrap();
+        let mut count = 0;
+        for i in 0..index.index_count() {
+            let digest = index.index_digest(i).unwrap();
+            if digest != &[0u8; 32] {
+                // unwritten entries of a partial fixed index are zero
+                known_chunks.insert(*digest);
+                count += 1;
+            }
+        }
+
+        log::info!(
+            "{}: resuming interrupted upload, {} chunks already on the server",
+            archive_name,
+            count
+        );
+
+        Ok(())
+    }
+
+    /// Chunker recorded in the previous backup's index of `archive_name`
+    ///
+    /// Returns `None` if the previous index was written without chunker information.
+    pub async fn previous_chunker(
+        &self,
+        archive_name: &str,
+        manifest: &BackupManifest,
+    ) -> Result<Option<ChunkerConfig>, Error> {
+        let known_chunks = Arc::new(Mutex::new(HashSet::new()));
+        let index = self
+            .download_previous_dynamic_index(archive_name, manifest, known_chunks)
+            .await?;
+        Ok(index.chunker)
+    }
+
     /// Retrieve backup time of last backup
     pub async fn previous_backup_time(&self) -> Result<Option<i64>, Error> {
         let data = self.h2.get("previous_backup_time", None).await?;
@@ -596,6 +805,59 @@ impl BackupWriter {
         })
     }
 
+    /// Query the compression settings configured for the datastore.
+    ///
+    /// A configured zstd dictionary gets downloaded and registered, so that it is available for
+    /// encoding.
+    pub async fn server_compression(&self) -> Result<Compression, Error> {
+        let data = self.h2.get("compression", None).await?;
+
+        let mut compression = Compression::default();
+        if let Some(level) = data["level"].as_i64() {
+            compression.level = level as i32;
+        }
+        if let Some(id) = data["zstd-dictionary"].as_str() {
+            let id = u32::from_str_radix(id, 16)?;
+            compression.dictionary = Some(self.download_zstd_dictionary(id).await?);
+        }
+
+        Ok(compression)
+    }
+
+    /// Download and register the zstd dictionary with `id`, unless already registered.
+    pub async fn download_zstd_dictionary(&self, id: u32) -> Result<Arc<ZstdDictionary>, Error> {
+        if let Some(dictionary) = lookup_dictionary(&self.dictionary_store, id) {
+            return Ok(dictionary);
+        }
+
+        let mut data = Vec::new();
+        let param = json!({ "id": format!("{id:08x}") });
+        self.h2
+            .download("zstd_dictionary", Some(param), &mut data)
+            .await?;
+
+        let dictionary = ZstdDictionary::new(data);
+        if dictionary.id() != id {
+            bail!("downloaded zstd dictionary {id:08x} has wrong ID");
+        }
+        register_dictionary(&self.dictionary_store, dictionary)
+    }
+
+    /// Upload a zstd dictionary, the server needs it to accept chunks and blobs compressed with it.
+    pub async fn upload_zstd_dictionary(&self, dictionary: &ZstdDictionary) -> Result<(), Error> {
+        let param = json!({ "id": format!("{:08x}", dictionary.id()) });
+        self.h2
+            .upload(
+                "POST",
+                "zstd_dictionary",
+                Some(param),
+                "application/octet-stream",
+                dictionary.data().to_vec(),
+            )
+            .await?;
+        Ok(())
+    }
+
     /// Download backup manifest (index.json) of last backup
     pub async fn download_previous_manifest(&self) -> Result<BackupManifest, Error> {
         let mut raw_data = Vec::with_capacity(64 * 1024);
@@ -622,11 +884,12 @@ impl BackupWriter {
     fn upload_chunk_info_stream(
         h2: H2Client,
         wid: u64,
-        stream: impl Stream<Item = Result<bytes::BytesMut, Error>>,
+        stream: impl Stream<Item = Result<InjectedChunksInfo, Error>>,
         prefix: &str,
         known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
         crypt_config: Option<Arc<CryptConfig>>,
         compress: bool,
+        compression: Option<Arc<Compression>>,
     ) -> impl Future<Output = Result<UploadStats, Error>> {
         let total_chunks = Arc::new(AtomicUsize::new(0));
         let total_chunks2 = total_chunks.clone();
@@ -640,63 +903,235 @@ impl BackupWriter {
         let reused_len = Arc::new(AtomicUsize::new(0));
         let reused_len2 = reused_len.clone();
 
-        let append_chunk_path = format!("{}_index", prefix);
-        let upload_chunk_path = format!("{}_chunk", prefix);
-        let is_fixed_chunk_size = prefix == "fixed";
+        let server_known = {
+            let known_chunk_count = known_chunk_count.clone();
+            let reused_len = reused_len.clone();
+            let compressed_stream_len = compressed_stream_len.clone();
+            move |chunk_info: &ChunkInfo| {
+                known_chunk_count.fetch_add(1, Ordering::SeqCst);
+                reused_len.fetch_add(chunk_info.chunk_len as usize, Ordering::SeqCst);
+                compressed_stream_len.fetch_sub(chunk_info.chunk.raw_size(), Ordering::SeqCst);
+            }
+        };
 
-        let (upload_queue, upload_result) =
-            Self::append_chunk_queue(h2.clone(), wid, append_chunk_path);
+        let is_fixed_chunk_size = prefix == "fixed";
+        // the server cannot check the size of encrypted chunks, so it never reports them as known
+        let query_known = crypt_config.is_none();
 
         let start_time = std::time::Instant::now();
 
         let index_csum = Arc::new(Mutex::new(Some(openssl::sha::Sha256::new())));
         let index_csum_2 = index_csum.clone();
 
-        stream
-            .and_then(move |data| {
-                let chunk_len = data.len();
+        let chunk_info_stream = stream.and_then(move |chunk_info| {
+            let data = match chunk_info {
+                InjectedChunksInfo::Known(chunks) => {
+                    let mut known_chunks = known_chunks.lock().unwrap();
+                    let mut guard = index_csum.lock().unwrap();
+                    let csum = guard.as_mut().unwrap();
+
+                    let mut known = Vec::with_capacity(chunks.len());
+                    for (chunk_len, digest) in chunks {
+                        if !known_chunks.contains(&digest) {
+                            return future::err(format_err!(
+                                "reused chunk {} is not part of the previous index",
+                                hex::encode(digest)
+                            ));
+                        }
+                        known_chunks.insert(digest);
+
+                        total_chunks.fetch_add(1, Ordering::SeqCst);
+                        known_chunk_count.fetch_add(1, Ordering::SeqCst);
+                        reused_len.fetch_add(chunk_len as usize, Ordering::SeqCst);
+                        let offset =
+                            stream_len.fetch_add(chunk_len as usize, Ordering::SeqCst) as u64;
 
-                total_chunks.fetch_add(1, Ordering::SeqCst);
-                let offset = stream_len.fetch_add(chunk_len, Ordering::SeqCst) as u64;
+                        let chunk_end = offset + chunk_len;
+                        csum.update(&chunk_end.to_le_bytes());
+                        csum.update(&digest);
 
-                let mut chunk_builder = DataChunkBuilder::new(data.as_ref()).compress(compress);
+                        known.push((offset, digest));
+                    }
 
-                if let Some(ref crypt_config) = crypt_config {
-                    chunk_builder = chunk_builder.crypt_config(crypt_config);
+                    return future::ok(MergedChunkInfo::Known(known));
                 }
+                InjectedChunksInfo::Raw(data) => data,
+            };
+            let chunk_len = data.len();
 
-                let mut known_chunks = known_chunks.lock().unwrap();
-                let digest = chunk_builder.digest();
+            total_chunks.fetch_add(1, Ordering::SeqCst);
+            let offset = stream_len.fetch_add(chunk_len, Ordering::SeqCst) as u64;
 
-                let mut guard = index_csum.lock().unwrap();
-                let csum = guard.as_mut().unwrap();
+            let mut chunk_builder = DataChunkBuilder::new(data.as_ref()).compress(compress);
 
-                let chunk_end = offset + chunk_len as u64;
+            if let Some(ref compression) = compression {
+                chunk_builder = chunk_builder.compression(compression);
+            }
 
-                if !is_fixed_chunk_size {
-                    csum.update(&chunk_end.to_le_bytes());
-                }
-                csum.update(digest);
+            if let Some(ref crypt_config) = crypt_config {
+                chunk_builder = chunk_builder.crypt_config(crypt_config);
+            }
 
-                let chunk_is_known = known_chunks.contains(digest);
-                if chunk_is_known {
-                    known_chunk_count.fetch_add(1, Ordering::SeqCst);
-                    reused_len.fetch_add(chunk_len, Ordering::SeqCst);
-                    future::ok(MergedChunkInfo::Known(vec![(offset, *digest)]))
-                } else {
-                    let compressed_stream_len2 = compressed_stream_len.clone();
-                    known_chunks.insert(*digest);
-                    future::ready(chunk_builder.build().map(move |(chunk, digest)| {
-                        compressed_stream_len2.fetch_add(chunk.raw_size(), Ordering::SeqCst);
-                        MergedChunkInfo::New(ChunkInfo {
-                            chunk,
-                            digest,
-                            chunk_len: chunk_len as u64,
-                            offset,
+            let mut known_chunks = known_chunks.lock().unwrap();
+            let digest = chunk_builder.digest();
+
+            let mut guard = index_csum.lock().unwrap();
+            let csum = guard.as_mut().unwrap();
+
+            let chunk_end = offset + chunk_len as u64;
+
+            if !is_fixed_chunk_size {
+                csum.update(&chunk_end.to_le_bytes());
+            }
+            csum.update(digest);
+
+            let chunk_is_known = known_chunks.contains(digest);
+            if chunk_is_known {
+                known_chunk_count.fetch_add(1, Ordering::SeqCst);
+                reused_len.fetch_add(chunk_len, Ordering::SeqCst);
+                future::ok(MergedChunkInfo::Known(vec![(offset, *digest)]))
+            } else {
+                let compressed_stream_len2 = compressed_stream_len.clone();
+                known_chunks.insert(*digest);
+                future::ready(chunk_builder.build().map(move |(chunk, digest)| {
+                    compressed_stream_len2.fetch_add(chunk.raw_size(), Ordering::SeqCst);
+                    MergedChunkInfo::New(ChunkInfo {
+                        chunk,
+                        digest,
+                        chunk_len: chunk_len as u64,
+                        offset,
+                    })
+                }))
+            }
+        });
+
+        let chunk_info_stream = Self::skip_server_known_chunks(
+            h2.clone(),
+            chunk_info_stream,
+            server_known,
+            query_known,
+        );
+
+        Self::upload_merged_chunk_stream(h2, wid, prefix, chunk_info_stream).and_then(move |_| {
+            let duration = start_time.elapsed();
+            let chunk_count = total_chunks2.load(Ordering::SeqCst);
+            let chunk_reused = known_chunk_count2.load(Ordering::SeqCst);
+            let size = stream_len2.load(Ordering::SeqCst);
+            let size_reused = reused_len2.load(Ordering::SeqCst);
+            let size_compressed = compressed_stream_len2.load(Ordering::SeqCst) as usize;
+
+            let mut guard = index_csum_2.lock().unwrap();
+            let csum = guard.take().unwrap().finish();
+
+            futures::future::ok(UploadStats {
+                chunk_count,
+                chunk_reused,
+                size,
+                size_reused,
+                size_compressed,
+                duration,
+                csum,
+            })
+        })
+    }
+
+    /// Asks the server which of the new chunks in `stream` already exist in the datastore, those
+    /// are only appended to the index instead of being uploaded. `on_known` is called for each
+    /// of them.
+    ///
+    /// If the server does not support or allow the query, or `enabled` is false, all new chunks
+    /// get uploaded.
+    fn skip_server_known_chunks(
+        h2: H2Client,
+        stream: impl Stream<Item = Result<MergedChunkInfo, Error>>,
+        on_known: impl Fn(&ChunkInfo) + Clone,
+        enabled: bool,
+    ) -> impl Stream<Item = Result<MergedChunkInfo, Error>> {
+        let enabled = Arc::new(AtomicBool::new(enabled));
+
+        stream
+            .ready_chunks(KNOWN_CHUNKS_QUERY_SIZE)
+            .map(move |batch| {
+                let h2 = h2.clone();
+                let enabled = enabled.clone();
+                let on_known = on_known.clone();
+                async move {
+                    let batch = batch.into_iter().collect::<Result<Vec<_>, Error>>()?;
+
+                    let (digest_list, size_list): (Vec<_>, Vec<_>) = batch
+                        .iter()
+                        .filter_map(|info| match info {
+                            MergedChunkInfo::New(chunk_info) => {
+                                Some((hex::encode(chunk_info.digest), chunk_info.chunk_len))
+                            }
+                            MergedChunkInfo::Known(_) => None,
+                        })
+                        .unzip();
+
+                    if digest_list.is_empty() || !enabled.load(Ordering::SeqCst) {
+                        return Ok(batch);
+                    }
+
+                    let param = json!({ "digest-list": digest_list, "size-list": size_list });
+                    let known = match h2.post("known_chunks", Some(param)).await {
+                        Ok(known) => known,
+                        Err(err) => {
+                            if enabled.swap(false, Ordering::SeqCst) {
+                                log::debug!("unable to query known chunks, uploading all - {err}");
+                            }
+                            return Ok(batch);
+                        }
+                    };
+
+                    let known = known
+                        .as_array()
+                        .ok_or_else(|| format_err!("got unexpected known chunks response"))?
+                        .iter()
+                        .map(|digest| match digest.as_str() {
+                            Some(digest) => Ok(<[u8; 32]>::from_hex(digest)?),
+                            None => bail!("got unexpected known chunks response"),
+                        })
+                        .collect::<Result<HashSet<_>, Error>>()?;
+
+                    let batch = batch
+                        .into_iter()
+                        .map(|info| match info {
+                            MergedChunkInfo::New(chunk_info)
+                                if known.contains(&chunk_info.digest) =>
+                            {
+                                log::trace!(
+                                    "skip upload of chunk {} known to the server",
+                                    hex::encode(chunk_info.digest)
+                                );
+                                on_known(&chunk_info);
+                                MergedChunkInfo::Known(vec![(chunk_info.offset, chunk_info.digest)])
+                            }
+                            info => info,
                         })
-                    }))
+                        .collect::<Vec<_>>();
+
+                    Ok::<_, Error>(batch)
                 }
             })
+            .buffered(4)
+            .map_ok(|batch| futures::stream::iter(batch.into_iter().map(Ok::<_, Error>)))
+            .try_flatten()
+    }
+
+    /// Uploads new chunks and appends all chunks to the index writer `wid` on the server.
+    fn upload_merged_chunk_stream(
+        h2: H2Client,
+        wid: u64,
+        prefix: &str,
+        stream: impl Stream<Item = Result<MergedChunkInfo, Error>>,
+    ) -> impl Future<Output = Result<(), Error>> {
+        let append_chunk_path = format!("{}_index", prefix);
+        let upload_chunk_path = format!("{}_chunk", prefix);
+
+        let (upload_queue, upload_result) =
+            Self::append_chunk_queue(h2.clone(), wid, append_chunk_path);
+
+        stream
             .merge_known_chunks()
             .try_for_each(move |merged_chunk_info| {
                 let upload_queue = upload_queue.clone();
@@ -754,27 +1189,6 @@ impl BackupWriter {
                 }
             })
             .then(move |result| async move { upload_result.await?.and(result) }.boxed())
-            .and_then(move |_| {
-                let duration = start_time.elapsed();
-                let chunk_count = total_chunks2.load(Ordering::SeqCst);
-                let chunk_reused = known_chunk_count2.load(Ordering::SeqCst);
-                let size = stream_len2.load(Ordering::SeqCst);
-                let size_reused = reused_len2.load(Ordering::SeqCst);
-                let size_compressed = compressed_stream_len2.load(Ordering::SeqCst) as usize;
-
-                let mut guard = index_csum_2.lock().unwrap();
-                let csum = guard.take().unwrap().finish();
-
-                futures::future::ok(UploadStats {
-                    chunk_count,
-                    chunk_reused,
-                    size,
-                    size_reused,
-                    size_compressed,
-                    duration,
-                    csum,
-                })
-            })
     }
 
     /// Upload speed test - prints result to stderr
@@ -831,3 +1245,14 @@ impl BackupWriter {
         Ok(speed)
     }
 }
+
+fn add_chunker_param(param: &mut Value, chunker: &ChunkerConfig) {
+    // an index without chunker information implies the default one
+    if *chunker == ChunkerConfig::default() {
+        return;
+    }
+    param["chunker"] = json!(chunker.ty);
+    param["chunk-size-min"] = chunker.min.into();
+    param["chunk-size-avg"] = chunker.avg.into();
+    param["chunk-size-max"] = chunker.max.into();
+}
@@ -20,7 +20,8 @@ use proxmox_sys::fs::{create_path, CreateOptions};
 use pxar::accessor::ReadAt;
 use pxar::{EntryKind, Metadata};
 
-use pbs_datastore::catalog::{self, DirEntryAttribute};
+use pbs_api_types::HumanByte;
+use pbs_datastore::catalog::{self, CatalogEntryType, DirEntryAttribute};
 use proxmox_async::runtime::block_in_place;
 
 use crate::pxar::Flags;
@@ -95,6 +96,24 @@ pub fn catalog_shell_cli() -> CommandLineInterface {
                 "find",
                 CliCommand::new(&API_METHOD_FIND_COMMAND).arg_param(&["pattern"]),
             )
+            .insert(
+                "du",
+                CliCommand::new(&API_METHOD_DU_COMMAND)
+                    .arg_param(&["path"])
+                    .completion_cb("path", complete_path),
+            )
+            .insert(
+                "diff",
+                CliCommand::new(&API_METHOD_DIFF_COMMAND)
+                    .arg_param(&["snapshot", "path"])
+                    .completion_cb("path", complete_path),
+            )
+            .insert(
+                "history",
+                CliCommand::new(&API_METHOD_HISTORY_COMMAND)
+                    .arg_param(&["path"])
+                    .completion_cb("path", complete_path),
+            )
             .insert("exit", CliCommand::new(&API_METHOD_EXIT))
             .insert_help(),
     )
@@ -257,6 +276,66 @@ async fn find_command(pattern: String, select: bool) -> Result<(), Error> {
     Shell::with(move |shell| shell.find(pattern, select)).await
 }
 
+#[api(
+    input: {
+        properties: {
+            path: {
+                type: String,
+                optional: true,
+                description: "target path."
+            }
+        }
+    }
+)]
+/// Show the total size of the files in the working directory or given path, by entry.
+///
+/// The sizes are taken from the catalog, hardlinks are not counted.
+async fn du_command(path: Option<String>) -> Result<(), Error> {
+    let path = path.as_ref().map(Path::new);
+    Shell::with(move |shell| shell.du(path)).await
+}
+
+#[api(
+    input: {
+        properties: {
+            snapshot: {
+                type: String,
+                description: "Snapshot to compare with, either its path or its backup time."
+            },
+            path: {
+                type: String,
+                optional: true,
+                description: "target path."
+            }
+        }
+    }
+)]
+/// Show the entries added (A), deleted (D) or modified (M) compared to another snapshot.
+///
+/// Only the catalogs are compared, so modified files are detected by their size and modification
+/// time. Symbolic links in the path are not followed.
+async fn diff_command(snapshot: String, path: Option<String>) -> Result<(), Error> {
+    Shell::with(move |shell| shell.diff(snapshot, path.map(PathBuf::from))).await
+}
+
+#[api(
+    input: {
+        properties: {
+            path: {
+                type: String,
+                description: "target path."
+            }
+        }
+    }
+)]
+/// List the snapshots of the backup group containing an entry, with its size and modification
+/// time in each.
+///
+/// This downloads the catalogs of all snapshots of the group.
+async fn history_command(path: String) -> Result<(), Error> {
+    Shell::with(move |shell| shell.history(PathBuf::from(path))).await
+}
+
 #[api(
     input: {
         properties: {
@@ -367,6 +446,21 @@ impl<'a> Iterator for PathComponentIter<'a> {
     }
 }
 
+/// Access to the other snapshots of the backup group, used by the shell commands comparing
+/// snapshots.
+pub trait SnapshotCatalogs: Send + Sync {
+    /// Lists the snapshots of the backup group, oldest first.
+    fn list_snapshots<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<String>, Error>> + Send + 'a>>;
+
+    /// Downloads the catalog of one of the group's snapshots.
+    fn open_catalog<'a>(
+        &'a self,
+        snapshot: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CatalogReader, Error>> + Send + 'a>>;
+}
+
 pub struct Shell {
     /// Readline instance handling input and callbacks
     rl: rustyline::Editor<CliHelper>,
@@ -385,6 +479,12 @@ pub struct Shell {
 
     /// The current position in the archive.
     position: Vec<PathStackEntry>,
+
+    /// Access to the other snapshots of the group
+    snapshots: Option<Box<dyn SnapshotCatalogs>>,
+
+    /// Catalog of the snapshot last compared with, kept for further comparisons
+    other_catalog: Option<(String, CatalogReader)>,
 }
 
 #[derive(Clone)]
@@ -430,11 +530,19 @@ impl Shell {
             selected: HashMap::new(),
             accessor: archive,
             position,
+            snapshots: None,
+            other_catalog: None,
         };
         this.update_prompt();
         Ok(this)
     }
 
+    /// Enables the commands comparing the snapshot with the other snapshots of its group.
+    pub fn with_snapshot_catalogs(mut self, snapshots: Box<dyn SnapshotCatalogs>) -> Self {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
     async fn with<'a, Fut, R, F>(call: F) -> Result<R, Error>
     where
         F: FnOnce(&'a mut Shell) -> Fut,
@@ -771,6 +879,196 @@ impl Shell {
         Ok(())
     }
 
+    async fn du(&mut self, path: Option<&Path>) -> Result<(), Error> {
+        let stack = Self::lookup(
+            &self.position,
+            &mut self.catalog,
+            &self.accessor,
+            path,
+            &mut Some(0),
+        )
+        .await?;
+
+        let last = &stack.last().unwrap().catalog;
+        let entries = if last.is_directory() {
+            self.catalog.read_dir(last)?
+        } else {
+            vec![last.clone()]
+        };
+
+        let mut out = std::io::stdout();
+        let mut total_size = 0;
+        let mut total_files = 0;
+        for entry in entries {
+            let (size, files) = match entry.attr {
+                DirEntryAttribute::Directory { .. } => self.catalog.disk_usage(&entry)?,
+                DirEntryAttribute::File { size, .. } => (size, 1),
+                _ => (0, 0),
+            };
+            total_size += size;
+            total_files += files;
+
+            write!(out, "{:>10}  ", HumanByte::from(size).to_string())?;
+            out.write_all(&entry.name)?;
+            if entry.is_directory() {
+                out.write_all(b"/")?;
+            }
+            out.write_all(b"\n")?;
+        }
+        writeln!(
+            out,
+            "{:>10}  total ({total_files} files)",
+            HumanByte::from(total_size).to_string(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Resolves a path to the names of its components below the archive root, without following
+    /// symlinks, so that it can be looked up in the catalogs of other snapshots too.
+    fn resolve_names(&self, path: Option<&Path>) -> Result<Vec<Vec<u8>>, Error> {
+        use std::path::Component;
+
+        let mut names: Vec<Vec<u8>> = self.position[1..]
+            .iter()
+            .map(|entry| entry.catalog.name.clone())
+            .collect();
+
+        for component in path.into_iter().flat_map(Path::components) {
+            match component {
+                Component::Prefix(_) => bail!("invalid path component (prefix)"),
+                Component::RootDir => names.clear(),
+                Component::CurDir => (),
+                Component::ParentDir => drop(names.pop()),
+                Component::Normal(name) => names.push(name.as_bytes().to_vec()),
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// Looks up an entry by the names returned by [`resolve_names`](Self::resolve_names).
+    fn lookup_names(
+        catalog: &mut CatalogReader,
+        archive_name: &[u8],
+        names: &[Vec<u8>],
+    ) -> Result<Option<catalog::DirEntry>, Error> {
+        let root = catalog.root()?;
+        let mut entry = match catalog.lookup(&root, archive_name)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        for name in names {
+            if !entry.is_directory() {
+                return Ok(None);
+            }
+            entry = match catalog.lookup(&entry, name)? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+        }
+
+        Ok(Some(entry))
+    }
+
+    async fn open_other_catalog(&self, snapshot: &str) -> Result<CatalogReader, Error> {
+        match &self.snapshots {
+            Some(snapshots) => snapshots.open_catalog(snapshot).await,
+            None => bail!("accessing other snapshots is not supported"),
+        }
+    }
+
+    async fn diff(&mut self, snapshot: String, path: Option<PathBuf>) -> Result<(), Error> {
+        let names = self.resolve_names(path.as_deref())?;
+
+        let mut other_catalog = match self.other_catalog.take() {
+            Some((name, catalog)) if name == snapshot => catalog,
+            _ => self.open_other_catalog(&snapshot).await?,
+        };
+        let result = self.diff_names(&mut other_catalog, &names);
+        self.other_catalog = Some((snapshot, other_catalog));
+
+        result
+    }
+
+    fn diff_names(
+        &mut self,
+        other_catalog: &mut CatalogReader,
+        names: &[Vec<u8>],
+    ) -> Result<(), Error> {
+        let archive_name = self.position[0].catalog.name.clone();
+
+        let old = Self::lookup_names(other_catalog, &archive_name, names)?;
+        let new = Self::lookup_names(&mut self.catalog, &archive_name, names)?;
+        if old.is_none() && new.is_none() {
+            bail!("no such file or directory in either snapshot");
+        }
+
+        let mut path = Vec::new();
+        for name in names {
+            path.push(b'/');
+            path.extend_from_slice(name);
+        }
+
+        catalog::diff_entry(
+            other_catalog,
+            old.as_ref(),
+            &mut self.catalog,
+            new.as_ref(),
+            &mut path,
+            &mut std::io::stdout(),
+        )
+    }
+
+    async fn history(&mut self, path: PathBuf) -> Result<(), Error> {
+        let names = self.resolve_names(Some(&path))?;
+        let archive_name = self.position[0].catalog.name.clone();
+
+        let snapshots = match &self.snapshots {
+            Some(snapshots) => snapshots.list_snapshots().await?,
+            None => bail!("accessing other snapshots is not supported"),
+        };
+
+        let mut found_some = false;
+        for snapshot in snapshots {
+            // not kept, groups can have lots of snapshots
+            let mut catalog = match self.open_other_catalog(&snapshot).await {
+                Ok(catalog) => catalog,
+                Err(err) => {
+                    log::warn!("skipping snapshot {snapshot} - {err}");
+                    continue;
+                }
+            };
+
+            let entry = match Self::lookup_names(&mut catalog, &archive_name, &names)? {
+                Some(entry) => entry,
+                None => continue,
+            };
+            found_some = true;
+
+            let (size, mtime) = match entry.attr {
+                DirEntryAttribute::File { size, mtime } => (size, Some(mtime)),
+                DirEntryAttribute::Directory { .. } => (catalog.disk_usage(&entry)?.0, None),
+                ref attr => {
+                    println!("{snapshot}  {}", CatalogEntryType::from(attr));
+                    continue;
+                }
+            };
+            let mtime = match mtime {
+                Some(mtime) => proxmox_time::strftime_local("%F %T", mtime)?,
+                None => "-".to_string(),
+            };
+            println!("{snapshot}  {size:>12}  {mtime}");
+        }
+
+        if !found_some {
+            println!("no snapshot contains {path:?}");
+        }
+
+        Ok(())
+    }
+
     async fn cd(&mut self, path: Option<&Path>) -> Result<(), Error> {
         match path {
             Some(path) => {
@@ -1,30 +1,157 @@
 use std::pin::Pin;
+use std::sync::mpsc;
 use std::task::{Context, Poll};
 
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use bytes::BytesMut;
 use futures::ready;
 use futures::stream::{Stream, TryStream};
 
-use pbs_datastore::Chunker;
+use pbs_datastore::{BuzHashChunker, Chunker, ChunkerConfig};
+
+use crate::inject_reused_chunks::InjectChunks;
+
+/// Holds the queues for forced chunk boundaries and reused chunk injections
+pub struct InjectionData {
+    boundaries: mpsc::Receiver<InjectChunks>,
+    next_boundary: Option<InjectChunks>,
+    injections: mpsc::Sender<InjectChunks>,
+    consumed: u64,
+}
+
+impl InjectionData {
+    /// Forced boundaries are received from the archiver via `boundaries`, reused chunks are
+    /// passed on to the uploader via `injections`.
+    pub fn new(
+        boundaries: mpsc::Receiver<InjectChunks>,
+        injections: mpsc::Sender<InjectChunks>,
+    ) -> Self {
+        Self {
+            boundaries,
+            next_boundary: None,
+            injections,
+            consumed: 0,
+        }
+    }
+}
 
 /// Split input stream into dynamic sized chunks
 pub struct ChunkStream<S: Unpin> {
     input: S,
-    chunker: Chunker,
+    chunker: Box<dyn Chunker>,
     buffer: BytesMut,
     scan_pos: usize,
+    finished: bool,
+    injection_data: Option<InjectionData>,
 }
 
 impl<S: Unpin> ChunkStream<S> {
-    pub fn new(input: S, chunk_size: Option<usize>) -> Self {
+    pub fn new(input: S, chunk_size: Option<usize>, injection_data: Option<InjectionData>) -> Self {
+        let chunker = BuzHashChunker::new(chunk_size.unwrap_or(4 * 1024 * 1024));
+        Self::with_chunker_impl(input, Box::new(chunker), injection_data)
+    }
+
+    /// Split the input with the chunker described by `config`.
+    pub fn with_chunker(
+        input: S,
+        config: &ChunkerConfig,
+        injection_data: Option<InjectionData>,
+    ) -> Self {
+        Self::with_chunker_impl(input, config.create(), injection_data)
+    }
+
+    fn with_chunker_impl(
+        input: S,
+        chunker: Box<dyn Chunker>,
+        injection_data: Option<InjectionData>,
+    ) -> Self {
         Self {
             input,
-            chunker: Chunker::new(chunk_size.unwrap_or(4 * 1024 * 1024)),
+            chunker,
             buffer: BytesMut::new(),
             scan_pos: 0,
+            finished: false,
+            injection_data,
+        }
+    }
+
+    /// Cut the buffered data at the next forced boundary, if the boundary is already buffered.
+    ///
+    /// Returns the chunk preceding the boundary, if any. Reused chunks starting at the boundary
+    /// are passed on to the uploader.
+    fn handle_forced_boundary(&mut self) -> Result<Option<BytesMut>, Error> {
+        let Self {
+            chunker,
+            buffer,
+            scan_pos,
+            injection_data,
+            ..
+        } = self;
+
+        let inject = match injection_data {
+            Some(inject) => inject,
+            None => return Ok(None),
+        };
+
+        loop {
+            if inject.next_boundary.is_none() {
+                inject.next_boundary = inject.boundaries.try_recv().ok();
+            }
+
+            let boundary = match inject.next_boundary {
+                Some(ref next) => next.boundary,
+                None => return Ok(None),
+            };
+
+            let pos = boundary.checked_sub(inject.consumed).ok_or_else(|| {
+                format_err!(
+                    "forced chunk boundary {} before current offset {}",
+                    boundary,
+                    inject.consumed
+                )
+            })?;
+
+            if pos > buffer.len() as u64 {
+                return Ok(None); // need more data
+            }
+            let pos = pos as usize;
+
+            if pos > 0 {
+                if *scan_pos < pos {
+                    let cut = chunker.scan(&buffer[*scan_pos..pos]);
+                    if cut != 0 {
+                        let chunk_size = *scan_pos + cut;
+                        *scan_pos = 0;
+                        inject.consumed += chunk_size as u64;
+                        return Ok(Some(buffer.split_to(chunk_size)));
+                    }
+                }
+                chunker.reset();
+                *scan_pos = 0;
+                inject.consumed += pos as u64;
+                return Ok(Some(buffer.split_to(pos)));
+            }
+
+            let injection = inject.next_boundary.take().unwrap();
+            chunker.reset();
+            *scan_pos = 0;
+            inject.consumed += injection.size;
+
+            if !injection.chunks.is_empty() {
+                inject
+                    .injections
+                    .send(injection)
+                    .map_err(|_| format_err!("unable to pass on reused chunks - uploader gone"))?;
+            }
         }
     }
+
+    fn count_consumed(&mut self, chunk: BytesMut) -> BytesMut {
+        if let Some(ref mut inject) = self.injection_data {
+            inject.consumed += chunk.len() as u64;
+        }
+        chunk
+    }
 }
 
 impl<S: Unpin> Unpin for ChunkStream<S> {}
@@ -40,6 +167,22 @@ where
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
         loop {
+            match this.handle_forced_boundary() {
+                Ok(Some(chunk)) => return Poll::Ready(Some(Ok(chunk))),
+                Ok(None) => (),
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+
+            if this.finished {
+                this.scan_pos = 0;
+                if !this.buffer.is_empty() {
+                    let chunk = this.buffer.split();
+                    return Poll::Ready(Some(Ok(this.count_consumed(chunk))));
+                } else {
+                    return Poll::Ready(None);
+                }
+            }
+
             if this.scan_pos < this.buffer.len() {
                 let boundary = this.chunker.scan(&this.buffer[this.scan_pos..]);
 
@@ -51,7 +194,7 @@ where
                 } else if chunk_size <= this.buffer.len() {
                     let result = this.buffer.split_to(chunk_size);
                     this.scan_pos = 0;
-                    return Poll::Ready(Some(Ok(result)));
+                    return Poll::Ready(Some(Ok(this.count_consumed(result))));
                 } else {
                     panic!("got unexpected chunk boundary from chunker");
                 }
@@ -62,12 +205,8 @@ where
                     return Poll::Ready(Some(Err(err.into())));
                 }
                 None => {
-                    this.scan_pos = 0;
-                    if !this.buffer.is_empty() {
-                        return Poll::Ready(Some(Ok(this.buffer.split())));
-                    } else {
-                        return Poll::Ready(None);
-                    }
+                    // handle boundaries at the end of the input before the last chunk
+                    this.finished = true;
                 }
                 Some(Ok(data)) => {
                     this.buffer.extend_from_slice(data.as_ref());
@@ -132,3 +271,86 @@ where
         }
     }
 }
+
+#[test]
+fn test_chunk_stream_forced_boundaries() -> Result<(), Error> {
+    use futures::stream::StreamExt;
+
+    let (boundaries_tx, boundaries_rx) = mpsc::channel();
+    let (injections_tx, injections_rx) = mpsc::channel();
+
+    // boundaries are archive offsets, including the 4096 byte payload dropped by the writer
+    boundaries_tx.send(InjectChunks {
+        boundary: 1000,
+        chunks: Vec::new(),
+        size: 0,
+    })?;
+    boundaries_tx.send(InjectChunks {
+        boundary: 5000,
+        chunks: vec![(4096, [1u8; 32])],
+        size: 4096,
+    })?;
+    drop(boundaries_tx);
+
+    // the data arrives in parts not aligned to the boundaries
+    let input = futures::stream::iter(vec![
+        Ok::<_, Error>(vec![0u8; 3000]),
+        Ok(vec![1u8; 3000]),
+        Ok(vec![2u8; 2000]),
+    ]);
+
+    let stream = ChunkStream::new(
+        input,
+        None,
+        Some(InjectionData::new(boundaries_rx, injections_tx)),
+    );
+    let chunks: Vec<BytesMut> = futures::executor::block_on(stream.collect::<Vec<_>>())
+        .into_iter()
+        .collect::<Result<_, _>>()?;
+
+    let sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.len()).collect();
+    assert_eq!(sizes, vec![1000, 4000, 3000]);
+
+    // only the reused chunks are passed on to the uploader
+    let injection = injections_rx.try_recv()?;
+    assert_eq!(injection.boundary, 5000);
+    assert_eq!(injection.chunks, vec![(4096, [1u8; 32])]);
+    assert!(injections_rx.try_recv().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_chunk_stream_boundary_in_the_past() {
+    use futures::stream::StreamExt;
+
+    let (boundaries_tx, boundaries_rx) = mpsc::channel();
+    let (injections_tx, _injections_rx) = mpsc::channel();
+
+    boundaries_tx
+        .send(InjectChunks {
+            boundary: 1000,
+            chunks: Vec::new(),
+            size: 0,
+        })
+        .unwrap();
+    boundaries_tx
+        .send(InjectChunks {
+            boundary: 500,
+            chunks: Vec::new(),
+            size: 0,
+        })
+        .unwrap();
+
+    let input = futures::stream::iter(vec![Ok::<_, Error>(vec![0u8; 2000])]);
+    let stream = ChunkStream::new(
+        input,
+        None,
+        Some(InjectionData::new(boundaries_rx, injections_tx)),
+    );
+    // the stream does not recover from the error, so stop there
+    let result: Vec<_> = futures::executor::block_on(stream.take(2).collect::<Vec<_>>());
+
+    assert_eq!(result[0].as_ref().unwrap().len(), 1000);
+    assert!(result[1].is_err());
+}
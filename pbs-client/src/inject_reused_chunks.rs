@@ -0,0 +1,319 @@
+//! Reuse of unchanged file payloads from a previous pxar archive.
+//!
+//! When the metadata of a regular file did not change since the previous
+//! backup, the archiver does not read the file again. Instead it tells the
+//! [`Injector`] to replace the file payload by the chunks already referenced
+//! by the previous archive index. The payload bytes are dropped by the
+//! [`InjectionWriter`], the chunker is forced to cut at the payload start and
+//! the reused chunks get appended to the new index by the uploader.
+
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use anyhow::{bail, format_err, Error};
+use bytes::BytesMut;
+use futures::ready;
+use futures::stream::{Stream, TryStream};
+
+/// Chunk boundary forced by the archiver, optionally followed by reused chunks.
+#[derive(Debug)]
+pub struct InjectChunks {
+    /// Archive offset of the forced chunk boundary.
+    pub boundary: u64,
+    /// Reused chunks as `(chunk size, digest)`, starting at `boundary`.
+    pub chunks: Vec<(u64, [u8; 32])>,
+    /// Number of archive bytes covered by `chunks`.
+    pub size: u64,
+}
+
+struct InjectorState {
+    position: u64,
+    skip: u64,
+    boundaries: mpsc::Sender<InjectChunks>,
+}
+
+/// Handle used by the archiver to force chunk boundaries and to inject
+/// reused chunks at the current archive position.
+#[derive(Clone)]
+pub struct Injector {
+    state: Arc<Mutex<InjectorState>>,
+}
+
+impl Injector {
+    pub fn new(boundaries: mpsc::Sender<InjectChunks>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(InjectorState {
+                position: 0,
+                skip: 0,
+                boundaries,
+            })),
+        }
+    }
+
+    /// Wrap the archive output, so that the archive position is tracked and
+    /// injected payloads are not passed on.
+    pub fn writer<W: Write>(&self, inner: W) -> InjectionWriter<W> {
+        InjectionWriter {
+            inner,
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    /// Force a chunk boundary at the current archive position.
+    pub fn force_boundary(&self) -> Result<(), Error> {
+        self.inject(Vec::new(), 0)
+    }
+
+    /// Force a chunk boundary at the current archive position and replace the
+    /// following `size` bytes by the given reused chunks.
+    ///
+    /// The caller still has to write the `size` bytes to the archive, the
+    /// writer drops them.
+    pub fn inject(&self, chunks: Vec<(u64, [u8; 32])>, size: u64) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.skip != 0 {
+            bail!("unable to inject chunks - previous injection not finished");
+        }
+
+        let boundary = state.position;
+        state
+            .boundaries
+            .send(InjectChunks {
+                boundary,
+                chunks,
+                size,
+            })
+            .map_err(|_| format_err!("unable to inject chunks - chunker gone"))?;
+        state.skip = size;
+
+        Ok(())
+    }
+}
+
+/// Archive output wrapper created by [`Injector::writer`].
+pub struct InjectionWriter<W> {
+    inner: W,
+    state: Arc<Mutex<InjectorState>>,
+}
+
+impl<W: Write> Write for InjectionWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.skip > 0 {
+            let skipped = state.skip.min(buf.len() as u64);
+            state.skip -= skipped;
+            state.position += skipped;
+            return Ok(skipped as usize);
+        }
+
+        let written = self.inner.write(buf)?;
+        state.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Chunk stream items, either chunk data or chunks reused from the previous index.
+pub(crate) enum InjectedChunksInfo {
+    /// Reused chunks as `(chunk size, digest)`.
+    Known(Vec<(u64, [u8; 32])>),
+    /// Newly chunked data.
+    Raw(BytesMut),
+}
+
+/// Merge the reused chunks sent by the chunker into the chunk stream.
+///
+/// The chunker sends each injection before passing on the chunk following it,
+/// so an injection is always received before the chunk it has to precede.
+pub(crate) struct InjectReusedChunks<S> {
+    input: S,
+    injections: Option<mpsc::Receiver<InjectChunks>>,
+    next_injection: Option<InjectChunks>,
+    buffered: Option<BytesMut>,
+    stream_len: u64,
+    finished: bool,
+}
+
+impl<S> InjectReusedChunks<S> {
+    pub(crate) fn new(input: S, injections: Option<mpsc::Receiver<InjectChunks>>) -> Self {
+        Self {
+            input,
+            injections,
+            next_injection: None,
+            buffered: None,
+            stream_len: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<S: Unpin> Unpin for InjectReusedChunks<S> {}
+
+impl<S> Stream for InjectReusedChunks<S>
+where
+    S: TryStream<Ok = BytesMut> + Unpin,
+    S::Error: Into<Error>,
+{
+    type Item = Result<InjectedChunksInfo, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.next_injection.is_none() {
+                if let Some(ref injections) = this.injections {
+                    this.next_injection = injections.try_recv().ok();
+                }
+            }
+
+            if let Some(ref injection) = this.next_injection {
+                if injection.boundary < this.stream_len {
+                    return Poll::Ready(Some(Err(format_err!(
+                        "got injection for offset {} after reaching offset {}",
+                        injection.boundary,
+                        this.stream_len,
+                    ))));
+                }
+                if injection.boundary == this.stream_len {
+                    let injection = this.next_injection.take().unwrap();
+                    let size: u64 = injection.chunks.iter().map(|(size, _)| size).sum();
+                    if size != injection.size {
+                        return Poll::Ready(Some(Err(format_err!(
+                            "injected chunks do not match injected size ({} != {})",
+                            size,
+                            injection.size,
+                        ))));
+                    }
+                    this.stream_len += size;
+                    if injection.chunks.is_empty() {
+                        continue; // forced boundary only
+                    }
+                    return Poll::Ready(Some(Ok(InjectedChunksInfo::Known(injection.chunks))));
+                }
+            }
+
+            if let Some(data) = this.buffered.take() {
+                this.stream_len += data.len() as u64;
+                return Poll::Ready(Some(Ok(InjectedChunksInfo::Raw(data))));
+            }
+
+            if this.finished {
+                if let Some(ref injection) = this.next_injection {
+                    return Poll::Ready(Some(Err(format_err!(
+                        "stream ended at offset {} before injection at offset {}",
+                        this.stream_len,
+                        injection.boundary,
+                    ))));
+                }
+                return Poll::Ready(None);
+            }
+
+            // recheck pending injections after each received chunk
+            match ready!(Pin::new(&mut this.input).try_poll_next(cx)) {
+                Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                Some(Ok(data)) => this.buffered = Some(data),
+                None => this.finished = true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn collect_injected(
+    input: Vec<usize>,
+    injections: Vec<InjectChunks>,
+) -> Vec<Result<InjectedChunksInfo, Error>> {
+    use futures::stream::StreamExt;
+
+    let (injections_tx, injections_rx) = mpsc::channel();
+    for injection in injections {
+        injections_tx.send(injection).unwrap();
+    }
+
+    let input = futures::stream::iter(
+        input
+            .into_iter()
+            .map(|size| Ok::<_, Error>(BytesMut::from(&vec![0u8; size][..]))),
+    );
+    let mut stream = InjectReusedChunks::new(input, Some(injections_rx));
+
+    // the stream does not recover from errors, so stop at the first one
+    futures::executor::block_on(async move {
+        let mut items = Vec::new();
+        while let Some(item) = stream.next().await {
+            let failed = item.is_err();
+            items.push(item);
+            if failed {
+                break;
+            }
+        }
+        items
+    })
+}
+
+#[test]
+fn test_inject_reused_chunks() {
+    let result = collect_injected(
+        vec![1000, 4000, 3000],
+        vec![InjectChunks {
+            boundary: 5000,
+            chunks: vec![(1024, [1u8; 32]), (3072, [2u8; 32])],
+            size: 4096,
+        }],
+    );
+
+    let items: Vec<String> = result
+        .into_iter()
+        .map(|item| match item.unwrap() {
+            InjectedChunksInfo::Raw(data) => format!("raw {}", data.len()),
+            InjectedChunksInfo::Known(chunks) => format!("known {}", chunks.len()),
+        })
+        .collect();
+    assert_eq!(items, vec!["raw 1000", "raw 4000", "known 2", "raw 3000"]);
+}
+
+#[test]
+fn test_inject_reused_chunks_errors() {
+    // injected chunks must cover the injected size
+    let result = collect_injected(
+        vec![1000],
+        vec![InjectChunks {
+            boundary: 1000,
+            chunks: vec![(1024, [1u8; 32])],
+            size: 4096,
+        }],
+    );
+    assert!(result[0].is_ok());
+    assert!(result[1].is_err());
+
+    // injections must not be behind the stream
+    let result = collect_injected(
+        vec![1000, 1000],
+        vec![InjectChunks {
+            boundary: 500,
+            chunks: vec![(1024, [1u8; 32])],
+            size: 1024,
+        }],
+    );
+    assert!(result[0].is_ok());
+    assert!(result[1].is_err());
+
+    // the stream must not end before all injections are handled
+    let result = collect_injected(
+        vec![1000],
+        vec![InjectChunks {
+            boundary: 2000,
+            chunks: vec![(1024, [1u8; 32])],
+            size: 1024,
+        }],
+    );
+    assert!(result[0].is_ok());
+    assert!(result[1].is_err());
+}
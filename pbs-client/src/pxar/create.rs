@@ -2,6 +2,7 @@ use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString, OsStr};
 use std::fmt;
 use std::io::{self, Read, Write};
+use std::ops::Range;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::path::{Path, PathBuf};
@@ -14,18 +15,26 @@ use nix::dir::Dir;
 use nix::errno::Errno;
 use nix::fcntl::OFlag;
 use nix::sys::stat::{FileStat, Mode};
+use serde::{Deserialize, Serialize};
 
 use pathpatterns::{MatchEntry, MatchFlag, MatchList, MatchType, PatternFlag};
+use pxar::accessor::aio::{Accessor, Directory};
+use pxar::accessor::ReadAt;
 use pxar::encoder::{LinkOffset, SeqWrite};
-use pxar::Metadata;
+use pxar::{EntryKind, Metadata};
 
 use proxmox_io::vec;
 use proxmox_lang::c_str;
+use proxmox_schema::api;
 use proxmox_sys::error::SysError;
 use proxmox_sys::fs::{self, acl, xattr};
 
+use pbs_api_types::HumanByte;
 use pbs_datastore::catalog::BackupCatalogWriter;
+use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::index::IndexFile;
 
+use crate::inject_reused_chunks::Injector;
 use crate::pxar::metadata::errno_is_unsupported;
 use crate::pxar::tools::assert_single_path_component;
 use crate::pxar::Flags;
@@ -41,8 +50,125 @@ pub struct PxarCreateOptions {
     pub entries_max: usize,
     /// Skip lost+found directory
     pub skip_lost_and_found: bool,
+    /// Previous archive to reuse unchanged file payloads from
+    pub previous_ref: Option<Arc<PxarPrevRef>>,
+    /// Records the inode numbers of files with chunk aligned payloads, for the next backup
+    pub payload_inodes: Option<Arc<Mutex<PayloadInodes>>>,
 }
 
+#[api]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// How to detect file changes when creating a file archive.
+pub enum ChangeDetectionMode {
+    /// Read and chunk the contents of all files.
+    #[default]
+    Legacy,
+    /// Reuse the chunks of files with unchanged size and modification time from the previous
+    /// snapshot, without reading them.
+    Metadata,
+}
+
+type PrevReader = Arc<dyn ReadAt + Send + Sync>;
+
+/// Reference to the same archive in the previous snapshot.
+pub struct PxarPrevRef {
+    /// Accessor for the previous archive
+    pub accessor: Accessor<PrevReader>,
+    /// Index of the previous archive
+    pub index: DynamicIndexReader,
+    /// Backup time of the previous snapshot
+    pub backup_time: i64,
+    /// Inode numbers of the files with chunk aligned payloads in the previous archive
+    pub inodes: PayloadInodes,
+}
+
+impl PxarPrevRef {
+    /// Get the chunks of the previous archive covering exactly `range`.
+    ///
+    /// Returns `None` if `range` does not start and end at a chunk boundary.
+    fn chunks_for_range(&self, range: Range<u64>) -> Option<Vec<(u64, [u8; 32])>> {
+        if range.start >= self.index.index_bytes() {
+            return None;
+        }
+
+        let (start_idx, start_offset) = self.index.chunk_from_offset(range.start)?;
+        if start_offset != 0 {
+            return None;
+        }
+
+        let mut chunks = Vec::new();
+        for pos in start_idx..self.index.index_count() {
+            let info = self.index.chunk_info(pos)?;
+            chunks.push((info.range.end - info.range.start, info.digest));
+            if info.range.end >= range.end {
+                return (info.range.end == range.end).then_some(chunks);
+            }
+        }
+
+        None
+    }
+}
+
+/// Inode numbers of the files whose payload is aligned to chunk boundaries, by archive path.
+///
+/// Archives do not record inode numbers, so they are stored in a separate blob next to the
+/// archive, see [`payload_inodes_blob_name`]. This allows detecting files replaced by another
+/// file with the same size and modification time.
+#[derive(Debug, Default, PartialEq)]
+pub struct PayloadInodes(HashMap<PathBuf, u64>);
+
+impl PayloadInodes {
+    /// Parses the blob content written by [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(mut data: &[u8]) -> Result<Self, Error> {
+        let mut inodes = HashMap::new();
+        while !data.is_empty() {
+            if data.len() < 12 {
+                bail!("truncated payload inode table");
+            }
+            let ino = u64::from_le_bytes(data[..8].try_into().unwrap());
+            let len = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+            data = &data[12..];
+            if data.len() < len {
+                bail!("truncated payload inode table");
+            }
+            inodes.insert(PathBuf::from(OsStr::from_bytes(&data[..len])), ino);
+            data = &data[len..];
+        }
+        Ok(Self(inodes))
+    }
+
+    /// Serializes the table as a sequence of inode number, path length and path.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (path, ino) in self.0.iter() {
+            let path = path.as_os_str().as_bytes();
+            data.extend_from_slice(&ino.to_le_bytes());
+            data.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            data.extend_from_slice(path);
+        }
+        data
+    }
+
+    fn insert(&mut self, path: PathBuf, ino: u64) {
+        self.0.insert(path, ino);
+    }
+
+    fn get(&self, path: &Path) -> Option<u64> {
+        self.0.get(path).copied()
+    }
+}
+
+/// Returns the name of the blob holding the [`PayloadInodes`] of the archive `archive_name`.
+pub fn payload_inodes_blob_name(archive_name: &str) -> String {
+    let name = archive_name.strip_suffix(".didx").unwrap_or(archive_name);
+    format!("{name}.inodes.blob")
+}
+
+/// File payloads of at least this size are aligned to chunk boundaries when reusing chunks,
+/// smaller files are always read.
+const MIN_REUSED_PAYLOAD_SIZE: u64 = 1024 * 1024;
+
 fn detect_fs_type(fd: RawFd) -> Result<i64, Error> {
     let mut fs_stat = std::mem::MaybeUninit::uninit();
     let res = unsafe { libc::fstatfs(fd, fs_stat.as_mut_ptr()) };
@@ -157,6 +283,12 @@ struct Archiver {
     errors: ErrorReporter,
     logger: Logger,
     file_copy_buffer: Vec<u8>,
+    injector: Option<Injector>,
+    previous_ref: Option<Arc<PxarPrevRef>>,
+    previous_dir: Option<Directory<PrevReader>>,
+    payload_inodes: Option<Arc<Mutex<PayloadInodes>>>,
+    reused_files: u64,
+    reused_bytes: u64,
 }
 
 type Encoder<'a, T> = pxar::encoder::aio::Encoder<'a, T>;
@@ -168,6 +300,7 @@ pub async fn create_archive<T, F>(
     callback: F,
     catalog: Option<Arc<Mutex<dyn BackupCatalogWriter + Send>>>,
     options: PxarCreateOptions,
+    injector: Option<Injector>,
 ) -> Result<(), Error>
 where
     T: SeqWrite + Send,
@@ -195,6 +328,11 @@ where
         set.insert(stat.st_dev);
     }
 
+    let previous_dir = match (&injector, &options.previous_ref) {
+        (Some(_), Some(previous_ref)) => Some(previous_ref.accessor.open_root().await?),
+        _ => None,
+    };
+
     let mut encoder = Encoder::new(&mut writer, &metadata).await?;
 
     let mut patterns = options.patterns;
@@ -223,12 +361,27 @@ where
         errors: ErrorReporter,
         logger: Logger,
         file_copy_buffer: vec::undefined(4 * 1024 * 1024),
+        injector,
+        previous_ref: options.previous_ref,
+        previous_dir,
+        payload_inodes: options.payload_inodes,
+        reused_files: 0,
+        reused_bytes: 0,
     };
 
     archiver
         .archive_dir_contents(&mut encoder, source_dir, true)
         .await?;
     encoder.finish().await?;
+
+    if archiver.previous_ref.is_some() {
+        log::info!(
+            "reused {} unchanged files ({}) from previous archive",
+            archiver.reused_files,
+            HumanByte::from(archiver.reused_bytes),
+        );
+    }
+
     Ok(())
 }
 
@@ -609,7 +762,7 @@ impl Archiver {
                 }
 
                 let offset: LinkOffset = self
-                    .add_regular_file(encoder, fd, file_name, &metadata, file_size)
+                    .add_regular_file(encoder, fd, file_name, &metadata, stat)
                     .await?;
 
                 if stat.st_nlink > 1 {
@@ -705,6 +858,11 @@ impl Archiver {
             }
         }
 
+        let old_previous_dir = self.previous_dir.take();
+        if let Some(ref previous_dir) = old_previous_dir {
+            self.previous_dir = lookup_previous_dir(previous_dir, dir_name).await;
+        }
+
         let result = if skip_contents {
             writeln!(self.logger, "skipping mount point: {:?}", self.path)?;
             Ok(())
@@ -712,6 +870,7 @@ impl Archiver {
             self.archive_dir_contents(&mut encoder, dir, false).await
         };
 
+        self.previous_dir = old_previous_dir;
         self.fs_magic = old_fs_magic;
         self.fs_feature_flags = old_fs_feature_flags;
         self.current_st_dev = old_st_dev;
@@ -726,11 +885,60 @@ impl Archiver {
         fd: OwnedFd,
         file_name: &Path,
         metadata: &Metadata,
-        file_size: u64,
+        stat: &FileStat,
     ) -> Result<LinkOffset, Error> {
+        let file_size = stat.st_size as u64;
+        let injector = match self.injector {
+            Some(ref injector) if file_size >= MIN_REUSED_PAYLOAD_SIZE => Some(injector.clone()),
+            _ => None,
+        };
+
+        let reusable_chunks = match (&injector, &self.previous_ref, &self.previous_dir) {
+            (Some(_), Some(previous_ref), Some(previous_dir)) => {
+                lookup_reusable_chunks(
+                    previous_ref,
+                    previous_dir,
+                    &self.path,
+                    file_name,
+                    metadata,
+                    stat,
+                )
+                .await
+            }
+            _ => None,
+        };
+
+        // the payload is aligned in any case, either reused or read again
+        if let (Some(_), Some(payload_inodes)) = (&injector, &self.payload_inodes) {
+            payload_inodes
+                .lock()
+                .unwrap()
+                .insert(self.path.clone(), stat.st_ino);
+        }
+
         let mut file = unsafe { std::fs::File::from_raw_fd(fd.into_raw_fd()) };
         let mut remaining = file_size;
         let mut out = encoder.create_file(metadata, file_name, file_size).await?;
+
+        if let Some(ref injector) = injector {
+            if let Some(chunks) = reusable_chunks {
+                // the injection writer drops the payload, so just write zeroes
+                injector.inject(chunks, file_size)?;
+                let to_zero = remaining.min(self.file_copy_buffer.len() as u64) as usize;
+                vec::clear(&mut self.file_copy_buffer[..to_zero]);
+                while remaining != 0 {
+                    let fill = remaining.min(self.file_copy_buffer.len() as u64) as usize;
+                    out.write_all(&self.file_copy_buffer[..fill]).await?;
+                    remaining -= fill as u64;
+                }
+                self.reused_files += 1;
+                self.reused_bytes += file_size;
+                return Ok(out.file_offset());
+            }
+            // align the payload to chunk boundaries, so the next backup can reuse it
+            injector.force_boundary()?;
+        }
+
         while remaining != 0 {
             let mut got = match file.read(&mut self.file_copy_buffer[..]) {
                 Ok(0) => break,
@@ -756,6 +964,10 @@ impl Archiver {
             }
         }
 
+        if let Some(ref injector) = injector {
+            injector.force_boundary()?;
+        }
+
         Ok(out.file_offset())
     }
 
@@ -788,6 +1000,72 @@ impl Archiver {
     }
 }
 
+/// Look up the chunks of a regular file in the previous archive, if its size and modification time
+/// did not change and its payload is aligned to chunk boundaries.
+///
+/// Modification times can be set arbitrarily (`touch -d`, `rsync -t`), so the file's inode must
+/// not have changed since the previous backup started either. Archives do not record the ctime,
+/// so it is compared against the previous backup time: writes, `utimes` calls and renames all bump
+/// the ctime. The inode number `path` had in the previous backup is taken from its
+/// [`PayloadInodes`], so that files replaced by another one are detected as well.
+async fn lookup_reusable_chunks(
+    previous_ref: &PxarPrevRef,
+    previous_dir: &Directory<PrevReader>,
+    path: &Path,
+    file_name: &Path,
+    metadata: &Metadata,
+    stat: &FileStat,
+) -> Option<Vec<(u64, [u8; 32])>> {
+    if stat.st_ctime >= previous_ref.backup_time {
+        return None;
+    }
+
+    if previous_ref.inodes.get(path) != Some(stat.st_ino) {
+        return None;
+    }
+
+    let entry = match previous_dir.lookup(file_name).await {
+        Ok(entry) => entry?,
+        Err(err) => {
+            log::warn!(
+                "failed to look up {:?} in previous archive - {}",
+                file_name,
+                err
+            );
+            return None;
+        }
+    };
+
+    let size = match entry.kind() {
+        EntryKind::File { size, .. } => *size,
+        _ => return None,
+    };
+
+    if size != stat.st_size as u64 || entry.metadata().stat.mtime != metadata.stat.mtime {
+        return None;
+    }
+
+    let end = entry.entry_range_info().entry_range.end;
+    previous_ref.chunks_for_range((end - size)..end)
+}
+
+/// Enter the directory `name` of the previous archive, if it exists there.
+async fn lookup_previous_dir(
+    previous_dir: &Directory<PrevReader>,
+    name: &OsStr,
+) -> Option<Directory<PrevReader>> {
+    let result = match previous_dir.lookup(name).await {
+        Ok(Some(entry)) if entry.is_dir() => entry.enter_directory().await.map(Some),
+        Ok(_) => Ok(None),
+        Err(err) => Err(err),
+    };
+
+    result.unwrap_or_else(|err| {
+        log::warn!("failed to look up {:?} in previous archive - {}", name, err);
+        None
+    })
+}
+
 fn get_metadata(
     fd: RawFd,
     stat: &FileStat,
@@ -1132,3 +1410,27 @@ fn generate_pxar_excludes_cli(patterns: &[MatchEntry]) -> Vec<u8> {
 
     content
 }
+
+#[test]
+fn test_payload_inodes() -> Result<(), Error> {
+    let mut inodes = PayloadInodes::default();
+    inodes.insert(PathBuf::from("etc/big file"), 42);
+    inodes.insert(PathBuf::from(OsStr::from_bytes(b"non-utf8-\xff")), u64::MAX);
+
+    let parsed = PayloadInodes::from_bytes(&inodes.to_bytes())?;
+    assert_eq!(parsed, inodes);
+    assert_eq!(parsed.get(Path::new("etc/big file")), Some(42));
+    assert_eq!(parsed.get(Path::new("etc/other")), None);
+
+    let data = inodes.to_bytes();
+    assert!(PayloadInodes::from_bytes(&data[..data.len() - 1]).is_err());
+    assert!(PayloadInodes::from_bytes(&data[..4]).is_err());
+    assert_eq!(PayloadInodes::from_bytes(&[])?, PayloadInodes::default());
+
+    assert_eq!(
+        payload_inodes_blob_name("root.pxar.didx"),
+        "root.pxar.inodes.blob"
+    );
+
+    Ok(())
+}
@@ -56,7 +56,10 @@ pub(crate) mod tools;
 mod flags;
 pub use flags::Flags;
 
-pub use create::{create_archive, PxarCreateOptions};
+pub use create::{
+    create_archive, payload_inodes_blob_name, ChangeDetectionMode, PayloadInodes,
+    PxarCreateOptions, PxarPrevRef,
+};
 pub use extract::{
     create_tar, create_zip, extract_archive, extract_sub_dir, extract_sub_dir_seq, ErrorHandler,
     PxarExtractOptions,
@@ -17,6 +17,8 @@ use proxmox_io::StdChannelWriter;
 
 use pbs_datastore::catalog::CatalogWriter;
 
+use crate::inject_reused_chunks::{InjectChunks, Injector};
+
 /// Stream implementation to encode and upload .pxar archives.
 ///
 /// The hyper client needs an async Stream for file upload, so we
@@ -40,6 +42,7 @@ impl PxarBackupStream {
         dir: Dir,
         catalog: Arc<Mutex<CatalogWriter<W>>>,
         options: crate::pxar::PxarCreateOptions,
+        boundaries: Option<std::sync::mpsc::Sender<InjectChunks>>,
     ) -> Result<Self, Error> {
         let (tx, rx) = std::sync::mpsc::sync_channel(10);
 
@@ -48,10 +51,13 @@ impl PxarBackupStream {
         let error = Arc::new(Mutex::new(None));
         let error2 = Arc::clone(&error);
         let handler = async move {
-            let writer = TokioWriterAdapter::new(std::io::BufWriter::with_capacity(
-                buffer_size,
-                StdChannelWriter::new(tx),
-            ));
+            let writer = std::io::BufWriter::with_capacity(buffer_size, StdChannelWriter::new(tx));
+            let injector = boundaries.map(Injector::new);
+            let writer: Box<dyn Write + Send> = match injector {
+                Some(ref injector) => Box::new(injector.writer(writer)),
+                None => Box::new(writer),
+            };
+            let writer = TokioWriterAdapter::new(writer);
 
             let writer = pxar::encoder::sync::StandardWriter::new(writer);
             if let Err(err) = crate::pxar::create_archive(
@@ -64,6 +70,7 @@ impl PxarBackupStream {
                 },
                 Some(catalog),
                 options,
+                injector,
             )
             .await
             {
@@ -87,10 +94,11 @@ impl PxarBackupStream {
         dirname: &Path,
         catalog: Arc<Mutex<CatalogWriter<W>>>,
         options: crate::pxar::PxarCreateOptions,
+        boundaries: Option<std::sync::mpsc::Sender<InjectChunks>>,
     ) -> Result<Self, Error> {
         let dir = nix::dir::Dir::open(dirname, OFlag::O_DIRECTORY, Mode::empty())?;
 
-        Self::new(dir, catalog, options)
+        Self::new(dir, catalog, options, boundaries)
     }
 }
 
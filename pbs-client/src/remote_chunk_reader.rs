@@ -44,8 +44,14 @@ impl RemoteChunkReader {
         }
     }
 
+    /// The name the zstd dictionaries of the remote datastore are registered with.
+    pub fn dictionary_store(&self) -> &str {
+        self.client.dictionary_store()
+    }
+
     /// Downloads raw chunk. This only verifies the (untrusted) CRC32, use
-    /// DataBlob::verify_unencrypted or DataBlob::decode before storing/processing further.
+    /// DataBlob::verify_unencrypted_for_store or DataBlob::decode_for_store before
+    /// storing/processing further.
     pub async fn read_raw_chunk(&self, digest: &[u8; 32]) -> Result<DataBlob, Error> {
         let mut chunk_data = Vec::with_capacity(4 * 1024 * 1024);
 
@@ -53,6 +59,10 @@ impl RemoteChunkReader {
 
         let chunk = DataBlob::load_from_reader(&mut &chunk_data[..])?;
 
+        if let Some(id) = chunk.zstd_dictionary_id() {
+            self.client.download_zstd_dictionary(id).await?;
+        }
+
         match self.crypt_mode {
             CryptMode::Encrypt => match chunk.crypt_mode()? {
                 CryptMode::Encrypt => Ok(chunk),
@@ -80,7 +90,11 @@ impl ReadChunk for RemoteChunkReader {
 
         let chunk = ReadChunk::read_raw_chunk(self, digest)?;
 
-        let raw_data = chunk.decode(self.crypt_config.as_ref().map(Arc::as_ref), Some(digest))?;
+        let raw_data = chunk.decode_for_store(
+            self.client.dictionary_store(),
+            self.crypt_config.as_ref().map(Arc::as_ref),
+            Some(digest),
+        )?;
 
         let use_cache = self.cache_hint.contains_key(digest);
         if use_cache {
@@ -110,8 +124,11 @@ impl AsyncReadChunk for RemoteChunkReader {
 
             let chunk = Self::read_raw_chunk(self, digest).await?;
 
-            let raw_data =
-                chunk.decode(self.crypt_config.as_ref().map(Arc::as_ref), Some(digest))?;
+            let raw_data = chunk.decode_for_store(
+                self.client.dictionary_store(),
+                self.crypt_config.as_ref().map(Arc::as_ref),
+                Some(digest),
+            )?;
 
             let use_cache = self.cache_hint.contains_key(digest);
             if use_cache {
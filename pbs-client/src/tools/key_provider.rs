@@ -0,0 +1,186 @@
+//! External key providers
+//!
+//! A key provider hands out encryption keys on demand, so that hosts do not need to keep
+//! plaintext key files around. It is configured via `PBS_KEY_PROVIDER`, either as command line,
+//! or as `unix:<path>` to talk to an agent listening on a local socket.
+//!
+//! Two requests are supported:
+//!
+//! * `get-key`: return the encryption key.
+//! * `unwrap-key`: return the unwrapped form of a wrapped key, used if a key file or key fd is
+//!   passed in addition to the provider.
+//!
+//! Commands get the request as last argument, and the wrapped key on stdin. Sockets receive one
+//! line of JSON, `{"command": "<request>"}`, with the hex encoded wrapped key as `data` property.
+//!
+//! In both cases the key (usually a JSON key config) is expected as answer, on stdout or until
+//! the socket gets closed.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, format_err, Error};
+use serde_json::json;
+
+use proxmox_router::cli::shellword_split;
+
+const ENV_VAR_PBS_KEY_PROVIDER: &str = "PBS_KEY_PROVIDER";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum KeyProvider {
+    /// Command with its arguments
+    Command(Vec<String>),
+    /// Path to a unix socket
+    Socket(PathBuf),
+}
+
+impl std::str::FromStr for KeyProvider {
+    type Err = Error;
+
+    fn from_str(provider: &str) -> Result<Self, Error> {
+        if let Some(path) = provider.strip_prefix("unix:") {
+            if path.is_empty() {
+                bail!("key provider socket path is empty");
+            }
+            return Ok(KeyProvider::Socket(PathBuf::from(path)));
+        }
+
+        let args = shellword_split(provider)?;
+        if args.is_empty() {
+            bail!("key provider command is empty");
+        }
+        Ok(KeyProvider::Command(args))
+    }
+}
+
+impl std::fmt::Display for KeyProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            KeyProvider::Command(args) => write!(f, "command '{}'", args[0]),
+            KeyProvider::Socket(path) => write!(f, "socket {:?}", path),
+        }
+    }
+}
+
+impl KeyProvider {
+    /// Returns the key provider configured via `PBS_KEY_PROVIDER`, if any.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        match std::env::var(ENV_VAR_PBS_KEY_PROVIDER) {
+            Ok(provider) => Ok(Some(provider.parse()?)),
+            Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(std::env::VarError::NotUnicode(_)) => {
+                bail!("{} contains bad characters", ENV_VAR_PBS_KEY_PROVIDER)
+            }
+        }
+    }
+
+    /// Request the encryption key.
+    pub fn get_key(&self) -> Result<Vec<u8>, Error> {
+        self.request("get-key", None)
+    }
+
+    /// Request the unwrapped form of a wrapped key.
+    pub fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, Error> {
+        self.request("unwrap-key", Some(wrapped))
+    }
+
+    fn request(&self, request: &str, data: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+        let key = match self {
+            KeyProvider::Command(args) => run_provider_command(args, request, data),
+            KeyProvider::Socket(path) => query_provider_socket(path, request, data),
+        }
+        .map_err(|err| format_err!("key provider {} failed on '{}' - {}", self, request, err))?;
+
+        if key.iter().all(u8::is_ascii_whitespace) {
+            bail!("key provider {} returned no key on '{}'", self, request);
+        }
+        Ok(key)
+    }
+}
+
+fn run_provider_command(
+    args: &[String],
+    request: &str,
+    data: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .arg(request)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Some(data) = data {
+            stdin.write_all(data)?;
+        }
+        // dropping stdin closes it, so the provider sees EOF
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("command exited with {}", output.status);
+    }
+    Ok(output.stdout)
+}
+
+fn query_provider_socket(
+    path: &Path,
+    request: &str,
+    data: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    let mut stream = UnixStream::connect(path)?;
+
+    let mut message = json!({ "command": request });
+    if let Some(data) = data {
+        message["data"] = hex::encode(data).into();
+    }
+    let mut message = serde_json::to_vec(&message)?;
+    message.push(b'\n');
+    stream.write_all(&message)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut key = Vec::new();
+    stream.read_to_end(&mut key)?;
+    Ok(key)
+}
+
+#[test]
+fn test_parse_key_provider() -> Result<(), Error> {
+    assert_eq!(
+        "unix:/run/kms.sock".parse::<KeyProvider>()?,
+        KeyProvider::Socket(PathBuf::from("/run/kms.sock")),
+    );
+    assert_eq!(
+        "/usr/bin/kms-client --vault 'backup keys'".parse::<KeyProvider>()?,
+        KeyProvider::Command(vec![
+            "/usr/bin/kms-client".to_string(),
+            "--vault".to_string(),
+            "backup keys".to_string(),
+        ]),
+    );
+    assert!("unix:".parse::<KeyProvider>().is_err());
+    assert!("".parse::<KeyProvider>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_key_provider_command() -> Result<(), Error> {
+    let provider = KeyProvider::Command(vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        r#"if [ "$0" = unwrap-key ]; then tr a-z A-Z; else echo key; fi"#.to_string(),
+    ]);
+
+    assert_eq!(provider.get_key()?, b"key\n");
+    assert_eq!(provider.unwrap_key(b"wrapped")?, b"WRAPPED");
+
+    let provider = KeyProvider::Command(vec!["false".to_string()]);
+    assert!(provider.get_key().is_err());
+
+    Ok(())
+}
@@ -9,10 +9,16 @@ use proxmox_schema::*;
 use proxmox_sys::fs::file_get_contents;
 use proxmox_sys::linux::tty;
 
-use pbs_api_types::CryptMode;
+use pbs_api_types::{BackupDir, BackupNamespace, CryptMode, Fingerprint, SnapshotListItem};
+use pbs_key_config::KeyConfig;
+
+use super::key_provider::KeyProvider;
+use crate::HttpClient;
 
 pub const DEFAULT_ENCRYPTION_KEY_FILE_NAME: &str = "encryption-key.json";
 pub const DEFAULT_MASTER_PUBKEY_FILE_NAME: &str = "master-public.pem";
+/// Directory (below the xdg config dir) keeping encryption keys replaced by a key rotation.
+pub const RETIRED_ENCRYPTION_KEYS_DIR_NAME: &str = "retired-keys";
 
 pub const KEYFILE_SCHEMA: Schema =
     StringSchema::new("Path to encryption key. All data will be encrypted using this key.")
@@ -32,11 +38,23 @@ pub const MASTER_PUBKEY_FD_SCHEMA: Schema =
         .minimum(0)
         .schema();
 
+pub const ADDITIONAL_MASTER_PUBKEY_FILE_SCHEMA: Schema = StringSchema::new(
+    "Path to an additional master public key. The encryption key is stored once for every master key.")
+    .schema();
+
+pub const MASTER_KEY_THRESHOLD_SCHEMA: Schema = IntegerSchema::new(
+    "Split the encryption key into one share per master key, so that this many master keys are required to restore it.")
+    .minimum(2)
+    .maximum(255)
+    .schema();
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum KeySource {
     DefaultKey,
     Fd,
     Path(String),
+    Retired(Fingerprint),
+    Provider,
 }
 
 pub fn format_key_source(source: &KeySource, key_type: &str) -> String {
@@ -44,6 +62,10 @@ pub fn format_key_source(source: &KeySource, key_type: &str) -> String {
         KeySource::DefaultKey => format!("Using default {} key..", key_type),
         KeySource::Fd => format!("Using {} key from file descriptor..", key_type),
         KeySource::Path(path) => format!("Using {} key from '{}'..", key_type, path),
+        KeySource::Retired(fingerprint) => {
+            format!("Using retired {} key {}..", key_type, fingerprint)
+        }
+        KeySource::Provider => format!("Using {} key from key provider..", key_type),
     }
 }
 
@@ -74,6 +96,20 @@ impl KeyWithSource {
             key,
         }
     }
+
+    pub fn from_retired(fingerprint: Fingerprint, key: Vec<u8>) -> Self {
+        Self {
+            source: KeySource::Retired(fingerprint),
+            key,
+        }
+    }
+
+    pub fn from_provider(key: Vec<u8>) -> Self {
+        Self {
+            source: KeySource::Provider,
+            key,
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -160,6 +196,15 @@ fn do_crypto_parameters(param: &Value, keep_keyfd_open: bool) -> Result<CryptoPa
         }
     };
 
+    let key = match KeyProvider::from_env()? {
+        Some(provider) if mode != Some(CryptMode::None) => match key {
+            // explicitly passed keys are wrapped, the provider has to unwrap them
+            Some(key) => Some(KeyWithSource::from_provider(provider.unwrap_key(&key.key)?)),
+            None => Some(KeyWithSource::from_provider(provider.get_key()?)),
+        },
+        _ => key,
+    };
+
     let master_pubkey = match (master_pubkey_file, master_pubkey_fd) {
         (None, None) => None,
         (Some(_), Some(_)) => bail!("--keyfile and --keyfd are mutually exclusive"),
@@ -259,6 +304,26 @@ fn do_crypto_parameters(param: &Value, keep_keyfd_open: bool) -> Result<CryptoPa
     Ok(res)
 }
 
+/// Reads the master public keys passed via `--additional-master-pubkey-file`.
+pub fn additional_master_pubkeys(param: &Value) -> Result<Vec<KeyWithSource>, Error> {
+    let files = match param.get("additional-master-pubkey-file") {
+        Some(Value::Array(files)) => files,
+        Some(_) => bail!("bad --additional-master-pubkey-file parameter type"),
+        None => return Ok(Vec::new()),
+    };
+
+    files
+        .iter()
+        .map(|file| match file {
+            Value::String(path) => Ok(KeyWithSource::from_path(
+                path.clone(),
+                file_get_contents(path)?,
+            )),
+            _ => bail!("bad --additional-master-pubkey-file parameter type"),
+        })
+        .collect()
+}
+
 pub fn find_default_master_pubkey() -> Result<Option<PathBuf>, Error> {
     super::find_xdg_file(
         DEFAULT_MASTER_PUBKEY_FILE_NAME,
@@ -287,6 +352,114 @@ pub fn place_default_encryption_key() -> Result<PathBuf, Error> {
     )
 }
 
+fn retired_encryption_key_file_name(fingerprint: &Fingerprint) -> PathBuf {
+    let mut path = PathBuf::from(RETIRED_ENCRYPTION_KEYS_DIR_NAME);
+    path.push(format!("{}.json", hex::encode(fingerprint.bytes())));
+    path
+}
+
+pub fn find_retired_encryption_key(fingerprint: &Fingerprint) -> Result<Option<PathBuf>, Error> {
+    super::find_xdg_file(
+        retired_encryption_key_file_name(fingerprint),
+        "retired encryption key file",
+    )
+}
+
+pub fn place_retired_encryption_key(fingerprint: &Fingerprint) -> Result<PathBuf, Error> {
+    super::place_xdg_file(
+        retired_encryption_key_file_name(fingerprint),
+        "retired encryption key file",
+    )
+}
+
+/// List the files of all retired encryption keys.
+pub fn list_retired_encryption_keys() -> Result<Vec<PathBuf>, Error> {
+    let mut list = super::base_directories()?.list_config_files(RETIRED_ENCRYPTION_KEYS_DIR_NAME);
+    list.retain(|path| path.extension().map(|ext| ext == "json").unwrap_or(false));
+    list.sort();
+    Ok(list)
+}
+
+pub fn read_retired_encryption_key(
+    fingerprint: &Fingerprint,
+) -> Result<Option<KeyWithSource>, Error> {
+    find_retired_encryption_key(fingerprint)?
+        .map(|path| {
+            file_get_contents(path)
+                .map(|data| KeyWithSource::from_retired(fingerprint.clone(), data))
+        })
+        .transpose()
+}
+
+/// Use the retired key `snapshot` was encrypted with, if it differs from `enc_key`.
+///
+/// Snapshots created before a key rotation still depend on the old key. The given key is kept if
+/// the snapshot is not encrypted or no matching retired key is available.
+pub async fn select_snapshot_key(
+    client: &HttpClient,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    enc_key: Option<KeyWithSource>,
+) -> Result<Option<KeyWithSource>, Error> {
+    let enc_key = match enc_key {
+        Some(enc_key) => enc_key,
+        None => return Ok(None),
+    };
+
+    // keys created by very old versions do not record their fingerprint
+    if serde_json::from_slice::<KeyConfig>(&enc_key.key)?
+        .fingerprint
+        .is_none()
+    {
+        return Ok(Some(enc_key));
+    }
+
+    let path = format!("api2/json/admin/datastore/{}/snapshots", store);
+    let mut args = serde_json::to_value(&snapshot.group)?;
+    if !ns.is_root() {
+        args["ns"] = serde_json::to_value(ns)?;
+    }
+    let mut result = client.get(&path, Some(args)).await?;
+    let list: Vec<SnapshotListItem> = serde_json::from_value(result["data"].take())?;
+
+    let fingerprint = list
+        .into_iter()
+        .find(|item| item.backup == *snapshot)
+        .and_then(|item| item.fingerprint);
+
+    select_key_for_fingerprint(fingerprint.as_ref(), Some(enc_key))
+}
+
+/// Like [`select_snapshot_key`], for a snapshot whose key `fingerprint` is already known, for
+/// example from listing all snapshots of a group at once.
+pub fn select_key_for_fingerprint(
+    fingerprint: Option<&Fingerprint>,
+    enc_key: Option<KeyWithSource>,
+) -> Result<Option<KeyWithSource>, Error> {
+    let (enc_key, fingerprint) = match (enc_key, fingerprint) {
+        (Some(enc_key), Some(fingerprint)) => (enc_key, fingerprint),
+        (enc_key, _) => return Ok(enc_key),
+    };
+
+    // keys created by very old versions do not record their fingerprint
+    let current = match serde_json::from_slice::<KeyConfig>(&enc_key.key)?.fingerprint {
+        Some(current) => current,
+        None => return Ok(Some(enc_key)),
+    };
+    if *fingerprint == current {
+        return Ok(Some(enc_key));
+    }
+
+    match read_retired_encryption_key(fingerprint)? {
+        Some(retired) => {
+            log::info!("Snapshot was created with retired key {}", fingerprint);
+            Ok(Some(retired))
+        }
+        None => Ok(Some(enc_key)),
+    }
+}
+
 #[cfg(not(test))]
 pub(crate) fn read_optional_default_encryption_key() -> Result<Option<KeyWithSource>, Error> {
     find_default_encryption_key()?
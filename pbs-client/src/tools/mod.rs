@@ -19,6 +19,7 @@ use pbs_api_types::{Authid, BackupNamespace, RateLimitConfig, UserWithTokens, BA
 
 use crate::{BackupRepository, HttpClient, HttpClientOptions};
 
+pub mod key_provider;
 pub mod key_source;
 
 const ENV_VAR_PBS_FINGERPRINT: &str = "PBS_FINGERPRINT";
@@ -8,7 +8,9 @@ pub mod media_pool;
 pub mod metrics;
 pub mod network;
 pub mod prune;
+pub mod quota;
 pub mod remote;
+pub mod s3;
 pub mod sync;
 pub mod tape_job;
 pub mod token_shadow;
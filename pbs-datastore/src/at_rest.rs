@@ -0,0 +1,311 @@
+//! Server side at-rest encryption of chunk and snapshot files
+//!
+//! Datastores with at-rest encryption have a random datastore key, sealed either with a
+//! passphrase or with a key file, which is stored as [`AT_REST_KEY_FILE_NAME`] in the datastore
+//! base directory. Every chunk file, and every index, blob and manifest of a snapshot, gets
+//! encrypted with that key before it is written to disk (see
+//! [`AtRestChunkHeader`](crate::file_formats::AtRestChunkHeader)) and decrypted when it is
+//! loaded, so backup and reader protocol, sync, verification and garbage collection keep working
+//! on the plain files.
+//!
+//! Keys sealed with a passphrase cannot be unsealed without user interaction, so such datastores
+//! need to be unlocked after every boot, which places the datastore key below
+//! [`AT_REST_UNLOCKED_KEYS_DIR`].
+
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use nix::sys::stat::Mode;
+use openssl::symm::{decrypt_aead, encrypt_aead};
+
+use pbs_api_types::{DatastoreEncryptionConfig, DatastoreKeySealing, Fingerprint, Kdf};
+use pbs_key_config::KeyConfig;
+use pbs_tools::crypt_config::CryptConfig;
+use proxmox_sys::fs::{
+    create_path, file_get_contents, file_get_optional_contents, replace_file, CreateOptions,
+};
+
+use crate::file_formats::{
+    AtRestChunkHeader, AT_REST_ENCRYPTED_CHUNK_MAGIC_1_0, AT_REST_ENCRYPTED_FILE_MAGIC_1_0,
+};
+
+/// Name of the sealed datastore key, relative to the datastore base directory.
+pub const AT_REST_KEY_FILE_NAME: &str = ".at-rest-key.json";
+
+/// Directory path where the keys of unlocked datastores are kept (tmpfs).
+pub const AT_REST_UNLOCKED_KEYS_DIR: &str =
+    concat!(pbs_buildcfg::PROXMOX_BACKUP_RUN_DIR_M!(), "/at-rest-keys");
+
+/// Additional size of an encrypted chunk file, compared to the plain chunk.
+pub const AT_REST_HEADER_SIZE: usize = std::mem::size_of::<AtRestChunkHeader>();
+
+/// Returns true if `data` is an at-rest encrypted chunk file.
+pub fn is_encrypted_at_rest(data: &[u8]) -> bool {
+    data.len() >= AT_REST_HEADER_SIZE && data[..8] == AT_REST_ENCRYPTED_CHUNK_MAGIC_1_0
+}
+
+/// Returns true if `data` is an at-rest encrypted snapshot file.
+pub fn is_encrypted_file_at_rest(data: &[u8]) -> bool {
+    data.len() >= AT_REST_HEADER_SIZE && data[..8] == AT_REST_ENCRYPTED_FILE_MAGIC_1_0
+}
+
+/// The unsealed datastore key
+pub struct AtRestKey {
+    config: CryptConfig,
+}
+
+impl AtRestKey {
+    pub fn new(key: [u8; 32]) -> Result<Self, Error> {
+        Ok(Self {
+            config: CryptConfig::new(key)?,
+        })
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::new(self.config.fingerprint())
+    }
+
+    /// Encrypts the raw chunk data, bound to the chunk's digest.
+    pub fn encrypt_chunk(&self, data: &[u8], digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        self.encrypt(&AT_REST_ENCRYPTED_CHUNK_MAGIC_1_0, data, digest)
+    }
+
+    /// Decrypts an at-rest encrypted chunk file, returning the raw chunk data.
+    pub fn decrypt_chunk(&self, data: &[u8], digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        if !is_encrypted_at_rest(data) {
+            bail!("not an at-rest encrypted chunk");
+        }
+
+        self.decrypt(data, digest).map_err(|_| {
+            format_err!("unable to decrypt chunk - wrong datastore key or corrupt data")
+        })
+    }
+
+    /// Encrypts the content of a snapshot file, like an index, blob or manifest, bound to the
+    /// file's path relative to the datastore base directory.
+    pub fn encrypt_file(&self, data: &[u8], path: &Path) -> Result<Vec<u8>, Error> {
+        self.encrypt(&AT_REST_ENCRYPTED_FILE_MAGIC_1_0, data, &file_aad(path))
+    }
+
+    /// Decrypts an at-rest encrypted snapshot file stored at `path`, relative to the datastore
+    /// base directory, returning the plain file content.
+    pub fn decrypt_file(&self, data: &[u8], path: &Path) -> Result<Vec<u8>, Error> {
+        if !is_encrypted_file_at_rest(data) {
+            bail!("not an at-rest encrypted file");
+        }
+
+        self.decrypt(data, &file_aad(path)).map_err(|_| {
+            format_err!("unable to decrypt file {path:?} - wrong datastore key or corrupt data")
+        })
+    }
+
+    fn encrypt(&self, magic: &[u8; 8], data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut iv = [0u8; 16];
+        proxmox_sys::linux::fill_with_random_data(&mut iv)?;
+        let mut tag = [0u8; 16];
+
+        let encrypted = encrypt_aead(
+            *self.config.cipher(),
+            self.config.enc_key(),
+            Some(&iv),
+            aad,
+            data,
+            &mut tag,
+        )?;
+
+        let mut file_data = Vec::with_capacity(AT_REST_HEADER_SIZE + encrypted.len());
+        file_data.extend_from_slice(magic);
+        file_data.extend_from_slice(&iv);
+        file_data.extend_from_slice(&tag);
+        file_data.extend_from_slice(&encrypted);
+
+        Ok(file_data)
+    }
+
+    fn decrypt(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        let iv = &data[8..24];
+        let tag = &data[24..AT_REST_HEADER_SIZE];
+
+        Ok(decrypt_aead(
+            *self.config.cipher(),
+            self.config.enc_key(),
+            Some(iv),
+            aad,
+            &data[AT_REST_HEADER_SIZE..],
+            tag,
+        )?)
+    }
+}
+
+// Files are bound to their path, so that they cannot be swapped with other encrypted files of
+// the datastore, like the index of another snapshot.
+fn file_aad(path: &Path) -> Vec<u8> {
+    let path = path.as_os_str().as_bytes();
+    let mut aad = Vec::with_capacity(AT_REST_ENCRYPTED_FILE_MAGIC_1_0.len() + path.len());
+    aad.extend_from_slice(&AT_REST_ENCRYPTED_FILE_MAGIC_1_0);
+    aad.extend_from_slice(path);
+    aad
+}
+
+fn sealed_key_path(base: &Path) -> PathBuf {
+    base.join(AT_REST_KEY_FILE_NAME)
+}
+
+fn unlocked_key_path(store: &str) -> PathBuf {
+    PathBuf::from(AT_REST_UNLOCKED_KEYS_DIR).join(format!("{store}.key"))
+}
+
+fn load_sealed_key(base: &Path) -> Result<KeyConfig, Error> {
+    let path = sealed_key_path(base);
+    let data = file_get_contents(&path)?;
+    serde_json::from_slice(&data)
+        .map_err(|err| format_err!("unable to parse sealed datastore key {path:?} - {err}"))
+}
+
+/// Creates a new datastore key in `base`, sealed with `secret`.
+///
+/// The secret is either the passphrase, or the content of the sealing key file.
+pub fn create_sealed_key(base: &Path, secret: &[u8]) -> Result<Fingerprint, Error> {
+    let path = sealed_key_path(base);
+    if path.exists() {
+        bail!("datastore at {base:?} already has a datastore key");
+    }
+
+    let (key, key_config) = KeyConfig::new(secret, Kdf::Scrypt)?;
+    key_config.store(&path, false)?;
+
+    // the proxy needs to read it for key file sealed datastores
+    let backup_user = pbs_config::backup_user()?;
+    nix::unistd::chown(&path, Some(backup_user.uid), Some(backup_user.gid))?;
+
+    Ok(AtRestKey::new(key)?.fingerprint())
+}
+
+/// Unseals the datastore key stored in `base`.
+pub fn unseal_key(base: &Path, secret: &[u8]) -> Result<AtRestKey, Error> {
+    let (key, _created, _fingerprint) = load_sealed_key(base)?
+        .decrypt(&|| Ok(secret.to_vec()))
+        .map_err(|err| format_err!("unable to unseal datastore key - {err}"))?;
+
+    AtRestKey::new(key)
+}
+
+/// Unlocks a passphrase sealed datastore until the next reboot.
+pub fn unlock(store: &str, base: &Path, passphrase: &[u8]) -> Result<Fingerprint, Error> {
+    let key = unseal_key(base, passphrase)?;
+
+    let backup_user = pbs_config::backup_user()?;
+    let dir_opts = CreateOptions::new()
+        .perm(Mode::from_bits_truncate(0o700))
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    create_path(AT_REST_UNLOCKED_KEYS_DIR, None, Some(dir_opts))?;
+
+    let file_opts = CreateOptions::new()
+        .perm(Mode::from_bits_truncate(0o600))
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    replace_file(
+        unlocked_key_path(store),
+        key.config.enc_key(),
+        file_opts,
+        false,
+    )?;
+
+    Ok(key.fingerprint())
+}
+
+/// Loads the datastore key, according to the datastore's encryption configuration.
+pub fn load_key(
+    store: &str,
+    base: &Path,
+    config: &DatastoreEncryptionConfig,
+) -> Result<AtRestKey, Error> {
+    match config.sealing.unwrap_or_default() {
+        DatastoreKeySealing::Keyfile => {
+            // unwrap: checked when parsing the config
+            let keyfile = config.keyfile.as_deref().unwrap();
+            let secret = file_get_contents(keyfile)
+                .map_err(|err| format_err!("unable to read datastore key file - {err}"))?;
+            unseal_key(base, &secret)
+        }
+        DatastoreKeySealing::Passphrase => {
+            let key = match file_get_optional_contents(unlocked_key_path(store))? {
+                Some(key) => key,
+                None => bail!(
+                    "datastore '{store}' is locked, unlock it with \
+                    'proxmox-backup-manager datastore unlock {store}'"
+                ),
+            };
+            let key: [u8; 32] = key
+                .try_into()
+                .map_err(|_| format_err!("unlocked key of datastore '{store}' has wrong size"))?;
+            let key = AtRestKey::new(key)?;
+
+            if let Some(fingerprint) = load_sealed_key(base)?.fingerprint {
+                if fingerprint != key.fingerprint() {
+                    bail!("unlocked key of datastore '{store}' does not match its datastore key");
+                }
+            }
+
+            Ok(key)
+        }
+    }
+}
+
+#[test]
+fn test_encrypt_decrypt_chunk() -> Result<(), Error> {
+    let key = AtRestKey::new([7u8; 32])?;
+    let data = b"some raw chunk data";
+    let digest = openssl::sha::sha256(data);
+
+    let encrypted = key.encrypt_chunk(data, &digest)?;
+    assert!(is_encrypted_at_rest(&encrypted));
+    assert_eq!(encrypted.len(), data.len() + AT_REST_HEADER_SIZE);
+    assert_eq!(key.decrypt_chunk(&encrypted, &digest)?, data);
+
+    // bound to the digest
+    assert!(key.decrypt_chunk(&encrypted, &[0u8; 32]).is_err());
+
+    let mut tampered = encrypted.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    assert!(key.decrypt_chunk(&tampered, &digest).is_err());
+
+    let other_key = AtRestKey::new([8u8; 32])?;
+    assert!(other_key.decrypt_chunk(&encrypted, &digest).is_err());
+
+    assert!(!is_encrypted_at_rest(data));
+
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_decrypt_file() -> Result<(), Error> {
+    let key = AtRestKey::new([7u8; 32])?;
+    let data = b"some index or blob";
+    let path = Path::new("vm/100/2023-01-01T00:00:00Z/drive-scsi0.img.fidx");
+
+    let encrypted = key.encrypt_file(data, path)?;
+    assert!(is_encrypted_file_at_rest(&encrypted));
+    assert!(!is_encrypted_at_rest(&encrypted));
+    assert_eq!(encrypted.len(), data.len() + AT_REST_HEADER_SIZE);
+    assert_eq!(key.decrypt_file(&encrypted, path)?, data);
+
+    // bound to the path
+    let other_path = Path::new("vm/101/2023-01-01T00:00:00Z/drive-scsi0.img.fidx");
+    assert!(key.decrypt_file(&encrypted, other_path).is_err());
+
+    // chunks and files cannot be mixed up
+    let digest = openssl::sha::sha256(data);
+    assert!(key
+        .decrypt_file(&key.encrypt_chunk(data, &digest)?, path)
+        .is_err());
+    assert!(key.decrypt_chunk(&encrypted, &digest).is_err());
+
+    let other_key = AtRestKey::new([8u8; 32])?;
+    assert!(other_key.decrypt_file(&encrypted, path).is_err());
+
+    Ok(())
+}
@@ -8,7 +8,8 @@ use anyhow::{bail, format_err, Error};
 use proxmox_sys::fs::{lock_dir_noblock, replace_file, CreateOptions};
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, GroupFilter, BACKUP_DATE_REGEX, BACKUP_FILE_REGEX,
+    Authid, BackupNamespace, BackupType, GroupFilter, SnapshotVerifyState, VerifyState,
+    BACKUP_DATE_REGEX, BACKUP_FILE_REGEX,
 };
 use pbs_config::{open_backup_lockfile, BackupLockGuard};
 
@@ -106,6 +107,7 @@ impl BackupGroup {
                     backup_dir,
                     files,
                     protected,
+                    verify_state: None,
                 });
 
                 Ok(())
@@ -218,6 +220,8 @@ impl BackupGroup {
             std::fs::remove_dir_all(&path).map_err(|err| {
                 format_err!("removing group directory {:?} failed - {}", path, err)
             })?;
+            self.store
+                .remove_from_backend(&self.relative_group_path(), true)?;
         }
 
         Ok(removed_all_snaps)
@@ -408,7 +412,7 @@ impl BackupDir {
         path.push(filename);
 
         proxmox_lang::try_block!({
-            let mut file = std::fs::File::open(&path)?;
+            let mut file = self.store.open_snapshot_file(&path)?;
             DataBlob::load_from_reader(&mut file)
         })
         .map_err(|err| format_err!("unable to load blob '{:?}' - {}", path, err))
@@ -458,6 +462,8 @@ impl BackupDir {
         std::fs::remove_dir_all(&full_path).map_err(|err| {
             format_err!("removing backup snapshot {:?} failed - {}", full_path, err,)
         })?;
+        self.store
+            .remove_from_backend(&self.relative_path(), true)?;
 
         // the manifest doesn't exist anymore, no need to keep the lock (already done by guard?)
         if let Ok(path) = self.manifest_lock_path() {
@@ -506,13 +512,15 @@ impl BackupDir {
         let manifest = serde_json::to_value(manifest)?;
         let manifest = serde_json::to_string_pretty(&manifest)?;
         let blob = DataBlob::encode(manifest.as_bytes(), None, true)?;
-        let raw_data = blob.raw_data();
 
         let mut path = self.full_path();
         path.push(MANIFEST_BLOB_NAME);
+        let raw_data = self.store.encode_snapshot_file(blob.raw_data(), &path)?;
 
         // atomic replace invalidates flock - no other writes past this point!
-        replace_file(&path, raw_data, CreateOptions::new(), false)?;
+        replace_file(&path, &raw_data, CreateOptions::new(), false)?;
+        self.store
+            .upload_to_backend(&self.relative_path().join(MANIFEST_BLOB_NAME))?;
         Ok(())
     }
 
@@ -545,6 +553,10 @@ impl BackupDir {
             println!("remove unused file {:?}", item.file_name());
             let dirfd = item.parent_fd();
             let _res = unsafe { libc::unlinkat(dirfd, item.file_name().as_ptr(), 0) };
+            if let Ok(name) = std::str::from_utf8(file_name) {
+                self.store
+                    .remove_from_backend(&self.relative_path().join(name), false)?;
+            }
         }
 
         Ok(())
@@ -602,6 +614,8 @@ pub struct BackupInfo {
     pub files: Vec<String>,
     /// Protection Status
     pub protected: bool,
+    /// Verification state, only available after `load_verify_state`
+    pub verify_state: Option<VerifyState>,
 }
 
 impl BackupInfo {
@@ -615,9 +629,29 @@ impl BackupInfo {
             backup_dir,
             files,
             protected,
+            verify_state: None,
         })
     }
 
+    /// Load the verification state from the manifest.
+    ///
+    /// This is not done on listing, as it requires reading the manifest of every snapshot.
+    pub fn load_verify_state(&mut self) -> Result<(), Error> {
+        self.verify_state = None;
+
+        if !self.is_finished() {
+            return Ok(());
+        }
+
+        let (manifest, _) = self.backup_dir.load_manifest()?;
+        let verify_state = manifest.unprotected["verify_state"].clone();
+        if let Ok(verify_state) = serde_json::from_value::<SnapshotVerifyState>(verify_state) {
+            self.verify_state = Some(verify_state.state);
+        }
+
+        Ok(())
+    }
+
     pub fn sort_list(list: &mut [BackupInfo], ascendending: bool) {
         if ascendending {
             // oldest first
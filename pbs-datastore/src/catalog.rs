@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::ffi::{CStr, CString, OsStr};
 use std::fmt;
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -691,6 +692,30 @@ impl<R: Read + Seek> CatalogReader<R> {
         Ok(())
     }
 
+    /// Sums up the sizes of all files below a directory.
+    ///
+    /// Returns the total size and the number of files. Hardlinks are not counted, as the catalog
+    /// does not record their target.
+    pub fn disk_usage(&mut self, dir: &DirEntry) -> Result<(u64, u64), Error> {
+        let mut total_size = 0;
+        let mut files = 0;
+        for entry in self.read_dir(dir)? {
+            match entry.attr {
+                DirEntryAttribute::Directory { .. } => {
+                    let (dir_size, dir_files) = self.disk_usage(&entry)?;
+                    total_size += dir_size;
+                    files += dir_files;
+                }
+                DirEntryAttribute::File { size, .. } => {
+                    total_size += size;
+                    files += 1;
+                }
+                _ => (),
+            }
+        }
+        Ok((total_size, files))
+    }
+
     /// Returns the list of content of the given path
     pub fn list_dir_contents(&mut self, path: &[u8]) -> Result<Vec<ArchiveEntry>, Error> {
         let dir = self.lookup_recursive(path)?;
@@ -716,6 +741,76 @@ impl<R: Read + Seek> CatalogReader<R> {
     }
 }
 
+/// Prints the differences between two versions of an entry to `out`, recursing into directories.
+///
+/// Each changed entry gets printed on its own line, prefixed with `A` (added), `D` (deleted) or
+/// `M` (modified), with `path` being the entry's path. Directories are suffixed with a `/`.
+pub fn diff_entry<R1: Read + Seek, R2: Read + Seek>(
+    old_catalog: &mut CatalogReader<R1>,
+    old: Option<&DirEntry>,
+    new_catalog: &mut CatalogReader<R2>,
+    new: Option<&DirEntry>,
+    path: &mut Vec<u8>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    let (operation, entry) = match (old, new) {
+        (Some(old), Some(new)) if old.is_directory() && new.is_directory() => {
+            return diff_dir(old_catalog, old, new_catalog, new, path, out);
+        }
+        (Some(old), Some(new)) if old.attr == new.attr => return Ok(()),
+        (Some(_), Some(new)) => ("M", new),
+        (None, Some(new)) => ("A", new),
+        (Some(old), None) => ("D", old),
+        (None, None) => return Ok(()),
+    };
+
+    out.write_all(operation.as_bytes())?;
+    out.write_all(b"  ")?;
+    out.write_all(path)?;
+    if entry.is_directory() {
+        out.write_all(b"/")?;
+    }
+    out.write_all(b"\n")?;
+
+    Ok(())
+}
+
+fn diff_dir<R1: Read + Seek, R2: Read + Seek>(
+    old_catalog: &mut CatalogReader<R1>,
+    old_dir: &DirEntry,
+    new_catalog: &mut CatalogReader<R2>,
+    new_dir: &DirEntry,
+    path: &mut Vec<u8>,
+    out: &mut dyn Write,
+) -> Result<(), Error> {
+    // entries by name, (old, new)
+    let mut entries = BTreeMap::<Vec<u8>, (Option<_>, Option<_>)>::new();
+    for entry in old_catalog.read_dir(old_dir)? {
+        entries.entry(entry.name.clone()).or_default().0 = Some(entry);
+    }
+    for entry in new_catalog.read_dir(new_dir)? {
+        entries.entry(entry.name.clone()).or_default().1 = Some(entry);
+    }
+
+    let len = path.len();
+    for (name, (old, new)) in entries {
+        path.truncate(len);
+        path.push(b'/');
+        path.extend_from_slice(&name);
+        diff_entry(
+            old_catalog,
+            old.as_ref(),
+            new_catalog,
+            new.as_ref(),
+            path,
+            out,
+        )?;
+    }
+    path.truncate(len);
+
+    Ok(())
+}
+
 /// Serialize i64 as short, variable length byte sequence
 ///
 /// Stores 7 bits per byte, Bit 8 indicates the end of the sequence (when not set).
@@ -897,6 +992,133 @@ fn test_catalog_i64_compatibility() {
     test_encode_decode(u64::MAX);
 }
 
+#[test]
+fn test_catalog_disk_usage() -> Result<(), Error> {
+    let name = |name: &str| CString::new(name).unwrap();
+
+    let mut data = Vec::new();
+    let mut writer = CatalogWriter::new(&mut data)?;
+    writer.start_directory(&name("root.pxar.didx"))?;
+    writer.add_file(&name("a"), 100, 0)?;
+    writer.start_directory(&name("sub"))?;
+    writer.add_file(&name("b"), 20, 0)?;
+    writer.add_symlink(&name("link"))?;
+    writer.start_directory(&name("empty"))?;
+    writer.end_directory()?;
+    writer.add_file(&name("c"), 3, 0)?;
+    writer.end_directory()?;
+    writer.end_directory()?;
+    writer.finish()?;
+    drop(writer);
+
+    let mut reader = CatalogReader::new(std::io::Cursor::new(data));
+    let archive = reader.lookup_recursive(b"/root.pxar.didx")?;
+    assert_eq!(reader.disk_usage(&archive)?, (123, 3));
+
+    let sub = reader.lookup_recursive(b"/root.pxar.didx/sub")?;
+    assert_eq!(reader.disk_usage(&sub)?, (23, 2));
+
+    let empty = reader.lookup_recursive(b"/root.pxar.didx/sub/empty")?;
+    assert_eq!(reader.disk_usage(&empty)?, (0, 0));
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn test_catalog(
+    build: impl FnOnce(&mut CatalogWriter<&mut Vec<u8>>) -> Result<(), Error>,
+) -> Result<CatalogReader<std::io::Cursor<Vec<u8>>>, Error> {
+    let mut data = Vec::new();
+    let mut writer = CatalogWriter::new(&mut data)?;
+    writer.start_directory(&CString::new("root.pxar.didx").unwrap())?;
+    build(&mut writer)?;
+    writer.end_directory()?;
+    writer.finish()?;
+    drop(writer);
+
+    Ok(CatalogReader::new(std::io::Cursor::new(data)))
+}
+
+#[cfg(test)]
+fn test_diff(
+    old_catalog: &mut CatalogReader<std::io::Cursor<Vec<u8>>>,
+    new_catalog: &mut CatalogReader<std::io::Cursor<Vec<u8>>>,
+    path: &[u8],
+) -> Result<String, Error> {
+    let old = old_catalog.lookup_recursive(path)?;
+    let new = new_catalog.lookup_recursive(path)?;
+
+    let mut out = Vec::new();
+    diff_entry(
+        old_catalog,
+        Some(&old),
+        new_catalog,
+        Some(&new),
+        &mut path.to_vec(),
+        &mut out,
+    )?;
+
+    Ok(String::from_utf8(out)?)
+}
+
+#[test]
+fn test_catalog_diff() -> Result<(), Error> {
+    let name = |name: &str| CString::new(name).unwrap();
+
+    let mut old = test_catalog(|writer| {
+        writer.add_file(&name("same"), 10, 0)?;
+        writer.add_file(&name("resized"), 10, 0)?;
+        writer.add_file(&name("touched"), 10, 0)?;
+        writer.add_file(&name("deleted"), 10, 0)?;
+        writer.add_file(&name("retyped"), 10, 0)?;
+        writer.start_directory(&name("sub"))?;
+        writer.add_file(&name("b"), 20, 0)?;
+        writer.start_directory(&name("gone"))?;
+        writer.add_file(&name("c"), 3, 0)?;
+        writer.end_directory()?;
+        writer.end_directory()
+    })?;
+    let mut new = test_catalog(|writer| {
+        writer.add_file(&name("added"), 10, 0)?;
+        writer.add_file(&name("same"), 10, 0)?;
+        writer.add_file(&name("resized"), 11, 0)?;
+        writer.add_file(&name("touched"), 10, 1)?;
+        writer.add_symlink(&name("retyped"))?;
+        writer.start_directory(&name("sub"))?;
+        writer.start_directory(&name("new"))?;
+        writer.end_directory()?;
+        writer.add_file(&name("b"), 20, 0)?;
+        writer.end_directory()
+    })?;
+
+    assert_eq!(
+        test_diff(&mut old, &mut new, b"/root.pxar.didx")?,
+        "A  /root.pxar.didx/added\n\
+        D  /root.pxar.didx/deleted\n\
+        M  /root.pxar.didx/resized\n\
+        M  /root.pxar.didx/retyped\n\
+        D  /root.pxar.didx/sub/gone/\n\
+        A  /root.pxar.didx/sub/new/\n\
+        M  /root.pxar.didx/touched\n",
+    );
+
+    // limited to the given directory
+    assert_eq!(
+        test_diff(&mut old, &mut new, b"/root.pxar.didx/sub")?,
+        "D  /root.pxar.didx/sub/gone/\nA  /root.pxar.didx/sub/new/\n",
+    );
+
+    // no differences
+    let mut same = test_catalog(|writer| writer.add_file(&name("same"), 10, 0))?;
+    let mut same_again = test_catalog(|writer| writer.add_file(&name("same"), 10, 0))?;
+    assert_eq!(
+        test_diff(&mut same, &mut same_again, b"/root.pxar.didx")?,
+        ""
+    );
+
+    Ok(())
+}
+
 /// An entry in a hierarchy of files for restore and listing.
 #[api]
 #[derive(Serialize, Deserialize)]
@@ -0,0 +1,106 @@
+//! Compact set of chunk digests used to mark chunks during garbage collection.
+//!
+//! This is a bloom filter: every digest sets `HASH_COUNT` bits, taken from different parts of the
+//! digest. As chunk digests are already uniformly distributed, no further hashing is required.
+//!
+//! False positives are possible, but only mean that an unused chunk is kept until a later garbage
+//! collection run. False negatives are impossible, so chunks in use are never removed.
+
+/// Number of bits set per digest.
+const HASH_COUNT: usize = 4;
+
+/// Number of bits reserved per expected chunk, gives a false positive rate of about 0.25%.
+const BITS_PER_CHUNK: u64 = 16;
+
+/// Minimum size of the bitmap in bits (128 KiB).
+const MIN_BITS: u64 = 1 << 20;
+
+pub struct ChunkBitmap {
+    bits: Vec<u64>,
+    mask: u64,
+    marked: u64,
+}
+
+impl ChunkBitmap {
+    /// Create a bitmap sized for `expected_chunks` digests.
+    ///
+    /// Inserting more digests than expected is safe, but increases the false positive rate.
+    pub fn new(expected_chunks: u64) -> Self {
+        let bit_count = expected_chunks
+            .saturating_mul(BITS_PER_CHUNK)
+            .max(MIN_BITS)
+            .next_power_of_two();
+
+        Self {
+            bits: vec![0u64; (bit_count / 64) as usize],
+            mask: bit_count - 1,
+            marked: 0,
+        }
+    }
+
+    /// Size of the bitmap in bytes.
+    pub fn byte_size(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<u64>()
+    }
+
+    /// Mark `digest` as used.
+    ///
+    /// Returns `false` if the digest was (probably) marked already.
+    pub fn insert(&mut self, digest: &[u8; 32]) -> bool {
+        let mut new = false;
+        for pos in bit_positions(digest, self.mask) {
+            let word = &mut self.bits[(pos / 64) as usize];
+            new |= *word & (1 << (pos % 64)) == 0;
+            *word |= 1 << (pos % 64);
+        }
+        if new {
+            self.marked += 1;
+        }
+        new
+    }
+
+    /// Number of distinct digests marked, digests which were false positives when inserted are
+    /// not counted.
+    pub fn marked_count(&self) -> u64 {
+        self.marked
+    }
+
+    /// Check whether `digest` is (probably) marked as used.
+    pub fn contains(&self, digest: &[u8; 32]) -> bool {
+        bit_positions(digest, self.mask)
+            .all(|pos| self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+    }
+}
+
+fn bit_positions(digest: &[u8; 32], mask: u64) -> impl Iterator<Item = u64> + '_ {
+    digest
+        .chunks_exact(8)
+        .take(HASH_COUNT)
+        .map(move |part| u64::from_le_bytes(part.try_into().unwrap()) & mask)
+}
+
+#[test]
+fn test_chunk_bitmap() {
+    let mut bitmap = ChunkBitmap::new(1000);
+    assert_eq!(bitmap.byte_size(), (MIN_BITS / 8) as usize);
+
+    let digest = |i: u32| -> [u8; 32] { openssl::sha::sha256(&i.to_le_bytes()) };
+
+    for i in 0..1000 {
+        bitmap.insert(&digest(i));
+    }
+    assert!(!bitmap.insert(&digest(0)));
+    assert!(bitmap.marked_count() <= 1000 && bitmap.marked_count() > 990);
+
+    for i in 0..1000 {
+        assert!(bitmap.contains(&digest(i)));
+    }
+
+    let false_positives = (1000..101_000)
+        .filter(|i| bitmap.contains(&digest(*i)))
+        .count();
+    assert!(
+        false_positives < 10,
+        "too many false positives: {false_positives}"
+    );
+}
@@ -1,10 +1,13 @@
-use std::os::unix::io::AsRawFd;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::{bail, format_err, Error};
 
-use pbs_api_types::{DatastoreFSyncLevel, GarbageCollectionStatus};
+use pbs_api_types::{DatastoreEncryptionConfig, DatastoreFSyncLevel, GarbageCollectionStatus};
 use proxmox_sys::fs::{create_dir, create_path, file_type_from_file_stat, CreateOptions};
 use proxmox_sys::process_locker::{
     ProcessLockExclusiveGuard, ProcessLockSharedGuard, ProcessLocker,
@@ -12,18 +15,65 @@ use proxmox_sys::process_locker::{
 use proxmox_sys::task_log;
 use proxmox_sys::WorkerTaskContext;
 
+use crate::at_rest::{
+    self, is_encrypted_at_rest, is_encrypted_file_at_rest, AtRestKey, AT_REST_HEADER_SIZE,
+};
+use crate::chunk_bitmap::ChunkBitmap;
+use crate::file_formats::AT_REST_ENCRYPTED_FILE_MAGIC_1_0;
 use crate::DataBlob;
 
+/// A directory holding a part of the chunks of a chunk store.
+struct ChunkShard {
+    /// The original path, only used for logging.
+    path: PathBuf,
+    chunk_dir: PathBuf,
+    /// Mixed into the digest to rank the shards for a chunk, derived from the shard path.
+    seed: u64,
+}
+
+impl ChunkShard {
+    fn new(path: PathBuf, id: &[u8]) -> Self {
+        let hash = openssl::sha::sha256(id);
+        Self {
+            chunk_dir: ChunkStore::chunk_dir(&path),
+            path,
+            seed: u64::from_le_bytes(hash[..8].try_into().unwrap()),
+        }
+    }
+
+    // rendezvous hashing: every chunk is placed on the shard with the highest score, so adding a
+    // shard only moves the chunks which now score highest on the new one
+    fn score(&self, digest: &[u8; 32]) -> u64 {
+        let mut x = u64::from_le_bytes(digest[..8].try_into().unwrap()) ^ self.seed;
+        // splitmix64 finalizer
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^ (x >> 31)
+    }
+}
+
 /// File system based chunk store
+///
+/// The chunks are stored in the `.chunks` directory of the base path and, optionally, of
+/// additional shard directories. Each chunk belongs to exactly one shard, determined by its
+/// digest.
 pub struct ChunkStore {
     name: String, // used for error reporting
     pub(crate) base: PathBuf,
-    chunk_dir: PathBuf,
+    shards: RwLock<Vec<ChunkShard>>,
+    at_rest: RwLock<AtRestState>,
     mutex: Mutex<()>,
     locker: Option<Arc<Mutex<ProcessLocker>>>,
     sync_level: DatastoreFSyncLevel,
 }
 
+/// At-rest encryption configuration, the datastore key gets loaded on first use.
+#[derive(Default)]
+struct AtRestState {
+    config: Option<String>,
+    key: Option<Arc<AtRestKey>>,
+}
+
 // TODO: what about sysctl setting vm.vfs_cache_pressure (0 - 100) ?
 
 pub fn verify_chunk_size(size: usize) -> Result<(), Error> {
@@ -65,7 +115,8 @@ impl ChunkStore {
         Self {
             name: String::new(),
             base: PathBuf::new(),
-            chunk_dir: PathBuf::new(),
+            shards: RwLock::new(Vec::new()),
+            at_rest: RwLock::new(AtRestState::default()),
             mutex: Mutex::new(()),
             locker: None,
             sync_level: Default::default(),
@@ -96,21 +147,45 @@ impl ChunkStore {
     {
         let base: PathBuf = path.into();
 
+        Self::create_shard(name, &base, uid, gid, worker)?;
+
+        // create lock file with correct owner/group
+        let options = CreateOptions::new().owner(uid).group(gid);
+        let lockfile_path = Self::lockfile_path(&base);
+        proxmox_sys::fs::replace_file(lockfile_path, b"", options, false)?;
+
+        Self::open(name, base, sync_level)
+    }
+
+    /// Creates the chunk directory structure below `path`, either for a new chunk store or to
+    /// add it as shard to an existing one.
+    pub fn create_shard<P>(
+        name: &str,
+        path: P,
+        uid: nix::unistd::Uid,
+        gid: nix::unistd::Gid,
+        worker: Option<&dyn WorkerTaskContext>,
+    ) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        let base = path.as_ref();
+
         if !base.is_absolute() {
             bail!("expected absolute path - got {base:?}");
         }
 
-        let chunk_dir = Self::chunk_dir(&base);
+        let chunk_dir = Self::chunk_dir(base);
 
         let options = CreateOptions::new().owner(uid).group(gid);
 
         let default_options = CreateOptions::new();
 
-        match create_path(&base, Some(default_options), Some(options.clone())) {
+        match create_path(base, Some(default_options), Some(options.clone())) {
             Err(err) => bail!("unable to create chunk store '{name}' at {base:?} - {err}"),
             Ok(res) => {
                 if !res {
-                    nix::unistd::chown(&base, Some(uid), Some(gid))?
+                    nix::unistd::chown(base, Some(uid), Some(gid))?
                 }
             }
         }
@@ -119,10 +194,6 @@ impl ChunkStore {
             bail!("unable to create chunk store '{name}' subdir {chunk_dir:?} - {err}");
         }
 
-        // create lock file with correct owner/group
-        let lockfile_path = Self::lockfile_path(&base);
-        proxmox_sys::fs::replace_file(lockfile_path, b"", options.clone(), false)?;
-
         // create 64*1024 subdirs
         let mut last_percentage = 0;
 
@@ -146,7 +217,7 @@ impl ChunkStore {
             }
         }
 
-        Self::open(name, base, sync_level)
+        Ok(())
     }
 
     fn lockfile_path<P: Into<PathBuf>>(base: P) -> PathBuf {
@@ -181,16 +252,224 @@ impl ChunkStore {
 
         let locker = ProcessLocker::new(&lockfile_path)?;
 
+        let shards = vec![ChunkShard::new(base.clone(), b"")];
+
         Ok(ChunkStore {
             name: name.to_owned(),
             base,
-            chunk_dir,
+            shards: RwLock::new(shards),
+            at_rest: RwLock::new(AtRestState::default()),
             locker: Some(locker),
             mutex: Mutex::new(()),
             sync_level,
         })
     }
 
+    /// Sets the additional shard directories, in addition to the base path.
+    ///
+    /// Shards can only be added, the chunks of a removed shard would not be found anymore.
+    pub(crate) fn set_shards(&self, paths: &[String]) -> Result<(), Error> {
+        let mut shards = vec![ChunkShard::new(self.base.clone(), b"")];
+
+        for path in paths {
+            let shard = ChunkShard::new(PathBuf::from(path), path.as_bytes());
+            if let Err(err) = std::fs::metadata(&shard.chunk_dir) {
+                bail!(
+                    "unable to open chunk store '{}' shard at {:?} - {err}",
+                    self.name,
+                    shard.chunk_dir,
+                );
+            }
+            shards.push(shard);
+        }
+
+        let mut current = self.shards.write().unwrap();
+        for shard in current.iter() {
+            if !shards.iter().any(|s| s.chunk_dir == shard.chunk_dir) {
+                bail!(
+                    "removing shard {:?} from chunk store '{}' is not supported",
+                    shard.path,
+                    self.name,
+                );
+            }
+        }
+        *current = shards;
+
+        Ok(())
+    }
+
+    /// Sets the at-rest encryption configuration property string.
+    ///
+    /// The datastore key is only loaded when first needed, passphrase sealed datastores might
+    /// still be locked at this point.
+    pub(crate) fn set_at_rest_encryption(&self, config: Option<&str>) -> Result<(), Error> {
+        if let Some(config) = config {
+            DatastoreEncryptionConfig::parse(config)?;
+        }
+
+        let mut state = self.at_rest.write().unwrap();
+        if state.config.as_deref() != config {
+            state.config = config.map(String::from);
+            state.key = None;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the datastore key if at-rest encryption is configured.
+    fn at_rest_key(&self) -> Result<Option<Arc<AtRestKey>>, Error> {
+        let state = self.at_rest.read().unwrap();
+        let config = match &state.config {
+            Some(config) => config.clone(),
+            None => return Ok(None),
+        };
+        if let Some(key) = &state.key {
+            return Ok(Some(Arc::clone(key)));
+        }
+        drop(state);
+
+        let encryption = DatastoreEncryptionConfig::parse(&config)?;
+        let key = Arc::new(at_rest::load_key(&self.name, &self.base, &encryption)?);
+
+        let mut state = self.at_rest.write().unwrap();
+        // the configuration might have changed while loading the key
+        if state.config.as_ref() == Some(&config) {
+            state.key = Some(Arc::clone(&key));
+        }
+
+        Ok(Some(key))
+    }
+
+    /// Encodes raw chunk data the way it is stored on disk, encrypted if the datastore is
+    /// configured for at-rest encryption.
+    pub fn encode_chunk_file(&self, raw_data: &[u8], digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        match self.at_rest_key()? {
+            Some(key) => key.encrypt_chunk(raw_data, digest),
+            None => Ok(raw_data.to_vec()),
+        }
+    }
+
+    /// Decodes a chunk file's content to the raw chunk data.
+    ///
+    /// Chunks written before at-rest encryption got enabled are still stored in plain.
+    pub fn decode_chunk_file(&self, data: Vec<u8>, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        if !is_encrypted_at_rest(&data) {
+            return Ok(data);
+        }
+        match self.at_rest_key()? {
+            Some(key) => key.decrypt_chunk(&data, digest),
+            None => bail!(
+                "chunk {} on store '{}' is encrypted at rest, but no encryption is configured",
+                hex::encode(digest),
+                self.name,
+            ),
+        }
+    }
+
+    /// Encodes the content of a snapshot file (index, blob or manifest) the way it is stored on
+    /// disk at `path`, encrypted if the datastore is configured for at-rest encryption.
+    pub fn encode_file<'a>(&self, data: &'a [u8], path: &Path) -> Result<Cow<'a, [u8]>, Error> {
+        match self.at_rest_key()? {
+            Some(key) => Ok(Cow::Owned(key.encrypt_file(data, self.file_path(path))?)),
+            None => Ok(Cow::Borrowed(data)),
+        }
+    }
+
+    /// Decodes the content of the snapshot file stored at `path`.
+    ///
+    /// Files written before at-rest encryption got enabled are still stored in plain.
+    pub fn decode_file(&self, data: Vec<u8>, path: &Path) -> Result<Vec<u8>, Error> {
+        if !is_encrypted_file_at_rest(&data) {
+            return Ok(data);
+        }
+        match self.at_rest_key()? {
+            Some(key) => key.decrypt_file(&data, self.file_path(path)),
+            None => bail!(
+                "file {path:?} on store '{}' is encrypted at rest, but no encryption is configured",
+                self.name,
+            ),
+        }
+    }
+
+    /// Encrypts the plain snapshot file at `path` in place, if the datastore is configured for
+    /// at-rest encryption.
+    ///
+    /// Used for files written piecewise, like indexes, before they get renamed to their final
+    /// name `target`, which the encrypted content is bound to. Returns true if the file got
+    /// encrypted.
+    pub fn encrypt_file_in_place(&self, path: &Path, target: &Path) -> Result<bool, Error> {
+        let key = match self.at_rest_key()? {
+            Some(key) => key,
+            None => return Ok(false),
+        };
+
+        let data = std::fs::read(path)?;
+        if is_encrypted_file_at_rest(&data) {
+            return Ok(false);
+        }
+        let data = key.encrypt_file(&data, self.file_path(target))?;
+
+        proxmox_sys::fs::replace_file(
+            path,
+            &data,
+            CreateOptions::new(),
+            self.sync_level == DatastoreFSyncLevel::File,
+        )
+        .map_err(|err| format_err!("unable to encrypt {path:?} at rest - {err}"))?;
+
+        Ok(true)
+    }
+
+    /// Returns a file with the plain content of `file`, the opened snapshot file at `path`.
+    ///
+    /// At-rest encrypted files get decrypted into an anonymous memory file, so that the plain
+    /// content never gets written to disk. Plain files are returned as they are.
+    pub fn decode_file_handle(&self, mut file: File, path: &Path) -> Result<File, Error> {
+        let mut magic = [0u8; 8];
+        let encrypted = match file.read_exact(&mut magic) {
+            Ok(()) => magic == AT_REST_ENCRYPTED_FILE_MAGIC_1_0,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => false,
+            Err(err) => return Err(err.into()),
+        };
+        file.seek(SeekFrom::Start(0))?;
+        if !encrypted {
+            return Ok(file);
+        }
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let data = self.decode_file(data, path)?;
+
+        let fd = unsafe {
+            libc::memfd_create(
+                b"pbs-at-rest\0".as_ptr() as *const libc::c_char,
+                libc::MFD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            bail!(
+                "unable to create memfd - {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        let mut plain = unsafe { File::from_raw_fd(fd) };
+        plain.write_all(&data)?;
+        plain.seek(SeekFrom::Start(0))?;
+
+        Ok(plain)
+    }
+
+    /// Opens the snapshot file at `path` for reading its plain content.
+    pub fn open_file(&self, path: &Path) -> Result<File, Error> {
+        self.decode_file_handle(File::open(path)?, path)
+    }
+
+    // At-rest encrypted files are bound to their path relative to the base directory, so that
+    // they cannot be swapped with each other.
+    fn file_path<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.base).unwrap_or(path)
+    }
+
     pub fn touch_chunk(&self, digest: &[u8; 32]) -> Result<(), Error> {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
@@ -203,6 +482,15 @@ impl ChunkStore {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
 
+        let (chunk_path, _digest_str) = self.chunk_path(digest);
+        if self.shards.read().unwrap().len() == 1 {
+            return self.cond_touch_path(&chunk_path, assert_exists);
+        }
+
+        if self.cond_touch_path(&chunk_path, false)? {
+            return Ok(true);
+        }
+        // the chunk might just have been moved to another shard by a rebalance
         let (chunk_path, _digest_str) = self.chunk_path(digest);
         self.cond_touch_path(&chunk_path, assert_exists)
     }
@@ -258,14 +546,27 @@ impl ChunkStore {
         use nix::fcntl::OFlag;
         use nix::sys::stat::Mode;
 
-        let base_handle =
-            Dir::open(&self.chunk_dir, OFlag::O_RDONLY, Mode::empty()).map_err(|err| {
-                format_err!(
-                    "unable to open store '{}' chunk dir {:?} - {err}",
-                    self.name,
-                    self.chunk_dir,
-                )
-            })?;
+        let chunk_dirs: Vec<PathBuf> = self
+            .shards
+            .read()
+            .unwrap()
+            .iter()
+            .map(|shard| shard.chunk_dir.clone())
+            .collect();
+        let total = chunk_dirs.len() * 0x10000;
+
+        let base_handles = chunk_dirs
+            .iter()
+            .map(|chunk_dir| {
+                Dir::open(chunk_dir, OFlag::O_RDONLY, Mode::empty()).map_err(|err| {
+                    format_err!(
+                        "unable to open store '{}' chunk dir {:?} - {err}",
+                        self.name,
+                        chunk_dir,
+                    )
+                })
+            })
+            .collect::<Result<Vec<Dir>, Error>>()?;
 
         let mut done = false;
         let mut inner: Option<proxmox_sys::fs::ReadDir> = None;
@@ -304,13 +605,14 @@ impl ChunkStore {
 
                 inner = None;
 
-                if at == 0x10000 {
+                if at == total {
                     done = true;
                     return None;
                 }
 
-                let subdir: &str = &format!("{:04x}", at);
-                percentage = (at * 100) / 0x10000;
+                let base_handle = &base_handles[at / 0x10000];
+                let subdir: &str = &format!("{:04x}", at % 0x10000);
+                percentage = (at * 100) / total;
                 at += 1;
                 match proxmox_sys::fs::read_subdir(base_handle.as_raw_fd(), subdir) {
                     Ok(dir) => {
@@ -343,13 +645,18 @@ impl ChunkStore {
         ProcessLocker::oldest_shared_lock(self.locker.clone().unwrap())
     }
 
+    /// Remove all chunks not accessed since the start of phase 1.
+    ///
+    /// If `marked` is set, chunks marked in it are kept regardless of their atime. Returns the
+    /// number of marked chunks found, so that missing chunks can be detected.
     pub fn sweep_unused_chunks(
         &self,
         oldest_writer: i64,
         phase1_start_time: i64,
+        marked: Option<&ChunkBitmap>,
         status: &mut GarbageCollectionStatus,
         worker: &dyn WorkerTaskContext,
-    ) -> Result<(), Error> {
+    ) -> Result<u64, Error> {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
 
@@ -366,6 +673,7 @@ impl ChunkStore {
 
         let mut last_percentage = 0;
         let mut chunk_count = 0;
+        let mut marked_count = 0;
 
         for (entry, percentage, bad) in self.get_chunk_iterator()? {
             if last_percentage != percentage {
@@ -397,7 +705,18 @@ impl ChunkStore {
 
                 chunk_count += 1;
 
-                if stat.st_atime < min_atime {
+                let in_use = match marked {
+                    Some(marked) => Self::is_marked(marked, dirfd, filename, bad),
+                    None => false,
+                };
+
+                if in_use {
+                    if !bad {
+                        status.disk_chunks += 1;
+                        marked_count += 1;
+                    }
+                    status.disk_bytes += stat.st_size as u64;
+                } else if stat.st_atime < min_atime {
                     //let age = now - stat.st_atime;
                     //println!("UNLINK {}  {:?}", age/(3600*24), filename);
                     if let Err(err) = unlinkat(Some(dirfd), filename, UnlinkatFlags::NoRemoveDir) {
@@ -432,7 +751,38 @@ impl ChunkStore {
             drop(lock);
         }
 
-        Ok(())
+        Ok(marked_count)
+    }
+
+    // Check if the chunk file ``filename`` is marked in ``marked``.
+    //
+    // Like with atime based marking, a .bad file is only kept if the chunk is in use and missing,
+    // so that it can still be used for recovery.
+    fn is_marked(
+        marked: &ChunkBitmap,
+        dirfd: std::os::unix::io::RawFd,
+        filename: &std::ffi::CStr,
+        bad: bool,
+    ) -> bool {
+        let name = &filename.to_bytes()[..64];
+        let mut digest = [0u8; 32];
+        if hex::decode_to_slice(name, &mut digest).is_err() || !marked.contains(&digest) {
+            return false;
+        }
+        if !bad {
+            return true;
+        }
+
+        let chunk_name = match std::ffi::CString::new(name) {
+            Ok(chunk_name) => chunk_name,
+            Err(_) => return false,
+        };
+        let res = nix::sys::stat::fstatat(
+            dirfd,
+            chunk_name.as_c_str(),
+            nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+        );
+        matches!(res, Err(nix::errno::Errno::ENOENT))
     }
 
     pub fn insert_chunk(&self, chunk: &DataBlob, digest: &[u8; 32]) -> Result<(bool, u64), Error> {
@@ -443,10 +793,15 @@ impl ChunkStore {
 
         let (chunk_path, digest_str) = self.chunk_path(digest);
 
+        let at_rest_key = self.at_rest_key()?;
+
         let lock = self.mutex.lock();
 
         let raw_data = chunk.raw_data();
-        let encoded_size = raw_data.len() as u64;
+        let mut encoded_size = raw_data.len() as u64;
+        if at_rest_key.is_some() {
+            encoded_size += AT_REST_HEADER_SIZE as u64;
+        }
 
         let name = &self.name;
 
@@ -474,9 +829,14 @@ impl ChunkStore {
             .parent()
             .ok_or_else(|| format_err!("unable to get chunk dir"))?;
 
+        let file_data = match at_rest_key {
+            Some(key) => Cow::Owned(key.encrypt_chunk(raw_data, digest)?),
+            None => Cow::Borrowed(raw_data),
+        };
+
         proxmox_sys::fs::replace_file(
             &chunk_path,
-            raw_data,
+            &file_data,
             CreateOptions::new(),
             self.sync_level == DatastoreFSyncLevel::File,
         )
@@ -496,18 +856,310 @@ impl ChunkStore {
         Ok((false, encoded_size))
     }
 
+    /// Returns the path of the chunk, and its digest as hex string.
+    ///
+    /// For sharded chunk stores this is where the chunk is currently stored, or, if it does not
+    /// exist, the path on the shard the chunk belongs to.
     pub fn chunk_path(&self, digest: &[u8; 32]) -> (PathBuf, String) {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
 
-        let mut chunk_path = self.chunk_dir.clone();
-        let prefix = digest_to_prefix(digest);
-        chunk_path.push(&prefix);
+        let shards = self.shards.read().unwrap();
         let digest_str = hex::encode(digest);
-        chunk_path.push(&digest_str);
+        let prefix = digest_to_prefix(digest);
+        let path_on = |shard: &ChunkShard| {
+            let mut chunk_path = shard.chunk_dir.clone();
+            chunk_path.push(&prefix);
+            chunk_path.push(&digest_str);
+            chunk_path
+        };
+
+        let index = Self::shard_index(&shards, digest);
+        let chunk_path = path_on(&shards[index]);
+        if shards.len() == 1 || chunk_path.symlink_metadata().is_ok() {
+            return (chunk_path, digest_str);
+        }
+
+        // not moved to its shard yet
+        for (i, shard) in shards.iter().enumerate() {
+            if i == index {
+                continue;
+            }
+            let other_path = path_on(shard);
+            if other_path.symlink_metadata().is_ok() {
+                return (other_path, digest_str);
+            }
+        }
+
         (chunk_path, digest_str)
     }
 
+    /// Calls `access` with the path of the chunk, see [`chunk_path`](Self::chunk_path).
+    ///
+    /// For sharded chunk stores the lookup is retried once if the chunk is not found, as a
+    /// rebalance might just have moved it to another shard.
+    pub fn access_chunk<T>(
+        &self,
+        digest: &[u8; 32],
+        access: impl Fn(&Path) -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        let (chunk_path, _digest_str) = self.chunk_path(digest);
+        match access(&chunk_path) {
+            Err(err)
+                if err.kind() == std::io::ErrorKind::NotFound
+                    && self.shards.read().unwrap().len() > 1 =>
+            {
+                let (chunk_path, _digest_str) = self.chunk_path(digest);
+                access(&chunk_path)
+            }
+            result => result,
+        }
+    }
+
+    fn shard_index(shards: &[ChunkShard], digest: &[u8; 32]) -> usize {
+        if shards.len() == 1 {
+            return 0;
+        }
+        shards
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, shard)| shard.score(digest))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    /// Moves all chunks (and .bad files) which are not stored on the shard they belong to.
+    ///
+    /// Needed after adding a shard, until then those chunks need an extra lookup.
+    pub fn rebalance(&self, worker: &dyn WorkerTaskContext) -> Result<(), Error> {
+        let backup_user = pbs_config::backup_user()?;
+        let options = CreateOptions::new()
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        self.rebalance_shards(worker, options)
+    }
+
+    // `options` are used for the moved chunk files
+    fn rebalance_shards(
+        &self,
+        worker: &dyn WorkerTaskContext,
+        options: CreateOptions,
+    ) -> Result<(), Error> {
+        // unwrap: only `None` in unit tests
+        assert!(self.locker.is_some());
+
+        let shards: Vec<(PathBuf, PathBuf)> = self
+            .shards
+            .read()
+            .unwrap()
+            .iter()
+            .map(|shard| (shard.path.clone(), shard.chunk_dir.clone()))
+            .collect();
+
+        if shards.len() == 1 {
+            task_log!(worker, "chunk store '{}' is not sharded", self.name);
+            return Ok(());
+        }
+
+        let mut moved_chunks = 0;
+        let mut moved_bytes = 0;
+
+        for (path, chunk_dir) in shards.iter() {
+            task_log!(worker, "rebalancing chunks on {path:?}");
+            let mut last_percentage = 0;
+
+            for i in 0..0x10000 {
+                let percentage = (i * 100) / 0x10000;
+                if percentage != last_percentage {
+                    task_log!(
+                        worker,
+                        "processed {percentage}% ({moved_chunks} chunks moved)"
+                    );
+                    last_percentage = percentage;
+                }
+
+                worker.check_abort()?;
+                worker.fail_on_shutdown()?;
+
+                let subdir = chunk_dir.join(format!("{i:04x}"));
+                let entries = match std::fs::read_dir(&subdir) {
+                    Ok(entries) => entries,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => bail!("unable to read chunk dir {subdir:?} - {err}"),
+                };
+
+                for entry in entries {
+                    let entry = entry?;
+                    let file_name = entry.file_name();
+                    let name = match file_name.to_str() {
+                        Some(name) if name.len() == 64 || name.len() == 64 + ".0.bad".len() => name,
+                        _ => continue,
+                    };
+                    let mut digest = [0u8; 32];
+                    if hex::decode_to_slice(&name[..64], &mut digest).is_err() {
+                        continue;
+                    }
+
+                    let target = {
+                        let shards = self.shards.read().unwrap();
+                        let index = Self::shard_index(&shards, &digest);
+                        shards[index].chunk_dir.clone()
+                    };
+                    if target == *chunk_dir {
+                        continue;
+                    }
+
+                    let mut target_path = target;
+                    target_path.push(digest_to_prefix(&digest));
+                    target_path.push(name);
+
+                    moved_bytes +=
+                        self.move_chunk_file(&entry.path(), &target_path, options.clone())?;
+                    moved_chunks += 1;
+                }
+            }
+        }
+
+        task_log!(
+            worker,
+            "moved {moved_chunks} chunks ({})",
+            pbs_api_types::HumanByte::from(moved_bytes),
+        );
+
+        Ok(())
+    }
+
+    /// Encrypts all chunks which are still stored in plain, after at-rest encryption got set up.
+    pub fn encrypt_chunks(&self, worker: &dyn WorkerTaskContext) -> Result<(), Error> {
+        // unwrap: only `None` in unit tests
+        assert!(self.locker.is_some());
+
+        let key = match self.at_rest_key()? {
+            Some(key) => key,
+            None => bail!(
+                "chunk store '{}' has no at-rest encryption set up",
+                self.name
+            ),
+        };
+
+        let chunk_dirs: Vec<PathBuf> = self
+            .shards
+            .read()
+            .unwrap()
+            .iter()
+            .map(|shard| shard.chunk_dir.clone())
+            .collect();
+
+        let mut encrypted_chunks = 0;
+
+        for chunk_dir in chunk_dirs.iter() {
+            task_log!(worker, "encrypting chunks in {chunk_dir:?}");
+            let mut last_percentage = 0;
+
+            for i in 0..0x10000 {
+                let percentage = (i * 100) / 0x10000;
+                if percentage != last_percentage {
+                    task_log!(
+                        worker,
+                        "processed {percentage}% ({encrypted_chunks} chunks encrypted)"
+                    );
+                    last_percentage = percentage;
+                }
+
+                worker.check_abort()?;
+                worker.fail_on_shutdown()?;
+
+                let subdir = chunk_dir.join(format!("{i:04x}"));
+                let entries = match std::fs::read_dir(&subdir) {
+                    Ok(entries) => entries,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => bail!("unable to read chunk dir {subdir:?} - {err}"),
+                };
+
+                for entry in entries {
+                    let entry = entry?;
+                    let file_name = entry.file_name();
+                    // bad chunks are kept as they are
+                    let name = match file_name.to_str() {
+                        Some(name) if name.len() == 64 => name,
+                        _ => continue,
+                    };
+                    let mut digest = [0u8; 32];
+                    if hex::decode_to_slice(name, &mut digest).is_err() {
+                        continue;
+                    }
+
+                    if self.encrypt_chunk_file(&key, &entry.path(), &digest)? {
+                        encrypted_chunks += 1;
+                    }
+                }
+            }
+        }
+
+        task_log!(worker, "encrypted {encrypted_chunks} chunks");
+
+        Ok(())
+    }
+
+    // Encrypts a plain chunk file in place, returns false if there was nothing to encrypt.
+    fn encrypt_chunk_file(
+        &self,
+        key: &AtRestKey,
+        path: &Path,
+        digest: &[u8; 32],
+    ) -> Result<bool, Error> {
+        let _lock = self.mutex.lock();
+
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            // removed by a concurrent garbage collection
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => bail!("unable to read chunk {path:?} - {err}"),
+        };
+        if data.is_empty() || is_encrypted_at_rest(&data) {
+            return Ok(false);
+        }
+        let data = key.encrypt_chunk(&data, digest)?;
+
+        proxmox_sys::fs::replace_file(
+            path,
+            &data,
+            CreateOptions::new(),
+            self.sync_level == DatastoreFSyncLevel::File,
+        )
+        .map_err(|err| format_err!("unable to encrypt chunk {path:?} at rest - {err}"))?;
+
+        Ok(true)
+    }
+
+    // Moves a chunk file to another shard, which usually is on another file system.
+    fn move_chunk_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        options: CreateOptions,
+    ) -> Result<u64, Error> {
+        let _lock = self.mutex.lock();
+
+        let data = match std::fs::read(source) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => bail!("unable to read chunk {source:?} - {err}"),
+        };
+
+        // the chunk might have been inserted on its shard meanwhile
+        if target.symlink_metadata().is_err() {
+            proxmox_sys::fs::replace_file(target, &data, options, true)
+                .map_err(|err| format_err!("unable to write chunk {target:?} - {err}"))?;
+        }
+
+        std::fs::remove_file(source)
+            .map_err(|err| format_err!("unable to remove chunk {source:?} - {err}"))?;
+
+        Ok(data.len() as u64)
+    }
+
     pub fn relative_path(&self, path: &Path) -> PathBuf {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
@@ -528,6 +1180,11 @@ impl ChunkStore {
         self.base.clone()
     }
 
+    /// Mutex serializing chunk insertion and removal.
+    pub(crate) fn mutex(&self) -> &Mutex<()> {
+        &self.mutex
+    }
+
     pub fn try_shared_lock(&self) -> Result<ProcessLockSharedGuard, Error> {
         // unwrap: only `None` in unit tests
         ProcessLocker::try_shared_lock(self.locker.clone().unwrap())
@@ -584,3 +1241,130 @@ fn test_chunk_store1() {
 
     if let Err(_e) = std::fs::remove_dir_all(".testdir") { /* ignore */ }
 }
+
+#[test]
+fn test_chunk_shard_placement() {
+    let shards: Vec<ChunkShard> = ["", "/mnt/disk1", "/mnt/disk2"]
+        .iter()
+        .map(|path| ChunkShard::new(PathBuf::from(path), path.as_bytes()))
+        .collect();
+
+    let digest = |i: u32| -> [u8; 32] { openssl::sha::sha256(&i.to_le_bytes()) };
+
+    let mut counts = [0; 4];
+    let mut moved = 0;
+    let extended: Vec<ChunkShard> = ["", "/mnt/disk1", "/mnt/disk2", "/mnt/disk3"]
+        .iter()
+        .map(|path| ChunkShard::new(PathBuf::from(path), path.as_bytes()))
+        .collect();
+
+    for i in 0..10_000 {
+        let digest = digest(i);
+        let old = ChunkStore::shard_index(&shards, &digest);
+        assert_eq!(old, ChunkStore::shard_index(&shards, &digest));
+
+        let new = ChunkStore::shard_index(&extended, &digest);
+        counts[new] += 1;
+        if old != new {
+            // chunks may only move to the new shard
+            assert_eq!(new, 3);
+            moved += 1;
+        }
+    }
+
+    for count in counts {
+        assert!(
+            (2000..3000).contains(&count),
+            "uneven distribution: {counts:?}"
+        );
+    }
+    assert_eq!(moved, counts[3]);
+}
+
+#[cfg(test)]
+struct TestWorker;
+
+#[cfg(test)]
+impl WorkerTaskContext for TestWorker {
+    fn abort_requested(&self) -> bool {
+        false
+    }
+
+    fn shutdown_requested(&self) -> bool {
+        false
+    }
+
+    fn log(&self, _level: log::Level, _message: &std::fmt::Arguments) {}
+}
+
+#[test]
+fn test_chunk_store_rebalance() {
+    let mut path = std::fs::canonicalize(".").unwrap(); // we need absolute path
+    path.push(".testdir-rebalance");
+
+    if let Err(_e) = std::fs::remove_dir_all(&path) { /* ignore */ }
+
+    let base = path.join("base");
+    let shard = path.join("shard");
+
+    let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())
+        .unwrap()
+        .unwrap();
+    let chunk_store = ChunkStore::create(
+        "test",
+        &base,
+        user.uid,
+        user.gid,
+        None,
+        DatastoreFSyncLevel::None,
+    )
+    .unwrap();
+
+    let chunks: Vec<_> = (0..32u32)
+        .map(|i| {
+            crate::data_blob::DataChunkBuilder::new(&i.to_le_bytes())
+                .build()
+                .unwrap()
+        })
+        .collect();
+    for (chunk, digest) in chunks.iter() {
+        chunk_store.insert_chunk(chunk, digest).unwrap();
+    }
+
+    ChunkStore::create_shard("test", &shard, user.uid, user.gid, None).unwrap();
+    let shard_str = shard.to_str().unwrap().to_string();
+    chunk_store.set_shards(&[shard_str.clone()]).unwrap();
+
+    let shards = vec![
+        ChunkShard::new(base.clone(), b""),
+        ChunkShard::new(shard.clone(), shard_str.as_bytes()),
+    ];
+    let on_new_shard = |digest: &[u8; 32]| ChunkStore::shard_index(&shards, digest) == 1;
+    assert!(chunks.iter().any(|(_, digest)| on_new_shard(digest)));
+
+    // chunks not moved yet are still found on the base
+    for (chunk, digest) in chunks.iter() {
+        let data = chunk_store
+            .access_chunk(digest, |path| std::fs::read(path))
+            .unwrap();
+        assert_eq!(data, chunk.raw_data());
+        assert!(chunk_store.chunk_path(digest).0.starts_with(&base));
+    }
+
+    chunk_store
+        .rebalance_shards(&TestWorker, CreateOptions::new())
+        .unwrap();
+
+    for (chunk, digest) in chunks.iter() {
+        let (chunk_path, _) = chunk_store.chunk_path(digest);
+        let expected = if on_new_shard(digest) { &shard } else { &base };
+        assert!(chunk_path.starts_with(expected), "{chunk_path:?}");
+        assert_eq!(std::fs::read(&chunk_path).unwrap(), chunk.raw_data());
+
+        // inserting again finds the moved chunk
+        let (exists, _) = chunk_store.insert_chunk(chunk, digest).unwrap();
+        assert!(exists);
+    }
+
+    if let Err(_e) = std::fs::remove_dir_all(&path) { /* ignore */ }
+}
@@ -1,3 +1,24 @@
+use anyhow::{bail, Error};
+
+use pbs_api_types::ChunkerType;
+
+/// Content defined chunking algorithm
+///
+/// Implementations keep track of the current chunk and report the position of the next chunk
+/// boundary. The produced boundaries must only depend on the data, not on how the data is split
+/// up into `scan` calls.
+pub trait Chunker: Send {
+    /// Scans the specified data for a chunk border. Returns 0 if none
+    /// was found (and the function should be called with more data
+    /// later on), or another value indicating the position of a
+    /// border.
+    fn scan(&mut self, data: &[u8]) -> usize;
+
+    /// Reset the chunker state, so that the next scanned byte starts a
+    /// new chunk. Used when a chunk boundary is forced from outside.
+    fn reset(&mut self);
+}
+
 /// Note: window size 32 or 64, is faster because we can
 /// speedup modulo operations, but always computes hash 0
 /// for constant data streams .. 0,0,0,0,0,0
@@ -14,8 +35,7 @@ const CA_CHUNKER_WINDOW_SIZE: usize = 64;
 /// information please take a look at the [Rolling
 /// Hash](https://en.wikipedia.org/wiki/Rolling_hash) article from
 /// Wikipedia.
-
-pub struct Chunker {
+pub struct BuzHashChunker {
     h: u32,
     window_size: usize,
     chunk_size: usize,
@@ -67,12 +87,23 @@ const BUZHASH_TABLE: [u32; 256] = [
     0x5eff22f4, 0x6027f4cc, 0x77178b3c, 0xae507131, 0x7bf7cabc, 0xf9c18d66, 0x593ade65, 0xd95ddf11,
 ];
 
-impl Chunker {
+impl BuzHashChunker {
     /// Create a new Chunker instance, which produces and average
     /// chunk size of `chunk_size_avg` (need to be a power of two). We
     /// allow variation from `chunk_size_avg/4` up to a maximum of
     /// `chunk_size_avg*4`.
     pub fn new(chunk_size_avg: usize) -> Self {
+        Self::with_bounds(chunk_size_avg >> 2, chunk_size_avg, chunk_size_avg << 2)
+    }
+
+    /// Create a new Chunker instance with explicit minimal and maximal
+    /// chunk sizes. See the note in the implementation about how other
+    /// bounds than `avg/4` and `avg*4` skew the resulting chunk sizes.
+    pub fn with_bounds(
+        chunk_size_min: usize,
+        chunk_size_avg: usize,
+        chunk_size_max: usize,
+    ) -> Self {
         // The chunk cut discriminator. In order to get an average
         // chunk size of avg, we cut whenever for a hash value "h" at
         // byte "i" given the descriminator "d(avg)": h(i) mod d(avg)
@@ -95,8 +126,8 @@ impl Chunker {
             h: 0,
             window_size: 0,
             chunk_size: 0,
-            chunk_size_min: chunk_size_avg >> 2,
-            chunk_size_max: chunk_size_avg << 2,
+            chunk_size_min,
+            chunk_size_max,
             _chunk_size_avg: chunk_size_avg,
             _discriminator: discriminator,
             break_test_mask,
@@ -105,11 +136,40 @@ impl Chunker {
         }
     }
 
-    /// Scans the specified data for a chunk border. Returns 0 if none
-    /// was found (and the function should be called with more data
-    /// later on), or another value indicating the position of a
-    /// border.
-    pub fn scan(&mut self, data: &[u8]) -> usize {
+    // fast implementation avoiding modulo
+    // #[inline(always)]
+    fn shall_break(&self) -> bool {
+        if self.chunk_size >= self.chunk_size_max {
+            return true;
+        }
+
+        if self.chunk_size < self.chunk_size_min {
+            return false;
+        }
+
+        //(self.h & 0x1ffff) <= 2 //THIS IS SLOW!!!
+
+        //(self.h & self.break_test_mask) <= 2 // Bad on 0 streams
+
+        (self.h & self.break_test_mask) >= self.break_test_minimum
+    }
+
+    // This is the original implementation from casync
+    /*
+    #[inline(always)]
+    fn shall_break_orig(&self) -> bool {
+
+        if self.chunk_size >= self.chunk_size_max { return true; }
+
+        if self.chunk_size < self.chunk_size_min { return false; }
+
+        (self.h % self.discriminator) == (self.discriminator - 1)
+    }
+     */
+}
+
+impl Chunker for BuzHashChunker {
+    fn scan(&mut self, data: &[u8]) -> usize {
         let window_len = self.window.len();
         let data_len = data.len();
 
@@ -167,8 +227,92 @@ impl Chunker {
         0
     }
 
-    // fast implementation avoiding modulo
-    // #[inline(always)]
+    fn reset(&mut self) {
+        self.h = 0;
+        self.chunk_size = 0;
+        self.window_size = 0;
+    }
+}
+
+/// Number of trailing bytes the FastCDC gear hash depends on.
+const FASTCDC_HASH_WINDOW: usize = 64;
+
+/// Gear table used by [`FastCdcChunker`]
+///
+/// Generated with splitmix64 from a fixed seed. The table defines where chunk boundaries are
+/// placed, so it must never change, or existing archives lose their deduplication.
+const FASTCDC_GEAR_TABLE: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x7062_735f_6661_7374; // "pbs_fast"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Gear hash based chunker (FastCDC)
+///
+/// Implements the chunking scheme from "FastCDC: a Fast and Efficient Content-Defined
+/// Chunking Approach for Data Deduplication" (Xia et al., USENIX ATC 2016). The gear hash
+/// only needs a shift and an add per byte, and bytes which cannot influence the first possible
+/// cut point are not hashed at all.
+///
+/// Normalized chunking uses a stricter mask before reaching the average chunk size and a
+/// looser one afterwards, which keeps the chunk size distribution close to the average.
+pub struct FastCdcChunker {
+    hash: u64,
+    chunk_size: usize,
+
+    chunk_size_min: usize,
+    chunk_size_avg: usize,
+    chunk_size_max: usize,
+
+    mask_small: u64,
+    mask_large: u64,
+}
+
+impl FastCdcChunker {
+    /// Create a new FastCDC chunker with an average chunk size of
+    /// `chunk_size_avg` (need to be a power of two), allowing chunks
+    /// from `chunk_size_avg/4` up to `chunk_size_avg*4`.
+    pub fn new(chunk_size_avg: usize) -> Self {
+        Self::with_bounds(chunk_size_avg >> 2, chunk_size_avg, chunk_size_avg << 2)
+    }
+
+    /// Create a new FastCDC chunker with explicit minimal and maximal
+    /// chunk sizes.
+    pub fn with_bounds(
+        chunk_size_min: usize,
+        chunk_size_avg: usize,
+        chunk_size_max: usize,
+    ) -> Self {
+        if chunk_size_avg.count_ones() != 1 || chunk_size_avg < 16 {
+            panic!("got unexpected chunk size - not a power of two.");
+        }
+
+        // the mask bits are taken from the top of the hash, as those
+        // depend on the most input bytes
+        let bits = chunk_size_avg.trailing_zeros();
+        let mask_small = !0u64 << (64 - (bits + 2));
+        let mask_large = !0u64 << (64 - (bits - 2));
+
+        Self {
+            hash: 0,
+            chunk_size: 0,
+            chunk_size_min,
+            chunk_size_avg,
+            chunk_size_max,
+            mask_small,
+            mask_large,
+        }
+    }
+
     fn shall_break(&self) -> bool {
         if self.chunk_size >= self.chunk_size_max {
             return true;
@@ -178,29 +322,146 @@ impl Chunker {
             return false;
         }
 
-        //(self.h & 0x1ffff) <= 2 //THIS IS SLOW!!!
+        let mask = if self.chunk_size < self.chunk_size_avg {
+            self.mask_small
+        } else {
+            self.mask_large
+        };
 
-        //(self.h & self.break_test_mask) <= 2 // Bad on 0 streams
+        (self.hash & mask) == 0
+    }
+}
 
-        (self.h & self.break_test_mask) >= self.break_test_minimum
+impl Chunker for FastCdcChunker {
+    fn scan(&mut self, data: &[u8]) -> usize {
+        let data_len = data.len();
+
+        let mut pos = 0;
+
+        // skip bytes which do not influence the hash at the minimal chunk size
+        let hash_start = self.chunk_size_min.saturating_sub(FASTCDC_HASH_WINDOW);
+        if self.chunk_size < hash_start {
+            let skip = (hash_start - self.chunk_size).min(data_len);
+            self.chunk_size += skip;
+            pos += skip;
+        }
+
+        while pos < data_len {
+            self.hash = (self.hash << 1).wrapping_add(FASTCDC_GEAR_TABLE[data[pos] as usize]);
+            self.chunk_size += 1;
+            pos += 1;
+
+            if self.shall_break() {
+                self.reset();
+                return pos;
+            }
+        }
+
+        0
     }
 
-    // This is the original implementation from casync
-    /*
-    #[inline(always)]
-    fn shall_break_orig(&self) -> bool {
+    fn reset(&mut self) {
+        self.hash = 0;
+        self.chunk_size = 0;
+    }
+}
 
-        if self.chunk_size >= self.chunk_size_max { return true; }
+/// Smallest supported minimal chunk size, the buzhash window must fit into a chunk.
+const CHUNK_SIZE_LOWER_LIMIT: usize = CA_CHUNKER_WINDOW_SIZE;
+/// Largest supported maximal chunk size.
+const CHUNK_SIZE_UPPER_LIMIT: usize = 16 * 1024 * 1024;
 
-        if self.chunk_size < self.chunk_size_min { return false; }
+/// Chunker algorithm together with its chunk size bounds
+///
+/// This is recorded in the header of dynamic indexes, so that later backups can split the
+/// data the same way and keep deduplicating against the previous snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkerConfig {
+    pub ty: ChunkerType,
+    pub min: usize,
+    pub avg: usize,
+    pub max: usize,
+}
 
-        (self.h % self.discriminator) == (self.discriminator - 1)
+impl Default for ChunkerConfig {
+    /// Buzhash with 4 MiB average chunk size, as used before the chunker became selectable.
+    fn default() -> Self {
+        let avg = 4 * 1024 * 1024;
+        Self {
+            ty: ChunkerType::Buzhash,
+            min: avg >> 2,
+            avg,
+            max: avg << 2,
+        }
     }
-     */
 }
 
-#[test]
-fn test_chunker1() {
+impl ChunkerConfig {
+    /// Use the default bounds of `avg/4` and `avg*4`.
+    pub fn new(ty: ChunkerType, avg: usize) -> Result<Self, Error> {
+        Self::with_bounds(ty, avg >> 2, avg, avg << 2)
+    }
+
+    pub fn with_bounds(ty: ChunkerType, min: usize, avg: usize, max: usize) -> Result<Self, Error> {
+        if avg.count_ones() != 1 {
+            bail!("average chunk size {avg} is not a power of two");
+        }
+        if min < CHUNK_SIZE_LOWER_LIMIT || max > CHUNK_SIZE_UPPER_LIMIT {
+            bail!(
+                "chunk size bounds {min}..{max} outside of supported range \
+                {CHUNK_SIZE_LOWER_LIMIT}..{CHUNK_SIZE_UPPER_LIMIT}"
+            );
+        }
+        if min > avg || avg > max {
+            bail!("chunk size bounds {min}/{avg}/{max} are not ordered (min/avg/max)");
+        }
+        Ok(Self { ty, min, avg, max })
+    }
+
+    /// Create a new chunker instance for this configuration.
+    pub fn create(&self) -> Box<dyn Chunker> {
+        match self.ty {
+            ChunkerType::Buzhash => {
+                Box::new(BuzHashChunker::with_bounds(self.min, self.avg, self.max))
+            }
+            ChunkerType::Fastcdc => {
+                Box::new(FastCdcChunker::with_bounds(self.min, self.avg, self.max))
+            }
+        }
+    }
+
+    /// On-disk representation as used in the dynamic index header.
+    ///
+    /// Layout: chunker type (u8, 1 = buzhash, 2 = fastcdc), 3 reserved bytes,
+    /// min, avg and max chunk size (u32, little endian). All zero means unknown.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut data = [0u8; 16];
+        data[0] = match self.ty {
+            ChunkerType::Buzhash => 1,
+            ChunkerType::Fastcdc => 2,
+        };
+        data[4..8].copy_from_slice(&(self.min as u32).to_le_bytes());
+        data[8..12].copy_from_slice(&(self.avg as u32).to_le_bytes());
+        data[12..16].copy_from_slice(&(self.max as u32).to_le_bytes());
+        data
+    }
+
+    /// Parse the on-disk representation, returns `None` for indexes
+    /// written without chunker information or by an unknown chunker.
+    pub fn from_bytes(data: &[u8; 16]) -> Option<Self> {
+        let ty = match data[0] {
+            1 => ChunkerType::Buzhash,
+            2 => ChunkerType::Fastcdc,
+            _ => return None,
+        };
+        let read_u32 =
+            |start: usize| u32::from_le_bytes(data[start..start + 4].try_into().unwrap()) as usize;
+        Self::with_bounds(ty, read_u32(4), read_u32(8), read_u32(12)).ok()
+    }
+}
+
+#[cfg(test)]
+fn pseudo_random_data() -> Vec<u8> {
     let mut buffer = Vec::new();
 
     for i in 0..(256 * 1024) {
@@ -209,7 +470,13 @@ fn test_chunker1() {
             buffer.push(byte);
         }
     }
-    let mut chunker = Chunker::new(64 * 1024);
+    buffer
+}
+
+// chunk boundaries must not depend on how the data is fed into the chunker
+#[cfg(test)]
+fn check_feed_granularity(buffer: &[u8], create: impl Fn() -> Box<dyn Chunker>) -> Vec<usize> {
+    let mut chunker = create();
 
     let mut pos = 0;
     let mut last = 0;
@@ -229,7 +496,7 @@ fn test_chunker1() {
     }
     chunks1.push((last, buffer.len() - last));
 
-    let mut chunker = Chunker::new(64 * 1024);
+    let mut chunker = create();
 
     let mut pos = 0;
 
@@ -259,13 +526,55 @@ fn test_chunker1() {
         }
         println!("Chunks2:{}\n{:?}\n", size2, chunks2);
 
-        if size1 != 256 * 4 * 1024 {
+        if size1 != buffer.len() {
             panic!("wrong size for chunks1");
         }
-        if size2 != 256 * 4 * 1024 {
+        if size2 != buffer.len() {
             panic!("wrong size for chunks2");
         }
 
         panic!("got different chunks");
     }
+
+    chunks1.into_iter().map(|(_offset, len)| len).collect()
+}
+
+#[test]
+fn test_chunker1() {
+    let buffer = pseudo_random_data();
+    check_feed_granularity(&buffer, || Box::new(BuzHashChunker::new(64 * 1024)));
+}
+
+#[test]
+fn test_fastcdc_chunker() {
+    let buffer = pseudo_random_data();
+    let sizes = check_feed_granularity(&buffer, || Box::new(FastCdcChunker::new(64 * 1024)));
+
+    assert!(sizes.len() > 1, "no chunk boundary found");
+    let (last, complete) = sizes.split_last().unwrap();
+    assert!(*last <= 256 * 1024);
+    for size in complete {
+        assert!(
+            (16 * 1024..=256 * 1024).contains(size),
+            "chunk size {size} out of bounds"
+        );
+    }
+}
+
+#[test]
+fn test_chunker_config() -> Result<(), Error> {
+    for ty in [ChunkerType::Buzhash, ChunkerType::Fastcdc] {
+        let config = ChunkerConfig::with_bounds(ty, 128 * 1024, 1024 * 1024, 2 * 1024 * 1024)?;
+        assert_eq!(ChunkerConfig::from_bytes(&config.to_bytes()), Some(config));
+    }
+
+    assert_eq!(ChunkerConfig::from_bytes(&[0u8; 16]), None);
+    assert_eq!(
+        ChunkerConfig::new(ChunkerType::Buzhash, 4 * 1024 * 1024)?,
+        ChunkerConfig::default()
+    );
+    assert!(ChunkerConfig::new(ChunkerType::Fastcdc, 3 * 1024 * 1024).is_err());
+    assert!(ChunkerConfig::with_bounds(ChunkerType::Fastcdc, 8192, 4096, 65536).is_err());
+
+    Ok(())
 }
@@ -1,6 +1,7 @@
-use std::io::Write;
+use std::io::{Read, Write};
+use std::sync::Arc;
 
-use anyhow::{bail, Error};
+use anyhow::{bail, format_err, Error};
 use openssl::symm::{decrypt_aead, Mode};
 
 use proxmox_io::{ReadExt, WriteExt};
@@ -9,9 +10,31 @@ use pbs_api_types::CryptMode;
 use pbs_tools::crypt_config::CryptConfig;
 
 use super::file_formats::*;
+use super::zstd_dictionary::{lookup_dictionary, ZstdDictionary};
 
 const MAX_BLOB_SIZE: usize = 128 * 1024 * 1024;
 
+/// zstd level used if nothing else is configured.
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 1;
+
+/// Compression settings for encoding blobs
+#[derive(Clone)]
+pub struct Compression {
+    /// zstd compression level
+    pub level: i32,
+    /// Trained dictionary, only used for unencrypted data
+    pub dictionary: Option<Arc<ZstdDictionary>>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_COMPRESSION_LEVEL,
+            dictionary: None,
+        }
+    }
+}
+
 /// Encoded data chunk with digest and positional information
 pub struct ChunkInfo {
     pub chunk: DataBlob,
@@ -88,6 +111,16 @@ impl DataBlob {
         data: &[u8],
         config: Option<&CryptConfig>,
         compress: bool,
+    ) -> Result<Self, Error> {
+        let compression = Compression::default();
+        Self::encode_with_compression(data, config, compress.then_some(&compression))
+    }
+
+    /// Create a DataBlob, compressed with the given settings and optionally encrypted
+    pub fn encode_with_compression(
+        data: &[u8],
+        config: Option<&CryptConfig>,
+        compression: Option<&Compression>,
     ) -> Result<Self, Error> {
         if data.len() > MAX_BLOB_SIZE {
             bail!("data blob too large ({} bytes).", data.len());
@@ -95,8 +128,8 @@ impl DataBlob {
 
         let mut blob = if let Some(config) = config {
             let compr_data;
-            let (_compress, data, magic) = if compress {
-                compr_data = zstd::block::compress(data, 1)?;
+            let (_compress, data, magic) = if let Some(compression) = compression {
+                compr_data = zstd::block::compress(data, compression.level)?;
                 // Note: We only use compression if result is shorter
                 if compr_data.len() < data.len() {
                     (true, &compr_data[..], ENCR_COMPR_BLOB_MAGIC_1_0)
@@ -137,7 +170,37 @@ impl DataBlob {
             DataBlob { raw_data }
         } else {
             let max_data_len = data.len() + std::mem::size_of::<DataBlobHeader>();
-            if compress {
+            if let Some(dictionary) = compression.and_then(|c| c.dictionary.as_ref()) {
+                let level = compression.unwrap().level;
+                let mut comp_data = Vec::with_capacity(max_data_len);
+
+                let head = DictDataBlobHeader {
+                    head: DataBlobHeader {
+                        magic: DICT_COMPR_BLOB_MAGIC_1_0,
+                        crc: [0; 4],
+                    },
+                    dict_id: dictionary.id().to_le_bytes(),
+                };
+                unsafe {
+                    comp_data.write_le_value(head)?;
+                }
+
+                let mut encoder = zstd::stream::write::Encoder::with_dictionary(
+                    comp_data,
+                    level,
+                    dictionary.data(),
+                )?;
+                encoder.write_all(data)?;
+                let comp_data = encoder.finish()?;
+
+                if comp_data.len() < max_data_len {
+                    let mut blob = DataBlob {
+                        raw_data: comp_data,
+                    };
+                    blob.set_crc(blob.compute_crc());
+                    return Ok(blob);
+                }
+            } else if let Some(compression) = compression {
                 let mut comp_data = Vec::with_capacity(max_data_len);
 
                 let head = DataBlobHeader {
@@ -148,7 +211,7 @@ impl DataBlob {
                     comp_data.write_le_value(head)?;
                 }
 
-                zstd::stream::copy_encode(data, &mut comp_data, 1)?;
+                zstd::stream::copy_encode(data, &mut comp_data, compression.level)?;
 
                 if comp_data.len() < max_data_len {
                     let mut blob = DataBlob {
@@ -183,7 +246,10 @@ impl DataBlob {
         let magic = self.magic();
 
         Ok(
-            if magic == &UNCOMPRESSED_BLOB_MAGIC_1_0 || magic == &COMPRESSED_BLOB_MAGIC_1_0 {
+            if magic == &UNCOMPRESSED_BLOB_MAGIC_1_0
+                || magic == &COMPRESSED_BLOB_MAGIC_1_0
+                || magic == &DICT_COMPR_BLOB_MAGIC_1_0
+            {
                 CryptMode::None
             } else if magic == &ENCR_COMPR_BLOB_MAGIC_1_0 || magic == &ENCRYPTED_BLOB_MAGIC_1_0 {
                 CryptMode::Encrypt
@@ -194,10 +260,33 @@ impl DataBlob {
     }
 
     /// Decode blob data
+    ///
+    /// Blobs compressed with a zstd dictionary need
+    /// [`decode_for_store`](Self::decode_for_store).
     pub fn decode(
         &self,
         config: Option<&CryptConfig>,
         digest: Option<&[u8; 32]>,
+    ) -> Result<Vec<u8>, Error> {
+        self.decode_with_dictionaries(None, config, digest)
+    }
+
+    /// Decode blob data of the datastore (or, on clients, the repository) `store`, using its
+    /// registered zstd dictionaries.
+    pub fn decode_for_store(
+        &self,
+        store: &str,
+        config: Option<&CryptConfig>,
+        digest: Option<&[u8; 32]>,
+    ) -> Result<Vec<u8>, Error> {
+        self.decode_with_dictionaries(Some(store), config, digest)
+    }
+
+    fn decode_with_dictionaries(
+        &self,
+        store: Option<&str>,
+        config: Option<&CryptConfig>,
+        digest: Option<&[u8; 32]>,
     ) -> Result<Vec<u8>, Error> {
         let magic = self.magic();
 
@@ -218,6 +307,24 @@ impl DataBlob {
                 Self::verify_digest(&data, None, digest)?;
             }
             Ok(data)
+        } else if magic == &DICT_COMPR_BLOB_MAGIC_1_0 {
+            let id = self.zstd_dictionary_id().unwrap();
+            let dictionary = store
+                .and_then(|store| lookup_dictionary(store, id))
+                .ok_or_else(|| {
+                    format_err!("unable to decode blob - unknown zstd dictionary {id:08x}")
+                })?;
+            let data_start = std::mem::size_of::<DictDataBlobHeader>();
+            let mut decoder = zstd::stream::read::Decoder::with_dictionary(
+                &self.raw_data[data_start..],
+                dictionary.data(),
+            )?;
+            let mut data = Vec::new();
+            decoder.read_to_end(&mut data)?;
+            if let Some(digest) = digest {
+                Self::verify_digest(&data, None, digest)?;
+            }
+            Ok(data)
         } else if magic == &ENCR_COMPR_BLOB_MAGIC_1_0 || magic == &ENCRYPTED_BLOB_MAGIC_1_0 {
             let header_len = std::mem::size_of::<EncryptedDataBlobHeader>();
             let head = unsafe {
@@ -283,6 +390,17 @@ impl DataBlob {
         } else if magic == COMPRESSED_BLOB_MAGIC_1_0 || magic == UNCOMPRESSED_BLOB_MAGIC_1_0 {
             let blob = DataBlob { raw_data: data };
 
+            Ok(blob)
+        } else if magic == DICT_COMPR_BLOB_MAGIC_1_0 {
+            if data.len() < std::mem::size_of::<DictDataBlobHeader>() {
+                bail!(
+                    "dictionary compressed blob too small ({} bytes).",
+                    data.len()
+                );
+            }
+
+            let blob = DataBlob { raw_data: data };
+
             Ok(blob)
         } else {
             bail!("unable to parse raw blob - wrong magic");
@@ -295,16 +413,50 @@ impl DataBlob {
         magic == &ENCR_COMPR_BLOB_MAGIC_1_0 || magic == &ENCRYPTED_BLOB_MAGIC_1_0
     }
 
+    /// Returns the ID of the zstd dictionary needed to decode the blob, if any
+    pub fn zstd_dictionary_id(&self) -> Option<u32> {
+        if self.magic() != &DICT_COMPR_BLOB_MAGIC_1_0 {
+            return None;
+        }
+        let id_o = proxmox_lang::offsetof!(DictDataBlobHeader, dict_id);
+        Some(u32::from_le_bytes(
+            self.raw_data[id_o..id_o + 4].try_into().unwrap(),
+        ))
+    }
+
     /// Verify digest and data length for unencrypted chunks.
     ///
     /// To do that, we need to decompress data first. Please note that
     /// this is not possible for encrypted chunks. This function simply return Ok
     /// for encrypted chunks.
     /// Note: This does not call verify_crc, because this is usually done in load
+    ///
+    /// Chunks compressed with a zstd dictionary need
+    /// [`verify_unencrypted_for_store`](Self::verify_unencrypted_for_store).
     pub fn verify_unencrypted(
         &self,
         expected_chunk_size: usize,
         expected_digest: &[u8; 32],
+    ) -> Result<(), Error> {
+        self.verify_unencrypted_with_dictionaries(None, expected_chunk_size, expected_digest)
+    }
+
+    /// Like [`verify_unencrypted`](Self::verify_unencrypted), for chunks of the datastore (or, on
+    /// clients, the repository) `store`, using its registered zstd dictionaries.
+    pub fn verify_unencrypted_for_store(
+        &self,
+        store: &str,
+        expected_chunk_size: usize,
+        expected_digest: &[u8; 32],
+    ) -> Result<(), Error> {
+        self.verify_unencrypted_with_dictionaries(Some(store), expected_chunk_size, expected_digest)
+    }
+
+    fn verify_unencrypted_with_dictionaries(
+        &self,
+        store: Option<&str>,
+        expected_chunk_size: usize,
+        expected_digest: &[u8; 32],
     ) -> Result<(), Error> {
         let magic = self.magic();
 
@@ -313,7 +465,7 @@ impl DataBlob {
         }
 
         // verifies digest!
-        let data = self.decode(None, Some(expected_digest))?;
+        let data = self.decode_with_dictionaries(store, None, Some(expected_digest))?;
 
         if expected_chunk_size != data.len() {
             bail!(
@@ -474,6 +626,7 @@ pub struct DataChunkBuilder<'a, 'b> {
     digest_computed: bool,
     digest: [u8; 32],
     compress: bool,
+    compression: Option<&'b Compression>,
 }
 
 impl<'a, 'b> DataChunkBuilder<'a, 'b> {
@@ -485,17 +638,27 @@ impl<'a, 'b> DataChunkBuilder<'a, 'b> {
             digest_computed: false,
             digest: [0u8; 32],
             compress: true,
+            compression: None,
         }
     }
 
     /// Set compression flag.
     ///
-    /// If true, chunk data is compressed using zstd (level 1).
+    /// If true, chunk data is compressed using zstd (level 1 unless set with
+    /// ``compression``).
     pub fn compress(mut self, value: bool) -> Self {
         self.compress = value;
         self
     }
 
+    /// Set compression level and dictionary
+    ///
+    /// Only used if the compression flag is set.
+    pub fn compression(mut self, value: &'b Compression) -> Self {
+        self.compression = Some(value);
+        self
+    }
+
     /// Set encryption Configuration
     ///
     /// If set, chunks are encrypted
@@ -537,7 +700,13 @@ impl<'a, 'b> DataChunkBuilder<'a, 'b> {
             self.compute_digest();
         }
 
-        let chunk = DataBlob::encode(self.orig_data, self.config, self.compress)?;
+        let default_compression = Compression::default();
+        let compression = match self.compress {
+            true => Some(self.compression.unwrap_or(&default_compression)),
+            false => None,
+        };
+
+        let chunk = DataBlob::encode_with_compression(self.orig_data, self.config, compression)?;
         Ok((chunk, self.digest))
     }
 
@@ -10,6 +10,7 @@ use pbs_tools::crypt_config::CryptConfig;
 use crate::checksum_reader::ChecksumReader;
 use crate::crypt_reader::CryptReader;
 use crate::file_formats::{self, DataBlobHeader};
+use crate::zstd_dictionary::lookup_dictionary;
 
 enum BlobReaderState<'reader, R: Read> {
     Uncompressed {
@@ -43,7 +44,26 @@ pub struct DataBlobReader<'reader, R: Read> {
 unsafe impl<R: Read> Sync for DataBlobReader<'_, R> {}
 
 impl<R: Read> DataBlobReader<'_, R> {
-    pub fn new(mut reader: R, config: Option<Arc<CryptConfig>>) -> Result<Self, Error> {
+    /// Blobs compressed with a zstd dictionary need [`new_for_store`](Self::new_for_store).
+    pub fn new(reader: R, config: Option<Arc<CryptConfig>>) -> Result<Self, Error> {
+        Self::new_with_dictionaries(reader, config, None)
+    }
+
+    /// Read a blob of the datastore (or, on clients, the repository) `store`, using its
+    /// registered zstd dictionaries.
+    pub fn new_for_store(
+        reader: R,
+        config: Option<Arc<CryptConfig>>,
+        store: &str,
+    ) -> Result<Self, Error> {
+        Self::new_with_dictionaries(reader, config, Some(store))
+    }
+
+    fn new_with_dictionaries(
+        mut reader: R,
+        config: Option<Arc<CryptConfig>>,
+        store: Option<&str>,
+    ) -> Result<Self, Error> {
         let head: DataBlobHeader = unsafe { reader.read_le_value()? };
         match head.magic {
             file_formats::UNCOMPRESSED_BLOB_MAGIC_1_0 => {
@@ -68,6 +88,29 @@ impl<R: Read> DataBlobReader<'_, R> {
                     },
                 })
             }
+            file_formats::DICT_COMPR_BLOB_MAGIC_1_0 => {
+                let expected_crc = u32::from_le_bytes(head.crc);
+                let mut dict_id = [0u8; 4];
+                reader.read_exact(&mut dict_id)?;
+                let dict_id = u32::from_le_bytes(dict_id);
+                let dictionary = store
+                    .and_then(|store| lookup_dictionary(store, dict_id))
+                    .ok_or_else(|| {
+                        format_err!("unable to read blob - unknown zstd dictionary {dict_id:08x}")
+                    })?;
+                let csum_reader = ChecksumReader::new(reader, None);
+
+                let decompr = zstd::stream::read::Decoder::with_dictionary(
+                    BufReader::new(csum_reader),
+                    dictionary.data(),
+                )?;
+                Ok(Self {
+                    state: BlobReaderState::Compressed {
+                        expected_crc,
+                        decompr,
+                    },
+                })
+            }
             file_formats::ENCRYPTED_BLOB_MAGIC_1_0 => {
                 let config = config
                     .ok_or_else(|| format_err!("unable to read encrypted blob without key"))?;
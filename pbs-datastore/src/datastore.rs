@@ -1,11 +1,14 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use std::io::{self, Write};
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
+use hex::FromHex;
 use lazy_static::lazy_static;
 use nix::unistd::{unlinkat, UnlinkatFlags};
 
@@ -13,25 +16,39 @@ use proxmox_schema::ApiType;
 
 use proxmox_sys::error::SysError;
 use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
-use proxmox_sys::fs::{lock_dir_noblock, DirLockGuard};
+use proxmox_sys::fs::{lock_dir_noblock, lock_dir_noblock_shared, DirLockGuard};
 use proxmox_sys::process_locker::ProcessLockSharedGuard;
 use proxmox_sys::WorkerTaskContext;
 use proxmox_sys::{task_log, task_warn};
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, ChunkOrder, DataStoreConfig, DatastoreFSyncLevel,
-    DatastoreTuning, GarbageCollectionStatus, HumanByte, Operation, UPID,
+    Authid, BackupNamespace, BackupType, ChunkOrder, ChunkUsage, CryptMode, DataStoreChunkUsage,
+    DataStoreConfig, DataStoreMountStatus, DatastoreBackendConfig, DatastoreBackendType,
+    DatastoreFSyncLevel, DatastoreTuning, GarbageCollectionCheckpoint, GarbageCollectionStatus,
+    GcMarkMode, GroupChunkUsage, HumanByte, NamespaceChunkUsage, Operation, QuotaConfig,
+    QuotaStatus, QuotaUsage, SnapshotChunkUsage, UPID,
 };
+use pbs_tools::crypt_config::CryptConfig;
 
-use crate::backup_info::{BackupDir, BackupGroup};
+use crate::at_rest::AT_REST_HEADER_SIZE;
+use crate::backup_info::{BackupDir, BackupGroup, BackupInfo};
+use crate::chunk_bitmap::ChunkBitmap;
 use crate::chunk_store::ChunkStore;
 use crate::dynamic_index::{DynamicIndexReader, DynamicIndexWriter};
+use crate::file_formats::{
+    DictDataBlobHeader, AT_REST_ENCRYPTED_CHUNK_MAGIC_1_0, DICT_COMPR_BLOB_MAGIC_1_0,
+};
 use crate::fixed_index::{FixedIndexReader, FixedIndexWriter};
 use crate::hierarchy::{ListGroups, ListGroupsType, ListNamespaces, ListNamespacesRecursive};
 use crate::index::IndexFile;
 use crate::manifest::{archive_type, ArchiveType};
+use crate::s3::S3Client;
 use crate::task_tracking::{self, update_active_operations};
-use crate::DataBlob;
+use crate::zstd_dictionary::{
+    list_dictionaries, lookup_dictionary, register_dictionary, register_dictionary_dir,
+    ZstdDictionary, DICTIONARY_DIR,
+};
+use crate::{DataBlob, DataBlobReader};
 
 lazy_static! {
     static ref DATASTORE_MAP: Mutex<HashMap<String, Arc<DataStoreImpl>>> =
@@ -49,6 +66,82 @@ pub fn check_backup_owner(owner: &Authid, auth_id: &Authid) -> Result<(), Error>
     Ok(())
 }
 
+/// Returns the mount status of a datastore.
+///
+/// The backing device of a removable datastore counts as mounted if the filesystem on `path` is
+/// the one of the device with the configured UUID.
+pub fn get_datastore_mount_status(config: &DataStoreConfig) -> DataStoreMountStatus {
+    let uuid = match config.backing_device.as_deref() {
+        Some(uuid) => uuid,
+        None => return DataStoreMountStatus::NonRemovable,
+    };
+
+    let device = Path::new("/dev/disk/by-uuid").join(uuid);
+    match (
+        nix::sys::stat::stat(&device),
+        nix::sys::stat::stat(Path::new(&config.path)),
+    ) {
+        (Ok(device), Ok(mountpoint)) if device.st_rdev == mountpoint.st_dev => {
+            DataStoreMountStatus::Mounted
+        }
+        _ => DataStoreMountStatus::NotMounted,
+    }
+}
+
+/// Fails if the datastore is removable and its backing device is currently not mounted.
+pub fn ensure_datastore_is_mounted(config: &DataStoreConfig) -> Result<(), Error> {
+    match get_datastore_mount_status(config) {
+        DataStoreMountStatus::NotMounted => {
+            bail!("removable datastore '{}' is not mounted", config.name)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Backend the contents of a datastore are stored on
+#[derive(Clone)]
+pub enum DatastoreBackend {
+    /// Local filesystem below the datastore path
+    Filesystem,
+    /// S3 compatible object store, the datastore path holds a local cache of all metadata and of
+    /// recently used chunks
+    S3(Arc<S3Client>),
+}
+
+/// Returns the object key for the chunk with `digest`, mirroring the local chunk store layout.
+fn chunk_object_key(digest: &[u8; 32]) -> String {
+    let digest_str = hex::encode(digest);
+    format!(".chunks/{}/{}", &digest_str[..4], digest_str)
+}
+
+/// Returns the object key for a file below the datastore base path.
+fn object_key(relative_path: &Path) -> Result<String, Error> {
+    relative_path
+        .to_str()
+        .map(|path| path.trim_start_matches('/').to_string())
+        .ok_or_else(|| format_err!("non-utf8 path {relative_path:?} not supported"))
+}
+
+/// Checks whether the locally cached chunk at `chunk_path` got accessed since `cutoff`.
+///
+/// Callers need to hold the chunk store mutex.
+fn cached_chunk_accessed_since(chunk_path: &Path, cutoff: i64) -> bool {
+    match nix::sys::stat::lstat(chunk_path) {
+        Ok(stat) => stat.st_atime >= cutoff,
+        Err(_) => false,
+    }
+}
+
+/// Records the chunks in use during phase 1 of the garbage collection.
+enum GcMarker {
+    /// Update the atime of the chunks in the local chunk store.
+    Atime,
+    /// Collect all digests, the chunks are not necessarily stored locally.
+    Digests(HashSet<[u8; 32]>),
+    /// Collect all digests in a compact bitmap, the sweep phase skips marked chunks.
+    Bitmap(ChunkBitmap),
+}
+
 /// Datastore Management
 ///
 /// A Datastore can store severals backups, and provides the
@@ -61,6 +154,12 @@ pub struct DataStoreImpl {
     chunk_order: ChunkOrder,
     last_digest: Option<[u8; 32]>,
     sync_level: DatastoreFSyncLevel,
+    gc_mark_mode: GcMarkMode,
+    gc_max_runtime: Option<u64>,
+    backup_resume_timeout: Option<u64>,
+    compression_level: Option<i32>,
+    zstd_dictionary: Option<u32>,
+    backend: DatastoreBackend,
 }
 
 impl DataStoreImpl {
@@ -75,6 +174,12 @@ impl DataStoreImpl {
             chunk_order: Default::default(),
             last_digest: None,
             sync_level: Default::default(),
+            gc_mark_mode: Default::default(),
+            gc_max_runtime: None,
+            backup_resume_timeout: None,
+            compression_level: None,
+            zstd_dictionary: None,
+            backend: DatastoreBackend::Filesystem,
         })
     }
 }
@@ -140,6 +245,10 @@ impl DataStore {
             }
         }
 
+        if operation != Some(Operation::Lookup) {
+            ensure_datastore_is_mounted(&config)?;
+        }
+
         if let Some(operation) = operation {
             update_active_operations(name, operation, 1)?;
         }
@@ -171,6 +280,8 @@ impl DataStore {
                 tuning.sync_level.unwrap_or_default(),
             )?)
         };
+        chunk_store.set_shards(&config.chunk_shards()?)?;
+        chunk_store.set_at_rest_encryption(config.encryption.as_deref())?;
 
         let datastore = DataStore::with_store_and_config(chunk_store, config, Some(digest))?;
 
@@ -183,13 +294,27 @@ impl DataStore {
         }))
     }
 
-    /// removes all datastores that are not configured anymore
+    /// removes all datastores that are not configured anymore, and all removable datastores
+    /// that are not mounted or about to be unmounted, so that no open files block unmounting
     pub fn remove_unused_datastores() -> Result<(), Error> {
         let (config, _digest) = pbs_config::datastore::config()?;
 
         let mut map = DATASTORE_MAP.lock().unwrap();
         // removes all elements that are not in the config
-        map.retain(|key, _| config.sections.contains_key(key));
+        map.retain(|key, _| {
+            let store_config: DataStoreConfig = match config.lookup("datastore", key) {
+                Ok(store_config) => store_config,
+                Err(_) => return false,
+            };
+            if !store_config.is_removable() {
+                return true;
+            }
+            let unmounting = store_config
+                .get_maintenance_mode()
+                .map_or(false, |mode| mode.check(Some(Operation::Lookup)).is_err());
+            !unmounting
+                && get_datastore_mount_status(&store_config) == DataStoreMountStatus::Mounted
+        });
         Ok(())
     }
 
@@ -231,6 +356,8 @@ impl DataStore {
         )?;
         let chunk_store =
             ChunkStore::open(&name, &config.path, tuning.sync_level.unwrap_or_default())?;
+        chunk_store.set_shards(&config.chunk_shards()?)?;
+        chunk_store.set_at_rest_encryption(config.encryption.as_deref())?;
         let inner = Arc::new(Self::with_store_and_config(
             Arc::new(chunk_store),
             config,
@@ -269,17 +396,58 @@ impl DataStore {
                 .parse_property_string(config.tuning.as_deref().unwrap_or(""))?,
         )?;
 
+        let zstd_dictionary = tuning
+            .zstd_dictionary
+            .as_deref()
+            .map(|id| u32::from_str_radix(id, 16))
+            .transpose()?;
+
+        let dictionary_dir = chunk_store.base_path().join(DICTIONARY_DIR);
+        if let Err(err) = register_dictionary_dir(&config.name, &dictionary_dir) {
+            log::error!("error loading zstd dictionaries: {}", err);
+        }
+
+        let backend_config = DatastoreBackendConfig::parse(config.backend.as_deref())?;
+        let backend = match backend_config.ty.unwrap_or_default() {
+            DatastoreBackendType::Filesystem => DatastoreBackend::Filesystem,
+            DatastoreBackendType::S3 => {
+                // unwrap: checked when parsing the backend config
+                let client = S3Client::from_client_config(
+                    backend_config.client.as_deref().unwrap(),
+                    backend_config.bucket.as_deref().unwrap(),
+                )?;
+                DatastoreBackend::S3(Arc::new(client))
+            }
+        };
+
+        let chunk_order = match backend {
+            // chunks are not guaranteed to be cached locally, inode order is meaningless
+            DatastoreBackend::S3(_) => ChunkOrder::None,
+            DatastoreBackend::Filesystem => tuning.chunk_order.unwrap_or_default(),
+        };
+
         Ok(DataStoreImpl {
             chunk_store,
             gc_mutex: Mutex::new(()),
             last_gc_status: Mutex::new(gc_status),
             verify_new: config.verify_new.unwrap_or(false),
-            chunk_order: tuning.chunk_order.unwrap_or_default(),
+            chunk_order,
             last_digest,
             sync_level: tuning.sync_level.unwrap_or_default(),
+            gc_mark_mode: tuning.gc_mark_mode.unwrap_or_default(),
+            gc_max_runtime: config.gc_max_runtime,
+            backup_resume_timeout: tuning.backup_resume_timeout,
+            compression_level: tuning.compression_level,
+            zstd_dictionary,
+            backend,
         })
     }
 
+    /// Returns the backend the datastore contents are stored on.
+    pub fn backend(&self) -> &DatastoreBackend {
+        &self.inner.backend
+    }
+
     pub fn get_chunk_iterator(
         &self,
     ) -> Result<
@@ -311,7 +479,12 @@ impl DataStore {
     ) -> Result<FixedIndexReader, Error> {
         let full_path = self.inner.chunk_store.relative_path(filename.as_ref());
 
-        let index = FixedIndexReader::open(&full_path)?;
+        let index = self
+            .inner
+            .chunk_store
+            .open_file(&full_path)
+            .and_then(FixedIndexReader::new)
+            .map_err(|err| format_err!("Unable to open fixed index {:?} - {}", full_path, err))?;
 
         Ok(index)
     }
@@ -331,7 +504,12 @@ impl DataStore {
     ) -> Result<DynamicIndexReader, Error> {
         let full_path = self.inner.chunk_store.relative_path(filename.as_ref());
 
-        let index = DynamicIndexReader::open(&full_path)?;
+        let index = self
+            .inner
+            .chunk_store
+            .open_file(&full_path)
+            .and_then(DynamicIndexReader::new)
+            .map_err(|err| format_err!("Unable to open dynamic index {:?} - {}", full_path, err))?;
 
         Ok(index)
     }
@@ -361,7 +539,7 @@ impl DataStore {
                 continue;
             }
 
-            self.stat_chunk(&info.digest).map_err(|err| {
+            self.check_chunk_exists(&info.digest).map_err(|err| {
                 format_err!(
                     "fast_index_verification error, stat_chunk {} failed - {}",
                     hex::encode(info.digest),
@@ -643,6 +821,12 @@ impl DataStore {
         writeln!(file, "{}", auth_id)
             .map_err(|err| format_err!("unable to write owner file  {:?} - {}", path, err))?;
 
+        let mut relative_path = ns.path();
+        relative_path.push(backup_group.ty.as_str());
+        relative_path.push(&backup_group.id);
+        relative_path.push("owner");
+        self.upload_to_backend(&relative_path)?;
+
         Ok(())
     }
 
@@ -849,6 +1033,137 @@ impl DataStore {
         ListGroups::new(Arc::clone(self), ns)?.collect()
     }
 
+    /// Calculate the space used by a single backup group, or by all backup groups of a namespace
+    /// including its sub-namespaces.
+    ///
+    /// The logical size is the sum of the file sizes recorded in the snapshot manifests. The
+    /// unique size (sum of the stored sizes of all distinct chunks referenced by the indices)
+    /// requires reading every index file, so it is only calculated if `unique` is set.
+    pub fn calculate_usage(
+        self: &Arc<DataStore>,
+        ns: &BackupNamespace,
+        group: Option<&pbs_api_types::BackupGroup>,
+        unique: bool,
+    ) -> Result<QuotaUsage, Error> {
+        self.calculate_usage_since(ns, group, unique, i64::MIN)
+    }
+
+    /// Like [`calculate_usage`](Self::calculate_usage), only counting snapshots with a backup time
+    /// of at least `since`.
+    fn calculate_usage_since(
+        self: &Arc<DataStore>,
+        ns: &BackupNamespace,
+        group: Option<&pbs_api_types::BackupGroup>,
+        unique: bool,
+        since: i64,
+    ) -> Result<QuotaUsage, Error> {
+        let groups: Vec<BackupGroup> = match group {
+            Some(group) => vec![self.backup_group(ns.clone(), group.clone())],
+            None => {
+                let mut groups = Vec::new();
+                for ns in self.recursive_iter_backup_ns_ok(ns.clone(), None)? {
+                    groups.extend(self.iter_backup_groups_ok(ns)?);
+                }
+                groups
+            }
+        };
+
+        let mut logical_size = 0;
+        let mut unique_size = 0;
+        let mut chunks = HashSet::new();
+        let s3_chunk_sizes = match unique {
+            true => self.s3_chunk_sizes()?,
+            false => None,
+        };
+
+        for group in groups {
+            if !group.exists() {
+                continue;
+            }
+            for info in group.list_backups()? {
+                if info.backup_dir.backup_time() < since {
+                    continue;
+                }
+                // skip snapshots still in progress
+                let manifest = match info.backup_dir.load_manifest() {
+                    Ok((manifest, _)) => manifest,
+                    Err(_) => continue,
+                };
+
+                for file in manifest.files() {
+                    logical_size += file.size;
+
+                    if !unique {
+                        continue;
+                    }
+                    match archive_type(&file.filename)? {
+                        ArchiveType::FixedIndex | ArchiveType::DynamicIndex => (),
+                        ArchiveType::Blob => continue,
+                    }
+
+                    let mut path = info.backup_dir.full_path();
+                    path.push(&file.filename);
+                    let index = self.open_index(&path)?;
+
+                    for pos in 0..index.index_count() {
+                        let info = index.chunk_info(pos).unwrap();
+                        if chunks.insert(info.digest) {
+                            unique_size += self
+                                .stored_chunk_size(&info.digest, s3_chunk_sizes.as_ref())
+                                .unwrap_or(0);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(QuotaUsage {
+            logical_size,
+            unique_size: unique.then_some(unique_size),
+        })
+    }
+
+    /// Returns the usage of the quota `config` from the chunk usage calculation `chunk_usage`
+    /// (see [`last_chunk_usage`](Self::last_chunk_usage)), plus the usage of the snapshots
+    /// created since that calculation started.
+    ///
+    /// The chunks of newer snapshots are not deduplicated against older snapshots, and snapshots
+    /// removed since the calculation are still counted, so until the next calculation the result
+    /// is an upper bound. Returns `None` if the quota was not part of the calculation.
+    pub fn cached_quota_usage(
+        self: &Arc<DataStore>,
+        config: &QuotaConfig,
+        chunk_usage: &DataStoreChunkUsage,
+    ) -> Result<Option<QuotaUsage>, Error> {
+        let cached = chunk_usage.quotas.iter().find(|status| {
+            status.config.id == config.id
+                && status.config.store == config.store
+                && status.config.ns == config.ns
+                && status.config.group == config.group
+                && (config.max_unique_size.is_none() || status.usage.unique_size.is_some())
+        });
+        let (cached, since) = match (cached, chunk_usage.start_time) {
+            (Some(status), Some(start_time)) => (&status.usage, start_time),
+            _ => return Ok(None),
+        };
+
+        let unique = config.max_unique_size.is_some();
+        let newer = self.calculate_usage_since(
+            &config.ns.clone().unwrap_or_default(),
+            config.backup_group()?.as_ref(),
+            unique,
+            since,
+        )?;
+
+        Ok(Some(QuotaUsage {
+            logical_size: cached.logical_size + newer.logical_size,
+            unique_size: match unique {
+                true => Some(cached.unique_size.unwrap_or(0) + newer.unique_size.unwrap_or(0)),
+                false => None,
+            },
+        }))
+    }
+
     pub fn list_images(&self) -> Result<Vec<PathBuf>, Error> {
         let base = self.base_path();
 
@@ -909,11 +1224,15 @@ impl DataStore {
     }
 
     // mark chunks  used by ``index`` as used
+    //
+    // With a non-filesystem backend the chunks are recorded in ``marker`` instead of touching the
+    // local cache, so that unused cached chunks still age out.
     fn index_mark_used_chunks<I: IndexFile>(
         &self,
         index: I,
         file_name: &Path, // only used for error reporting
         status: &mut GarbageCollectionStatus,
+        marker: &mut GcMarker,
         worker: &dyn WorkerTaskContext,
     ) -> Result<(), Error> {
         status.index_file_count += 1;
@@ -923,13 +1242,32 @@ impl DataStore {
             worker.check_abort()?;
             worker.fail_on_shutdown()?;
             let digest = index.index_digest(pos).unwrap();
-            if !self.inner.chunk_store.cond_touch_chunk(digest, false)? {
+            if digest == &[0u8; 32] {
+                // unwritten entry of a partial fixed index kept for resuming a backup
+                continue;
+            }
+            let exists = match marker {
+                GcMarker::Atime => self.inner.chunk_store.cond_touch_chunk(digest, false)?,
+                GcMarker::Digests(marked) => {
+                    // missing chunks are reported by the sweep, which lists the bucket anyway
+                    marked.insert(*digest);
+                    continue;
+                }
+                GcMarker::Bitmap(bitmap) => {
+                    // missing chunks are counted by the sweep, .bad files of missing chunks are
+                    // kept there as well
+                    bitmap.insert(digest);
+                    continue;
+                }
+            };
+            if !exists {
                 let hex = hex::encode(digest);
                 task_warn!(
                     worker,
                     "warning: unable to access non-existent chunk {hex}, required by {file_name:?}"
                 );
-
+            }
+            if !exists && matches!(marker, GcMarker::Atime) {
                 // touch any corresponding .bad files to keep them around, meaning if a chunk is
                 // rewritten correctly they will be removed automatically, as well as if no index
                 // file requires the chunk anymore (won't get to this loop then)
@@ -945,22 +1283,56 @@ impl DataStore {
         Ok(())
     }
 
+    // Returns false if the mark phase was stopped because ``deadline`` was reached.
+    //
+    // If ``status`` has a checkpoint, all index files up to its last index are skipped and the
+    // checkpoint is updated and saved regularly, so that an interrupted run can be resumed.
+    // Index files written since the checkpoint's start time are never skipped, as backups do not
+    // touch chunks they reuse from a previous snapshot.
     fn mark_used_chunks(
         &self,
         status: &mut GarbageCollectionStatus,
+        marker: &mut GcMarker,
+        deadline: Option<std::time::Instant>,
         worker: &dyn WorkerTaskContext,
-    ) -> Result<(), Error> {
-        let image_list = self.list_images()?;
+    ) -> Result<bool, Error> {
+        let mut image_list = self.list_images()?;
+        image_list.sort_unstable();
         let image_count = image_list.len();
 
         let mut last_percentage: usize = 0;
 
         let mut strange_paths_count: u64 = 0;
 
+        use std::os::unix::fs::MetadataExt;
+
+        let base_path = self.base_path();
+        let resume_after = status.checkpoint.as_ref().and_then(|checkpoint| {
+            let last_index = base_path.join(checkpoint.last_index.as_ref()?);
+            Some((last_index, checkpoint.phase1_start_time))
+        });
+
         for (i, img) in image_list.into_iter().enumerate() {
+            if let Some((last_index, phase1_start_time)) = &resume_after {
+                if img <= *last_index {
+                    match std::fs::metadata(&img) {
+                        Ok(metadata) if metadata.mtime() < *phase1_start_time => continue,
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                        _ => (), // mark again to be safe
+                    }
+                }
+            }
+
             worker.check_abort()?;
             worker.fail_on_shutdown()?;
 
+            if let Some(deadline) = deadline {
+                if std::time::Instant::now() >= deadline {
+                    self.save_gc_status(status)?;
+                    return Ok(false);
+                }
+            }
+
             if let Some(backup_dir_path) = img.parent() {
                 let backup_dir_path = backup_dir_path.strip_prefix(self.base_path())?;
                 if let Some(backup_dir_str) = backup_dir_path.to_str() {
@@ -972,17 +1344,24 @@ impl DataStore {
 
             match std::fs::File::open(&img) {
                 Ok(file) => {
+                    let file = self
+                        .inner
+                        .chunk_store
+                        .decode_file_handle(file, &img)
+                        .map_err(|e| {
+                            format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
+                        })?;
                     if let Ok(archive_type) = archive_type(&img) {
                         if archive_type == ArchiveType::FixedIndex {
                             let index = FixedIndexReader::new(file).map_err(|e| {
                                 format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
                             })?;
-                            self.index_mark_used_chunks(index, &img, status, worker)?;
+                            self.index_mark_used_chunks(index, &img, status, marker, worker)?;
                         } else if archive_type == ArchiveType::DynamicIndex {
                             let index = DynamicIndexReader::new(file).map_err(|e| {
                                 format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
                             })?;
-                            self.index_mark_used_chunks(index, &img, status, worker)?;
+                            self.index_mark_used_chunks(index, &img, status, marker, worker)?;
                         }
                     }
                 }
@@ -990,6 +1369,11 @@ impl DataStore {
                 Err(err) => bail!("can't open index {} - {}", img.to_string_lossy(), err),
             }
 
+            if let Some(checkpoint) = status.checkpoint.as_mut() {
+                let relative_path = img.strip_prefix(&base_path)?;
+                checkpoint.last_index = Some(relative_path.to_string_lossy().into_owned());
+            }
+
             let percentage = (i + 1) * 100 / image_count;
             if percentage > last_percentage {
                 task_log!(
@@ -1000,6 +1384,9 @@ impl DataStore {
                     image_count,
                 );
                 last_percentage = percentage;
+                if status.checkpoint.is_some() {
+                    self.save_gc_status(status)?;
+                }
             }
         }
 
@@ -1011,7 +1398,26 @@ impl DataStore {
             );
         }
 
-        Ok(())
+        Ok(true)
+    }
+
+    // Persist ``status`` in the datastore, so that it survives restarts.
+    fn save_gc_status(&self, status: &GarbageCollectionStatus) -> Result<(), Error> {
+        let serialized = serde_json::to_string(status)?;
+
+        let mut path = self.base_path();
+        path.push(".gc-status");
+
+        let backup_user = pbs_config::backup_user()?;
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+        // set the correct owner/group/permissions while saving file
+        // owner(rw) = backup, group(r)= backup
+        let options = CreateOptions::new()
+            .perm(mode)
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        replace_file(path, serialized.as_bytes(), options, false)
     }
 
     pub fn last_gc_status(&self) -> GarbageCollectionStatus {
@@ -1033,29 +1439,184 @@ impl DataStore {
             // writer" information and thus no safe atime cutoff
             let _exclusive_lock = self.inner.chunk_store.try_exclusive_lock()?;
 
-            let phase1_start_time = proxmox_time::epoch_i64();
+            let last_status = self.last_gc_status();
+
+            // only atime based marking persists its progress and can be resumed
+            let resume = match (&self.inner.backend, last_status.checkpoint) {
+                (DatastoreBackend::Filesystem, Some(checkpoint)) => Some(checkpoint),
+                _ => None,
+            };
+
+            let mut gc_status = GarbageCollectionStatus {
+                upid: Some(upid.to_string()),
+                ..Default::default()
+            };
+
+            // the chunks touched by the previous run are only safe with its start time as cutoff
+            let phase1_start_time = match resume {
+                Some(checkpoint) => {
+                    task_log!(
+                        worker,
+                        "Resuming unfinished GC started at {}",
+                        proxmox_time::epoch_to_rfc3339_utc(checkpoint.phase1_start_time)?,
+                    );
+                    gc_status.index_file_count = last_status.index_file_count;
+                    gc_status.index_data_bytes = last_status.index_data_bytes;
+                    let phase1_start_time = checkpoint.phase1_start_time;
+                    gc_status.checkpoint = Some(checkpoint);
+                    phase1_start_time
+                }
+                None => proxmox_time::epoch_i64(),
+            };
+
             let oldest_writer = self
                 .inner
                 .chunk_store
                 .oldest_writer()
                 .unwrap_or(phase1_start_time);
 
-            let mut gc_status = GarbageCollectionStatus {
-                upid: Some(upid.to_string()),
-                ..Default::default()
+            let mut marker = match (&self.inner.backend, self.inner.gc_mark_mode) {
+                (DatastoreBackend::S3(_), _) => GcMarker::Digests(HashSet::new()),
+                (DatastoreBackend::Filesystem, _) if gc_status.checkpoint.is_some() => {
+                    GcMarker::Atime
+                }
+                (DatastoreBackend::Filesystem, GcMarkMode::Atime) => GcMarker::Atime,
+                (DatastoreBackend::Filesystem, GcMarkMode::Bitmap)
+                    if self.inner.gc_max_runtime.is_some() =>
+                {
+                    task_log!(
+                        worker,
+                        "bitmap marking cannot be resumed, using atime marking due to gc-max-runtime"
+                    );
+                    GcMarker::Atime
+                }
+                (DatastoreBackend::Filesystem, GcMarkMode::Bitmap) => {
+                    // size the bitmap after the chunk count of the last run
+                    let expected_chunks = last_status.disk_chunks + last_status.pending_chunks;
+                    if expected_chunks == 0 {
+                        task_log!(
+                            worker,
+                            "no previous chunk count to size the bitmap, using atime marking"
+                        );
+                        GcMarker::Atime
+                    } else {
+                        let bitmap = ChunkBitmap::new(expected_chunks as u64);
+                        task_log!(
+                            worker,
+                            "Using chunk bitmap for marking ({})",
+                            HumanByte::from(bitmap.byte_size()),
+                        );
+                        GcMarker::Bitmap(bitmap)
+                    }
+                }
+            };
+
+            if let GcMarker::Atime = marker {
+                gc_status
+                    .checkpoint
+                    .get_or_insert(GarbageCollectionCheckpoint {
+                        phase1_start_time,
+                        last_index: None,
+                    });
+            }
+
+            // stopping early without saving the progress would only restart from scratch
+            let deadline = match (&marker, self.inner.gc_max_runtime) {
+                (GcMarker::Atime, Some(minutes)) => {
+                    Some(std::time::Instant::now() + std::time::Duration::from_secs(minutes * 60))
+                }
+                (_, Some(_)) => {
+                    task_warn!(
+                        worker,
+                        "GC of datastores with S3 backend cannot be resumed, ignoring gc-max-runtime"
+                    );
+                    None
+                }
+                (_, None) => None,
             };
 
             task_log!(worker, "Start GC phase1 (mark used chunks)");
 
-            self.mark_used_chunks(&mut gc_status, worker)?;
+            let finished =
+                match self.mark_used_chunks(&mut gc_status, &mut marker, deadline, worker) {
+                    Ok(finished) => finished,
+                    Err(err) => {
+                        if gc_status.checkpoint.is_some() {
+                            // ignore errors, keep the checkpoint of the last save otherwise
+                            if self.save_gc_status(&gc_status).is_ok() {
+                                *self.inner.last_gc_status.lock().unwrap() = gc_status;
+                            }
+                        }
+                        return Err(err);
+                    }
+                };
+
+            if !finished {
+                task_log!(
+                    worker,
+                    "Reached maximum runtime of {} minutes, GC will be resumed on the next run",
+                    self.inner.gc_max_runtime.unwrap_or_default(),
+                );
+                *self.inner.last_gc_status.lock().unwrap() = gc_status;
+                return Ok(());
+            }
+
+            // marking is complete, a failure from here on restarts from scratch
+            gc_status.checkpoint = None;
 
             task_log!(worker, "Start GC phase2 (sweep unused chunks)");
-            self.inner.chunk_store.sweep_unused_chunks(
-                oldest_writer,
-                phase1_start_time,
-                &mut gc_status,
-                worker,
-            )?;
+            match (&self.inner.backend, marker) {
+                (DatastoreBackend::S3(s3_client), GcMarker::Digests(mut marked)) => {
+                    self.sweep_unused_s3_chunks(
+                        s3_client,
+                        &mut marked,
+                        oldest_writer.min(phase1_start_time) - 300,
+                        &mut gc_status,
+                        worker,
+                    )?;
+
+                    // evict chunks not accessed recently from the local cache
+                    let mut cache_status = GarbageCollectionStatus::default();
+                    self.inner.chunk_store.sweep_unused_chunks(
+                        oldest_writer,
+                        phase1_start_time,
+                        None,
+                        &mut cache_status,
+                        worker,
+                    )?;
+                    task_log!(
+                        worker,
+                        "Evicted from local cache: {} ({} chunks)",
+                        HumanByte::from(cache_status.removed_bytes),
+                        cache_status.removed_chunks,
+                    );
+                }
+                (_, marker) => {
+                    let bitmap = match &marker {
+                        GcMarker::Bitmap(bitmap) => Some(bitmap),
+                        _ => None,
+                    };
+                    let found = self.inner.chunk_store.sweep_unused_chunks(
+                        oldest_writer,
+                        phase1_start_time,
+                        bitmap,
+                        &mut gc_status,
+                        worker,
+                    )?;
+
+                    // the bitmap cannot tell which ones, false positives make this an estimate
+                    if let Some(bitmap) = bitmap {
+                        let missing = bitmap.marked_count().saturating_sub(found);
+                        if missing > 0 {
+                            task_warn!(
+                                worker,
+                                "warning: about {missing} referenced chunks are missing, verify \
+                                the datastore to find the affected snapshots"
+                            );
+                        }
+                    }
+                }
+            }
 
             task_log!(
                 worker,
@@ -1111,22 +1672,8 @@ impl DataStore {
                 task_log!(worker, "Average chunk size: {}", HumanByte::from(avg_chunk));
             }
 
-            if let Ok(serialized) = serde_json::to_string(&gc_status) {
-                let mut path = self.base_path();
-                path.push(".gc-status");
-
-                let backup_user = pbs_config::backup_user()?;
-                let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
-                // set the correct owner/group/permissions while saving file
-                // owner(rw) = backup, group(r)= backup
-                let options = CreateOptions::new()
-                    .perm(mode)
-                    .owner(backup_user.uid)
-                    .group(backup_user.gid);
-
-                // ignore errors
-                let _ = replace_file(path, serialized.as_bytes(), options, false);
-            }
+            // ignore errors
+            let _ = self.save_gc_status(&gc_status);
 
             *self.inner.last_gc_status.lock().unwrap() = gc_status;
         } else {
@@ -1136,91 +1683,1234 @@ impl DataStore {
         Ok(())
     }
 
-    pub fn try_shared_chunk_store_lock(&self) -> Result<ProcessLockSharedGuard, Error> {
-        self.inner.chunk_store.try_shared_lock()
-    }
+    /// Returns the sizes of all chunk objects in the bucket, for datastores with S3 backend.
+    fn s3_chunk_sizes(&self) -> Result<Option<HashMap<[u8; 32], u64>>, Error> {
+        let s3_client = match self.inner.backend {
+            DatastoreBackend::Filesystem => return Ok(None),
+            DatastoreBackend::S3(ref s3_client) => s3_client,
+        };
 
-    pub fn chunk_path(&self, digest: &[u8; 32]) -> (PathBuf, String) {
-        self.inner.chunk_store.chunk_path(digest)
-    }
+        let list = proxmox_async::runtime::block_on(s3_client.list_all_objects(".chunks/"))?;
+        let mut sizes = HashMap::with_capacity(list.len());
+        for item in list {
+            let digest_str = item.key.rsplit('/').next().unwrap_or_default();
+            let mut digest = [0u8; 32];
+            if hex::decode_to_slice(digest_str, &mut digest).is_ok() {
+                sizes.insert(digest, item.size);
+            }
+        }
 
-    pub fn cond_touch_chunk(&self, digest: &[u8; 32], assert_exists: bool) -> Result<bool, Error> {
-        self.inner
-            .chunk_store
-            .cond_touch_chunk(digest, assert_exists)
+        Ok(Some(sizes))
     }
 
-    pub fn insert_chunk(&self, chunk: &DataBlob, digest: &[u8; 32]) -> Result<(bool, u64), Error> {
-        self.inner.chunk_store.insert_chunk(chunk, digest)
+    /// Returns the size of the stored chunk file or object, `None` if the chunk is missing.
+    ///
+    /// `s3_chunk_sizes` has to be the result of [`s3_chunk_sizes`](Self::s3_chunk_sizes).
+    fn stored_chunk_size(
+        &self,
+        digest: &[u8; 32],
+        s3_chunk_sizes: Option<&HashMap<[u8; 32], u64>>,
+    ) -> Option<u64> {
+        match s3_chunk_sizes {
+            Some(sizes) => sizes.get(digest).copied(),
+            None => self.stat_chunk(digest).ok().map(|metadata| metadata.len()),
+        }
     }
 
-    pub fn stat_chunk(&self, digest: &[u8; 32]) -> Result<std::fs::Metadata, Error> {
-        let (chunk_path, _digest_str) = self.inner.chunk_store.chunk_path(digest);
-        std::fs::metadata(chunk_path).map_err(Error::from)
+    /// Returns the result of the last chunk usage calculation, if any.
+    pub fn last_chunk_usage(&self) -> Result<Option<DataStoreChunkUsage>, Error> {
+        let mut path = self.base_path();
+        path.push(".chunk-usage");
+
+        match file_read_optional_string(path)? {
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+            None => Ok(None),
+        }
     }
 
-    pub fn load_chunk(&self, digest: &[u8; 32]) -> Result<DataBlob, Error> {
-        let (chunk_path, digest_str) = self.inner.chunk_store.chunk_path(digest);
+    /// Calculate the unique and shared chunk bytes of all snapshots, backup groups and
+    /// namespaces, and store the result for [`last_chunk_usage`](Self::last_chunk_usage).
+    ///
+    /// A chunk is unique to a snapshot (group, namespace) if it is not referenced from anywhere
+    /// else. Sizes are the sizes of the chunk files on disk (or of the chunk objects in the
+    /// bucket), so they reflect compression. Every index file is read once, the digests of all
+    /// referenced chunks are kept in memory.
+    ///
+    /// The usage of the `quotas` configured for this datastore is calculated as well, see
+    /// [`cached_quota_usage`](Self::cached_quota_usage). Chunks shared between the namespaces
+    /// covered by a quota are only counted once.
+    pub fn calculate_chunk_usage(
+        self: &Arc<Self>,
+        worker: &dyn WorkerTaskContext,
+        upid: &UPID,
+        quotas: Vec<QuotaConfig>,
+    ) -> Result<(), Error> {
+        // index of the snapshot, group and namespace referencing a chunk, or SHARED
+        const SHARED: usize = usize::MAX;
+        struct ChunkOwner {
+            size: u64,
+            snapshot: usize,
+            group: usize,
+            ns: usize,
+        }
 
-        proxmox_lang::try_block!({
-            let mut file = std::fs::File::open(&chunk_path)?;
-            DataBlob::load_from_reader(&mut file)
-        })
-        .map_err(|err| {
-            format_err!(
-                "store '{}', unable to load chunk '{}' - {}",
-                self.name(),
-                digest_str,
-                err,
-            )
-        })
-    }
+        // collect the total referenced bytes as shared, unique bytes are moved over at the end
+        let mut namespaces: Vec<NamespaceChunkUsage> = Vec::new();
+        let mut groups: Vec<GroupChunkUsage> = Vec::new();
+        let mut snapshots: Vec<SnapshotChunkUsage> = Vec::new();
+        let mut chunks: HashMap<[u8; 32], ChunkOwner> = HashMap::new();
+        let mut missing_chunks = 0;
+
+        let start_time = proxmox_time::epoch_i64();
+
+        // the distinct chunks of each quota, only if it limits the unique size
+        let mut quotas: Vec<(QuotaStatus, Option<HashSet<[u8; 32]>>)> = quotas
+            .into_iter()
+            .filter(|config| config.store == self.name())
+            .map(|config| {
+                let unique = config.max_unique_size.is_some();
+                let usage = QuotaUsage {
+                    logical_size: 0,
+                    unique_size: unique.then_some(0),
+                };
+                (QuotaStatus { config, usage }, unique.then(HashSet::new))
+            })
+            .collect();
+
+        let s3_chunk_sizes = self.s3_chunk_sizes()?;
+
+        for ns in self.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+            let ns_index = namespaces.len();
+            let mut ns_chunks = HashSet::new();
+            let mut ns_total = 0;
+
+            for group in self.iter_backup_groups_ok(ns.clone())? {
+                let group_index = groups.len();
+                let mut group_chunks = HashSet::new();
+                let mut group_total = 0;
+
+                let group_quotas: Vec<usize> = quotas
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (status, _))| {
+                        status.config.applies_to(self.name(), &ns, group.group())
+                    })
+                    .map(|(i, _)| i)
+                    .collect();
+
+                for info in group.list_backups()? {
+                    worker.check_abort()?;
+
+                    let snapshot_index = snapshots.len();
+                    let mut snapshot_chunks = HashSet::new();
+                    let mut snapshot_total = 0;
+
+                    // like calculate_usage, only finished snapshots count towards the quotas
+                    let mut snapshot_quotas: &[usize] = &[];
+                    if !group_quotas.is_empty() {
+                        if let Ok((manifest, _)) = info.backup_dir.load_manifest() {
+                            let logical_size: u64 =
+                                manifest.files().iter().map(|file| file.size).sum();
+                            for &i in &group_quotas {
+                                quotas[i].0.usage.logical_size += logical_size;
+                            }
+                            snapshot_quotas = &group_quotas;
+                        }
+                    }
 
-    /// Updates the protection status of the specified snapshot.
-    pub fn update_protection(&self, backup_dir: &BackupDir, protection: bool) -> Result<(), Error> {
-        let full_path = backup_dir.full_path();
+                    for file in &info.files {
+                        match archive_type(file) {
+                            Ok(ArchiveType::FixedIndex) | Ok(ArchiveType::DynamicIndex) => (),
+                            _ => continue,
+                        }
 
-        if !full_path.exists() {
-            bail!("snapshot {} does not exist!", backup_dir.dir());
+                        let mut path = info.backup_dir.full_path();
+                        path.push(file);
+                        let index = match self.open_index(&path) {
+                            Ok(index) => index,
+                            Err(err) => {
+                                task_warn!(worker, "can't open index {:?} - {}", path, err);
+                                continue;
+                            }
+                        };
+
+                        for pos in 0..index.index_count() {
+                            let chunk = index.chunk_info(pos).unwrap();
+
+                            let owner = chunks.entry(chunk.digest).or_insert_with(|| {
+                                // missing chunks do not pin any space
+                                let size = self
+                                    .stored_chunk_size(&chunk.digest, s3_chunk_sizes.as_ref())
+                                    .unwrap_or_else(|| {
+                                        missing_chunks += 1;
+                                        0
+                                    });
+                                ChunkOwner {
+                                    size,
+                                    snapshot: snapshot_index,
+                                    group: group_index,
+                                    ns: ns_index,
+                                }
+                            });
+                            let size = owner.size;
+                            if owner.snapshot != snapshot_index {
+                                owner.snapshot = SHARED;
+                            }
+                            if owner.group != group_index {
+                                owner.group = SHARED;
+                            }
+                            if owner.ns != ns_index {
+                                owner.ns = SHARED;
+                            }
+
+                            if snapshot_chunks.insert(chunk.digest) {
+                                snapshot_total += size;
+                            }
+                            if group_chunks.insert(chunk.digest) {
+                                group_total += size;
+                            }
+                            if ns_chunks.insert(chunk.digest) {
+                                ns_total += size;
+                            }
+                            for &i in snapshot_quotas {
+                                let (status, quota_chunks) = &mut quotas[i];
+                                if let Some(quota_chunks) = quota_chunks {
+                                    if quota_chunks.insert(chunk.digest) {
+                                        *status.usage.unique_size.get_or_insert(0) += size;
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    snapshots.push(SnapshotChunkUsage {
+                        ns: ns.clone(),
+                        backup: info.backup_dir.dir().clone(),
+                        usage: ChunkUsage {
+                            unique_bytes: 0,
+                            shared_bytes: snapshot_total,
+                        },
+                    });
+                }
+
+                groups.push(GroupChunkUsage {
+                    ns: ns.clone(),
+                    backup: group.group().clone(),
+                    usage: ChunkUsage {
+                        unique_bytes: 0,
+                        shared_bytes: group_total,
+                    },
+                });
+            }
+
+            namespaces.push(NamespaceChunkUsage {
+                ns,
+                usage: ChunkUsage {
+                    unique_bytes: 0,
+                    shared_bytes: ns_total,
+                },
+            });
         }
 
-        let _guard = lock_dir_noblock(&full_path, "snapshot", "possibly running or in use")?;
+        fn move_to_unique(usage: &mut ChunkUsage, size: u64) {
+            usage.unique_bytes += size;
+            usage.shared_bytes -= size;
+        }
 
-        let protected_path = backup_dir.protected_file();
-        if protection {
-            std::fs::File::create(protected_path)
-                .map_err(|err| format_err!("could not create protection file: {}", err))?;
-        } else if let Err(err) = std::fs::remove_file(protected_path) {
-            // ignore error for non-existing file
-            if err.kind() != std::io::ErrorKind::NotFound {
-                bail!("could not remove protection file: {}", err);
+        for owner in chunks.values() {
+            if owner.snapshot != SHARED {
+                move_to_unique(&mut snapshots[owner.snapshot].usage, owner.size);
+            }
+            if owner.group != SHARED {
+                move_to_unique(&mut groups[owner.group].usage, owner.size);
+            }
+            if owner.ns != SHARED {
+                move_to_unique(&mut namespaces[owner.ns].usage, owner.size);
             }
         }
 
-        Ok(())
-    }
+        task_log!(
+            worker,
+            "Calculated usage of {} snapshots in {} groups and {} namespaces ({} chunks)",
+            snapshots.len(),
+            groups.len(),
+            namespaces.len(),
+            chunks.len(),
+        );
+        if missing_chunks > 0 {
+            task_warn!(worker, "{} referenced chunks are missing", missing_chunks);
+        }
 
-    pub fn verify_new(&self) -> bool {
-        self.inner.verify_new
+        let usage = DataStoreChunkUsage {
+            upid: Some(upid.to_string()),
+            start_time: Some(start_time),
+            time: proxmox_time::epoch_i64(),
+            namespaces,
+            groups,
+            snapshots,
+            quotas: quotas.into_iter().map(|(status, _)| status).collect(),
+        };
+
+        let mut path = self.base_path();
+        path.push(".chunk-usage");
+
+        let backup_user = pbs_config::backup_user()?;
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+        // owner(rw) = backup, group(r)= backup
+        let options = CreateOptions::new()
+            .perm(mode)
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        replace_file(
+            path,
+            serde_json::to_string(&usage)?.as_bytes(),
+            options,
+            false,
+        )?;
+
+        Ok(())
     }
 
-    /// returns a list of chunks sorted by their inode number on disk chunks that couldn't get
-    /// stat'ed are placed at the end of the list
-    pub fn get_chunks_in_order<F, A>(
+    /// Estimate the bytes a garbage collection could free once the `removed` snapshots are gone.
+    ///
+    /// Returns the size of all chunks which are only referenced by removed snapshots, and for
+    /// every entry of `removed` the size of the chunks only referenced by its own snapshots.
+    /// Sizes are the sizes of the chunk files on disk (or of the chunk objects in the bucket), and
+    /// every index file of the datastore is read to find the chunks still referenced by remaining
+    /// snapshots.
+    pub fn estimate_reclaimable_bytes(
         &self,
-        index: &(dyn IndexFile + Send),
-        skip_chunk: F,
-        check_abort: A,
-    ) -> Result<Vec<(usize, u64)>, Error>
-    where
-        F: Fn(&[u8; 32]) -> bool,
-        A: Fn(usize) -> Result<(), Error>,
-    {
-        let index_count = index.index_count();
-        let mut chunk_list = Vec::with_capacity(index_count);
-        use std::os::unix::fs::MetadataExt;
-        for pos in 0..index_count {
-            check_abort(pos)?;
+        removed: &[Vec<BackupInfo>],
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<(u64, Vec<u64>), Error> {
+        // index of the entry of `removed` referencing a chunk, or SHARED
+        const SHARED: usize = usize::MAX;
+
+        let mut chunks: HashMap<[u8; 32], usize> = HashMap::new();
+        let mut removed_paths = HashSet::new();
+
+        for (entry, list) in removed.iter().enumerate() {
+            for info in list {
+                worker.check_abort()?;
+
+                let snapshot_path = info.backup_dir.full_path();
+
+                for file in &info.files {
+                    match archive_type(file) {
+                        Ok(ArchiveType::FixedIndex) | Ok(ArchiveType::DynamicIndex) => (),
+                        _ => continue,
+                    }
+
+                    let path = snapshot_path.join(file);
+                    let index = match self.open_index(&path) {
+                        Ok(index) => index,
+                        Err(err) => {
+                            task_warn!(worker, "can't open index {:?} - {}", path, err);
+                            continue;
+                        }
+                    };
+
+                    for pos in 0..index.index_count() {
+                        let digest = index.index_digest(pos).unwrap();
+                        let owner = chunks.entry(*digest).or_insert(entry);
+                        if *owner != entry {
+                            *owner = SHARED;
+                        }
+                    }
+                }
+
+                removed_paths.insert(snapshot_path);
+            }
+        }
+
+        for path in self.list_images()? {
+            if chunks.is_empty() {
+                break;
+            }
+            worker.check_abort()?;
+
+            if let Some(snapshot_path) = path.parent() {
+                if removed_paths.contains(snapshot_path) {
+                    continue;
+                }
+            }
+
+            let index = match self.open_index(&path) {
+                Ok(index) => index,
+                Err(err) => {
+                    task_warn!(worker, "can't open index {:?} - {}", path, err);
+                    continue;
+                }
+            };
+
+            for pos in 0..index.index_count() {
+                let digest = index.index_digest(pos).unwrap();
+                chunks.remove(digest);
+            }
+        }
+
+        let s3_chunk_sizes = if chunks.is_empty() {
+            None
+        } else {
+            self.s3_chunk_sizes()?
+        };
+
+        let mut total = 0;
+        let mut entries = vec![0; removed.len()];
+        for (digest, owner) in chunks {
+            worker.check_abort()?;
+            // missing chunks free nothing
+            let size = self
+                .stored_chunk_size(&digest, s3_chunk_sizes.as_ref())
+                .unwrap_or(0);
+            total += size;
+            if owner != SHARED {
+                entries[owner] += size;
+            }
+        }
+
+        Ok((total, entries))
+    }
+
+    /// Removes chunks from the bucket which are neither marked nor were uploaded after `cutoff`.
+    ///
+    /// Every removal is rechecked while holding the chunk store mutex, which is also held while
+    /// inserting a chunk into the local cache: a chunk which was touched in the cache or
+    /// uploaded again since listing is kept.
+    fn sweep_unused_s3_chunks(
+        &self,
+        s3_client: &S3Client,
+        marked: &mut HashSet<[u8; 32]>,
+        cutoff: i64,
+        status: &mut GarbageCollectionStatus,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<(), Error> {
+        let mut continuation_token = None;
+        let mut chunk_count = 0;
+
+        loop {
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            let response = proxmox_async::runtime::block_on(
+                s3_client.list_objects_v2(".chunks/", continuation_token.as_deref()),
+            )?;
+
+            for item in response.contents {
+                worker.check_abort()?;
+                worker.fail_on_shutdown()?;
+
+                let digest = match item.key.rsplit('/').next().map(<[u8; 32]>::from_hex) {
+                    Some(Ok(digest)) => digest,
+                    _ => continue, // not a chunk
+                };
+
+                chunk_count += 1;
+
+                // found digests are removed, so that only missing chunks are left at the end
+                if marked.remove(&digest) || item.last_modified >= cutoff {
+                    status.disk_chunks += 1;
+                    status.disk_bytes += item.size;
+                    continue;
+                }
+
+                // the S3 calls are done without holding the chunk store mutex, so the cache is
+                // rechecked before removing anything
+                let (chunk_path, _digest_str) = self.chunk_path(&digest);
+                if self.cached_chunk_in_use(&chunk_path, cutoff) {
+                    status.disk_chunks += 1;
+                    status.disk_bytes += item.size;
+                    continue;
+                }
+
+                match proxmox_async::runtime::block_on(s3_client.head_object(&item.key))? {
+                    Some(head) if head.last_modified < cutoff => (),
+                    Some(head) => {
+                        status.disk_chunks += 1;
+                        status.disk_bytes += head.content_length;
+                        continue;
+                    }
+                    None => continue, // already gone
+                }
+
+                {
+                    let _lock = self.inner.chunk_store.mutex().lock().unwrap();
+                    if cached_chunk_accessed_since(&chunk_path, cutoff) {
+                        status.disk_chunks += 1;
+                        status.disk_bytes += item.size;
+                        continue;
+                    }
+                    // remove the cached chunk first, so that inserting it again uploads it
+                    if let Err(err) = std::fs::remove_file(&chunk_path) {
+                        if err.kind() != io::ErrorKind::NotFound {
+                            bail!("removing cached chunk {chunk_path:?} failed - {err}");
+                        }
+                    }
+                }
+
+                proxmox_async::runtime::block_on(s3_client.delete_object(&item.key))?;
+
+                // a concurrent backup might have inserted the chunk again in the meantime, make
+                // sure it is still present in the bucket in that case
+                let reinserted = {
+                    let _lock = self.inner.chunk_store.mutex().lock().unwrap();
+                    match std::fs::read(&chunk_path) {
+                        Ok(data) => Some(data),
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                        Err(err) => bail!("reading cached chunk {chunk_path:?} failed - {err}"),
+                    }
+                };
+                if let Some(data) = reinserted {
+                    proxmox_async::runtime::block_on(s3_client.put_object(&item.key, data))?;
+                    status.disk_chunks += 1;
+                    status.disk_bytes += item.size;
+                    continue;
+                }
+
+                status.removed_chunks += 1;
+                status.removed_bytes += item.size;
+            }
+
+            task_log!(worker, "processed {} chunks in bucket", chunk_count);
+
+            match response.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => break,
+            }
+        }
+
+        for digest in marked.iter() {
+            let hex = hex::encode(digest);
+            task_warn!(
+                worker,
+                "warning: referenced chunk {hex} is missing in the bucket"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the cached copy of a chunk got accessed since `cutoff`, under the chunk
+    /// store mutex.
+    fn cached_chunk_in_use(&self, chunk_path: &Path, cutoff: i64) -> bool {
+        let _lock = self.inner.chunk_store.mutex().lock().unwrap();
+        cached_chunk_accessed_since(chunk_path, cutoff)
+    }
+
+    /// Moves chunks to the shard they belong to, see [`ChunkStore::rebalance`].
+    pub fn rebalance_chunks(&self, worker: &dyn WorkerTaskContext) -> Result<(), Error> {
+        if let DatastoreBackend::S3(_) = self.inner.backend {
+            bail!("datastores with S3 backend cannot be sharded");
+        }
+        self.inner.chunk_store.rebalance(worker)
+    }
+
+    /// Encrypt the chunks, snapshot files and zstd dictionaries which are still stored in plain,
+    /// after at-rest encryption got set up for the datastore.
+    ///
+    /// Snapshots locked by another operation, like a running backup, are skipped with a warning,
+    /// so the task needs to be run again once they are done.
+    pub fn encrypt_existing_data(
+        self: &Arc<Self>,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<(), Error> {
+        if let DatastoreBackend::S3(_) = self.inner.backend {
+            bail!("encrypting existing data is not supported for datastores with S3 backend");
+        }
+
+        self.inner.chunk_store.encrypt_chunks(worker)?;
+
+        task_log!(worker, "encrypting snapshot files");
+        let mut encrypted_files = 0;
+        let mut skipped_snapshots = 0;
+        for ns in self.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+            for group in self.iter_backup_groups_ok(ns)? {
+                for info in group.list_backups()? {
+                    worker.check_abort()?;
+                    worker.fail_on_shutdown()?;
+                    match self.encrypt_existing_snapshot_files(&info) {
+                        Ok(count) => encrypted_files += count,
+                        Err(err) => {
+                            task_warn!(
+                                worker,
+                                "SKIPPED: snapshot {:?} - {err}",
+                                info.backup_dir.relative_path(),
+                            );
+                            skipped_snapshots += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        let dir = self.zstd_dictionary_dir();
+        for id in self.list_zstd_dictionaries()? {
+            let path = dir.join(ZstdDictionary::file_name(id));
+            if self.inner.chunk_store.encrypt_file_in_place(&path, &path)? {
+                encrypted_files += 1;
+            }
+        }
+
+        task_log!(
+            worker,
+            "encrypted {encrypted_files} snapshot and dictionary files"
+        );
+        if skipped_snapshots > 0 {
+            bail!(
+                "skipped {skipped_snapshots} snapshots, run the task again once they are unlocked"
+            );
+        }
+
+        Ok(())
+    }
+
+    // Snapshot files are never changed, except for the manifest, so holding the shared snapshot
+    // and the manifest lock is enough.
+    fn encrypt_existing_snapshot_files(&self, info: &BackupInfo) -> Result<usize, Error> {
+        let path = info.backup_dir.full_path();
+        let _guard = lock_dir_noblock_shared(&path, "snapshot", "locked by another operation")?;
+        let _manifest_guard = info.backup_dir.lock_manifest()?;
+
+        let mut count = 0;
+        for file in info.files.iter() {
+            let path = path.join(file);
+            if self.inner.chunk_store.encrypt_file_in_place(&path, &path)? {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    pub fn try_shared_chunk_store_lock(&self) -> Result<ProcessLockSharedGuard, Error> {
+        self.inner.chunk_store.try_shared_lock()
+    }
+
+    pub fn chunk_path(&self, digest: &[u8; 32]) -> (PathBuf, String) {
+        self.inner.chunk_store.chunk_path(digest)
+    }
+
+    /// See [`ChunkStore::access_chunk`].
+    pub fn access_chunk<T>(
+        &self,
+        digest: &[u8; 32],
+        access: impl Fn(&Path) -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        self.inner.chunk_store.access_chunk(digest, access)
+    }
+
+    pub fn cond_touch_chunk(&self, digest: &[u8; 32], assert_exists: bool) -> Result<bool, Error> {
+        self.inner
+            .chunk_store
+            .cond_touch_chunk(digest, assert_exists)
+    }
+
+    /// Checks whether the chunk exists and protects it from a running garbage collection, just
+    /// like inserting it again would.
+    ///
+    /// For the S3 backend only the local cache is checked, chunks missing there are reported as
+    /// not existing.
+    pub fn touch_existing_chunk(&self, digest: &[u8; 32]) -> Result<bool, Error> {
+        let _lock = self.inner.chunk_store.mutex().lock().unwrap();
+        self.inner.chunk_store.cond_touch_chunk(digest, false)
+    }
+
+    pub fn insert_chunk(&self, chunk: &DataBlob, digest: &[u8; 32]) -> Result<(bool, u64), Error> {
+        let s3_client = match self.inner.backend {
+            DatastoreBackend::Filesystem => {
+                return self.inner.chunk_store.insert_chunk(chunk, digest)
+            }
+            DatastoreBackend::S3(ref s3_client) => s3_client,
+        };
+
+        // chunks in the local cache are always present in the bucket as well
+        {
+            let _lock = self.inner.chunk_store.mutex().lock().unwrap();
+            if self.inner.chunk_store.cond_touch_chunk(digest, false)? {
+                return Ok((true, chunk.raw_data().len() as u64));
+            }
+        }
+
+        let key = chunk_object_key(digest);
+        let raw_data = self
+            .inner
+            .chunk_store
+            .encode_chunk_file(chunk.raw_data(), digest)?;
+        proxmox_async::runtime::block_on(s3_client.put_object(&key, raw_data.clone()))?;
+
+        let (_, encoded_size) = self.inner.chunk_store.insert_chunk(chunk, digest)?;
+
+        // a concurrent GC might have removed the object before the chunk got cached, it
+        // rechecks the cache before removing anything, so it's enough to check once
+        if proxmox_async::runtime::block_on(s3_client.head_object(&key))?.is_none() {
+            proxmox_async::runtime::block_on(s3_client.put_object(&key, raw_data))?;
+        }
+
+        Ok((false, encoded_size))
+    }
+
+    /// Decodes the content of a chunk file, which might be encrypted at rest.
+    pub fn decode_chunk_file(&self, data: Vec<u8>, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        self.inner.chunk_store.decode_chunk_file(data, digest)
+    }
+
+    /// Opens a snapshot file (index, blob or manifest) or a zstd dictionary for reading its plain
+    /// content, which might be encrypted at rest.
+    pub fn open_snapshot_file<P: AsRef<Path>>(&self, filename: P) -> Result<File, Error> {
+        let full_path = self.inner.chunk_store.relative_path(filename.as_ref());
+        self.inner.chunk_store.open_file(&full_path)
+    }
+
+    /// Like [`open_snapshot_file`](Self::open_snapshot_file), for the already opened file
+    /// `filename`.
+    pub fn decode_snapshot_file<P: AsRef<Path>>(
+        &self,
+        file: File,
+        filename: P,
+    ) -> Result<File, Error> {
+        let full_path = self.inner.chunk_store.relative_path(filename.as_ref());
+        self.inner.chunk_store.decode_file_handle(file, &full_path)
+    }
+
+    /// Encodes the content of a snapshot file the way it gets stored on disk as `filename`.
+    ///
+    /// At-rest encrypted content is bound to `filename`, so it has to be stored there, and not
+    /// be moved elsewhere later on.
+    pub fn encode_snapshot_file<'a, P: AsRef<Path>>(
+        &self,
+        data: &'a [u8],
+        filename: P,
+    ) -> Result<Cow<'a, [u8]>, Error> {
+        let full_path = self.inner.chunk_store.relative_path(filename.as_ref());
+        self.inner.chunk_store.encode_file(data, &full_path)
+    }
+
+    /// Encrypts a plain snapshot file written by other means, like downloaded archives, in place
+    /// if the datastore is configured for at-rest encryption. The encrypted content is bound to
+    /// `target`, the name the file gets renamed to afterwards.
+    pub fn encrypt_snapshot_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        filename: P,
+        target: Q,
+    ) -> Result<(), Error> {
+        let full_path = self.inner.chunk_store.relative_path(filename.as_ref());
+        let target = self.inner.chunk_store.relative_path(target.as_ref());
+        self.inner
+            .chunk_store
+            .encrypt_file_in_place(&full_path, &target)?;
+        Ok(())
+    }
+
+    pub fn stat_chunk(&self, digest: &[u8; 32]) -> Result<std::fs::Metadata, Error> {
+        self.inner
+            .chunk_store
+            .access_chunk(digest, |path| std::fs::metadata(path))
+            .map_err(Error::from)
+    }
+
+    /// Returns the magic number and the size of the blob stored in a chunk file, from the file
+    /// size and header only, without loading the chunk.
+    ///
+    /// The magic of chunks encrypted at rest is encrypted as well, so it is `None` for those.
+    pub fn chunk_blob_header(&self, digest: &[u8; 32]) -> Result<(Option<[u8; 8]>, u64), Error> {
+        let (magic, file_size) = self.inner.chunk_store.access_chunk(digest, |path| {
+            let mut file = std::fs::File::open(path)?;
+            let mut magic = [0u8; 8];
+            file.read_exact(&mut magic)?;
+            Ok((magic, file.metadata()?.len()))
+        })?;
+
+        if magic == AT_REST_ENCRYPTED_CHUNK_MAGIC_1_0 {
+            Ok((None, file_size.saturating_sub(AT_REST_HEADER_SIZE as u64)))
+        } else {
+            Ok((Some(magic), file_size))
+        }
+    }
+
+    /// Checks that the chunk exists, in the bucket for the S3 backend.
+    fn check_chunk_exists(&self, digest: &[u8; 32]) -> Result<(), Error> {
+        let s3_client = match self.inner.backend {
+            DatastoreBackend::Filesystem => return self.stat_chunk(digest).map(drop),
+            DatastoreBackend::S3(ref s3_client) => s3_client,
+        };
+
+        if self.stat_chunk(digest).is_ok() {
+            return Ok(());
+        }
+
+        let key = chunk_object_key(digest);
+        match proxmox_async::runtime::block_on(s3_client.head_object(&key))? {
+            Some(_) => Ok(()),
+            None => bail!(
+                "chunk object '{key}' not found in bucket '{}'",
+                s3_client.bucket()
+            ),
+        }
+    }
+
+    pub fn load_chunk(&self, digest: &[u8; 32]) -> Result<DataBlob, Error> {
+        proxmox_lang::try_block!({
+            let file = self
+                .inner
+                .chunk_store
+                .access_chunk(digest, |path| std::fs::File::open(path));
+            let mut file = match (file, &self.inner.backend) {
+                (Ok(file), DatastoreBackend::S3(_)) => {
+                    // keep recently used chunks in the local cache
+                    self.inner.chunk_store.cond_touch_chunk(digest, false)?;
+                    file
+                }
+                (Ok(file), DatastoreBackend::Filesystem) => file,
+                (Err(err), DatastoreBackend::S3(s3_client))
+                    if err.kind() == io::ErrorKind::NotFound =>
+                {
+                    return self.fetch_chunk(s3_client, digest);
+                }
+                (Err(err), _) => return Err(err.into()),
+            };
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            let data = self.inner.chunk_store.decode_chunk_file(data, digest)?;
+            DataBlob::load_from_reader(&mut &data[..])
+        })
+        .map_err(|err| {
+            format_err!(
+                "store '{}', unable to load chunk '{}' - {}",
+                self.name(),
+                hex::encode(digest),
+                err,
+            )
+        })
+    }
+
+    /// Downloads a chunk from the bucket and stores it in the local cache.
+    fn fetch_chunk(&self, s3_client: &S3Client, digest: &[u8; 32]) -> Result<DataBlob, Error> {
+        let key = chunk_object_key(digest);
+        let raw_data =
+            proxmox_async::runtime::block_on(s3_client.get_object(&key))?.ok_or_else(|| {
+                format_err!(
+                    "chunk object '{key}' not found in bucket '{}'",
+                    s3_client.bucket()
+                )
+            })?;
+
+        let raw_data = self.inner.chunk_store.decode_chunk_file(raw_data, digest)?;
+        let chunk = DataBlob::load_from_reader(&mut &raw_data[..])?;
+        self.inner.chunk_store.insert_chunk(&chunk, digest)?;
+
+        Ok(chunk)
+    }
+
+    /// Makes sure the chunk is available below [chunk_path](Self::chunk_path), which is only
+    /// required for backends not storing chunks on the local filesystem.
+    pub fn cache_chunk(&self, digest: &[u8; 32]) -> Result<(), Error> {
+        if let DatastoreBackend::S3(_) = self.inner.backend {
+            self.load_chunk(digest)?;
+        }
+        Ok(())
+    }
+
+    /// Uploads a file below the datastore base path to the backend.
+    ///
+    /// This is a no-op for the filesystem backend.
+    pub fn upload_to_backend(&self, relative_path: &Path) -> Result<(), Error> {
+        if let DatastoreBackend::S3(ref s3_client) = self.inner.backend {
+            let key = object_key(relative_path)?;
+            let data = std::fs::read(self.base_path().join(relative_path))
+                .map_err(|err| format_err!("reading {relative_path:?} failed - {err}"))?;
+            proxmox_async::runtime::block_on(s3_client.put_object(&key, data))?;
+        }
+        Ok(())
+    }
+
+    /// Removes a file, or with `recursive` set a whole directory, from the backend.
+    ///
+    /// This is a no-op for the filesystem backend.
+    pub fn remove_from_backend(&self, relative_path: &Path, recursive: bool) -> Result<(), Error> {
+        if let DatastoreBackend::S3(ref s3_client) = self.inner.backend {
+            let key = object_key(relative_path)?;
+            if !recursive {
+                return proxmox_async::runtime::block_on(s3_client.delete_object(&key));
+            }
+
+            let prefix = format!("{}/", key.trim_end_matches('/'));
+            proxmox_async::runtime::block_on(async {
+                for item in s3_client.list_all_objects(&prefix).await? {
+                    s3_client.delete_object(&item.key).await?;
+                }
+                Ok::<(), Error>(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Removes a chunk from the backend, used to get rid of corrupted chunks.
+    ///
+    /// This is a no-op for the filesystem backend.
+    pub fn remove_chunk_from_backend(&self, digest: &[u8; 32]) -> Result<(), Error> {
+        if let DatastoreBackend::S3(ref s3_client) = self.inner.backend {
+            let _lock = self.inner.chunk_store.mutex().lock().unwrap();
+            let key = chunk_object_key(digest);
+            proxmox_async::runtime::block_on(s3_client.delete_object(&key))?;
+        }
+        Ok(())
+    }
+
+    /// Uploads all files of a finished snapshot to the backend.
+    ///
+    /// This is a no-op for the filesystem backend.
+    pub fn upload_snapshot_to_backend(&self, backup_dir: &BackupDir) -> Result<(), Error> {
+        if let DatastoreBackend::Filesystem = self.inner.backend {
+            return Ok(());
+        }
+
+        let relative_path = backup_dir.relative_path();
+        for item in proxmox_sys::fs::read_subdir(libc::AT_FDCWD, &backup_dir.full_path())? {
+            let item = item?;
+            if item.file_type() != Some(nix::dir::Type::File) {
+                continue;
+            }
+            let name = match item.file_name().to_str() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            self.upload_to_backend(&relative_path.join(name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Downloads all metadata objects which are missing in the local cache from the backend.
+    ///
+    /// Used to (re-)populate the local cache of a datastore using the S3 backend, for example after
+    /// replacing the cache disk or to use an existing bucket on another host.
+    pub fn refresh_cache_from_backend(&self, worker: &dyn WorkerTaskContext) -> Result<(), Error> {
+        let s3_client = match self.inner.backend {
+            DatastoreBackend::Filesystem => bail!("datastore '{}' has no S3 backend", self.name()),
+            DatastoreBackend::S3(ref s3_client) => s3_client,
+        };
+
+        let backup_user = pbs_config::backup_user()?;
+        let options = CreateOptions::new()
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        let list = proxmox_async::runtime::block_on(s3_client.list_all_objects(""))?;
+        let mut fetched = 0;
+        for item in list {
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            if item.key.starts_with(".chunks/") {
+                continue;
+            }
+
+            let relative_path = PathBuf::from(&item.key);
+            if relative_path.is_absolute()
+                || relative_path
+                    .components()
+                    .any(|c| !matches!(c, std::path::Component::Normal(_)))
+            {
+                task_warn!(worker, "skipping object with unexpected key '{}'", item.key);
+                continue;
+            }
+
+            let path = self.base_path().join(&relative_path);
+            if path.exists() {
+                continue;
+            }
+
+            let data = match proxmox_async::runtime::block_on(s3_client.get_object(&item.key))? {
+                Some(data) => data,
+                None => continue, // vanished in the meantime
+            };
+
+            if let Some(parent) = path.parent() {
+                proxmox_sys::fs::create_path(parent, None, Some(options.clone()))?;
+            }
+            replace_file(&path, &data, options.clone(), false)?;
+            fetched += 1;
+        }
+
+        task_log!(
+            worker,
+            "fetched {} files from bucket '{}'",
+            fetched,
+            s3_client.bucket()
+        );
+
+        Ok(())
+    }
+
+    /// Updates the protection status of the specified snapshot.
+    pub fn update_protection(&self, backup_dir: &BackupDir, protection: bool) -> Result<(), Error> {
+        let full_path = backup_dir.full_path();
+
+        if !full_path.exists() {
+            bail!("snapshot {} does not exist!", backup_dir.dir());
+        }
+
+        let _guard = lock_dir_noblock(&full_path, "snapshot", "possibly running or in use")?;
+
+        let protected_path = backup_dir.protected_file();
+        if protection {
+            std::fs::File::create(protected_path)
+                .map_err(|err| format_err!("could not create protection file: {}", err))?;
+        } else if let Err(err) = std::fs::remove_file(protected_path) {
+            // ignore error for non-existing file
+            if err.kind() != std::io::ErrorKind::NotFound {
+                bail!("could not remove protection file: {}", err);
+            }
+        }
+
+        let relative_path = backup_dir.relative_path().join(".protected");
+        if protection {
+            self.upload_to_backend(&relative_path)?;
+        } else {
+            self.remove_from_backend(&relative_path, false)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn verify_new(&self) -> bool {
+        self.inner.verify_new
+    }
+
+    /// Returns how long (in minutes) interrupted backups are kept for resuming, if at all.
+    pub fn backup_resume_timeout(&self) -> Option<u64> {
+        self.inner.backup_resume_timeout
+    }
+
+    /// Returns the zstd compression level suggested to clients, if configured.
+    pub fn compression_level(&self) -> Option<i32> {
+        self.inner.compression_level
+    }
+
+    /// Returns the ID of the zstd dictionary suggested to clients, if configured.
+    pub fn zstd_dictionary_id(&self) -> Option<u32> {
+        self.inner.zstd_dictionary
+    }
+
+    /// Returns the directory the trained zstd dictionaries are stored in.
+    pub fn zstd_dictionary_dir(&self) -> PathBuf {
+        self.base_path().join(DICTIONARY_DIR)
+    }
+
+    /// Returns the dictionary with `id` stored in the datastore.
+    ///
+    /// Dictionaries are registered per datastore, this also makes sure that the registered one
+    /// is still stored in the datastore.
+    pub fn zstd_dictionary(&self, id: u32) -> Result<Arc<ZstdDictionary>, Error> {
+        let dir = self.zstd_dictionary_dir();
+        if let Some(dictionary) = lookup_dictionary(self.name(), id) {
+            if dir.join(ZstdDictionary::file_name(id)).exists() {
+                return Ok(dictionary);
+            }
+        }
+        let path = dir.join(ZstdDictionary::file_name(id));
+        let dictionary = ZstdDictionary::load_with(&dir, id, |data| {
+            self.inner.chunk_store.decode_file(data, &path)
+        })
+        .map_err(|err| {
+            format_err!(
+                "store '{}', unable to load zstd dictionary {id:08x} - {err}",
+                self.name()
+            )
+        })?;
+        register_dictionary(self.name(), dictionary)
+    }
+
+    /// Decodes a chunk or blob of the datastore, loading the zstd dictionary it needs, if any.
+    pub fn decode_blob(
+        &self,
+        blob: &DataBlob,
+        config: Option<&CryptConfig>,
+        digest: Option<&[u8; 32]>,
+    ) -> Result<Vec<u8>, Error> {
+        if let Some(id) = blob.zstd_dictionary_id() {
+            self.zstd_dictionary(id)?;
+        }
+        blob.decode_for_store(self.name(), config, digest)
+    }
+
+    /// Returns a reader for the plain content of an opened blob file of the datastore, loading
+    /// the zstd dictionary it needs, if any.
+    pub fn blob_reader(&self, mut file: File) -> Result<DataBlobReader<'static, File>, Error> {
+        let mut head = [0u8; std::mem::size_of::<DictDataBlobHeader>()];
+        if file.read_exact(&mut head).is_ok() && head[..8] == DICT_COMPR_BLOB_MAGIC_1_0 {
+            let id_o = proxmox_lang::offsetof!(DictDataBlobHeader, dict_id);
+            let id = u32::from_le_bytes(head[id_o..id_o + 4].try_into().unwrap());
+            self.zstd_dictionary(id)?;
+        }
+        file.seek(io::SeekFrom::Start(0))?;
+        DataBlobReader::new_for_store(file, None, self.name())
+    }
+
+    /// Verifies size and digest of an unencrypted chunk of the datastore, loading the zstd
+    /// dictionary it needs, if any. See [`DataBlob::verify_unencrypted`].
+    pub fn verify_unencrypted_chunk(
+        &self,
+        chunk: &DataBlob,
+        size: usize,
+        digest: &[u8; 32],
+    ) -> Result<(), Error> {
+        if let Some(id) = chunk.zstd_dictionary_id() {
+            self.zstd_dictionary(id)?;
+        }
+        chunk.verify_unencrypted_for_store(self.name(), size, digest)
+    }
+
+    /// Returns the IDs of all zstd dictionaries stored in the datastore.
+    pub fn list_zstd_dictionaries(&self) -> Result<Vec<u32>, Error> {
+        list_dictionaries(&self.zstd_dictionary_dir())
+    }
+
+    /// Store a dictionary in the datastore, unless it is already there.
+    ///
+    /// Used to keep dictionaries referenced by chunks and blobs copied from another datastore.
+    /// A different dictionary with the same ID is never replaced.
+    pub fn insert_zstd_dictionary(&self, dictionary: &ZstdDictionary) -> Result<(), Error> {
+        let file_name = ZstdDictionary::file_name(dictionary.id());
+        if self.zstd_dictionary_dir().join(&file_name).exists() {
+            let stored = self.zstd_dictionary(dictionary.id())?;
+            if stored.digest() != dictionary.digest() {
+                bail!(
+                    "store '{}', zstd dictionary {:08x} conflicts with the stored dictionary \
+                    with the same ID",
+                    self.name(),
+                    dictionary.id(),
+                );
+            }
+            return Ok(());
+        }
+
+        let backup_user = pbs_config::backup_user()?;
+        let options = CreateOptions::new()
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+        let dir = self.zstd_dictionary_dir();
+        let path = dir.join(&file_name);
+        dictionary.save_with(&dir, options, |data| {
+            self.inner.chunk_store.encode_file(data, &path)
+        })?;
+        self.upload_to_backend(&Path::new(DICTIONARY_DIR).join(file_name))
+    }
+
+    /// Train a zstd dictionary of at most `max_size` bytes on the small unencrypted chunks and
+    /// blobs of the datastore, store and register it.
+    ///
+    /// The dictionary only gets used once it is set as `zstd-dictionary` tuning option. Returns
+    /// its ID.
+    pub fn train_zstd_dictionary(
+        self: &Arc<Self>,
+        max_size: usize,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<u32, Error> {
+        // larger data compresses fine on its own
+        const MAX_SAMPLE_SIZE: usize = 128 * 1024;
+        // zstd suggests about 100 times the dictionary size as training data
+        let max_total = max_size * 100;
+
+        let mut samples: Vec<Vec<u8>> = Vec::new();
+        let mut total = 0;
+
+        let mut add_sample = |blob: DataBlob| -> Result<bool, Error> {
+            if blob.crypt_mode()? != CryptMode::None || blob.raw_size() as usize > MAX_SAMPLE_SIZE {
+                return Ok(total < max_total);
+            }
+            let data = self.decode_blob(&blob, None, None)?;
+            if !data.is_empty() && data.len() <= MAX_SAMPLE_SIZE {
+                total += data.len();
+                samples.push(data);
+            }
+            Ok(total < max_total)
+        };
+
+        task_log!(worker, "collecting samples from blobs");
+        'blobs: for ns in self.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+            for group in self.iter_backup_groups_ok(ns)? {
+                worker.check_abort()?;
+                for info in group.list_backups()? {
+                    for file in info.files.iter().filter(|file| file.ends_with(".blob")) {
+                        let blob = match info.backup_dir.load_blob(file) {
+                            Ok(blob) => blob,
+                            Err(_) => continue,
+                        };
+                        if !add_sample(blob)? {
+                            break 'blobs;
+                        }
+                    }
+                }
+            }
+        }
+
+        task_log!(worker, "collecting samples from chunks");
+        for (entry, _percentage, bad) in self.get_chunk_iterator()? {
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            if bad {
+                continue;
+            }
+            let digest = match entry?
+                .file_name()
+                .to_str()
+                .ok()
+                .and_then(|name| <[u8; 32]>::from_hex(name).ok())
+            {
+                Some(digest) => digest,
+                None => continue,
+            };
+            match self.stat_chunk(&digest) {
+                Ok(stat) if stat.len() as usize <= MAX_SAMPLE_SIZE => (),
+                _ => continue,
+            }
+            if let Ok(blob) = self.load_chunk(&digest) {
+                if !add_sample(blob)? {
+                    break;
+                }
+            }
+        }
+
+        if samples.len() < 10 {
+            bail!("not enough small unencrypted chunks or blobs to train a dictionary");
+        }
+        task_log!(
+            worker,
+            "training dictionary on {} samples ({})",
+            samples.len(),
+            HumanByte::from(total),
+        );
+
+        let dictionary = ZstdDictionary::train(&samples, max_size)?;
+        let id = dictionary.id();
+        self.insert_zstd_dictionary(&dictionary)?;
+        register_dictionary(self.name(), dictionary)?;
+
+        task_log!(worker, "stored zstd dictionary {id:08x}");
+
+        Ok(id)
+    }
+
+    /// returns a list of chunks sorted by their inode number on disk chunks that couldn't get
+    /// stat'ed are placed at the end of the list
+    pub fn get_chunks_in_order<F, A>(
+        &self,
+        index: &(dyn IndexFile + Send),
+        skip_chunk: F,
+        check_abort: A,
+    ) -> Result<Vec<(usize, u64)>, Error>
+    where
+        F: Fn(&[u8; 32]) -> bool,
+        A: Fn(usize) -> Result<(), Error>,
+    {
+        let index_count = index.index_count();
+        let mut chunk_list = Vec::with_capacity(index_count);
+        use std::os::unix::fs::MetadataExt;
+        for pos in 0..index_count {
+            check_abort(pos)?;
 
             let info = index.chunk_info(pos).unwrap();
 
@@ -1357,7 +3047,7 @@ impl DataStore {
 
         let (operations, _lock) = task_tracking::get_active_operations_locked(name)?;
 
-        if operations.read != 0 || operations.write != 0 {
+        if operations.read != 0 || operations.write != 0 || operations.delete != 0 {
             bail!("datastore is currently in use");
         }
 
@@ -1381,10 +3071,12 @@ impl DataStore {
             remove("host", &mut ok);
 
             if ok {
-                if let Err(err) = std::fs::remove_file(base.join(".gc-status")) {
-                    if err.kind() != io::ErrorKind::NotFound {
-                        task_warn!(worker, "failed to remove .gc-status file: {err}");
-                        ok = false;
+                for file in [".gc-status", ".chunk-usage"] {
+                    if let Err(err) = std::fs::remove_file(base.join(file)) {
+                        if err.kind() != io::ErrorKind::NotFound {
+                            task_warn!(worker, "failed to remove {file} file: {err}");
+                            ok = false;
+                        }
                     }
                 }
             }
@@ -1392,6 +3084,15 @@ impl DataStore {
             // chunks get removed last and only if the backups were successfully deleted
             if ok {
                 remove(".chunks", &mut ok);
+                for shard in datastore_config.chunk_shards()? {
+                    let chunk_dir = PathBuf::from(shard).join(".chunks");
+                    if let Err(err) = std::fs::remove_dir_all(&chunk_dir) {
+                        if err.kind() != io::ErrorKind::NotFound {
+                            task_warn!(worker, "failed to remove {chunk_dir:?}: {err}");
+                            ok = false;
+                        }
+                    }
+                }
             }
         }
 
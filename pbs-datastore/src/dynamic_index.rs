@@ -15,15 +15,16 @@ use proxmox_sys::process_locker::ProcessLockSharedGuard;
 use proxmox_uuid::Uuid;
 use pxar::accessor::{MaybeReady, ReadAt, ReadAtOperation};
 
+use pbs_api_types::ChunkerType;
 use pbs_tools::lru_cache::LruCache;
 
 use crate::chunk_stat::ChunkStat;
 use crate::chunk_store::ChunkStore;
+use crate::chunker::{Chunker, ChunkerConfig};
 use crate::data_blob::{DataBlob, DataChunkBuilder};
 use crate::file_formats;
 use crate::index::{ChunkReadInfo, IndexFile};
 use crate::read_chunk::ReadChunk;
-use crate::Chunker;
 
 /// Header format definition for dynamic index files (`.dixd`)
 #[repr(C)]
@@ -33,7 +34,9 @@ pub struct DynamicIndexHeader {
     pub ctime: i64,
     /// Sha256 over the index ``SHA256(offset1||digest1||offset2||digest2||...)``
     pub index_csum: [u8; 32],
-    reserved: [u8; 4032], // overall size is one page (4096 bytes)
+    /// Chunker used to split the archive, see [`ChunkerConfig::to_bytes`] (all zero if unknown)
+    pub chunker: [u8; 16],
+    reserved: [u8; 4016], // overall size is one page (4096 bytes)
 }
 proxmox_lang::static_assert_size!(DynamicIndexHeader, 4096);
 // TODO: Once non-Copy unions are stabilized, use:
@@ -81,6 +84,8 @@ pub struct DynamicIndexReader {
     pub uuid: [u8; 16],
     pub ctime: i64,
     pub index_csum: [u8; 32],
+    /// Chunker recorded by the writer, `None` for older or foreign indexes
+    pub chunker: Option<ChunkerConfig>,
 }
 
 impl DynamicIndexReader {
@@ -95,6 +100,11 @@ impl DynamicIndexReader {
         &self.index
     }
 
+    /// Opens another reader for the same index file, sharing the underlying file handle.
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Self::new(self._file.try_clone()?)
+    }
+
     pub fn new(mut file: std::fs::File) -> Result<Self, Error> {
         // FIXME: This is NOT OUR job! Check the callers of this method and remove this!
         file.seek(SeekFrom::Start(0))?;
@@ -144,6 +154,7 @@ impl DynamicIndexReader {
             ctime,
             uuid: header.uuid,
             index_csum: header.index_csum,
+            chunker: ChunkerConfig::from_bytes(&header.chunker),
         })
     }
 
@@ -278,6 +289,7 @@ pub struct DynamicIndexWriter {
     filename: PathBuf,
     tmp_filename: PathBuf,
     csum: Option<openssl::sha::Sha256>,
+    chunker: Option<ChunkerConfig>,
     pub uuid: [u8; 16],
     pub ctime: i64,
 }
@@ -328,9 +340,15 @@ impl DynamicIndexWriter {
             ctime,
             uuid: *uuid.as_bytes(),
             csum,
+            chunker: None,
         })
     }
 
+    /// Record the chunker used to split the archive in the index header.
+    pub fn set_chunker(&mut self, chunker: ChunkerConfig) {
+        self.chunker = Some(chunker);
+    }
+
     // fixme: use add_chunk instead?
     pub fn insert_chunk(&self, chunk: &DataBlob, digest: &[u8; 32]) -> Result<(bool, u64), Error> {
         self.store.insert_chunk(chunk, digest)
@@ -355,8 +373,18 @@ impl DynamicIndexWriter {
         let index_csum = csum.finish();
 
         self.writer.write_all(&index_csum)?;
+
+        if let Some(chunker) = &self.chunker {
+            let chunker_offset = proxmox_lang::offsetof!(DynamicIndexHeader, chunker);
+            self.writer.seek(SeekFrom::Start(chunker_offset as u64))?;
+            self.writer.write_all(&chunker.to_bytes())?;
+        }
+
         self.writer.flush()?;
 
+        self.store
+            .encrypt_file_in_place(&self.tmp_filename, &self.filename)?;
+
         if let Err(err) = std::fs::rename(&self.tmp_filename, &self.filename) {
             bail!("Atomic rename file {:?} failed - {}", self.filename, err);
         }
@@ -364,6 +392,13 @@ impl DynamicIndexWriter {
         Ok(index_csum)
     }
 
+    /// Like `close()`, but stores the index file at `path` instead of the path it was created
+    /// with.
+    pub fn close_as(&mut self, path: &Path) -> Result<[u8; 32], Error> {
+        self.filename = self.store.relative_path(path);
+        self.close()
+    }
+
     // fixme: rename to add_digest
     pub fn add_chunk(&mut self, offset: u64, digest: &[u8; 32]) -> Result<(), Error> {
         if self.closed {
@@ -392,7 +427,7 @@ impl DynamicIndexWriter {
 pub struct DynamicChunkWriter {
     index: DynamicIndexWriter,
     closed: bool,
-    chunker: Chunker,
+    chunker: Box<dyn Chunker>,
     stat: ChunkStat,
     chunk_offset: usize,
     last_chunk: usize,
@@ -401,14 +436,25 @@ pub struct DynamicChunkWriter {
 
 impl DynamicChunkWriter {
     pub fn new(index: DynamicIndexWriter, chunk_size: usize) -> Self {
+        let config = ChunkerConfig {
+            ty: ChunkerType::Buzhash,
+            min: chunk_size >> 2,
+            avg: chunk_size,
+            max: chunk_size << 2,
+        };
+        Self::with_chunker(index, config)
+    }
+
+    pub fn with_chunker(mut index: DynamicIndexWriter, config: ChunkerConfig) -> Self {
+        index.set_chunker(config);
         Self {
             index,
             closed: false,
-            chunker: Chunker::new(chunk_size),
+            chunker: config.create(),
             stat: ChunkStat::new(0),
             chunk_offset: 0,
             last_chunk: 0,
-            chunk_buffer: Vec::with_capacity(chunk_size * 4),
+            chunk_buffer: Vec::with_capacity(config.max),
         }
     }
 
@@ -11,6 +11,9 @@ pub const UNCOMPRESSED_BLOB_MAGIC_1_0: [u8; 8] = [66, 171, 56, 7, 190, 131, 112,
 //openssl::sha::sha256(b"Proxmox Backup zstd compressed blob v1.0")[0..8]
 pub const COMPRESSED_BLOB_MAGIC_1_0: [u8; 8] = [49, 185, 88, 66, 111, 182, 163, 127];
 
+// openssl::sha::sha256(b"Proxmox Backup zstd dictionary compressed blob v1.0")[0..8]
+pub const DICT_COMPR_BLOB_MAGIC_1_0: [u8; 8] = [156, 117, 112, 165, 250, 90, 167, 127];
+
 // openssl::sha::sha256(b"Proxmox Backup encrypted blob v1.0")[0..8]
 pub const ENCRYPTED_BLOB_MAGIC_1_0: [u8; 8] = [123, 103, 133, 190, 34, 45, 76, 240];
 
@@ -23,6 +26,12 @@ pub const FIXED_SIZED_CHUNK_INDEX_1_0: [u8; 8] = [47, 127, 65, 237, 145, 253, 15
 // openssl::sha::sha256(b"Proxmox Backup dynamic sized chunk index v1.0")[0..8]
 pub const DYNAMIC_SIZED_CHUNK_INDEX_1_0: [u8; 8] = [28, 145, 78, 165, 25, 186, 179, 205];
 
+// openssl::sha::sha256(b"Proxmox Backup at-rest encrypted chunk v1.0")[0..8]
+pub const AT_REST_ENCRYPTED_CHUNK_MAGIC_1_0: [u8; 8] = [221, 174, 121, 9, 183, 254, 138, 72];
+
+// openssl::sha::sha256(b"Proxmox Backup at-rest encrypted file v1.0")[0..8]
+pub const AT_REST_ENCRYPTED_FILE_MAGIC_1_0: [u8; 8] = [90, 81, 159, 38, 162, 199, 76, 85];
+
 /// Data blob binary storage format
 ///
 /// The format start with a 8 byte magic number to identify the type,
@@ -59,6 +68,41 @@ pub struct EncryptedDataBlobHeader {
     pub tag: [u8; 16],
 }
 
+/// Dictionary compressed data blob binary storage format
+///
+/// The ``DataBlobHeader`` for blobs compressed with a trained zstd
+/// dictionary additionally contains the 4 byte ID of the dictionary,
+/// followed by the compressed data:
+///
+/// (MAGIC || CRC32 || DICT_ID || Data)
+///
+/// Dictionaries are only used for unencrypted data.
+#[derive(Endian)]
+#[repr(C, packed)]
+pub struct DictDataBlobHeader {
+    pub head: DataBlobHeader,
+    pub dict_id: [u8; 4],
+}
+
+/// At-rest encrypted chunk file format
+///
+/// With server side at-rest encryption, the complete chunk (a ``DataBlob``)
+/// gets encrypted with the datastore key before it is written to disk, using
+/// the chunk digest as additional authenticated data:
+///
+/// (MAGIC || IV || TAG || EncryptedBlob)
+///
+/// Snapshot files (indexes, blobs and manifests) use the same header with
+/// ``AT_REST_ENCRYPTED_FILE_MAGIC_1_0``, the magic serves as additional
+/// authenticated data instead of the digest.
+#[derive(Endian)]
+#[repr(C, packed)]
+pub struct AtRestChunkHeader {
+    pub magic: [u8; 8],
+    pub iv: [u8; 16],
+    pub tag: [u8; 16],
+}
+
 /// Header size for different file types
 ///
 /// Panics on unknown magic numbers.
@@ -66,6 +110,7 @@ pub fn header_size(magic: &[u8; 8]) -> usize {
     match *magic {
         UNCOMPRESSED_BLOB_MAGIC_1_0 => std::mem::size_of::<DataBlobHeader>(),
         COMPRESSED_BLOB_MAGIC_1_0 => std::mem::size_of::<DataBlobHeader>(),
+        DICT_COMPR_BLOB_MAGIC_1_0 => std::mem::size_of::<DictDataBlobHeader>(),
         ENCRYPTED_BLOB_MAGIC_1_0 => std::mem::size_of::<EncryptedDataBlobHeader>(),
         ENCR_COMPR_BLOB_MAGIC_1_0 => std::mem::size_of::<EncryptedDataBlobHeader>(),
         _ => panic!("unknown blob magic"),
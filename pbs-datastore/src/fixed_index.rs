@@ -354,6 +354,9 @@ impl FixedIndexWriter {
         self.file.write_all(&index_csum)?;
         self.file.flush()?;
 
+        self.store
+            .encrypt_file_in_place(&self.tmp_filename, &self.filename)?;
+
         if let Err(err) = std::fs::rename(&self.tmp_filename, &self.filename) {
             bail!("Atomic rename file {:?} failed - {}", self.filename, err);
         }
@@ -361,6 +364,13 @@ impl FixedIndexWriter {
         Ok(index_csum)
     }
 
+    /// Like `close()`, but stores the index file at `path` instead of the path it was created
+    /// with.
+    pub fn close_as(&mut self, path: &Path) -> Result<[u8; 32], Error> {
+        self.filename = self.store.relative_path(path);
+        self.close()
+    }
+
     pub fn check_chunk_alignment(&self, offset: usize, chunk_len: usize) -> Result<usize, Error> {
         if offset < chunk_len {
             bail!("got chunk with small offset ({} < {}", offset, chunk_len);
@@ -167,11 +167,13 @@ macro_rules! PROXMOX_BACKUP_READER_PROTOCOL_ID_V1 {
     };
 }
 
+pub mod at_rest;
 pub mod backup_info;
 pub mod cached_chunk_reader;
 pub mod catalog;
 pub mod checksum_reader;
 pub mod checksum_writer;
+pub mod chunk_bitmap;
 pub mod chunk_stat;
 pub mod chunk_store;
 pub mod chunker;
@@ -186,8 +188,10 @@ pub mod manifest;
 pub mod paperkey;
 pub mod prune;
 pub mod read_chunk;
+pub mod s3;
 pub mod store_progress;
 pub mod task_tracking;
+pub mod zstd_dictionary;
 
 pub mod dynamic_index;
 pub mod fixed_index;
@@ -196,7 +200,7 @@ pub use backup_info::{BackupDir, BackupGroup, BackupInfo};
 pub use checksum_reader::ChecksumReader;
 pub use checksum_writer::ChecksumWriter;
 pub use chunk_store::ChunkStore;
-pub use chunker::Chunker;
+pub use chunker::{BuzHashChunker, Chunker, ChunkerConfig, FastCdcChunker};
 pub use crypt_reader::CryptReader;
 pub use crypt_writer::CryptWriter;
 pub use data_blob::DataBlob;
@@ -206,7 +210,9 @@ pub use manifest::BackupManifest;
 pub use store_progress::StoreProgress;
 
 mod datastore;
-pub use datastore::{check_backup_owner, DataStore};
+pub use datastore::{
+    check_backup_owner, ensure_datastore_is_mounted, get_datastore_mount_status, DataStore,
+};
 
 mod hierarchy;
 pub use hierarchy::{
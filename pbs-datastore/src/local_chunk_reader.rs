@@ -57,7 +57,11 @@ impl ReadChunk for LocalChunkReader {
     fn read_chunk(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
         let chunk = ReadChunk::read_raw_chunk(self, digest)?;
 
-        let raw_data = chunk.decode(self.crypt_config.as_ref().map(Arc::as_ref), Some(digest))?;
+        let raw_data = self.store.decode_blob(
+            &chunk,
+            self.crypt_config.as_ref().map(Arc::as_ref),
+            Some(digest),
+        )?;
 
         Ok(raw_data)
     }
@@ -69,9 +73,14 @@ impl AsyncReadChunk for LocalChunkReader {
         digest: &'a [u8; 32],
     ) -> Pin<Box<dyn Future<Output = Result<DataBlob, Error>> + Send + 'a>> {
         Box::pin(async move {
-            let (path, _) = self.store.chunk_path(digest);
+            proxmox_async::runtime::block_in_place(|| self.store.cache_chunk(digest))?;
 
-            let raw_data = tokio::fs::read(&path).await?;
+            let raw_data = proxmox_async::runtime::block_in_place(|| {
+                let raw_data = self
+                    .store
+                    .access_chunk(digest, |path| std::fs::read(path))?;
+                self.store.decode_chunk_file(raw_data, digest)
+            })?;
 
             let chunk = DataBlob::load_from_reader(&mut &raw_data[..])?;
             self.ensure_crypt_mode(chunk.crypt_mode()?)?;
@@ -87,8 +96,13 @@ impl AsyncReadChunk for LocalChunkReader {
         Box::pin(async move {
             let chunk = AsyncReadChunk::read_raw_chunk(self, digest).await?;
 
-            let raw_data =
-                chunk.decode(self.crypt_config.as_ref().map(Arc::as_ref), Some(digest))?;
+            let raw_data = proxmox_async::runtime::block_in_place(|| {
+                self.store.decode_blob(
+                    &chunk,
+                    self.crypt_config.as_ref().map(Arc::as_ref),
+                    Some(digest),
+                )
+            })?;
 
             // fixme: verify digest?
 
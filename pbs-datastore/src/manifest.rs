@@ -13,6 +13,26 @@ pub const MANIFEST_LOCK_NAME: &str = ".index.json.lck";
 pub const CLIENT_LOG_BLOB_NAME: &str = "client.log.blob";
 pub const ENCRYPTED_KEY_BLOB_NAME: &str = "rsa-encrypted.key.blob";
 
+/// Name of the blob storing the key encrypted with the `index`-th master key.
+///
+/// The first one uses [`ENCRYPTED_KEY_BLOB_NAME`], to stay compatible with older clients.
+pub fn encrypted_key_blob_name(index: usize) -> String {
+    match index {
+        0 => ENCRYPTED_KEY_BLOB_NAME.to_string(),
+        n => format!("rsa-encrypted-{}.key.blob", n),
+    }
+}
+
+/// Checks if `name` is one of the names returned by [`encrypted_key_blob_name`].
+pub fn is_encrypted_key_blob_name(name: &str) -> bool {
+    name == ENCRYPTED_KEY_BLOB_NAME
+        || name
+            .strip_prefix("rsa-encrypted-")
+            .and_then(|rest| rest.strip_suffix(".key.blob"))
+            .map(|n| n.parse::<usize>().is_ok())
+            .unwrap_or(false)
+}
+
 fn crypt_mode_none() -> CryptMode {
     CryptMode::None
 }
@@ -1,9 +1,10 @@
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
-use anyhow::Error;
+use anyhow::{bail, Error};
 
-use pbs_api_types::KeepOptions;
+use pbs_api_types::{KeepOptions, VerifyState};
+use proxmox_time::TimeSpan;
 
 use super::BackupInfo;
 
@@ -36,18 +37,30 @@ impl std::fmt::Display for PruneMark {
     }
 }
 
+// With `skip_failed`, snapshots with a failed verification do not use up one of the `keep` slots:
+// they are kept if their period gets selected, but the slot stays open for an older snapshot of
+// the same period. Failed snapshots are only kept within the first `keep` periods though, so that
+// a group whose snapshots always fail does not grow without limit.
 fn mark_selections<F: Fn(&BackupInfo) -> Result<String, Error>>(
     mark: &mut HashMap<PathBuf, PruneMark>,
     list: &[BackupInfo],
     keep: usize,
+    skip_failed: bool,
     select_id: F,
 ) -> Result<(), Error> {
+    let skipped = |info: &BackupInfo| skip_failed && info.verify_state == Some(VerifyState::Failed);
+
     let mut include_hash = HashSet::new();
+    // all periods seen so far, including those with only failed snapshots
+    let mut window_hash = HashSet::new();
 
     let mut already_included = HashSet::new();
     for info in list {
         let backup_id = info.backup_dir.relative_path();
         if let Some(PruneMark::Keep) = mark.get(&backup_id) {
+            if skipped(info) {
+                continue;
+            }
             let sel_id: String = select_id(info)?;
             already_included.insert(sel_id);
         }
@@ -72,8 +85,13 @@ fn mark_selections<F: Fn(&BackupInfo) -> Result<String, Error>>(
             if include_hash.len() >= keep {
                 break;
             }
-            include_hash.insert(sel_id);
-            mark.insert(backup_id, PruneMark::Keep);
+            window_hash.insert(sel_id.clone());
+            if !skipped(info) {
+                mark.insert(backup_id, PruneMark::Keep);
+                include_hash.insert(sel_id);
+            } else if window_hash.len() <= keep {
+                mark.insert(backup_id, PruneMark::Keep);
+            }
         } else {
             mark.insert(backup_id, PruneMark::Remove);
         }
@@ -103,7 +121,68 @@ fn remove_incomplete_snapshots(mark: &mut HashMap<PathBuf, PruneMark>, list: &[B
     }
 }
 
+fn time_span_cutoff(now: i64, time_span: &str) -> Result<i64, Error> {
+    let time_span: TimeSpan = time_span.parse()?;
+    Ok(now - f64::from(time_span) as i64)
+}
+
+/// Keep all snapshots created after `cutoff`.
+fn mark_within(mark: &mut HashMap<PathBuf, PruneMark>, list: &[BackupInfo], cutoff: i64) {
+    // list is sorted newest first
+    for info in list {
+        if info.backup_dir.backup_time() < cutoff {
+            break;
+        }
+        let backup_id = info.backup_dir.relative_path();
+        if mark.get(&backup_id).is_some() {
+            continue;
+        }
+        if info.protected {
+            mark.insert(backup_id, PruneMark::Protected);
+        } else {
+            mark.insert(backup_id, PruneMark::Keep);
+        }
+    }
+}
+
+/// Only keep the newest `max_old` of the kept snapshots created before `cutoff`.
+fn limit_old(
+    mark: &mut HashMap<PathBuf, PruneMark>,
+    list: &[BackupInfo],
+    cutoff: i64,
+    max_old: usize,
+) {
+    let mut kept = 0;
+    for info in list {
+        if info.backup_dir.backup_time() >= cutoff {
+            continue;
+        }
+        let backup_id = info.backup_dir.relative_path();
+        if let Some(PruneMark::Keep) = mark.get(&backup_id) {
+            if kept < max_old {
+                kept += 1;
+            } else {
+                mark.insert(backup_id, PruneMark::Remove);
+            }
+        }
+    }
+}
+
+/// Load the verification state of all snapshots, if required by the keep `options`.
+pub fn load_verify_states(list: &mut [BackupInfo], options: &KeepOptions) {
+    if !options.needs_verify_state() {
+        return;
+    }
+    for info in list.iter_mut() {
+        // snapshots with an unreadable manifest are handled like unverified ones
+        let _ = info.load_verify_state();
+    }
+}
+
 /// This filters incomplete and kept backups.
+///
+/// Options depending on the verification state require it to be loaded, see
+/// `load_verify_states`.
 pub fn compute_prune_info(
     mut list: Vec<BackupInfo>,
     options: &KeepOptions,
@@ -114,8 +193,17 @@ pub fn compute_prune_info(
 
     remove_incomplete_snapshots(&mut mark, &list);
 
+    let now = proxmox_time::epoch_i64();
+
+    if let Some(keep_within) = &options.keep_within {
+        let cutoff = time_span_cutoff(now, keep_within)?;
+        mark_within(&mut mark, &list, cutoff);
+    }
+
+    let skip = options.skip_failed_verify.unwrap_or(false);
+
     if let Some(keep_last) = options.keep_last {
-        mark_selections(&mut mark, &list, keep_last as usize, |info| {
+        mark_selections(&mut mark, &list, keep_last as usize, skip, |info| {
             Ok(info.backup_dir.backup_time_string().to_owned())
         })?;
     }
@@ -123,19 +211,19 @@ pub fn compute_prune_info(
     use proxmox_time::strftime_local;
 
     if let Some(keep_hourly) = options.keep_hourly {
-        mark_selections(&mut mark, &list, keep_hourly as usize, |info| {
+        mark_selections(&mut mark, &list, keep_hourly as usize, skip, |info| {
             strftime_local("%Y/%m/%d/%H", info.backup_dir.backup_time()).map_err(Error::from)
         })?;
     }
 
     if let Some(keep_daily) = options.keep_daily {
-        mark_selections(&mut mark, &list, keep_daily as usize, |info| {
+        mark_selections(&mut mark, &list, keep_daily as usize, skip, |info| {
             strftime_local("%Y/%m/%d", info.backup_dir.backup_time()).map_err(Error::from)
         })?;
     }
 
     if let Some(keep_weekly) = options.keep_weekly {
-        mark_selections(&mut mark, &list, keep_weekly as usize, |info| {
+        mark_selections(&mut mark, &list, keep_weekly as usize, skip, |info| {
             // Note: Use iso-week year/week here. This year number
             // might not match the calendar year number.
             strftime_local("%G/%V", info.backup_dir.backup_time()).map_err(Error::from)
@@ -143,17 +231,35 @@ pub fn compute_prune_info(
     }
 
     if let Some(keep_monthly) = options.keep_monthly {
-        mark_selections(&mut mark, &list, keep_monthly as usize, |info| {
+        mark_selections(&mut mark, &list, keep_monthly as usize, skip, |info| {
             strftime_local("%Y/%m", info.backup_dir.backup_time()).map_err(Error::from)
         })?;
     }
 
     if let Some(keep_yearly) = options.keep_yearly {
-        mark_selections(&mut mark, &list, keep_yearly as usize, |info| {
+        mark_selections(&mut mark, &list, keep_yearly as usize, skip, |info| {
             strftime_local("%Y", info.backup_dir.backup_time()).map_err(Error::from)
         })?;
     }
 
+    if let Some(max_old) = options.max_old {
+        let old_age = match &options.old_age {
+            Some(old_age) => old_age,
+            None => bail!("'max-old' requires 'old-age' to be set"),
+        };
+        let cutoff = time_span_cutoff(now, old_age)?;
+        limit_old(&mut mark, &list, cutoff, max_old as usize);
+    }
+
+    if options.keep_last_verified.unwrap_or(false) {
+        let last_verified = list
+            .iter()
+            .find(|info| info.is_finished() && info.verify_state == Some(VerifyState::Ok));
+        if let Some(info) = last_verified {
+            mark.insert(info.backup_dir.relative_path(), PruneMark::Keep);
+        }
+    }
+
     let prune_info: Vec<(BackupInfo, PruneMark)> = list
         .into_iter()
         .map(|info| {
@@ -0,0 +1,545 @@
+//! Minimal client for S3 compatible object stores
+//!
+//! Only implements the operations required by the datastore backend. Requests are signed using
+//! the AWS signature version 4 scheme, which is supported by all common S3 implementations.
+
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use hyper::client::{Client, HttpConnector};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_LENGTH, HOST, LAST_MODIFIED};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::X509StoreContextRef;
+
+use proxmox_http::client::HttpsConnector;
+
+use pbs_api_types::S3ClientConfig;
+
+/// Region used if none is configured, also accepted by most non-AWS implementations
+pub const S3_DEFAULT_REGION: &str = "us-east-1";
+
+const S3_TCP_KEEPALIVE_TIME: u32 = 120;
+const S3_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// Options required to connect to a bucket
+#[derive(Clone)]
+pub struct S3ClientOptions {
+    pub endpoint: String,
+    pub port: Option<u16>,
+    pub use_http: bool,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub path_style: bool,
+    pub fingerprint: Option<String>,
+}
+
+impl S3ClientOptions {
+    /// Combines the S3 client configuration with its secret key and the bucket to use.
+    pub fn from_config(config: S3ClientConfig, secret_key: String, bucket: String) -> Self {
+        Self {
+            endpoint: config.endpoint,
+            port: config.port,
+            use_http: config.use_http.unwrap_or(false),
+            region: config
+                .region
+                .unwrap_or_else(|| S3_DEFAULT_REGION.to_string()),
+            bucket,
+            access_key: config.access_key,
+            secret_key,
+            path_style: config.path_style.unwrap_or(false),
+            fingerprint: config.fingerprint,
+        }
+    }
+}
+
+/// Metadata of an object as returned by a `HEAD` request
+pub struct HeadObjectResponse {
+    pub content_length: u64,
+    pub last_modified: i64,
+}
+
+/// A single entry of an object listing
+pub struct ListObjectsV2Item {
+    pub key: String,
+    pub last_modified: i64,
+    pub size: u64,
+}
+
+/// One page of an object listing
+pub struct ListObjectsV2Response {
+    pub contents: Vec<ListObjectsV2Item>,
+    /// Token to request the next page, `None` if this was the last one
+    pub next_continuation_token: Option<String>,
+}
+
+/// Client to access a single bucket of an S3 compatible object store
+pub struct S3Client {
+    client: Client<HttpsConnector>,
+    options: S3ClientOptions,
+    authority: String,
+}
+
+impl S3Client {
+    pub fn new(options: S3ClientOptions) -> Result<Self, Error> {
+        let mut ssl_connector_builder = SslConnector::builder(SslMethod::tls())?;
+
+        if let Some(fingerprint) = options.fingerprint.clone() {
+            let fingerprint = fingerprint.to_lowercase();
+            ssl_connector_builder.set_verify_callback(SslVerifyMode::PEER, move |valid, ctx| {
+                match verify_fingerprint(valid, ctx, &fingerprint) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        log::error!("S3 certificate validation failed - {err}");
+                        false
+                    }
+                }
+            });
+        }
+
+        let mut httpc = HttpConnector::new();
+        httpc.set_nodelay(true);
+        httpc.enforce_http(false);
+        httpc.set_connect_timeout(Some(S3_CONNECT_TIMEOUT));
+
+        let https = HttpsConnector::with_connector(
+            httpc,
+            ssl_connector_builder.build(),
+            S3_TCP_KEEPALIVE_TIME,
+        );
+        let client = Client::builder().build::<_, Body>(https);
+
+        let host = if options.path_style {
+            options.endpoint.clone()
+        } else {
+            format!("{}.{}", options.bucket, options.endpoint)
+        };
+        let authority = match options.port {
+            Some(port) => format!("{host}:{port}"),
+            None => host,
+        };
+
+        Ok(Self {
+            client,
+            options,
+            authority,
+        })
+    }
+
+    /// Opens `bucket` using the S3 client configuration with id `client_id`.
+    pub fn from_client_config(client_id: &str, bucket: &str) -> Result<Self, Error> {
+        let (config, _digest) = pbs_config::s3::config()?;
+        let client: pbs_api_types::S3Client = config.lookup("s3client", client_id)?;
+
+        Self::new(S3ClientOptions::from_config(
+            client.config,
+            client.secret_key,
+            bucket.to_string(),
+        ))
+    }
+
+    /// Name of the bucket this client operates on.
+    pub fn bucket(&self) -> &str {
+        &self.options.bucket
+    }
+
+    /// Checks whether the bucket exists and is accessible with the configured credentials.
+    pub async fn head_bucket(&self) -> Result<(), Error> {
+        let response = self.send(Method::HEAD, "", &[], Vec::new()).await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND => bail!("bucket '{}' does not exist", self.bucket()),
+            StatusCode::FORBIDDEN => bail!("access to bucket '{}' denied", self.bucket()),
+            status => bail!("checking bucket '{}' failed - {status}", self.bucket()),
+        }
+    }
+
+    /// Uploads `data` as object `key`, replacing any existing object.
+    pub async fn put_object(&self, key: &str, data: Vec<u8>) -> Result<(), Error> {
+        let response = self.send(Method::PUT, key, &[], data).await?;
+        Self::check_response(response, "put", key).await?;
+        Ok(())
+    }
+
+    /// Downloads object `key`, returns `None` if it does not exist.
+    pub async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let response = self.send(Method::GET, key, &[], Vec::new()).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let data = Self::check_response(response, "get", key).await?;
+        Ok(Some(data))
+    }
+
+    /// Queries the metadata of object `key`, returns `None` if it does not exist.
+    pub async fn head_object(&self, key: &str) -> Result<Option<HeadObjectResponse>, Error> {
+        let response = self.send(Method::HEAD, key, &[], Vec::new()).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if !status.is_success() => bail!("head object '{key}' failed - {status}"),
+            _ => (),
+        }
+
+        let headers = response.headers();
+        let content_length = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| format_err!("head object '{key}' - missing content length"))?;
+        let last_modified = headers
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| format_err!("head object '{key}' - missing last modified"))?;
+
+        Ok(Some(HeadObjectResponse {
+            content_length,
+            last_modified: parse_http_date(last_modified)?,
+        }))
+    }
+
+    /// Deletes object `key`, deleting a non-existing object is not an error.
+    pub async fn delete_object(&self, key: &str) -> Result<(), Error> {
+        let response = self.send(Method::DELETE, key, &[], Vec::new()).await?;
+        Self::check_response(response, "delete", key).await?;
+        Ok(())
+    }
+
+    /// Lists one page of the objects whose key starts with `prefix`.
+    pub async fn list_objects_v2(
+        &self,
+        prefix: &str,
+        continuation_token: Option<&str>,
+    ) -> Result<ListObjectsV2Response, Error> {
+        let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+        if let Some(token) = continuation_token {
+            query.push(("continuation-token", token));
+        }
+
+        let response = self.send(Method::GET, "", &query, Vec::new()).await?;
+        let body = Self::check_response(response, "list", prefix).await?;
+        let body = std::str::from_utf8(&body)
+            .map_err(|err| format_err!("object listing is not valid utf8 - {err}"))?;
+
+        parse_list_objects_v2(body)
+    }
+
+    /// Lists all objects whose key starts with `prefix`, following continuation tokens.
+    pub async fn list_all_objects(&self, prefix: &str) -> Result<Vec<ListObjectsV2Item>, Error> {
+        let mut list = Vec::new();
+        let mut token = None;
+        loop {
+            let response = self.list_objects_v2(prefix, token.as_deref()).await?;
+            list.extend(response.contents);
+            match response.next_continuation_token {
+                Some(next) => token = Some(next),
+                None => return Ok(list),
+            }
+        }
+    }
+
+    async fn check_response(
+        response: Response<Body>,
+        operation: &str,
+        key: &str,
+    ) -> Result<Vec<u8>, Error> {
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            let message = String::from_utf8_lossy(&body);
+            bail!("S3 {operation} '{key}' failed - {status}: {message}");
+        }
+        Ok(body.to_vec())
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<Response<Body>, Error> {
+        let mut path = String::from("/");
+        if self.options.path_style {
+            path.push_str(&self.options.bucket);
+            if !key.is_empty() {
+                path.push('/');
+            }
+        }
+        path.push_str(&uri_encode(key, false));
+
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .into_iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<String>>()
+            .join("&");
+
+        let payload_hash = hex::encode(openssl::sha::sha256(&body));
+        let amz_date: String = proxmox_time::epoch_to_rfc3339_utc(proxmox_time::epoch_i64())?
+            .chars()
+            .filter(|c| *c != '-' && *c != ':')
+            .collect();
+
+        let authorization =
+            self.authorization(method.as_str(), &path, &query, &payload_hash, &amz_date)?;
+
+        let scheme = if self.options.use_http {
+            "http"
+        } else {
+            "https"
+        };
+        let uri = if query.is_empty() {
+            format!("{scheme}://{}{path}", self.authority)
+        } else {
+            format!("{scheme}://{}{path}?{query}", self.authority)
+        };
+
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(HOST, HeaderValue::from_str(&self.authority)?)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header(AUTHORIZATION, authorization)
+            .header(CONTENT_LENGTH, body.len())
+            .body(Body::from(body))?;
+
+        self.client
+            .request(request)
+            .await
+            .map_err(|err| format_err!("S3 request to '{}' failed - {err}", self.authority))
+    }
+
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        query: &str,
+        payload_hash: &str,
+        amz_date: &str,
+    ) -> Result<String, Error> {
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{}/s3/aws4_request", self.options.region);
+
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\nhost:{}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{SIGNED_HEADERS}\n{payload_hash}",
+            self.authority,
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(openssl::sha::sha256(canonical_request.as_bytes())),
+        );
+
+        let secret = format!("AWS4{}", self.options.secret_key);
+        let key = hmac_sha256(secret.as_bytes(), date.as_bytes())?;
+        let key = hmac_sha256(&key, self.options.region.as_bytes())?;
+        let key = hmac_sha256(&key, b"s3")?;
+        let key = hmac_sha256(&key, b"aws4_request")?;
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes())?);
+
+        Ok(format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={SIGNED_HEADERS}, Signature={signature}",
+            self.options.access_key,
+        ))
+    }
+}
+
+fn verify_fingerprint(
+    openssl_valid: bool,
+    ctx: &mut X509StoreContextRef,
+    expected_fingerprint: &str,
+) -> Result<(), Error> {
+    if openssl_valid {
+        return Ok(());
+    }
+
+    if ctx.error_depth() != 0 {
+        bail!("context depth != 0");
+    }
+
+    let cert = ctx
+        .current_cert()
+        .ok_or_else(|| format_err!("context lacks current certificate."))?;
+    let fp = cert.digest(MessageDigest::sha256())?;
+    let fp_string = fp
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<String>>()
+        .join(":");
+
+    if fp_string != expected_fingerprint {
+        bail!("fingerprint {fp_string} does not match expected fingerprint");
+    }
+
+    Ok(())
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Percent-encodes everything but unreserved characters, as required by the signature scheme.
+fn uri_encode(input: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Parses an RFC 7231 HTTP date, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`.
+fn parse_http_date(date: &str) -> Result<i64, Error> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        bail!("unable to parse http date '{date}'");
+    }
+    let month = MONTHS
+        .iter()
+        .position(|month| *month == parts[2])
+        .ok_or_else(|| format_err!("unable to parse http date '{date}'"))?;
+
+    proxmox_time::parse_rfc3339(&format!(
+        "{}-{:02}-{:0>2}T{}Z",
+        parts[3],
+        month + 1,
+        parts[1],
+        parts[4]
+    ))
+}
+
+/// Parses an ISO 8601 timestamp as used in listings, e.g. `2009-10-12T17:50:30.000Z`.
+fn parse_iso8601(timestamp: &str) -> Result<i64, Error> {
+    match timestamp.split_once('.') {
+        Some((time, _fraction)) => proxmox_time::parse_rfc3339(&format!("{time}Z")),
+        None => proxmox_time::parse_rfc3339(timestamp),
+    }
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Returns the contents of all `<tag>` elements in `xml`, nested elements are not supported.
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                elements.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    elements
+}
+
+fn xml_element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    xml_elements(xml, tag).into_iter().next()
+}
+
+fn parse_list_objects_v2(xml: &str) -> Result<ListObjectsV2Response, Error> {
+    let mut contents = Vec::new();
+    for entry in xml_elements(xml, "Contents") {
+        let key = xml_element(entry, "Key")
+            .ok_or_else(|| format_err!("object listing entry without key"))?;
+        let last_modified = xml_element(entry, "LastModified")
+            .ok_or_else(|| format_err!("object listing entry '{key}' without last modified"))?;
+        let size = xml_element(entry, "Size")
+            .ok_or_else(|| format_err!("object listing entry '{key}' without size"))?;
+
+        contents.push(ListObjectsV2Item {
+            key: xml_unescape(key),
+            last_modified: parse_iso8601(last_modified)?,
+            size: size.parse()?,
+        });
+    }
+
+    let truncated = xml_element(xml, "IsTruncated") == Some("true");
+    let next_continuation_token = match xml_element(xml, "NextContinuationToken") {
+        Some(token) if truncated => Some(xml_unescape(token)),
+        _ => None,
+    };
+
+    Ok(ListObjectsV2Response {
+        contents,
+        next_continuation_token,
+    })
+}
+
+#[test]
+fn test_list_objects_v2_parsing() -> Result<(), Error> {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>bucket</Name>
+  <Prefix>.chunks/</Prefix>
+  <KeyCount>2</KeyCount>
+  <IsTruncated>true</IsTruncated>
+  <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+  <Contents>
+    <Key>.chunks/0000/0000aa</Key>
+    <LastModified>2009-10-12T17:50:30.000Z</LastModified>
+    <Size>434234</Size>
+  </Contents>
+  <Contents>
+    <Key>ns/a&amp;b</Key>
+    <LastModified>2009-10-12T17:50:31Z</LastModified>
+    <Size>1</Size>
+  </Contents>
+</ListBucketResult>"#;
+
+    let response = parse_list_objects_v2(xml)?;
+    assert_eq!(response.contents.len(), 2);
+    assert_eq!(response.contents[0].key, ".chunks/0000/0000aa");
+    assert_eq!(response.contents[0].last_modified, 1255369830);
+    assert_eq!(response.contents[0].size, 434234);
+    assert_eq!(response.contents[1].key, "ns/a&b");
+    assert_eq!(response.contents[1].last_modified, 1255369831);
+    assert_eq!(
+        response.next_continuation_token.as_deref(),
+        Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=")
+    );
+
+    assert_eq!(
+        parse_http_date("Mon, 12 Oct 2009 17:50:30 GMT")?,
+        1255369830
+    );
+    assert_eq!(
+        uri_encode("vm/100/2023-01-01T00:00:00Z", false),
+        "vm/100/2023-01-01T00%3A00%3A00Z"
+    );
+
+    Ok(())
+}
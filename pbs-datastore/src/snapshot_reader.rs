@@ -96,6 +96,8 @@ impl SnapshotReader {
     }
 
     /// Opens a file inside the snapshot (using openat) for reading
+    ///
+    /// Files encrypted at rest are returned decrypted.
     pub fn open_file(&self, filename: &str) -> Result<File, Error> {
         let raw_fd = nix::fcntl::openat(
             self.locked_dir.as_raw_fd(),
@@ -104,7 +106,9 @@ impl SnapshotReader {
             nix::sys::stat::Mode::empty(),
         )?;
         let file = unsafe { File::from_raw_fd(raw_fd) };
-        Ok(file)
+        self.snapshot
+            .datastore()
+            .decode_snapshot_file(file, self.snapshot.relative_path().join(filename))
     }
 
     /// Returns an iterator for all chunks not skipped by `skip_fn`.
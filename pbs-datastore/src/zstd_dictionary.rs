@@ -0,0 +1,274 @@
+//! Trained zstd dictionaries
+//!
+//! Small blobs and chunks compress badly on their own, as zstd has little data to learn from. A
+//! dictionary trained on typical data of a datastore improves the ratio considerably. Blobs
+//! compressed with a dictionary reference it by ID, so everyone decoding them needs the same
+//! dictionary: they are stored in the [`DICTIONARY_DIR`] of the datastore and registered in a
+//! process wide table before use.
+//!
+//! The ID is only a short prefix of the dictionary's digest, so dictionaries are registered per
+//! datastore (or, on clients, per repository), and the ID must be unique within it.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use lazy_static::lazy_static;
+
+use proxmox_sys::fs::{file_get_contents, replace_file, CreateOptions};
+
+/// Directory below the datastore base path holding the dictionaries.
+pub const DICTIONARY_DIR: &str = ".zstd-dictionaries";
+
+/// Default maximum size of trained dictionaries.
+pub const DEFAULT_DICTIONARY_SIZE: usize = 112 * 1024;
+
+/// Upper limit for the size of dictionaries.
+pub const MAX_DICTIONARY_SIZE: usize = 1024 * 1024;
+
+lazy_static! {
+    static ref DICTIONARY_MAP: Mutex<HashMap<(String, [u8; 32]), Arc<ZstdDictionary>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// A trained zstd dictionary
+pub struct ZstdDictionary {
+    id: u32,
+    digest: [u8; 32],
+    data: Vec<u8>,
+}
+
+impl ZstdDictionary {
+    /// Create a dictionary from its raw data, the ID is derived from its content.
+    pub fn new(data: Vec<u8>) -> Self {
+        let digest = openssl::sha::sha256(&data);
+        let id = u32::from_le_bytes(digest[0..4].try_into().unwrap());
+        Self { id, digest, data }
+    }
+
+    /// Train a dictionary of at most `max_size` bytes on `samples`.
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Self, Error> {
+        let data = zstd::dict::from_samples(samples, max_size)
+            .map_err(|err| format_err!("training zstd dictionary failed - {err}"))?;
+        Ok(Self::new(data))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The SHA-256 digest of the dictionary data, the ID is its prefix.
+    pub fn digest(&self) -> &[u8; 32] {
+        &self.digest
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// File name of the dictionary with `id` inside the dictionary directory.
+    pub fn file_name(id: u32) -> String {
+        format!("{id:08x}.dict")
+    }
+
+    /// Load the dictionary with `id` from `dir`.
+    pub fn load(dir: &Path, id: u32) -> Result<Self, Error> {
+        Self::load_with(dir, id, Ok)
+    }
+
+    /// Like [`load`](Self::load), but passes the file content through `decode`, for example to
+    /// decrypt it.
+    pub fn load_with<F>(dir: &Path, id: u32, decode: F) -> Result<Self, Error>
+    where
+        F: FnOnce(Vec<u8>) -> Result<Vec<u8>, Error>,
+    {
+        let path = dir.join(Self::file_name(id));
+        let dict = Self::new(decode(file_get_contents(&path)?)?);
+        if dict.id != id {
+            bail!("zstd dictionary {path:?} is corrupt (ID mismatch)");
+        }
+        Ok(dict)
+    }
+
+    /// Store the dictionary in `dir`, creating the directory if needed.
+    pub fn save(&self, dir: &Path, options: CreateOptions) -> Result<PathBuf, Error> {
+        self.save_with(dir, options, |data| Ok(Cow::Borrowed(data)))
+    }
+
+    /// Like [`save`](Self::save), but stores the data returned by `encode`, for example to
+    /// encrypt it.
+    pub fn save_with<F>(
+        &self,
+        dir: &Path,
+        options: CreateOptions,
+        encode: F,
+    ) -> Result<PathBuf, Error>
+    where
+        F: for<'a> FnOnce(&'a [u8]) -> Result<Cow<'a, [u8]>, Error>,
+    {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(Self::file_name(self.id));
+        replace_file(&path, &encode(&self.data)?, options, true)?;
+        Ok(path)
+    }
+}
+
+fn find_dictionary(
+    map: &HashMap<(String, [u8; 32]), Arc<ZstdDictionary>>,
+    store: &str,
+    id: u32,
+) -> Option<Arc<ZstdDictionary>> {
+    map.iter()
+        .find(|((dict_store, _), dict)| dict_store == store && dict.id == id)
+        .map(|(_, dict)| Arc::clone(dict))
+}
+
+/// Make `dict` available for decoding blobs of the datastore (or repository) `store`.
+///
+/// Returns the already registered dictionary if it has the same digest. A different dictionary
+/// with the same ID is never replaced, as blobs could not tell them apart.
+pub fn register_dictionary(
+    store: &str,
+    dict: ZstdDictionary,
+) -> Result<Arc<ZstdDictionary>, Error> {
+    let mut map = DICTIONARY_MAP.lock().unwrap();
+    if let Some(registered) = find_dictionary(&map, store, dict.id) {
+        if registered.digest != dict.digest {
+            bail!(
+                "zstd dictionary {:08x} of '{store}' conflicts with a different registered \
+                dictionary with the same ID",
+                dict.id,
+            );
+        }
+        return Ok(registered);
+    }
+
+    let dict = Arc::new(dict);
+    map.insert((store.to_string(), dict.digest), Arc::clone(&dict));
+    Ok(dict)
+}
+
+/// Look up a dictionary registered for the datastore (or repository) `store`.
+pub fn lookup_dictionary(store: &str, id: u32) -> Option<Arc<ZstdDictionary>> {
+    let map = DICTIONARY_MAP.lock().unwrap();
+    find_dictionary(&map, store, id)
+}
+
+/// List the IDs of all dictionaries stored in `dir`.
+///
+/// A missing directory is not an error, there simply are no dictionaries.
+pub fn list_dictionaries(dir: &Path) -> Result<Vec<u32>, Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => bail!("unable to read zstd dictionary directory {dir:?} - {err}"),
+    };
+
+    let mut list = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        if let Some(id) = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".dict"))
+            .and_then(|id| u32::from_str_radix(id, 16).ok())
+        {
+            list.push(id);
+        }
+    }
+
+    Ok(list)
+}
+
+/// Register all dictionaries stored in `dir` for `store`, returns the number of dictionaries
+/// found.
+pub fn register_dictionary_dir(store: &str, dir: &Path) -> Result<usize, Error> {
+    let list = list_dictionaries(dir)?;
+    for id in list.iter().copied() {
+        if lookup_dictionary(store, id).is_none() {
+            register_dictionary(store, ZstdDictionary::load(dir, id)?)?;
+        }
+    }
+    Ok(list.len())
+}
+
+#[test]
+fn test_dictionary_id() {
+    let dict = ZstdDictionary::new(b"some dictionary content".to_vec());
+    assert_eq!(ZstdDictionary::new(dict.data().to_vec()).id(), dict.id());
+    assert_eq!(ZstdDictionary::file_name(0x1234abcd), "1234abcd.dict");
+}
+
+#[test]
+fn test_dictionary_blob_roundtrip() -> Result<(), Error> {
+    use crate::data_blob::{Compression, DataBlob};
+
+    let dictionary = register_dictionary(
+        "test-roundtrip",
+        ZstdDictionary::new(b"proxmox backup test dictionary ".repeat(16)),
+    )?;
+    let compression = Compression {
+        level: 3,
+        dictionary: Some(Arc::clone(&dictionary)),
+    };
+
+    let data = b"proxmox backup test data ".repeat(64);
+    let blob = DataBlob::encode_with_compression(&data, None, Some(&compression))?;
+    assert_eq!(blob.zstd_dictionary_id(), Some(dictionary.id()));
+    assert_eq!(blob.decode_for_store("test-roundtrip", None, None)?, data);
+    // dictionaries are only available to the store they are registered for
+    assert!(blob.decode_for_store("test-other", None, None).is_err());
+    assert!(blob.decode(None, None).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_dictionary_registration() -> Result<(), Error> {
+    let dict = ZstdDictionary::new(b"first dictionary".to_vec());
+    let id = dict.id();
+    let digest = *dict.digest();
+
+    let registered = register_dictionary("test-registration", dict)?;
+    assert_eq!(registered.digest(), &digest);
+
+    // registering the same dictionary again returns the registered one
+    let again = register_dictionary(
+        "test-registration",
+        ZstdDictionary::new(b"first dictionary".to_vec()),
+    )?;
+    assert!(Arc::ptr_eq(&registered, &again));
+
+    // a different dictionary with the same ID must not replace it
+    let conflicting = ZstdDictionary {
+        id,
+        digest: [0u8; 32],
+        data: b"second dictionary".to_vec(),
+    };
+    assert!(register_dictionary("test-registration", conflicting).is_err());
+    assert!(Arc::ptr_eq(
+        &lookup_dictionary("test-registration", id).unwrap(),
+        &registered
+    ));
+
+    // but other stores are independent
+    let other = ZstdDictionary {
+        id,
+        digest: [0u8; 32],
+        data: b"second dictionary".to_vec(),
+    };
+    register_dictionary("test-registration-other", other)?;
+    assert_eq!(
+        lookup_dictionary("test-registration-other", id)
+            .unwrap()
+            .digest(),
+        &[0u8; 32]
+    );
+    assert_eq!(
+        lookup_dictionary("test-registration", id).unwrap().digest(),
+        &digest
+    );
+
+    Ok(())
+}
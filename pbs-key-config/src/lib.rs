@@ -11,6 +11,8 @@ use pbs_api_types::{Fingerprint, Kdf, KeyInfo};
 
 use pbs_tools::crypt_config::CryptConfig;
 
+mod shamir;
+
 /// Key derivation function configuration
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum KeyDerivationConfig {
@@ -287,7 +289,24 @@ pub fn rsa_encrypt_key_config(
     rsa: openssl::rsa::Rsa<openssl::pkey::Public>,
     key: &KeyConfig,
 ) -> Result<Vec<u8>, Error> {
-    let data = serde_json::to_string(key)?.as_bytes().to_vec();
+    rsa_encrypt_json(rsa, key)
+}
+
+/// RSA deccrypt a KeyConfig using a private key
+pub fn rsa_decrypt_key_config(
+    rsa: openssl::rsa::Rsa<openssl::pkey::Private>,
+    key: &[u8],
+    passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
+) -> Result<([u8; 32], i64, Fingerprint), Error> {
+    let data = rsa_decrypt_data(rsa, key)?;
+    decrypt_key(&data, passphrase)
+}
+
+fn rsa_encrypt_json<T: Serialize>(
+    rsa: openssl::rsa::Rsa<openssl::pkey::Public>,
+    value: &T,
+) -> Result<Vec<u8>, Error> {
+    let data = serde_json::to_string(value)?.as_bytes().to_vec();
 
     let mut buffer = vec![0u8; rsa.size() as usize];
     let len = rsa.public_encrypt(&data, &mut buffer, openssl::rsa::Padding::PKCS1)?;
@@ -297,17 +316,132 @@ pub fn rsa_encrypt_key_config(
     Ok(buffer)
 }
 
-/// RSA deccrypt a KeyConfig using a private key
-pub fn rsa_decrypt_key_config(
+fn rsa_decrypt_data(
     rsa: openssl::rsa::Rsa<openssl::pkey::Private>,
-    key: &[u8],
-    passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
-) -> Result<([u8; 32], i64, Fingerprint), Error> {
+    data: &[u8],
+) -> Result<Vec<u8>, Error> {
     let mut buffer = vec![0u8; rsa.size() as usize];
     let decrypted = rsa
-        .private_decrypt(key, &mut buffer, openssl::rsa::Padding::PKCS1)
+        .private_decrypt(data, &mut buffer, openssl::rsa::Padding::PKCS1)
         .map_err(|err| format_err!("failed to decrypt KeyConfig using RSA - {}", err))?;
-    decrypt_key(&buffer[..decrypted], passphrase)
+    buffer.truncate(decrypted);
+    Ok(buffer)
+}
+
+/// One share of an encryption key split with Shamir's secret sharing
+///
+/// Each share gets encrypted with a different master key, so that `threshold` master key
+/// holders are needed to restore the encryption key.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct KeyShare {
+    /// Number of shares required to restore the key
+    pub threshold: u8,
+    /// Share index, starting at 1
+    pub index: u8,
+    #[serde(with = "proxmox_serde::epoch_as_rfc3339")]
+    pub created: i64,
+    /// Fingerprint of the split key
+    pub fingerprint: Fingerprint,
+    #[serde(with = "proxmox_serde::bytes_as_base64")]
+    pub data: Vec<u8>,
+}
+
+/// Split an encryption key into `count` shares, any `threshold` of which restore the key.
+pub fn split_key(
+    key: &[u8; 32],
+    created: i64,
+    threshold: u8,
+    count: u8,
+) -> Result<Vec<KeyShare>, Error> {
+    let fingerprint = Fingerprint::new(CryptConfig::new(*key)?.fingerprint());
+
+    let shares = shamir::split_secret(key, threshold, count)?
+        .into_iter()
+        .map(|(index, data)| KeyShare {
+            threshold,
+            index,
+            created,
+            fingerprint: fingerprint.clone(),
+            data,
+        })
+        .collect();
+
+    Ok(shares)
+}
+
+/// Restore an encryption key from shares created by [`split_key`].
+pub fn combine_key_shares(shares: &[KeyShare]) -> Result<([u8; 32], i64, Fingerprint), Error> {
+    let first = match shares.first() {
+        Some(first) => first,
+        None => bail!("no key shares given"),
+    };
+
+    if shares
+        .iter()
+        .any(|share| share.fingerprint != first.fingerprint || share.threshold != first.threshold)
+    {
+        bail!("key shares belong to different keys");
+    }
+
+    let threshold = first.threshold as usize;
+    if shares.len() < threshold {
+        bail!(
+            "got {} key shares, but {} are required to restore the key",
+            shares.len(),
+            threshold,
+        );
+    }
+
+    let shares: Vec<(u8, Vec<u8>)> = shares[..threshold]
+        .iter()
+        .map(|share| (share.index, share.data.clone()))
+        .collect();
+
+    let key: [u8; 32] = shamir::combine_shares(&shares)?
+        .try_into()
+        .map_err(|_| format_err!("restored key has wrong length"))?;
+
+    let fingerprint = Fingerprint::new(CryptConfig::new(key)?.fingerprint());
+    if fingerprint != first.fingerprint {
+        bail!(
+            "restored key has wrong fingerprint {} (expected {})",
+            fingerprint,
+            first.fingerprint,
+        );
+    }
+
+    Ok((key, first.created, fingerprint))
+}
+
+/// RSA encrypt a KeyShare using a public key
+pub fn rsa_encrypt_key_share(
+    rsa: openssl::rsa::Rsa<openssl::pkey::Public>,
+    share: &KeyShare,
+) -> Result<Vec<u8>, Error> {
+    rsa_encrypt_json(rsa, share)
+}
+
+/// Content of an RSA encrypted key blob
+pub enum RecoveryKey {
+    /// The complete key, as `(key, created, fingerprint)`
+    Key([u8; 32], i64, Fingerprint),
+    /// A single share of a split key
+    Share(KeyShare),
+}
+
+/// RSA decrypt either a KeyConfig or a KeyShare using a private key
+pub fn rsa_decrypt_recovery_key(
+    rsa: openssl::rsa::Rsa<openssl::pkey::Private>,
+    key: &[u8],
+    passphrase: &dyn Fn() -> Result<Vec<u8>, Error>,
+) -> Result<RecoveryKey, Error> {
+    let data = rsa_decrypt_data(rsa, key)?;
+    if let Ok(share) = serde_json::from_slice::<KeyShare>(&data) {
+        return Ok(RecoveryKey::Share(share));
+    }
+    let (key, created, fingerprint) = decrypt_key(&data, passphrase)?;
+    Ok(RecoveryKey::Key(key, created, fingerprint))
 }
 
 #[test]
@@ -393,3 +527,27 @@ fn fingerprint_checks() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn key_share_test() -> Result<(), Error> {
+    let key: [u8; 32] = (0u8..32u8).collect::<Vec<u8>>().try_into().unwrap();
+    let created = proxmox_time::epoch_i64();
+
+    let shares = split_key(&key, created, 2, 3)?;
+    assert_eq!(shares.len(), 3);
+
+    let (restored, restored_created, fingerprint) = combine_key_shares(&shares[1..])?;
+    assert_eq!(restored, key);
+    assert_eq!(restored_created, created);
+    assert_eq!(fingerprint, shares[0].fingerprint);
+
+    combine_key_shares(&shares[..1]).expect_err("restoring key from a single share worked");
+
+    // shares must never be mistaken for a KeyConfig and vice versa
+    let data = serde_json::to_vec(&shares[0])?;
+    serde_json::from_slice::<KeyConfig>(&data).expect_err("parsed KeyShare as KeyConfig");
+    let data = serde_json::to_vec(&KeyConfig::without_password(key)?)?;
+    serde_json::from_slice::<KeyShare>(&data).expect_err("parsed KeyConfig as KeyShare");
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+//! Shamir's secret sharing over GF(2^8)
+//!
+//! Every byte of the secret is split independently, using a random polynomial of degree
+//! `threshold - 1` whose constant term is the secret byte. A share consists of the x coordinate
+//! (`1..=255`) and the evaluated polynomials, so any `threshold` shares can restore the secret,
+//! while fewer shares reveal nothing about it.
+
+use anyhow::{bail, Error};
+
+/// Multiply in GF(2^8), using the AES reduction polynomial (x^8 + x^4 + x^3 + x + 1).
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse in GF(2^8), computed as `a^254`.
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exp = 254u8;
+    while exp != 0 {
+        if exp & 1 != 0 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Split `secret` into `count` shares, any `threshold` of which restore it.
+///
+/// Returns `(x, data)` tuples, with `x` starting at 1.
+pub fn split_secret(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<(u8, Vec<u8>)>, Error> {
+    if threshold < 1 {
+        bail!("threshold must be at least 1");
+    }
+    if count < threshold {
+        bail!("got {count} shares, but threshold is {threshold}");
+    }
+
+    let degree = threshold as usize - 1;
+    let mut coefficients = vec![0u8; secret.len() * degree];
+    proxmox_sys::linux::fill_with_random_data(&mut coefficients)?;
+
+    let shares = (1..=count)
+        .map(|x| {
+            let data = secret
+                .iter()
+                .enumerate()
+                .map(|(pos, byte)| {
+                    // Horner's method, highest coefficient first, the secret byte is the constant
+                    coefficients[pos * degree..(pos + 1) * degree]
+                        .iter()
+                        .rev()
+                        .chain(std::iter::once(byte))
+                        .fold(0u8, |acc, c| gf_mul(acc, x) ^ c)
+                })
+                .collect();
+            (x, data)
+        })
+        .collect();
+
+    Ok(shares)
+}
+
+/// Restore a secret from shares created by [`split_secret`].
+///
+/// All passed shares are used for the interpolation, so pass exactly `threshold` of them.
+pub fn combine_shares(shares: &[(u8, Vec<u8>)]) -> Result<Vec<u8>, Error> {
+    let len = match shares.first() {
+        Some((_, data)) => data.len(),
+        None => bail!("no shares given"),
+    };
+
+    for (i, (x, data)) in shares.iter().enumerate() {
+        if *x == 0 {
+            bail!("invalid share index 0");
+        }
+        if data.len() != len {
+            bail!("shares differ in length");
+        }
+        if shares[..i].iter().any(|(other, _)| other == x) {
+            bail!("got share {x} more than once");
+        }
+    }
+
+    // Lagrange interpolation at x = 0
+    let mut secret = vec![0u8; len];
+    for (i, (xi, data)) in shares.iter().enumerate() {
+        let mut basis = 1u8;
+        for (j, (xj, _)) in shares.iter().enumerate() {
+            if i != j {
+                basis = gf_mul(basis, gf_mul(*xj, gf_inv(xj ^ xi)));
+            }
+        }
+        for (byte, y) in secret.iter_mut().zip(data) {
+            *byte ^= gf_mul(basis, *y);
+        }
+    }
+
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gf_inverse() {
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn test_split_combine() -> Result<(), Error> {
+        let secret: Vec<u8> = (0u8..32u8).collect();
+
+        let shares = split_secret(&secret, 3, 5)?;
+        assert_eq!(shares.len(), 5);
+
+        assert_eq!(combine_shares(&shares[..3])?, secret);
+        assert_eq!(combine_shares(&shares[2..])?, secret);
+        let picked = [shares[4].clone(), shares[0].clone(), shares[2].clone()];
+        assert_eq!(combine_shares(&picked)?, secret);
+
+        // too few shares interpolate a different value
+        assert_ne!(combine_shares(&shares[..2])?, secret);
+
+        assert!(combine_shares(&[shares[0].clone(), shares[0].clone()]).is_err());
+        assert!(split_secret(&secret, 3, 2).is_err());
+
+        let shares = split_secret(&secret, 1, 2)?;
+        assert_eq!(shares[0].1, secret);
+
+        Ok(())
+    }
+}
@@ -14,9 +14,10 @@ use proxmox_router::{
 };
 use proxmox_schema::{api, ApiType, ReturnType};
 
-use pbs_api_types::{BackupNamespace, BackupType};
+use pbs_api_types::{BackupNamespace, BackupType, ChunkerType};
 use pbs_client::tools::key_source::get_encryption_key_password;
 use pbs_client::{BackupRepository, BackupWriter};
+use pbs_datastore::chunker::ChunkerConfig;
 use pbs_datastore::data_blob::{DataBlob, DataChunkBuilder};
 use pbs_key_config::{load_and_decrypt_key, KeyDerivationConfig};
 use pbs_tools::crypt_config::CryptConfig;
@@ -56,6 +57,12 @@ struct Speed {
         "verify": {
             type: Speed,
         },
+        "chunker_buzhash": {
+            type: Speed,
+        },
+        "chunker_fastcdc": {
+            type: Speed,
+        },
     },
 )]
 #[derive(Copy, Clone, Serialize)]
@@ -73,6 +80,10 @@ struct BenchmarkResult {
     aes256_gcm: Speed,
     /// Verify speed
     verify: Speed,
+    /// Buzhash chunker speed
+    chunker_buzhash: Speed,
+    /// FastCDC chunker speed
+    chunker_fastcdc: Speed,
 }
 
 static BENCHMARK_RESULT_2020_TOP: BenchmarkResult = BenchmarkResult {
@@ -100,6 +111,14 @@ static BENCHMARK_RESULT_2020_TOP: BenchmarkResult = BenchmarkResult {
         speed: None,
         top: 1_000_000.0 * 758.0, // AMD Ryzen 7 2700X
     },
+    chunker_buzhash: Speed {
+        speed: None,
+        top: 1_000_000.0 * 460.0, // Intel Xeon, no AMD Ryzen 7 2700X reference yet
+    },
+    chunker_fastcdc: Speed {
+        speed: None,
+        top: 1_000_000.0 * 860.0, // Intel Xeon, no AMD Ryzen 7 2700X reference yet
+    },
 };
 
 #[api(
@@ -210,6 +229,18 @@ fn render_result(output_format: &str, benchmark_result: &BenchmarkResult) -> Res
                 .header("AES256 GCM encryption speed")
                 .right_align(false)
                 .renderer(render_speed),
+        )
+        .column(
+            ColumnConfig::new("chunker_buzhash")
+                .header("Buzhash chunker speed")
+                .right_align(false)
+                .renderer(render_speed),
+        )
+        .column(
+            ColumnConfig::new("chunker_fastcdc")
+                .header("FastCDC chunker speed")
+                .right_align(false)
+                .renderer(render_speed),
         );
 
     format_and_print_result_full(&mut data, &return_type, output_format, &options);
@@ -236,6 +267,7 @@ async fn test_upload_speed(
         &(BackupType::Host, "benchmark".to_string(), backup_time).into(),
         false,
         true,
+        false,
     )
     .await?;
 
@@ -361,5 +393,38 @@ fn test_crypt_speed(benchmark_result: &mut BenchmarkResult) -> Result<(), Error>
 
     log::info!("Verify speed: {:.2} MB/s", speed / 1_000_000.0);
 
+    for chunker_type in [ChunkerType::Buzhash, ChunkerType::Fastcdc] {
+        let config = ChunkerConfig::new(chunker_type, 64 * 1024)?;
+        let mut chunker = config.create();
+
+        let start_time = std::time::Instant::now();
+
+        let mut bytes = 0;
+        loop {
+            let mut pos = 0;
+            while pos < random_data.len() {
+                let boundary = chunker.scan(&random_data[pos..]);
+                if boundary == 0 {
+                    break;
+                }
+                pos += boundary;
+            }
+            chunker.reset();
+            bytes += random_data.len();
+            if start_time.elapsed().as_micros() > 1_000_000 {
+                break;
+            }
+        }
+        let speed = (bytes as f64) / start_time.elapsed().as_secs_f64();
+
+        let (result, name) = match chunker_type {
+            ChunkerType::Buzhash => (&mut benchmark_result.chunker_buzhash, "Buzhash"),
+            ChunkerType::Fastcdc => (&mut benchmark_result.chunker_fastcdc, "FastCDC"),
+        };
+        result.speed = Some(speed);
+
+        log::info!("{name} chunker speed: {:.2} MB/s", speed / 1_000_000.0);
+    }
+
     Ok(())
 }
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+use std::future::Future;
 use std::io::{Seek, SeekFrom};
 use std::os::unix::fs::OpenOptionsExt;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
 use serde_json::Value;
@@ -8,18 +11,22 @@ use serde_json::Value;
 use proxmox_router::cli::*;
 use proxmox_schema::api;
 
-use pbs_api_types::BackupNamespace;
-use pbs_client::tools::key_source::get_encryption_key_password;
-use pbs_client::{BackupReader, RemoteChunkReader};
+use pbs_api_types::{BackupGroup, BackupNamespace, Fingerprint, SnapshotListItem};
+use pbs_client::catalog_shell::SnapshotCatalogs;
+use pbs_client::tools::key_source::{
+    get_encryption_key_password, select_key_for_fingerprint, KeyWithSource,
+};
+use pbs_client::{BackupReader, BackupRepository, RemoteChunkReader};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json::required_string_param;
 
 use crate::{
-    complete_backup_snapshot, complete_group_or_snapshot, complete_namespace,
-    complete_pxar_archive_name, complete_repository, connect, crypto_parameters, decrypt_key,
-    dir_or_last_from_group, extract_repository_from_value, format_key_source, optional_ns_param,
-    record_repository, BackupDir, BufferedDynamicReadAt, BufferedDynamicReader, CatalogReader,
-    DynamicIndexReader, IndexFile, Shell, CATALOG_NAME, KEYFD_SCHEMA, REPO_URL_SCHEMA,
+    api_datastore_list_snapshots, complete_backup_snapshot, complete_group_or_snapshot,
+    complete_namespace, complete_pxar_archive_name, complete_repository, connect,
+    crypto_parameters, decrypt_key, dir_or_last_from_group, extract_repository_from_value,
+    format_key_source, optional_ns_param, record_repository, select_snapshot_key, BackupDir,
+    BufferedDynamicReadAt, BufferedDynamicReader, CatalogReader, DynamicIndexReader, IndexFile,
+    Shell, CATALOG_NAME, KEYFD_SCHEMA, REPO_URL_SCHEMA,
 };
 
 #[api(
@@ -57,7 +64,11 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
     let path = required_string_param(&param, "snapshot")?;
     let snapshot: BackupDir = path.parse()?;
 
-    let crypto = crypto_parameters(&param)?;
+    let client = connect(&repo)?;
+
+    let mut crypto = crypto_parameters(&param)?;
+    crypto.enc_key =
+        select_snapshot_key(&client, repo.store(), &backup_ns, &snapshot, crypto.enc_key).await?;
 
     let crypt_config = match crypto.enc_key {
         None => None,
@@ -72,8 +83,6 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
         }
     };
 
-    let client = connect(&repo)?;
-
     let client = BackupReader::start(
         client,
         crypt_config.clone(),
@@ -84,6 +93,20 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
     )
     .await?;
 
+    let mut catalog_reader = download_catalog(&client, crypt_config).await?;
+
+    catalog_reader.dump()?;
+
+    record_repository(&repo);
+
+    Ok(Value::Null)
+}
+
+/// Downloads the catalog of the snapshot opened by `client`.
+async fn download_catalog(
+    client: &Arc<BackupReader>,
+    crypt_config: Option<Arc<CryptConfig>>,
+) -> Result<CatalogReader<std::fs::File>, Error> {
     let (manifest, _) = client.download_manifest().await?;
     manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
 
@@ -115,13 +138,132 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
 
     catalogfile.seek(SeekFrom::Start(0))?;
 
-    let mut catalog_reader = CatalogReader::new(catalogfile);
+    Ok(CatalogReader::new(catalogfile))
+}
 
-    catalog_reader.dump()?;
+/// The other snapshots of the group opened in the catalog shell.
+///
+/// Snapshots created before a key rotation get decrypted with the matching retired key, see
+/// `select_snapshot_key`.
+struct GroupCatalogs {
+    repo: BackupRepository,
+    ns: BackupNamespace,
+    group: BackupGroup,
+    enc_key: Option<KeyWithSource>,
+    /// Decrypted keys, so that each key's password is only asked for once
+    crypt_configs: Mutex<HashMap<Vec<u8>, Arc<CryptConfig>>>,
+    /// Key fingerprints of the group's snapshots, from the last listing
+    fingerprints: Mutex<Vec<(BackupDir, Option<Fingerprint>)>>,
+}
 
-    record_repository(&repo);
+impl GroupCatalogs {
+    async fn list_group(&self) -> Result<Vec<SnapshotListItem>, Error> {
+        let client = connect(&self.repo)?;
+        let list =
+            api_datastore_list_snapshots(&client, self.repo.store(), &self.ns, Some(&self.group))
+                .await?;
+        let list: Vec<SnapshotListItem> = serde_json::from_value(list)?;
+
+        *self.fingerprints.lock().unwrap() = list
+            .iter()
+            .map(|item| (item.backup.clone(), item.fingerprint.clone()))
+            .collect();
+
+        Ok(list)
+    }
+
+    // The group only gets listed again for snapshots missing in the last listing.
+    async fn snapshot_fingerprint(
+        &self,
+        snapshot: &BackupDir,
+    ) -> Result<Option<Fingerprint>, Error> {
+        let lookup = |fingerprints: &[(BackupDir, Option<Fingerprint>)]| {
+            fingerprints
+                .iter()
+                .find(|(backup, _)| backup == snapshot)
+                .map(|(_, fingerprint)| fingerprint.clone())
+        };
+
+        let cached = lookup(&self.fingerprints.lock().unwrap());
+        if let Some(fingerprint) = cached {
+            return Ok(fingerprint);
+        }
+        self.list_group().await?;
+        Ok(lookup(&self.fingerprints.lock().unwrap()).flatten())
+    }
 
-    Ok(Value::Null)
+    fn crypt_config(&self, key: &KeyWithSource) -> Result<Arc<CryptConfig>, Error> {
+        if let Some(crypt_config) = self.crypt_configs.lock().unwrap().get(&key.key) {
+            return Ok(Arc::clone(crypt_config));
+        }
+
+        let (decrypted, _created, _fingerprint) =
+            decrypt_key(&key.key, &get_encryption_key_password).map_err(|err| {
+                log::error!("{}", format_key_source(&key.source, "encryption"));
+                err
+            })?;
+        let crypt_config = Arc::new(CryptConfig::new(decrypted)?);
+
+        self.crypt_configs
+            .lock()
+            .unwrap()
+            .insert(key.key.clone(), Arc::clone(&crypt_config));
+
+        Ok(crypt_config)
+    }
+}
+
+impl SnapshotCatalogs for GroupCatalogs {
+    fn list_snapshots<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<String>, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut list = self.list_group().await?;
+
+            list.sort_unstable_by(|a, b| a.backup.time.cmp(&b.backup.time));
+
+            Ok(list
+                .into_iter()
+                .map(|item| item.backup.to_string())
+                .collect())
+        })
+    }
+
+    fn open_catalog<'a>(
+        &'a self,
+        snapshot: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CatalogReader<std::fs::File>, Error>> + Send + 'a>>
+    {
+        Box::pin(async move {
+            // allow to just pass the backup time of a snapshot of the same group
+            let snapshot: BackupDir = match snapshot.parse() {
+                Ok(snapshot) => snapshot,
+                Err(_) => (self.group.clone(), proxmox_time::parse_rfc3339(snapshot)?).into(),
+            };
+
+            let fingerprint = match self.enc_key {
+                Some(_) => self.snapshot_fingerprint(&snapshot).await?,
+                None => None,
+            };
+            let enc_key = select_key_for_fingerprint(fingerprint.as_ref(), self.enc_key.clone())?;
+            let crypt_config = match enc_key {
+                None => None,
+                Some(key) => Some(self.crypt_config(&key)?),
+            };
+
+            let client = BackupReader::start(
+                connect(&self.repo)?,
+                crypt_config.clone(),
+                self.repo.store(),
+                &self.ns,
+                &snapshot,
+                true,
+            )
+            .await?;
+
+            download_catalog(&client, crypt_config).await
+        })
+    }
 }
 
 #[api(
@@ -167,17 +309,27 @@ async fn catalog_shell(param: Value) -> Result<(), Error> {
 
     let crypto = crypto_parameters(&param)?;
 
-    let crypt_config = match crypto.enc_key {
+    let group_catalogs = GroupCatalogs {
+        repo: repo.clone(),
+        ns: backup_ns.clone(),
+        group: backup_dir.group.clone(),
+        enc_key: crypto.enc_key.clone(),
+        crypt_configs: Mutex::new(HashMap::new()),
+        fingerprints: Mutex::new(Vec::new()),
+    };
+
+    let enc_key = select_snapshot_key(
+        &client,
+        repo.store(),
+        &backup_ns,
+        &backup_dir,
+        crypto.enc_key,
+    )
+    .await?;
+
+    let crypt_config = match enc_key {
         None => None,
-        Some(key) => {
-            let (key, _created, _fingerprint) = decrypt_key(&key.key, &get_encryption_key_password)
-                .map_err(|err| {
-                    log::error!("{}", format_key_source(&key.source, "encryption"));
-                    err
-                })?;
-            let crypt_config = CryptConfig::new(key)?;
-            Some(Arc::new(crypt_config))
-        }
+        Some(key) => Some(group_catalogs.crypt_config(&key)?),
     };
 
     let server_archive_name = if archive_name.ends_with(".pxar") {
@@ -251,7 +403,9 @@ async fn catalog_shell(param: Value) -> Result<(), Error> {
 
     catalogfile.seek(SeekFrom::Start(0))?;
     let catalog_reader = CatalogReader::new(catalogfile);
-    let state = Shell::new(catalog_reader, &server_archive_name, decoder).await?;
+    let state = Shell::new(catalog_reader, &server_archive_name, decoder)
+        .await?
+        .with_snapshot_catalogs(Box::new(group_catalogs));
 
     log::info!("Starting interactive shell");
     state.shell().await?;
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::path::PathBuf;
 
 use anyhow::{bail, format_err, Error};
@@ -8,17 +9,25 @@ use proxmox_router::cli::{
     complete_file_name, format_and_print_result_full, get_output_format, CliCommand, CliCommandMap,
     ColumnConfig, OUTPUT_FORMAT,
 };
-use proxmox_schema::{api, ApiType, ReturnType};
+use proxmox_schema::{api, ApiType, ArraySchema, ReturnType};
 use proxmox_sys::fs::{file_get_contents, replace_file, CreateOptions};
 use proxmox_sys::linux::tty;
 
-use pbs_api_types::{Kdf, KeyInfo, PASSWORD_HINT_SCHEMA};
+use pbs_api_types::{
+    BackupNamespace, Fingerprint, Kdf, KeyInfo, SnapshotListItem, PASSWORD_HINT_SCHEMA,
+};
 use pbs_client::tools::key_source::{
-    find_default_encryption_key, find_default_master_pubkey, get_encryption_key_password,
-    place_default_encryption_key, place_default_master_pubkey,
+    crypto_parameters, find_default_encryption_key, find_default_master_pubkey,
+    get_encryption_key_password, list_retired_encryption_keys, place_default_encryption_key,
+    place_default_master_pubkey, place_retired_encryption_key, KEYFD_SCHEMA, KEYFILE_SCHEMA,
 };
 use pbs_datastore::paperkey::{generate_paper_key, PaperkeyFormat};
-use pbs_key_config::{rsa_decrypt_key_config, KeyConfig};
+use pbs_key_config::{combine_key_shares, rsa_decrypt_recovery_key, KeyConfig, RecoveryKey};
+
+use crate::{
+    api_datastore_list_snapshots, complete_namespace, complete_repository, connect,
+    extract_repository_from_value, optional_ns_param, record_repository, REPO_URL_SCHEMA,
+};
 
 #[api]
 #[derive(Deserialize, Serialize)]
@@ -83,8 +92,15 @@ fn create(kdf: Option<Kdf>, path: Option<String>, hint: Option<String>) -> Resul
         }
     };
 
-    let kdf = kdf.unwrap_or_default();
+    let key_config = new_key_config(kdf.unwrap_or_default(), hint)?;
+
+    key_config.store(path, false)?;
 
+    Ok(())
+}
+
+/// Generate a new random key, protected by a passphrase read from the tty unless `kdf` is none.
+fn new_key_config(kdf: Kdf, hint: Option<String>) -> Result<KeyConfig, Error> {
     let mut key = [0u8; 32];
     proxmox_sys::linux::fill_with_random_data(&mut key)?;
 
@@ -94,9 +110,7 @@ fn create(kdf: Option<Kdf>, path: Option<String>, hint: Option<String>) -> Resul
                 bail!("password hint not allowed for Kdf::None");
             }
 
-            let key_config = KeyConfig::without_password(key)?;
-
-            key_config.store(path, false)?;
+            KeyConfig::without_password(key)
         }
         Kdf::Scrypt | Kdf::PBKDF2 => {
             // always read passphrase from tty
@@ -109,9 +123,239 @@ fn create(kdf: Option<Kdf>, path: Option<String>, hint: Option<String>) -> Resul
             let mut key_config = KeyConfig::with_key(&key, &password, kdf)?;
             key_config.hint = hint;
 
-            key_config.store(&path, false)?;
+            Ok(key_config)
         }
     }
+}
+
+#[api(
+    input: {
+        properties: {
+            kdf: {
+                type: Kdf,
+                optional: true,
+            },
+            path: {
+                description: "Key file. Without this the default encryption key will be rotated.",
+                optional: true,
+            },
+            hint: {
+                schema: PASSWORD_HINT_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Replace the encryption key with a newly created one.
+///
+/// The old key is kept as retired key, so that snapshots created with it can still be restored.
+/// New backups use the new key and cannot reuse data of snapshots created with the old key.
+fn rotate(kdf: Option<Kdf>, path: Option<String>, hint: Option<String>) -> Result<(), Error> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => find_default_encryption_key()?
+            .ok_or_else(|| format_err!("no encryption file provided and no default file found"))?,
+    };
+
+    let old_config = KeyConfig::load(&path)?;
+    // decrypting makes sure the old key is usable before it gets retired
+    let (_key, _created, old_fingerprint) = old_config.decrypt(&get_encryption_key_password)?;
+
+    let new_config = new_key_config(kdf.unwrap_or_default(), hint)?;
+
+    let retired_path = place_retired_encryption_key(&old_fingerprint)?;
+    old_config.store(&retired_path, false)?;
+    log::info!("retired key {} to {:?}", old_fingerprint, retired_path);
+
+    new_config.store(&path, true)?;
+    if let Some(fingerprint) = &new_config.fingerprint {
+        log::info!("new key {} stored at {:?}", fingerprint, path);
+    }
+    log::info!("make sure to create a backup of the new key (see 'paperkey')");
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List encryption keys retired by a key rotation.
+fn list_retired(param: Value) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+
+    let mut list = Vec::new();
+    for path in list_retired_encryption_keys()? {
+        let config: KeyConfig = serde_json::from_slice(&file_get_contents(&path)?)?;
+        let mut info: KeyInfo = (&config).into();
+        info.path = Some(format!("{:?}", path));
+        list.push(info);
+    }
+
+    let options = proxmox_router::cli::default_table_format_options()
+        .column(ColumnConfig::new("path"))
+        .column(ColumnConfig::new("kdf"))
+        .column(ColumnConfig::new("created").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("fingerprint"))
+        .column(ColumnConfig::new("hint"));
+
+    format_and_print_result_full(
+        &mut serde_json::to_value(list)?,
+        &RETIRED_KEY_LIST_RETURN_TYPE,
+        &output_format,
+        &options,
+    );
+
+    Ok(())
+}
+
+const RETIRED_KEY_LIST_RETURN_TYPE: ReturnType = ReturnType {
+    optional: false,
+    schema: &ArraySchema::new("List of retired keys.", &KeyInfo::API_SCHEMA).schema(),
+};
+
+#[api]
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+/// Whether the key of a snapshot is locally available
+pub enum SnapshotKeyState {
+    /// Encrypted with the current key
+    Current,
+    /// Encrypted with a retired key
+    Retired,
+    /// Encrypted with a key which is not available locally
+    Unknown,
+}
+
+#[api(
+    properties: {
+        "key-state": {
+            type: SnapshotKeyState,
+        },
+    },
+)]
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Encryption key of a snapshot
+pub struct SnapshotKeyInfo {
+    /// Snapshot path
+    pub snapshot: String,
+    /// Fingerprint of the key the snapshot was encrypted with
+    pub fingerprint: String,
+    pub key_state: SnapshotKeyState,
+}
+
+const SNAPSHOT_KEY_LIST_RETURN_TYPE: ReturnType = ReturnType {
+    optional: false,
+    schema: &ArraySchema::new("List of encrypted snapshots.", &SnapshotKeyInfo::API_SCHEMA)
+        .schema(),
+};
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            keyfile: {
+                schema: KEYFILE_SCHEMA,
+                optional: true,
+            },
+            "keyfd": {
+                schema: KEYFD_SCHEMA,
+                optional: true,
+            },
+            retired: {
+                type: Boolean,
+                description: "Only list snapshots which depend on a retired key.",
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List encrypted snapshots together with the key they depend on.
+async fn snapshots(retired: bool, param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let backup_ns = optional_ns_param(&param)?;
+    let output_format = get_output_format(&param);
+
+    let current = match crypto_parameters(&param)?.enc_key {
+        Some(key) => {
+            let config: KeyConfig = serde_json::from_slice(&key.key)?;
+            match config.fingerprint {
+                Some(fingerprint) => Some(fingerprint),
+                None => Some(config.decrypt(&get_encryption_key_password)?.2),
+            }
+        }
+        None => None,
+    };
+
+    let mut retired_keys = HashSet::new();
+    for path in list_retired_encryption_keys()? {
+        if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+            if let Ok(fingerprint) = stem.parse::<Fingerprint>() {
+                retired_keys.insert(fingerprint);
+            }
+        }
+    }
+
+    let client = connect(&repo)?;
+    let data = api_datastore_list_snapshots(&client, repo.store(), &backup_ns, None).await?;
+    record_repository(&repo);
+
+    let snapshots: Vec<SnapshotListItem> = serde_json::from_value(data)?;
+
+    let mut list = Vec::new();
+    for item in snapshots {
+        let fingerprint = match item.fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => continue,
+        };
+        let key_state = if current.as_ref() == Some(&fingerprint) {
+            SnapshotKeyState::Current
+        } else if retired_keys.contains(&fingerprint) {
+            SnapshotKeyState::Retired
+        } else {
+            SnapshotKeyState::Unknown
+        };
+        if retired && key_state != SnapshotKeyState::Retired {
+            continue;
+        }
+        list.push(SnapshotKeyInfo {
+            snapshot: item.backup.to_string(),
+            fingerprint: fingerprint.signature(),
+            key_state,
+        });
+    }
+
+    let options = proxmox_router::cli::default_table_format_options()
+        .sortby("snapshot", false)
+        .column(ColumnConfig::new("snapshot"))
+        .column(ColumnConfig::new("fingerprint"))
+        .column(ColumnConfig::new("key-state"));
+
+    format_and_print_result_full(
+        &mut serde_json::to_value(list)?,
+        &SNAPSHOT_KEY_LIST_RETURN_TYPE,
+        &output_format,
+        &options,
+    );
 
     Ok(())
 }
@@ -125,6 +369,24 @@ fn create(kdf: Option<Kdf>, path: Option<String>, hint: Option<String>) -> Resul
             "encrypted-keyfile": {
                 description: "RSA-encrypted keyfile to import.",
             },
+            "additional-master-keyfile": {
+                type: Array,
+                description: "Further (private) master keys, required to restore a split key.",
+                optional: true,
+                items: {
+                    type: String,
+                    description: "(Private) master key to use.",
+                },
+            },
+            "additional-encrypted-keyfile": {
+                type: Array,
+                description: "RSA-encrypted key shares, matching the additional master keys in order.",
+                optional: true,
+                items: {
+                    type: String,
+                    description: "RSA-encrypted key share.",
+                },
+            },
             kdf: {
                 type: Kdf,
                 optional: true,
@@ -142,9 +404,14 @@ fn create(kdf: Option<Kdf>, path: Option<String>, hint: Option<String>) -> Resul
     },
 )]
 /// Import an encrypted backup of an encryption key using a (private) master key.
+///
+/// Keys split into shares require one master key per share, up to the threshold used for the
+/// backup.
 async fn import_with_master_key(
     master_keyfile: String,
     encrypted_keyfile: String,
+    additional_master_keyfile: Option<Vec<String>>,
+    additional_encrypted_keyfile: Option<Vec<String>>,
     kdf: Option<Kdf>,
     path: Option<String>,
     hint: Option<String>,
@@ -161,17 +428,48 @@ async fn import_with_master_key(
         }
     };
 
-    let encrypted_key = file_get_contents(&encrypted_keyfile)?;
-    let master_key = file_get_contents(&master_keyfile)?;
-    let password = tty::read_password("Master Key Password: ")?;
+    let additional_master_keyfile = additional_master_keyfile.unwrap_or_default();
+    let additional_encrypted_keyfile = additional_encrypted_keyfile.unwrap_or_default();
+    if additional_master_keyfile.len() != additional_encrypted_keyfile.len() {
+        bail!("every additional encrypted keyfile needs a matching additional master keyfile");
+    }
 
-    let master_key = openssl::pkey::PKey::private_key_from_pem_passphrase(&master_key, &password)
-        .map_err(|err| format_err!("failed to read PEM-formatted private key - {}", err))?
-        .rsa()
-        .map_err(|err| format_err!("not a valid private RSA key - {}", err))?;
+    let keyfiles = std::iter::once((master_keyfile, encrypted_keyfile)).chain(
+        additional_master_keyfile
+            .into_iter()
+            .zip(additional_encrypted_keyfile),
+    );
+
+    let mut shares = Vec::new();
+    let mut restored_key = None;
+    for (master_keyfile, encrypted_keyfile) in keyfiles {
+        let encrypted_key = file_get_contents(&encrypted_keyfile)?;
+        let master_key = file_get_contents(&master_keyfile)?;
+        let password = tty::read_password(&format!("Master Key Password ({}): ", master_keyfile))?;
+
+        let master_key =
+            openssl::pkey::PKey::private_key_from_pem_passphrase(&master_key, &password)
+                .map_err(|err| format_err!("failed to read PEM-formatted private key - {}", err))?
+                .rsa()
+                .map_err(|err| format_err!("not a valid private RSA key - {}", err))?;
+
+        match rsa_decrypt_recovery_key(master_key, &encrypted_key, &get_encryption_key_password)? {
+            RecoveryKey::Key(key, created, _fingerprint) => {
+                restored_key = Some((key, created));
+                break;
+            }
+            RecoveryKey::Share(share) => shares.push(share),
+        }
+    }
 
-    let (key, created, _fingerprint) =
-        rsa_decrypt_key_config(master_key, &encrypted_key, &get_encryption_key_password)?;
+    let (key, created) = match restored_key {
+        Some(restored_key) => restored_key,
+        None => {
+            let (key, created, fingerprint) = combine_key_shares(&shares)?;
+            log::info!("Restored key {} from {} shares", fingerprint, shares.len());
+            (key, created)
+        }
+    };
 
     let kdf = kdf.unwrap_or_default();
     match kdf {
@@ -495,6 +793,8 @@ pub fn cli() -> CliCommandMap {
         .completion_cb("master-keyfile", complete_file_name)
         .arg_param(&["encrypted-keyfile"])
         .completion_cb("encrypted-keyfile", complete_file_name)
+        .completion_cb("additional-master-keyfile", complete_file_name)
+        .completion_cb("additional-encrypted-keyfile", complete_file_name)
         .arg_param(&["path"])
         .completion_cb("path", complete_file_name);
 
@@ -502,6 +802,17 @@ pub fn cli() -> CliCommandMap {
         .arg_param(&["path"])
         .completion_cb("path", complete_file_name);
 
+    let key_rotate_cmd_def = CliCommand::new(&API_METHOD_ROTATE)
+        .arg_param(&["path"])
+        .completion_cb("path", complete_file_name);
+
+    let key_list_retired_cmd_def = CliCommand::new(&API_METHOD_LIST_RETIRED);
+
+    let key_snapshots_cmd_def = CliCommand::new(&API_METHOD_SNAPSHOTS)
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("keyfile", complete_file_name);
+
     let key_create_master_key_cmd_def = CliCommand::new(&API_METHOD_CREATE_MASTER_KEY);
     let key_import_master_pubkey_cmd_def = CliCommand::new(&API_METHOD_IMPORT_MASTER_PUBKEY)
         .arg_param(&["path"])
@@ -524,6 +835,9 @@ pub fn cli() -> CliCommandMap {
         .insert("create-master-key", key_create_master_key_cmd_def)
         .insert("import-master-pubkey", key_import_master_pubkey_cmd_def)
         .insert("change-passphrase", key_change_passphrase_cmd_def)
+        .insert("rotate", key_rotate_cmd_def)
+        .insert("list-retired", key_list_retired_cmd_def)
+        .insert("snapshots", key_snapshots_cmd_def)
         .insert("show", key_show_cmd_def)
         .insert("show-master-pubkey", key_show_master_pubkey_cmd_def)
         .insert("paperkey", paper_key_cmd_def)
@@ -23,40 +23,49 @@ use proxmox_time::{epoch_i64, strftime_local};
 use pxar::accessor::{MaybeReady, ReadAt, ReadAtOperation};
 
 use pbs_api_types::{
-    Authid, BackupDir, BackupGroup, BackupNamespace, BackupPart, BackupType, CryptMode,
-    Fingerprint, GroupListItem, HumanByte, PruneJobOptions, PruneListItem, RateLimitConfig,
-    SnapshotListItem, StorageStatus, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, TRAFFIC_CONTROL_BURST_SCHEMA, TRAFFIC_CONTROL_RATE_SCHEMA,
+    Authid, BackupDir, BackupGroup, BackupNamespace, BackupPart, BackupType, ChunkerType,
+    CryptMode, Fingerprint, GroupListItem, HumanByte, PruneJobOptions, PruneListItem,
+    RateLimitConfig, SnapshotListItem, StorageStatus, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
+    BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, COMPRESSION_LEVEL_SCHEMA, TRAFFIC_CONTROL_BURST_SCHEMA,
+    TRAFFIC_CONTROL_RATE_SCHEMA,
 };
 use pbs_client::catalog_shell::Shell;
+use pbs_client::pxar::{payload_inodes_blob_name, ChangeDetectionMode, PayloadInodes, PxarPrevRef};
 use pbs_client::tools::{
     complete_archive_name, complete_auth_id, complete_backup_group, complete_backup_snapshot,
     complete_backup_source, complete_chunk_size, complete_group_or_snapshot,
     complete_img_archive_name, complete_namespace, complete_pxar_archive_name, complete_repository,
     connect, connect_rate_limited, extract_repository_from_value,
     key_source::{
-        crypto_parameters, format_key_source, get_encryption_key_password, KEYFD_SCHEMA,
-        KEYFILE_SCHEMA, MASTER_PUBKEY_FD_SCHEMA, MASTER_PUBKEY_FILE_SCHEMA,
+        additional_master_pubkeys, crypto_parameters, format_key_source,
+        get_encryption_key_password, select_snapshot_key, KeyWithSource,
+        ADDITIONAL_MASTER_PUBKEY_FILE_SCHEMA, KEYFD_SCHEMA, KEYFILE_SCHEMA,
+        MASTER_KEY_THRESHOLD_SCHEMA, MASTER_PUBKEY_FD_SCHEMA, MASTER_PUBKEY_FILE_SCHEMA,
     },
     CHUNK_SIZE_SCHEMA, REPO_URL_SCHEMA,
 };
 use pbs_client::{
     delete_ticket_info, parse_backup_specification, view_task_result, BackupReader,
     BackupRepository, BackupSpecificationType, BackupStats, BackupWriter, ChunkStream,
-    FixedChunkStream, HttpClient, PxarBackupStream, RemoteChunkReader, UploadOptions,
-    BACKUP_SOURCE_SCHEMA,
+    FixedChunkStream, HttpClient, InjectionData, PxarBackupStream, RemoteChunkReader,
+    UploadOptions, BACKUP_SOURCE_SCHEMA,
 };
 use pbs_datastore::catalog::{BackupCatalogWriter, CatalogReader, CatalogWriter};
 use pbs_datastore::chunk_store::verify_chunk_size;
+use pbs_datastore::chunker::ChunkerConfig;
+use pbs_datastore::data_blob::Compression;
 use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader};
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{
-    archive_type, ArchiveType, BackupManifest, ENCRYPTED_KEY_BLOB_NAME, MANIFEST_BLOB_NAME,
+    archive_type, encrypted_key_blob_name, is_encrypted_key_blob_name, ArchiveType, BackupManifest,
+    MANIFEST_BLOB_NAME,
 };
 use pbs_datastore::read_chunk::AsyncReadChunk;
 use pbs_datastore::CATALOG_NAME;
-use pbs_key_config::{decrypt_key, rsa_encrypt_key_config, KeyConfig};
+use pbs_key_config::{
+    decrypt_key, rsa_encrypt_key_config, rsa_encrypt_key_share, split_key, KeyConfig,
+};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json;
 
@@ -181,17 +190,33 @@ pub async fn dir_or_last_from_group(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn backup_directory<P: AsRef<Path>>(
     client: &BackupWriter,
     dir_path: P,
     archive_name: &str,
-    chunk_size: Option<usize>,
+    chunker: ChunkerConfig,
     catalog: Arc<Mutex<CatalogWriter<TokioWriterAdapter<StdChannelWriter<Error>>>>>,
     pxar_create_options: pbs_client::pxar::PxarCreateOptions,
-    upload_options: UploadOptions,
+    mut upload_options: UploadOptions,
+    change_detection_mode: ChangeDetectionMode,
 ) -> Result<BackupStats, Error> {
-    let pxar_stream = PxarBackupStream::open(dir_path.as_ref(), catalog, pxar_create_options)?;
-    let mut chunk_stream = ChunkStream::new(pxar_stream, chunk_size);
+    let (boundaries, injection_data, injections) = match change_detection_mode {
+        ChangeDetectionMode::Legacy => (None, None, None),
+        ChangeDetectionMode::Metadata => {
+            let (boundaries_tx, boundaries_rx) = std::sync::mpsc::channel();
+            let (injections_tx, injections_rx) = std::sync::mpsc::channel();
+            (
+                Some(boundaries_tx),
+                Some(InjectionData::new(boundaries_rx, injections_tx)),
+                Some(injections_rx),
+            )
+        }
+    };
+
+    let pxar_stream =
+        PxarBackupStream::open(dir_path.as_ref(), catalog, pxar_create_options, boundaries)?;
+    let mut chunk_stream = ChunkStream::with_chunker(pxar_stream, &chunker, injection_data);
 
     let (tx, rx) = mpsc::channel(10); // allow to buffer 10 chunks
 
@@ -207,9 +232,10 @@ async fn backup_directory<P: AsRef<Path>>(
     if upload_options.fixed_size.is_some() {
         bail!("cannot backup directory with fixed chunk size!");
     }
+    upload_options.chunker = Some(chunker);
 
     let stats = client
-        .upload_stream(archive_name, stream, upload_options)
+        .upload_stream(archive_name, stream, upload_options, injections)
         .await?;
 
     Ok(stats)
@@ -236,12 +262,102 @@ async fn backup_image<P: AsRef<Path>>(
     }
 
     let stats = client
-        .upload_stream(archive_name, stream, upload_options)
+        .upload_stream(archive_name, stream, upload_options, None)
         .await?;
 
     Ok(stats)
 }
 
+/// Select the chunker for the dynamic archive `archive_name`.
+///
+/// Without an explicitly requested chunker, the one recorded in the previous snapshot's index is
+/// reused, so that unchanged data is split the same way and deduplicates.
+async fn select_chunker(
+    client: &BackupWriter,
+    archive_name: &str,
+    previous_manifest: Option<&BackupManifest>,
+    chunker_type: Option<ChunkerType>,
+    chunk_size: Option<usize>,
+) -> Result<ChunkerConfig, Error> {
+    let default_size = ChunkerConfig::default().avg;
+
+    if let Some(ty) = chunker_type {
+        return ChunkerConfig::new(ty, chunk_size.unwrap_or(default_size));
+    }
+
+    let previous = match previous_manifest {
+        Some(manifest) => client
+            .previous_chunker(archive_name, manifest)
+            .await
+            .unwrap_or_else(|err| {
+                log::debug!("unable to read chunker of previous '{archive_name}' - {err}");
+                None
+            }),
+        None => None,
+    };
+
+    match (previous, chunk_size) {
+        (Some(previous), None) => Ok(previous),
+        (Some(previous), Some(size)) => ChunkerConfig::new(previous.ty, size),
+        (None, size) => ChunkerConfig::new(ChunkerType::Buzhash, size.unwrap_or(default_size)),
+    }
+}
+
+/// Open the archive `archive_name` of the previous snapshot for reusing unchanged files.
+async fn prepare_previous_ref(
+    backup_reader: &Arc<BackupReader>,
+    backup_time: i64,
+    manifest: &BackupManifest,
+    archive_name: &str,
+    crypt_config: Option<Arc<CryptConfig>>,
+    crypt_mode: CryptMode,
+) -> Result<Option<PxarPrevRef>, Error> {
+    let file_info = match manifest.lookup_file_info(archive_name) {
+        Ok(file_info) => file_info,
+        Err(_) => return Ok(None), // archive not part of the previous snapshot
+    };
+
+    if file_info.chunk_crypt_mode() != crypt_mode {
+        log::info!("{archive_name}: crypt mode changed, not reusing previous archive");
+        return Ok(None);
+    }
+
+    let inodes_name = payload_inodes_blob_name(archive_name);
+    if manifest.lookup_file_info(&inodes_name).is_err() {
+        log::info!("{archive_name}: no payload inode table, not reusing previous archive");
+        return Ok(None);
+    }
+    let mut inodes = Vec::new();
+    backup_reader
+        .download_blob(manifest, &inodes_name)
+        .await?
+        .read_to_end(&mut inodes)?;
+    let inodes = PayloadInodes::from_bytes(&inodes)?;
+
+    let index = backup_reader
+        .download_dynamic_index(manifest, archive_name)
+        .await?;
+    let most_used = index.find_most_used_chunks(8);
+
+    let chunk_reader = RemoteChunkReader::new(
+        backup_reader.clone(),
+        crypt_config,
+        file_info.chunk_crypt_mode(),
+        most_used,
+    );
+    let reader = BufferedDynamicReader::new(index.try_clone()?, chunk_reader);
+    let archive_size = reader.archive_size();
+    let reader: Arc<dyn ReadAt + Send + Sync> = Arc::new(BufferedDynamicReadAt::new(reader));
+    let accessor = pxar::accessor::aio::Accessor::new(reader, archive_size).await?;
+
+    Ok(Some(PxarPrevRef {
+        accessor,
+        index,
+        backup_time,
+        inodes,
+    }))
+}
+
 pub fn optional_ns_param(param: &Value) -> Result<BackupNamespace, Error> {
     Ok(match param.get("ns") {
         Some(Value::String(ns)) => ns.parse()?,
@@ -523,11 +639,12 @@ struct CatalogUploadResult {
 fn spawn_catalog_upload(
     client: Arc<BackupWriter>,
     encrypt: bool,
+    compression: Arc<Compression>,
 ) -> Result<CatalogUploadResult, Error> {
     let (catalog_tx, catalog_rx) = std::sync::mpsc::sync_channel(10); // allow to buffer 10 writes
     let catalog_stream = proxmox_async::blocking::StdChannelStream(catalog_rx);
-    let catalog_chunk_size = 512 * 1024;
-    let catalog_chunk_stream = ChunkStream::new(catalog_stream, Some(catalog_chunk_size));
+    let catalog_chunker = ChunkerConfig::new(ChunkerType::Buzhash, 512 * 1024)?;
+    let catalog_chunk_stream = ChunkStream::with_chunker(catalog_stream, &catalog_chunker, None);
 
     let catalog_writer = Arc::new(Mutex::new(CatalogWriter::new(TokioWriterAdapter::new(
         StdChannelWriter::new(catalog_tx),
@@ -538,12 +655,13 @@ fn spawn_catalog_upload(
     let upload_options = UploadOptions {
         encrypt,
         compress: true,
+        compression: Some(compression),
         ..UploadOptions::default()
     };
 
     tokio::spawn(async move {
         let catalog_upload_result = client
-            .upload_stream(CATALOG_NAME, catalog_chunk_stream, upload_options)
+            .upload_stream(CATALOG_NAME, catalog_chunk_stream, upload_options, None)
             .await;
 
         if let Err(ref err) = catalog_upload_result {
@@ -604,6 +722,18 @@ fn spawn_catalog_upload(
                schema: MASTER_PUBKEY_FD_SCHEMA,
                optional: true,
            },
+           "additional-master-pubkey-file": {
+               type: Array,
+               description: "List of additional master public keys.",
+               optional: true,
+               items: {
+                   schema: ADDITIONAL_MASTER_PUBKEY_FILE_SCHEMA,
+               },
+           },
+           "master-key-threshold": {
+               schema: MASTER_KEY_THRESHOLD_SCHEMA,
+               optional: true,
+           },
            "crypt-mode": {
                type: CryptMode,
                optional: true,
@@ -630,10 +760,21 @@ fn spawn_catalog_upload(
                schema: BACKUP_TIME_SCHEMA,
                optional: true,
            },
+           resume: {
+               type: Boolean,
+               description: "Resume the interrupted backup with the same 'backup-time', if the \
+                   server kept it.",
+               optional: true,
+               default: false,
+           },
            "chunk-size": {
                schema: CHUNK_SIZE_SCHEMA,
                optional: true,
            },
+           chunker: {
+               type: ChunkerType,
+               optional: true,
+           },
            rate: {
                schema: TRAFFIC_CONTROL_RATE_SCHEMA,
                optional: true,
@@ -663,6 +804,14 @@ fn spawn_catalog_upload(
                optional: true,
                default: false,
            },
+           "change-detection-mode": {
+               type: ChangeDetectionMode,
+               optional: true,
+           },
+           "compression-level": {
+               schema: COMPRESSION_LEVEL_SCHEMA,
+               optional: true,
+           },
        }
    }
 )]
@@ -672,6 +821,8 @@ async fn create_backup(
     all_file_systems: bool,
     skip_lost_and_found: bool,
     dry_run: bool,
+    change_detection_mode: Option<ChangeDetectionMode>,
+    chunker: Option<ChunkerType>,
     _info: &ApiMethod,
     _rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
@@ -681,6 +832,11 @@ async fn create_backup(
 
     let backup_time_opt = param["backup-time"].as_i64();
 
+    let resume = param["resume"].as_bool().unwrap_or(false);
+    if resume && backup_time_opt.is_none() {
+        bail!("resuming a backup requires the 'backup-time' of the interrupted backup");
+    }
+
     let chunk_size_opt = param["chunk-size"].as_u64().map(|v| (v * 1024) as usize);
 
     if let Some(size) = chunk_size_opt {
@@ -699,6 +855,10 @@ async fn create_backup(
     let rate_limit = RateLimitConfig::with_same_inout(rate, burst);
 
     let crypto = crypto_parameters(&param)?;
+    let additional_master_pubkeys = additional_master_pubkeys(&param)?;
+    let master_key_threshold = param["master-key-threshold"].as_u64().map(|t| t as u8);
+
+    let change_detection_mode = change_detection_mode.unwrap_or_default();
 
     let backup_id = param["backup-id"]
         .as_str()
@@ -825,7 +985,7 @@ async fn create_backup(
 
     let backup_time = backup_time_opt.unwrap_or_else(epoch_i64);
 
-    let client = connect_rate_limited(&repo, rate_limit)?;
+    let client = connect_rate_limited(&repo, rate_limit.clone())?;
     record_repository(&repo);
 
     let snapshot = BackupDir::from((backup_type, backup_id.to_owned(), backup_time));
@@ -844,8 +1004,13 @@ async fn create_backup(
         strftime_local("%c", epoch_i64())?
     );
 
-    let (crypt_config, rsa_encrypted_key) = match crypto.enc_key {
-        None => (None, None),
+    let (crypt_config, rsa_encrypted_keys) = match crypto.enc_key {
+        None => {
+            if !additional_master_pubkeys.is_empty() || master_key_threshold.is_some() {
+                bail!("additional master keys and a master key threshold require encryption");
+            }
+            (None, Vec::new())
+        }
         Some(key_with_source) => {
             log::info!(
                 "{}",
@@ -858,21 +1023,56 @@ async fn create_backup(
 
             let crypt_config = CryptConfig::new(key)?;
 
-            match crypto.master_pubkey {
-                Some(pem_with_source) => {
-                    log::info!("{}", format_key_source(&pem_with_source.source, "master"));
-
-                    let rsa = openssl::rsa::Rsa::public_key_from_pem(&pem_with_source.key)?;
+            let master_pubkeys: Vec<KeyWithSource> = match crypto.master_pubkey {
+                Some(pem_with_source) => std::iter::once(pem_with_source)
+                    .chain(additional_master_pubkeys)
+                    .collect(),
+                None if additional_master_pubkeys.is_empty() => Vec::new(),
+                None => bail!("additional master keys require a master key"),
+            };
+
+            let mut rsa_keys = Vec::with_capacity(master_pubkeys.len());
+            for pem_with_source in master_pubkeys {
+                log::info!("{}", format_key_source(&pem_with_source.source, "master"));
+                rsa_keys.push(openssl::rsa::Rsa::public_key_from_pem(
+                    &pem_with_source.key,
+                )?);
+            }
 
+            let enc_keys = match master_key_threshold {
+                None => {
                     let mut key_config = KeyConfig::without_password(key)?;
                     key_config.created = created; // keep original value
 
-                    let enc_key = rsa_encrypt_key_config(rsa, &key_config)?;
-
-                    (Some(Arc::new(crypt_config)), Some(enc_key))
+                    rsa_keys
+                        .into_iter()
+                        .map(|rsa| rsa_encrypt_key_config(rsa, &key_config))
+                        .collect::<Result<Vec<_>, Error>>()?
                 }
-                _ => (Some(Arc::new(crypt_config)), None),
-            }
+                Some(threshold) => {
+                    if rsa_keys.len() < threshold as usize || rsa_keys.len() > 255 {
+                        bail!(
+                            "master key threshold {} requires between {} and 255 master keys, got {}",
+                            threshold,
+                            threshold,
+                            rsa_keys.len(),
+                        );
+                    }
+                    log::info!(
+                        "Splitting encryption key, {} of {} master keys are required to restore it",
+                        threshold,
+                        rsa_keys.len(),
+                    );
+                    let shares = split_key(&key, created, threshold, rsa_keys.len() as u8)?;
+                    rsa_keys
+                        .into_iter()
+                        .zip(shares.iter())
+                        .map(|(rsa, share)| rsa_encrypt_key_share(rsa, share))
+                        .collect::<Result<Vec<_>, Error>>()?
+                }
+            };
+
+            (Some(Arc::new(crypt_config)), enc_keys)
         }
     };
 
@@ -884,10 +1084,34 @@ async fn create_backup(
         &snapshot,
         true,
         false,
+        resume,
     )
     .await?;
 
-    let download_previous_manifest = match client.previous_backup_time().await {
+    if client.is_resuming() {
+        log::info!("Resuming interrupted backup");
+    }
+
+    let mut compression = match client.server_compression().await {
+        Ok(compression) => compression,
+        Err(err) => {
+            log::debug!("unable to query compression settings, using defaults - {err}");
+            Compression::default()
+        }
+    };
+    if let Some(level) = param["compression-level"].as_i64() {
+        compression.level = level as i32;
+    }
+    if let Some(ref dictionary) = compression.dictionary {
+        if crypto.mode != CryptMode::Encrypt {
+            log::info!("Using zstd dictionary {:08x}", dictionary.id());
+        }
+    }
+    let compression = Arc::new(compression);
+
+    let previous_backup_time = client.previous_backup_time().await;
+
+    let download_previous_manifest = match previous_backup_time {
         Ok(Some(backup_time)) => {
             log::info!(
                 "Downloading previous manifest ({})",
@@ -925,6 +1149,29 @@ async fn create_backup(
         None
     };
 
+    // the previous snapshot is only read for metadata based change detection
+    let previous_reader = match (
+        change_detection_mode,
+        &previous_manifest,
+        previous_backup_time,
+    ) {
+        (ChangeDetectionMode::Metadata, Some(_), Ok(Some(backup_time))) if !dry_run => {
+            let previous_snapshot =
+                BackupDir::from((backup_type, backup_id.to_owned(), backup_time));
+            let reader = BackupReader::start(
+                connect_rate_limited(&repo, rate_limit)?,
+                crypt_config.clone(),
+                repo.store(),
+                &backup_ns,
+                &previous_snapshot,
+                false,
+            )
+            .await?;
+            Some((reader, backup_time))
+        }
+        _ => None,
+    };
+
     let mut manifest = BackupManifest::new(snapshot);
 
     let mut catalog = None;
@@ -946,6 +1193,7 @@ async fn create_backup(
             (BackupSpecificationType::CONFIG, false) => {
                 let upload_options = UploadOptions {
                     compress: true,
+                    compression: Some(compression.clone()),
                     encrypt: crypto.mode == CryptMode::Encrypt,
                     ..UploadOptions::default()
                 };
@@ -960,6 +1208,7 @@ async fn create_backup(
                 // fixme: remove - not needed anymore ?
                 let upload_options = UploadOptions {
                     compress: true,
+                    compression: Some(compression.clone()),
                     encrypt: crypto.mode == CryptMode::Encrypt,
                     ..UploadOptions::default()
                 };
@@ -973,8 +1222,11 @@ async fn create_backup(
             (BackupSpecificationType::PXAR, false) => {
                 // start catalog upload on first use
                 if catalog.is_none() {
-                    let catalog_upload_res =
-                        spawn_catalog_upload(client.clone(), crypto.mode == CryptMode::Encrypt)?;
+                    let catalog_upload_res = spawn_catalog_upload(
+                        client.clone(),
+                        crypto.mode == CryptMode::Encrypt,
+                        compression.clone(),
+                    )?;
                     catalog = Some(catalog_upload_res.catalog_writer);
                     catalog_result_rx = Some(catalog_upload_res.result);
                 }
@@ -986,30 +1238,84 @@ async fn create_backup(
                     .unwrap()
                     .start_directory(std::ffi::CString::new(target.as_str())?.as_c_str())?;
 
+                let previous_ref = match (&previous_reader, &previous_manifest) {
+                    (Some((reader, backup_time)), Some(previous_manifest)) => prepare_previous_ref(
+                        reader,
+                        *backup_time,
+                        previous_manifest,
+                        &target,
+                        crypt_config.clone(),
+                        crypto.mode,
+                    )
+                    .await
+                    .unwrap_or_else(|err| {
+                        log::warn!("Couldn't open previous archive '{target}' - {err}");
+                        None
+                    }),
+                    _ => None,
+                };
+
+                let payload_inodes = match change_detection_mode {
+                    ChangeDetectionMode::Legacy => None,
+                    ChangeDetectionMode::Metadata => {
+                        Some(Arc::new(Mutex::new(PayloadInodes::default())))
+                    }
+                };
+
                 let pxar_options = pbs_client::pxar::PxarCreateOptions {
                     device_set: devices.clone(),
                     patterns: pattern_list.clone(),
                     entries_max: entries_max as usize,
                     skip_lost_and_found,
+                    previous_ref: previous_ref.map(Arc::new),
+                    payload_inodes: payload_inodes.clone(),
                 };
 
                 let upload_options = UploadOptions {
                     previous_manifest: previous_manifest.clone(),
                     compress: true,
+                    compression: Some(compression.clone()),
                     encrypt: crypto.mode == CryptMode::Encrypt,
                     ..UploadOptions::default()
                 };
 
+                let chunker_config = select_chunker(
+                    &client,
+                    &target,
+                    previous_manifest.as_deref(),
+                    chunker,
+                    chunk_size_opt,
+                )
+                .await?;
+                log::debug!("{target}: using chunker {chunker_config:?}");
+
                 let stats = backup_directory(
                     &client,
                     &filename,
                     &target,
-                    chunk_size_opt,
+                    chunker_config,
                     catalog.clone(),
                     pxar_options,
                     upload_options,
+                    change_detection_mode,
                 )
                 .await?;
+
+                if let Some(payload_inodes) = payload_inodes {
+                    let inodes_name = payload_inodes_blob_name(&target);
+                    let upload_options = UploadOptions {
+                        compress: true,
+                        compression: Some(compression.clone()),
+                        encrypt: crypto.mode == CryptMode::Encrypt,
+                        ..UploadOptions::default()
+                    };
+                    let data = payload_inodes.lock().unwrap().to_bytes();
+                    let stats = client
+                        .upload_blob_from_data(data, &inodes_name, upload_options)
+                        .await?;
+                    manifest.add_file(inodes_name, stats.size, stats.csum, crypto.mode)?;
+                }
+
                 manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
                 catalog.lock().unwrap().end_directory()?;
             }
@@ -1020,7 +1326,9 @@ async fn create_backup(
                     previous_manifest: previous_manifest.clone(),
                     fixed_size: Some(size),
                     compress: true,
+                    compression: Some(compression.clone()),
                     encrypt: crypto.mode == CryptMode::Encrypt,
+                    ..UploadOptions::default()
                 };
 
                 let stats =
@@ -1052,8 +1360,8 @@ async fn create_backup(
         }
     }
 
-    if let Some(rsa_encrypted_key) = rsa_encrypted_key {
-        let target = ENCRYPTED_KEY_BLOB_NAME;
+    for (index, rsa_encrypted_key) in rsa_encrypted_keys.into_iter().enumerate() {
+        let target = encrypted_key_blob_name(index);
         log::info!("Upload RSA encoded key to '{:?}' as {}", repo, target);
         let options = UploadOptions {
             compress: false,
@@ -1061,9 +1369,9 @@ async fn create_backup(
             ..UploadOptions::default()
         };
         let stats = client
-            .upload_blob_from_data(rsa_encrypted_key, target, options)
+            .upload_blob_from_data(rsa_encrypted_key, &target, options)
             .await?;
-        manifest.add_file(target.to_string(), stats.size, stats.csum, crypto.mode)?;
+        manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
     }
     // create manifest (index.json)
     // manifests are never encrypted, but include a signature
@@ -1073,6 +1381,7 @@ async fn create_backup(
 
     log::debug!("Upload index.json to '{}'", repo);
 
+    // default compression, the manifest must be readable without a dictionary
     let options = UploadOptions {
         compress: true,
         encrypt: false,
@@ -1271,7 +1580,9 @@ async fn restore(
     let target = json::required_string_param(&param, "target")?;
     let target = if target == "-" { None } else { Some(target) };
 
-    let crypto = crypto_parameters(&param)?;
+    let mut crypto = crypto_parameters(&param)?;
+    crypto.enc_key =
+        select_snapshot_key(&client, repo.store(), &ns, &backup_dir, crypto.enc_key).await?;
 
     let crypt_config = match crypto.enc_key {
         None => None,
@@ -1299,7 +1610,7 @@ async fn restore(
 
     let (manifest, backup_index_data) = client.download_manifest().await?;
 
-    if archive_name == ENCRYPTED_KEY_BLOB_NAME && crypt_config.is_none() {
+    if is_encrypted_key_blob_name(&archive_name) && crypt_config.is_none() {
         log::info!("Restoring encrypted key blob without original key - skipping manifest fingerprint check!")
     } else {
         if manifest.signature.is_some() {
@@ -1447,6 +1758,12 @@ async fn restore(
                 optional: true,
                 description: "Just show what prune would do, but do not delete anything.",
             },
+            "estimate-reclaimable": {
+                type: bool,
+                optional: true,
+                default: false,
+                description: "With --dry-run, estimate the space a garbage collection would free.",
+            },
             group: {
                 type: String,
                 description: "Backup group",
@@ -1475,6 +1792,7 @@ async fn restore(
 /// Prune a backup repository.
 async fn prune(
     dry_run: Option<bool>,
+    estimate_reclaimable: bool,
     group: String,
     prune_options: PruneJobOptions,
     quiet: bool,
@@ -1494,6 +1812,9 @@ async fn prune(
     if let Some(dry_run) = dry_run {
         api_param["dry-run"] = dry_run.into();
     }
+    if estimate_reclaimable {
+        api_param["estimate-reclaimable"] = true.into();
+    }
     merge_group_into(api_param.as_object_mut().unwrap(), group);
 
     let mut result = client.post(&path, Some(api_param)).await?;
@@ -1551,6 +1872,11 @@ async fn prune(
 
     format_and_print_result_full(&mut data, return_type, &output_format, &options);
 
+    // the reclaimable space is estimated by a worker task
+    if let Some(upid) = result["upid"].as_str() {
+        view_task_result(&client, json!({ "data": upid }), &output_format).await?;
+    }
+
     Ok(Value::Null)
 }
 
@@ -1658,6 +1984,7 @@ fn main() {
         .completion_cb("backupspec", complete_backup_source)
         .completion_cb("keyfile", complete_file_name)
         .completion_cb("master-pubkey-file", complete_file_name)
+        .completion_cb("additional-master-pubkey-file", complete_file_name)
         .completion_cb("chunk-size", complete_chunk_size);
 
     let benchmark_cmd_def = CliCommand::new(&API_METHOD_BENCHMARK)
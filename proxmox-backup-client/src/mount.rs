@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::hash::BuildHasher;
 use std::os::unix::io::{AsRawFd, OwnedFd};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
@@ -16,14 +16,17 @@ use tokio::signal::unix::{signal, SignalKind};
 use proxmox_router::{cli::*, ApiHandler, ApiMethod, RpcEnvironment};
 use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
+use proxmox_sys::fs::file_get_contents;
 
 use pbs_api_types::BackupNamespace;
-use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::tools::key_source::{
+    get_encryption_key_password, select_snapshot_key, KeyWithSource,
+};
 use pbs_client::{BackupReader, RemoteChunkReader};
 use pbs_datastore::cached_chunk_reader::CachedChunkReader;
 use pbs_datastore::dynamic_index::BufferedDynamicReader;
 use pbs_datastore::index::IndexFile;
-use pbs_key_config::load_and_decrypt_key;
+use pbs_key_config::decrypt_key;
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json::required_string_param;
 
@@ -208,12 +211,22 @@ async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
     let path = required_string_param(&param, "snapshot")?;
     let backup_dir = dir_or_last_from_group(&client, &repo, &backup_ns, path).await?;
 
-    let keyfile = param["keyfile"].as_str().map(PathBuf::from);
-    let crypt_config = match keyfile {
+    let enc_key = match param["keyfile"].as_str() {
         None => None,
         Some(path) => {
             log::info!("Encryption key file: '{:?}'", path);
-            let (key, _, fingerprint) = load_and_decrypt_key(&path, &get_encryption_key_password)?;
+            Some(KeyWithSource::from_path(
+                path.to_string(),
+                file_get_contents(path)?,
+            ))
+        }
+    };
+    let enc_key =
+        select_snapshot_key(&client, repo.store(), &backup_ns, &backup_dir, enc_key).await?;
+    let crypt_config = match enc_key {
+        None => None,
+        Some(key) => {
+            let (key, _, fingerprint) = decrypt_key(&key.key, &get_encryption_key_password)?;
             log::info!("Encryption key fingerprint: '{}'", fingerprint);
             Some(Arc::new(CryptConfig::new(key)?))
         }
@@ -1,5 +1,6 @@
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -25,8 +26,8 @@ use pbs_client::pxar::{create_tar, create_zip, extract_sub_dir, extract_sub_dir_
 use pbs_client::tools::{
     complete_group_or_snapshot, complete_repository, connect, extract_repository_from_value,
     key_source::{
-        crypto_parameters_keep_fd, format_key_source, get_encryption_key_password, KEYFD_SCHEMA,
-        KEYFILE_SCHEMA,
+        crypto_parameters_keep_fd, format_key_source, get_encryption_key_password,
+        select_snapshot_key, CryptoParams, KeySource, KEYFD_SCHEMA, KEYFILE_SCHEMA,
     },
     REPO_URL_SCHEMA,
 };
@@ -84,16 +85,43 @@ fn parse_path(path: String, base64: bool) -> Result<ExtractPath, Error> {
     }
 }
 
-fn keyfile_path(param: &Value) -> Option<String> {
+fn keyfile_path(param: &Value, crypto: &CryptoParams) -> Result<Option<String>, Error> {
+    if let Some(key) = &crypto.enc_key {
+        // the key does not come from the given parameters, pass on what we selected
+        if matches!(key.source, KeySource::Provider | KeySource::Retired(_)) {
+            return key_memfd_path(&key.key).map(Some);
+        }
+    }
+
     if let Some(Value::String(keyfile)) = param.get("keyfile") {
-        return Some(keyfile.to_owned());
+        return Ok(Some(keyfile.to_owned()));
     }
 
     if let Some(Value::Number(keyfd)) = param.get("keyfd") {
-        return Some(format!("/dev/fd/{keyfd}"));
+        return Ok(Some(format!("/dev/fd/{keyfd}")));
+    }
+
+    Ok(None)
+}
+
+/// Pass a key from a key provider or a retired key via an inheritable memfd, so it never gets
+/// written to disk.
+fn key_memfd_path(key: &[u8]) -> Result<String, Error> {
+    use std::io::Write;
+
+    let fd = unsafe { libc::memfd_create(b"pbs-key\0".as_ptr() as *const libc::c_char, 0) };
+    if fd < 0 {
+        bail!(
+            "unable to create memfd - {}",
+            std::io::Error::last_os_error()
+        );
     }
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    file.write_all(key)?;
 
-    None
+    // keep it open for the block driver
+    let fd = file.into_raw_fd();
+    Ok(format!("/dev/fd/{fd}"))
 }
 
 async fn list_files(
@@ -250,8 +278,11 @@ async fn list(
     let snapshot: BackupDir = snapshot.parse()?;
     let path = parse_path(path, base64)?;
 
-    let keyfile = keyfile_path(&param);
-    let crypto = crypto_parameters_keep_fd(&param)?;
+    let client = connect(&repo)?;
+    let mut crypto = crypto_parameters_keep_fd(&param)?;
+    crypto.enc_key =
+        select_snapshot_key(&client, repo.store(), &ns, &snapshot, crypto.enc_key).await?;
+    let keyfile = keyfile_path(&param, &crypto)?;
     let crypt_config = match crypto.enc_key {
         None => None,
         Some(ref key) => {
@@ -414,8 +445,11 @@ async fn extract(
         None => Some(std::env::current_dir()?),
     };
 
-    let keyfile = keyfile_path(&param);
-    let crypto = crypto_parameters_keep_fd(&param)?;
+    let client = connect(&repo)?;
+    let mut crypto = crypto_parameters_keep_fd(&param)?;
+    crypto.enc_key =
+        select_snapshot_key(&client, repo.store(), &namespace, &snapshot, crypto.enc_key).await?;
+    let keyfile = keyfile_path(&param, &crypto)?;
     let crypt_config = match crypto.enc_key {
         None => None,
         Some(ref key) => {
@@ -428,7 +462,6 @@ async fn extract(
         }
     };
 
-    let client = connect(&repo)?;
     let client = BackupReader::start(
         client,
         crypt_config.clone(),
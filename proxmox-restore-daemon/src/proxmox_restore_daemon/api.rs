@@ -352,11 +352,21 @@ fn extract(
                         device_set: None,
                         patterns,
                         skip_lost_and_found: false,
+                        previous_ref: None,
+                        payload_inodes: None,
                     };
 
                     let pxar_writer = TokioWriter::new(writer);
-                    create_archive(dir, pxar_writer, Flags::DEFAULT, |_| Ok(()), None, options)
-                        .await
+                    create_archive(
+                        dir,
+                        pxar_writer,
+                        Flags::DEFAULT,
+                        |_| Ok(()),
+                        None,
+                        options,
+                        None,
+                    )
+                    .await
                 }
                 .await;
                 if let Err(err) = result {
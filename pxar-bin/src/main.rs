@@ -307,6 +307,8 @@ async fn create_archive(
         device_set,
         patterns,
         skip_lost_and_found: false,
+        previous_ref: None,
+        payload_inodes: None,
     };
 
     let source = PathBuf::from(source);
@@ -355,6 +357,7 @@ async fn create_archive(
         },
         None,
         options,
+        None,
     )
     .await?;
 
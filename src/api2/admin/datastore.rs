@@ -3,7 +3,7 @@
 use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
@@ -23,39 +23,41 @@ use proxmox_router::{
 };
 use proxmox_schema::*;
 use proxmox_sys::fs::{
-    file_read_firstline, file_read_optional_string, replace_file, CreateOptions,
+    create_path, file_read_firstline, file_read_optional_string, replace_file, CreateOptions,
 };
 use proxmox_sortable_macro::sortable;
-use proxmox_sys::{task_log, task_warn};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 
 use pxar::accessor::aio::Accessor;
 use pxar::EntryKind;
 
 use pbs_api_types::{
     print_ns_and_snapshot, print_store_and_ns, Authid, BackupContent, BackupNamespace, BackupType,
-    Counts, CryptMode, DataStoreListItem, DataStoreStatus, GarbageCollectionStatus, GroupListItem,
-    KeepOptions, Operation, PruneJobOptions, RRDMode, RRDTimeFrame, SnapshotListItem,
-    SnapshotVerifyState, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
-    BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, DATASTORE_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA,
-    MAX_NAMESPACE_DEPTH, NS_MAX_DEPTH_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP,
-    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY,
-    UPID_SCHEMA, VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    Counts, CryptMode, DataStoreChunkUsage, DataStoreConfig, DataStoreListItem,
+    DataStoreMountStatus, DataStoreStatus, DatastoreTuning, GarbageCollectionStatus, GroupListItem,
+    KeepOptions, Operation, PruneJobOptions, QuotaConfig, QuotaStatus, RRDMode, RRDTimeFrame,
+    SnapshotListItem, SnapshotVerifyState, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA,
+    BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, DATASTORE_SCHEMA,
+    IGNORE_VERIFIED_BACKUPS_SCHEMA, MAX_NAMESPACE_DEPTH, NS_MAX_DEPTH_SCHEMA, PRIV_DATASTORE_AUDIT,
+    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_READ,
+    PRIV_DATASTORE_VERIFY, PRIV_SYS_MODIFY, UPID_SCHEMA, VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    ZSTD_DICTIONARY_SIZE_SCHEMA,
 };
 use pbs_client::pxar::{create_tar, create_zip};
 use pbs_config::CachedUserInfo;
 use pbs_datastore::backup_info::BackupInfo;
 use pbs_datastore::cached_chunk_reader::CachedChunkReader;
 use pbs_datastore::catalog::{ArchiveEntry, CatalogReader};
+use pbs_datastore::chunk_store::ChunkStore;
 use pbs_datastore::data_blob::DataBlob;
-use pbs_datastore::data_blob_reader::DataBlobReader;
-use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader, LocalDynamicReadAt};
-use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::dynamic_index::{BufferedDynamicReader, LocalDynamicReadAt};
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME};
-use pbs_datastore::prune::compute_prune_info;
+use pbs_datastore::prune::{compute_prune_info, load_verify_states};
+use pbs_datastore::zstd_dictionary::DEFAULT_DICTIONARY_SIZE;
 use pbs_datastore::{
-    check_backup_owner, task_tracking, BackupDir, BackupGroup, DataStore, LocalChunkReader,
-    StoreProgress, CATALOG_NAME,
+    check_backup_owner, get_datastore_mount_status, task_tracking, BackupDir, BackupGroup,
+    DataStore, LocalChunkReader, StoreProgress, CATALOG_NAME,
 };
 use pbs_tools::json::required_string_param;
 use proxmox_rest_server::{formatter, WorkerTask};
@@ -63,8 +65,8 @@ use proxmox_rest_server::{formatter, WorkerTask};
 use crate::api2::backup::optional_ns_param;
 use crate::api2::node::rrd::create_value_from_rrd;
 use crate::backup::{
-    check_ns_privs_full, verify_all_backups, verify_backup_dir, verify_backup_group, verify_filter,
-    ListAccessibleBackupGroups, NS_PRIVS_OK,
+    check_ns_privs, check_ns_privs_full, verify_all_backups, verify_backup_dir,
+    verify_backup_group, verify_filter, ListAccessibleBackupGroups, NS_PRIVS_OK,
 };
 
 use crate::server::jobstate::Job;
@@ -194,6 +196,15 @@ pub fn list_groups(
 
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
 
+    let group_quotas: Vec<QuotaConfig> = read_quotas()?
+        .into_iter()
+        .filter(|quota| quota.group.is_some())
+        .collect();
+    let chunk_usage = match group_quotas.is_empty() {
+        true => DataStoreChunkUsage::default(),
+        false => datastore.last_chunk_usage()?.unwrap_or_default(),
+    };
+
     datastore
         .iter_backup_groups(ns.clone())? // FIXME: Namespaces and recursion parameters!
         .try_fold(Vec::new(), |mut group_info, group| {
@@ -239,6 +250,12 @@ pub fn list_groups(
             let note_path = get_group_note_path(&datastore, &ns, group.as_ref());
             let comment = file_read_firstline(&note_path).ok();
 
+            let quotas = group_quotas
+                .iter()
+                .filter(|quota| quota.applies_to(&store, &ns, group.group()))
+                .cloned();
+            let quotas = get_quota_status(&datastore, &chunk_usage, quotas);
+
             group_info.push(GroupListItem {
                 backup: group.into(),
                 last_backup: last_backup.backup_dir.backup_time(),
@@ -246,6 +263,7 @@ pub fn list_groups(
                 backup_count,
                 files: last_backup.files,
                 comment,
+                quotas,
             });
 
             Ok(group_info)
@@ -290,7 +308,7 @@ pub async fn delete_group(
             &auth_id,
             PRIV_DATASTORE_MODIFY,
             PRIV_DATASTORE_PRUNE,
-            Some(Operation::Write),
+            Some(Operation::Delete),
             &group,
         )?;
 
@@ -397,7 +415,7 @@ pub async fn delete_snapshot(
             &auth_id,
             PRIV_DATASTORE_MODIFY,
             PRIV_DATASTORE_PRUNE,
-            Some(Operation::Write),
+            Some(Operation::Delete),
             &backup_dir.group,
         )?;
 
@@ -599,6 +617,49 @@ unsafe fn list_snapshots_blocking(
     })
 }
 
+fn read_quotas() -> Result<Vec<QuotaConfig>, Error> {
+    let (config, _digest) = pbs_config::quota::config()?;
+    config.convert_to_typed_array("quota")
+}
+
+// get the usage of the given quotas from the last chunk usage calculation, quotas not part of it
+// only get their logical size calculated, failed calculations are only logged
+fn get_quota_status(
+    datastore: &Arc<DataStore>,
+    chunk_usage: &DataStoreChunkUsage,
+    quotas: impl Iterator<Item = QuotaConfig>,
+) -> Vec<QuotaStatus> {
+    quotas
+        .filter(|quota| quota.store == datastore.name())
+        .filter_map(|config| {
+            let group = match config.backup_group() {
+                Ok(group) => group,
+                Err(err) => {
+                    log::error!("invalid group in quota '{}' - {}", config.id, err);
+                    return None;
+                }
+            };
+            let ns = config.ns.clone().unwrap_or_default();
+            let usage = match datastore.cached_quota_usage(&config, chunk_usage) {
+                Ok(Some(usage)) => Ok(usage),
+                Ok(None) => datastore.calculate_usage(&ns, group.as_ref(), false),
+                Err(err) => Err(err),
+            };
+            match usage {
+                Ok(usage) => Some(QuotaStatus { config, usage }),
+                Err(err) => {
+                    log::error!(
+                        "failed to calculate usage of quota '{}' - {}",
+                        config.id,
+                        err
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
 async fn get_snapshots_count(
     store: &Arc<DataStore>,
     owner: Option<&Authid>,
@@ -690,7 +751,7 @@ pub async fn status(
     };
     let datastore = datastore?; // only unwrap no to avoid leaking existence info
 
-    let (counts, gc_status) = if verbose {
+    let (counts, gc_status, quotas) = if verbose {
         let filter_owner = if store_privs & PRIV_DATASTORE_AUDIT != 0 {
             None
         } else {
@@ -704,9 +765,31 @@ pub async fn status(
             None
         };
 
-        (counts, gc_status)
+        let quotas: Vec<QuotaConfig> = read_quotas()?
+            .into_iter()
+            .filter(|quota| {
+                let privs = user_info.lookup_privs(&auth_id, &quota.acl_path());
+                privs & (PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_BACKUP) != 0
+            })
+            .collect();
+        let quotas = if quotas.is_empty() {
+            Vec::new()
+        } else {
+            let datastore = Arc::clone(&datastore);
+            tokio::task::spawn_blocking(move || {
+                let chunk_usage = datastore.last_chunk_usage()?.unwrap_or_default();
+                Ok::<_, Error>(get_quota_status(
+                    &datastore,
+                    &chunk_usage,
+                    quotas.into_iter(),
+                ))
+            })
+            .await??
+        };
+
+        (counts, gc_status, quotas)
     } else {
-        (None, None)
+        (None, None, Vec::new())
     };
 
     Ok(if store_stats {
@@ -717,6 +800,7 @@ pub async fn status(
             avail: storage.available,
             gc_status,
             counts,
+            quotas,
         }
     } else {
         DataStoreStatus {
@@ -725,6 +809,7 @@ pub async fn status(
             avail: 0,
             gc_status,
             counts,
+            quotas,
         }
     })
 }
@@ -933,6 +1018,14 @@ pub fn verify(
                 default: false,
                 description: "Just show what prune would do, but do not delete anything.",
             },
+            "estimate-reclaimable": {
+                optional: true,
+                type: bool,
+                default: false,
+                description: "With 'dry-run', estimate the space a garbage collection would free \
+                    after removing the snapshots. This reads every index file of the datastore, \
+                    so it runs in a worker task whose UPID is returned in the 'upid' attribute.",
+            },
             "keep-options": {
                 type: KeepOptions,
                 flatten: true,
@@ -957,12 +1050,15 @@ pub fn verify(
 pub fn prune(
     group: pbs_api_types::BackupGroup,
     dry_run: bool,
+    estimate_reclaimable: bool,
     keep_options: KeepOptions,
     store: String,
     ns: Option<BackupNamespace>,
     _param: Value,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
+    crate::server::check_keep_options(&keep_options)?;
+
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let ns = ns.unwrap_or_default();
     let datastore = check_privs_and_load_store(
@@ -971,7 +1067,7 @@ pub fn prune(
         &auth_id,
         PRIV_DATASTORE_MODIFY,
         PRIV_DATASTORE_PRUNE,
-        Some(Operation::Write),
+        Some(Operation::Delete),
         &group,
     )?;
 
@@ -980,7 +1076,8 @@ pub fn prune(
 
     let mut prune_result = Vec::new();
 
-    let list = group.list_backups()?;
+    let mut list = group.list_backups()?;
+    load_verify_states(&mut list, &keep_options);
 
     let mut prune_info = compute_prune_info(list, &keep_options)?;
 
@@ -989,6 +1086,32 @@ pub fn prune(
     let keep_all = !keep_options.keeps_something();
 
     if dry_run {
+        let removed: Vec<BackupInfo> = prune_info
+            .iter()
+            .filter(|(_, mark)| !(keep_all || mark.keep()))
+            .map(|(info, _)| info.clone())
+            .collect();
+
+        if estimate_reclaimable && !removed.is_empty() {
+            // snapshots of a group share most of their chunks, so only the group total is useful
+            let group_name = group.group().to_string();
+            let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+            let upid_str = WorkerTask::new_thread(
+                "prune",
+                Some(worker_id),
+                auth_id.to_string(),
+                to_stdout,
+                move |worker| {
+                    crate::server::log_reclaim_estimate(
+                        &worker,
+                        &datastore,
+                        vec![(group_name, removed)],
+                    )
+                },
+            )?;
+            rpcenv["upid"] = Value::from(upid_str);
+        }
+
         for (info, mark) in prune_info {
             let keep = keep_all || mark.keep();
 
@@ -1099,6 +1222,8 @@ pub fn prune_datastore(
     _param: Value,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
+    crate::server::check_keep_options(&prune_options.keep)?;
+
     let user_info = CachedUserInfo::new()?;
 
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -1110,7 +1235,7 @@ pub fn prune_datastore(
         true,
     )?;
 
-    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Delete))?;
     let ns = prune_options.ns.clone().unwrap_or_default();
     let worker_id = format!("{}:{}", store, ns);
 
@@ -1129,6 +1254,156 @@ pub fn prune_datastore(
     Ok(upid_str)
 }
 
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Refresh the local cache of a datastore with S3 backend from the bucket contents.
+pub fn s3_refresh(store: String, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "s3-refresh",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| datastore.refresh_cache_from_backend(&*worker),
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Move all chunks of a sharded datastore to the shard they belong to.
+pub fn rebalance_chunks(store: String, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "rebalance-chunks",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| datastore.rebalance_chunks(&*worker),
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Encrypt the data a datastore stored in plain before at-rest encryption got set up.
+pub fn encrypt_existing_data(
+    store: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "encrypt-existing-data",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| datastore.encrypt_existing_data(&*worker),
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "max-size": {
+                schema: ZSTD_DICTIONARY_SIZE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Train a zstd dictionary on the small unencrypted chunks and blobs of a datastore.
+///
+/// The dictionary is stored in the datastore, it is used for new backups once it is set as
+/// 'zstd-dictionary' tuning option.
+pub fn train_zstd_dictionary(
+    store: String,
+    max_size: Option<usize>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let max_size = max_size.unwrap_or(DEFAULT_DICTIONARY_SIZE);
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "train-zstd-dictionary",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            datastore.train_zstd_dictionary(max_size, &*worker)?;
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
 #[api(
     input: {
         properties: {
@@ -1150,7 +1425,7 @@ pub fn start_garbage_collection(
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
-    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Delete))?;
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
     let job = Job::new("garbage_collection", &store)
@@ -1199,6 +1474,98 @@ pub fn garbage_collection_status(
     Ok(status)
 }
 
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Start calculating the deduplicated chunk usage.
+pub fn start_chunk_usage_calculation(
+    store: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let job = Job::new("chunk-usage", &store)
+        .map_err(|_| format_err!("chunk usage calculation already running"))?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = crate::server::do_chunk_usage_job(job, datastore, &auth_id, None, to_stdout)
+        .map_err(|err| {
+            format_err!(
+                "unable to start chunk usage calculation on datastore {} - {}",
+                store,
+                err
+            )
+        })?;
+
+    Ok(json!(upid_str))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: DataStoreChunkUsage,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires DATASTORE_AUDIT on /datastore/{store}[/{namespace}], namespaces \
+            without DATASTORE_AUDIT are left out.",
+    },
+)]
+/// Get the result of the last chunk usage calculation, for a namespace and its sub-namespaces.
+pub fn get_chunk_usage(
+    store: String,
+    ns: Option<BackupNamespace>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<DataStoreChunkUsage, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_AUDIT)?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let mut usage = datastore.last_chunk_usage()?.unwrap_or_default();
+
+    let user_info = CachedUserInfo::new()?;
+    let visible = |item_ns: &BackupNamespace| {
+        ns.contains(item_ns).is_some()
+            && user_info.lookup_privs(&auth_id, &item_ns.acl_path(&store)) & PRIV_DATASTORE_AUDIT
+                != 0
+    };
+
+    usage.namespaces.retain(|item| visible(&item.ns));
+    usage.groups.retain(|item| visible(&item.ns));
+    usage.snapshots.retain(|item| visible(&item.ns));
+    usage
+        .quotas
+        .retain(|item| visible(&item.config.ns.clone().unwrap_or_default()));
+
+    Ok(usage)
+}
+
 #[api(
     returns: {
         description: "List the accessible datastores.",
@@ -1312,9 +1679,9 @@ pub fn download_file(
         path.push(backup_dir.relative_path());
         path.push(&file_name);
 
-        let file = tokio::fs::File::open(&path)
-            .await
+        let file = proxmox_async::runtime::block_in_place(|| datastore.open_snapshot_file(&path))
             .map_err(|err| http_err!(BAD_REQUEST, "File open failed: {}", err))?;
+        let file = tokio::fs::File::from_std(file);
 
         let payload =
             tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
@@ -1407,7 +1774,7 @@ pub fn download_file_decoded(
 
         let body = match extension {
             "didx" => {
-                let index = DynamicIndexReader::open(&path).map_err(|err| {
+                let index = datastore.open_dynamic_reader(&path).map_err(|err| {
                     format_err!("unable to read dynamic index '{:?}' - {}", &path, err)
                 })?;
                 let (csum, size) = index.compute_csum();
@@ -1421,7 +1788,7 @@ pub fn download_file_decoded(
                 }))
             }
             "fidx" => {
-                let index = FixedIndexReader::open(&path).map_err(|err| {
+                let index = datastore.open_fixed_reader(&path).map_err(|err| {
                     format_err!("unable to read fixed index '{:?}' - {}", &path, err)
                 })?;
 
@@ -1440,18 +1807,17 @@ pub fn download_file_decoded(
                 )
             }
             "blob" => {
-                let file = std::fs::File::open(&path)
+                let file = datastore
+                    .open_snapshot_file(&path)
                     .map_err(|err| http_err!(BAD_REQUEST, "File open failed: {}", err))?;
 
                 // FIXME: load full blob to verify index checksum?
 
                 Body::wrap_stream(
-                    WrappedReaderStream::new(DataBlobReader::new(file, None)?).map_err(
-                        move |err| {
-                            eprintln!("error during streaming of '{:?}' - {}", path, err);
-                            err
-                        },
-                    ),
+                    WrappedReaderStream::new(datastore.blob_reader(file)?).map_err(move |err| {
+                        eprintln!("error during streaming of '{:?}' - {}", path, err);
+                        err
+                    }),
                 )
             }
             extension => {
@@ -1538,7 +1904,9 @@ pub fn upload_backup_log(
         // always verify blob/CRC at server side
         let blob = DataBlob::load_from_reader(&mut &data[..])?;
 
-        replace_file(&path, blob.raw_data(), CreateOptions::new(), false)?;
+        let raw_data = datastore.encode_snapshot_file(blob.raw_data(), &path)?;
+        replace_file(&path, &raw_data, CreateOptions::new(), false)?;
+        datastore.upload_to_backend(&backup_dir.relative_path().join(file_name))?;
 
         // fixme: use correct formatter
         Ok(formatter::JSON_FORMATTER.format_data(Value::Null, &*rpcenv))
@@ -1608,7 +1976,8 @@ pub async fn catalog(
         path.push(backup_dir.relative_path());
         path.push(file_name);
 
-        let index = DynamicIndexReader::open(&path)
+        let index = datastore
+            .open_dynamic_reader(&path)
             .map_err(|err| format_err!("unable to read dynamic index '{:?}' - {}", &path, err))?;
 
         let (csum, size) = index.compute_csum();
@@ -1701,7 +2070,8 @@ pub fn pxar_file_download(
         path.push(backup_dir.relative_path());
         path.push(pxar_name);
 
-        let index = DynamicIndexReader::open(&path)
+        let index = datastore
+            .open_dynamic_reader(&path)
             .map_err(|err| format_err!("unable to read dynamic index '{:?}' - {}", &path, err))?;
 
         let (csum, size) = index.compute_csum();
@@ -1839,6 +2209,7 @@ pub fn get_active_operations(store: String, _param: Value) -> Result<Value, Erro
     Ok(json!({
         "read": active_operations.read,
         "write": active_operations.write,
+        "delete": active_operations.delete,
     }))
 }
 
@@ -1933,6 +2304,9 @@ pub fn set_group_notes(
     let note_path = get_group_note_path(&datastore, &ns, &backup_group);
     replace_file(note_path, notes.as_bytes(), CreateOptions::new(), false)?;
 
+    let group = datastore.backup_group(ns, backup_group);
+    datastore.upload_to_backend(&group.relative_group_path().join(GROUP_NOTES_FILE_NAME))?;
+
     Ok(())
 }
 
@@ -2239,6 +2613,230 @@ pub async fn set_backup_owner(
     .await?
 }
 
+/// Mounts the backing device of a removable datastore on the datastore path.
+///
+/// Does nothing if the device is already mounted there.
+pub fn do_mount_device(datastore: &DataStoreConfig) -> Result<(), Error> {
+    let uuid = match datastore.backing_device.as_deref() {
+        Some(uuid) => uuid,
+        None => bail!("datastore '{}' is not removable", datastore.name),
+    };
+
+    if get_datastore_mount_status(datastore) == DataStoreMountStatus::Mounted {
+        return Ok(());
+    }
+
+    let device = Path::new("/dev/disk/by-uuid").join(uuid);
+    if !device.exists() {
+        bail!(
+            "backing device '{}' of datastore '{}' is not available",
+            uuid,
+            datastore.name
+        );
+    }
+
+    let backup_user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    create_path(&datastore.path, None, Some(options))?;
+
+    let mut command = std::process::Command::new("mount");
+    command.arg(&device).arg(&datastore.path);
+    proxmox_sys::command::run_command(command, None)
+        .map_err(|err| format_err!("mounting '{}' failed - {}", datastore.name, err))?;
+
+    Ok(())
+}
+
+/// Sets or clears the maintenance mode of a datastore, returning the previous one.
+fn set_maintenance_mode(store: &str, mode: Option<String>) -> Result<Option<String>, Error> {
+    let _lock = pbs_config::datastore::lock_config()?;
+    let (mut config, _digest) = pbs_config::datastore::config()?;
+    let mut datastore: DataStoreConfig = config.lookup("datastore", store)?;
+    let old_mode = std::mem::replace(&mut datastore.maintenance_mode, mode);
+    config.set_data(store, "datastore", &datastore)?;
+    pbs_config::datastore::save_config(&config)?;
+    Ok(old_mode)
+}
+
+async fn do_unmount_device(datastore: &DataStoreConfig, worker: &WorkerTask) -> Result<(), Error> {
+    if let Err(err) = crate::server::notify_datastore_removed().await {
+        task_warn!(worker, "failed to notify proxy about unmounting: {}", err);
+    }
+    DataStore::remove_unused_datastores()?;
+
+    let mut last_log = 0;
+    loop {
+        let operations = task_tracking::get_active_operations(&datastore.name)?;
+        if operations.read == 0 && operations.write == 0 && operations.delete == 0 {
+            break;
+        }
+        let now = proxmox_time::epoch_i64();
+        if now - last_log >= 10 {
+            task_log!(
+                worker,
+                "waiting for active operations to finish (read: {}, write: {}, delete: {})",
+                operations.read,
+                operations.write,
+                operations.delete
+            );
+            last_log = now;
+        }
+        worker.check_abort()?;
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    let mut command = std::process::Command::new("umount");
+    command.arg(&datastore.path);
+    proxmox_async::runtime::block_in_place(|| proxmox_sys::command::run_command(command, None))
+        .map_err(|err| format_err!("unmounting '{}' failed - {}", datastore.name, err))?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::And(&[
+            &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, false),
+            &Permission::Privilege(&["system", "disks"], PRIV_SYS_MODIFY, false)
+        ]),
+    },
+)]
+/// Mount a removable datastore and start its sync jobs configured to run on mount.
+pub fn mount(store: String, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let (section_config, _digest) = pbs_config::datastore::config()?;
+    let datastore: DataStoreConfig = section_config.lookup("datastore", &store)?;
+
+    if !datastore.is_removable() {
+        bail!("datastore '{}' is not removable", store);
+    }
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::spawn(
+        "mount-device",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move {
+            // drop stale references to a previously unplugged device
+            if let Err(err) = crate::server::notify_datastore_removed().await {
+                task_warn!(worker, "failed to notify proxy about mounting: {}", err);
+            }
+            DataStore::remove_unused_datastores()?;
+
+            proxmox_async::runtime::block_in_place(|| do_mount_device(&datastore))?;
+            task_log!(
+                worker,
+                "mounted datastore '{}' on {}",
+                datastore.name,
+                datastore.path
+            );
+
+            if !Path::new(&datastore.path).join(".chunks").exists() {
+                let tuning: DatastoreTuning = serde_json::from_value(
+                    DatastoreTuning::API_SCHEMA
+                        .parse_property_string(datastore.tuning.as_deref().unwrap_or(""))?,
+                )?;
+                let backup_user = pbs_config::backup_user()?;
+                proxmox_async::runtime::block_in_place(|| {
+                    ChunkStore::create(
+                        &datastore.name,
+                        &datastore.path,
+                        backup_user.uid,
+                        backup_user.gid,
+                        Some(&*worker),
+                        tuning.sync_level.unwrap_or_default(),
+                    )
+                })?;
+            }
+
+            if let Err(err) = crate::server::notify_datastore_mounted(&datastore.name).await {
+                task_warn!(worker, "failed to start sync jobs: {}", err);
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::And(&[
+            &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+            &Permission::Privilege(&["system", "disks"], PRIV_SYS_MODIFY, false)
+        ]),
+    },
+)]
+/// Unmount a removable datastore, waiting for running operations to finish first.
+pub fn unmount(store: String, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let (section_config, _digest) = pbs_config::datastore::config()?;
+    let datastore: DataStoreConfig = section_config.lookup("datastore", &store)?;
+
+    if !datastore.is_removable() {
+        bail!("datastore '{}' is not removable", store);
+    }
+    if get_datastore_mount_status(&datastore) != DataStoreMountStatus::Mounted {
+        bail!("datastore '{}' is not mounted", store);
+    }
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let old_mode = set_maintenance_mode(&store, Some("type=unmount".to_string()))?;
+
+    let upid_str = WorkerTask::spawn(
+        "unmount-device",
+        Some(store.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        {
+            let store = store.clone();
+            let old_mode = old_mode.clone();
+            move |worker| async move {
+                let result = do_unmount_device(&datastore, &worker).await;
+
+                set_maintenance_mode(&store, old_mode)?;
+
+                result
+            }
+        },
+    );
+
+    if upid_str.is_err() {
+        // the worker never ran, so it could not reset the maintenance mode
+        let _ = set_maintenance_mode(&store, old_mode);
+    }
+
+    upid_str
+}
+
 #[sortable]
 const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
     (
@@ -2250,6 +2848,12 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "change-owner",
         &Router::new().post(&API_METHOD_SET_BACKUP_OWNER),
     ),
+    (
+        "chunk-usage",
+        &Router::new()
+            .get(&API_METHOD_GET_CHUNK_USAGE)
+            .post(&API_METHOD_START_CHUNK_USAGE_CALCULATION),
+    ),
     (
         "download",
         &Router::new().download(&API_METHOD_DOWNLOAD_FILE),
@@ -2258,6 +2862,10 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "download-decoded",
         &Router::new().download(&API_METHOD_DOWNLOAD_FILE_DECODED),
     ),
+    (
+        "encrypt-existing-data",
+        &Router::new().post(&API_METHOD_ENCRYPT_EXISTING_DATA),
+    ),
     ("files", &Router::new().get(&API_METHOD_LIST_SNAPSHOT_FILES)),
     (
         "gc",
@@ -2277,6 +2885,7 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
             .get(&API_METHOD_LIST_GROUPS)
             .delete(&API_METHOD_DELETE_GROUP),
     ),
+    ("mount", &Router::new().post(&API_METHOD_MOUNT)),
     (
         "namespace",
         // FIXME: move into datastore:: sub-module?!
@@ -2303,7 +2912,12 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "pxar-file-download",
         &Router::new().download(&API_METHOD_PXAR_FILE_DOWNLOAD),
     ),
+    (
+        "rebalance-chunks",
+        &Router::new().post(&API_METHOD_REBALANCE_CHUNKS),
+    ),
     ("rrd", &Router::new().get(&API_METHOD_GET_RRD_STATS)),
+    ("s3-refresh", &Router::new().post(&API_METHOD_S3_REFRESH)),
     (
         "snapshots",
         &Router::new()
@@ -2311,6 +2925,11 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
             .delete(&API_METHOD_DELETE_SNAPSHOT),
     ),
     ("status", &Router::new().get(&API_METHOD_STATUS)),
+    (
+        "train-zstd-dictionary",
+        &Router::new().post(&API_METHOD_TRAIN_ZSTD_DICTIONARY),
+    ),
+    ("unmount", &Router::new().post(&API_METHOD_UNMOUNT)),
     (
         "upload-backup-log",
         &Router::new().upload(&API_METHOD_UPLOAD_BACKUP_LOG),
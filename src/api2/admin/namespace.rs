@@ -156,7 +156,7 @@ pub fn delete_namespace(
 
     check_ns_modification_privs(&store, &ns, &auth_id)?;
 
-    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Delete))?;
 
     if !datastore.remove_namespace_recursive(&ns, delete_groups)? {
         if delete_groups {
@@ -9,15 +9,19 @@ use serde_json::{json, Value};
 use proxmox_router::{RpcEnvironment, RpcEnvironmentType};
 use proxmox_sys::fs::{replace_file, CreateOptions};
 
-use pbs_api_types::Authid;
+use pbs_api_types::{Authid, QuotaConfig, QuotaUsage};
 use pbs_datastore::backup_info::{BackupDir, BackupInfo};
 use pbs_datastore::dynamic_index::DynamicIndexWriter;
 use pbs_datastore::fixed_index::FixedIndexWriter;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType, MANIFEST_BLOB_NAME};
 use pbs_datastore::{DataBlob, DataStore};
 use proxmox_rest_server::{formatter::*, WorkerTask};
 
 use crate::backup::verify_backup_dir_with_lock;
 
+use super::resume::{partial_archive_name, remove_resume_state, ResumeArchive, ResumeMarker};
+
 use hyper::{Body, Response};
 
 #[derive(Copy, Clone, Serialize)]
@@ -74,6 +78,41 @@ struct FixedWriterState {
 // key=digest, value=length
 type KnownChunksMap = HashMap<[u8; 32], u32>;
 
+/// Quota applying to the backup group, with the usage at backup start.
+pub struct ActiveQuota {
+    pub config: QuotaConfig,
+    pub usage: QuotaUsage,
+}
+
+impl ActiveQuota {
+    /// Raise an error if the usage plus the given additional sizes exceeds a limit.
+    pub fn check(&self, logical_size: u64, unique_size: u64) -> Result<(), Error> {
+        if let Some(max) = self.config.max_logical_size {
+            let used = self.usage.logical_size + logical_size;
+            if used > max.as_u64() {
+                bail!(
+                    "quota '{}' exceeded - logical size {} > {}",
+                    self.config.id,
+                    used,
+                    max.as_u64()
+                );
+            }
+        }
+        if let (Some(max), Some(usage)) = (self.config.max_unique_size, self.usage.unique_size) {
+            let used = usage + unique_size;
+            if used > max.as_u64() {
+                bail!(
+                    "quota '{}' exceeded - unique size {} > {}",
+                    self.config.id,
+                    used,
+                    max.as_u64()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
 struct SharedBackupState {
     finished: bool,
     uid_counter: usize,
@@ -83,6 +122,10 @@ struct SharedBackupState {
     known_chunks: KnownChunksMap,
     backup_size: u64, // sums up size of all files
     backup_stat: UploadStatistic,
+    quotas: Vec<ActiveQuota>,
+    quota_logical_size: u64, // logical size added by this backup
+    quota_unique_size: u64,  // sum of uploaded chunks (may include already referenced ones)
+    resume_archives: HashMap<String, ResumeArchive>, // partial indexes not yet picked up
 }
 
 impl SharedBackupState {
@@ -99,6 +142,16 @@ impl SharedBackupState {
         self.uid_counter += 1;
         self.uid_counter
     }
+
+    // Account added data and raise error if a quota is exceeded
+    fn account_quota(&mut self, logical_size: u64, unique_size: u64) -> Result<(), Error> {
+        self.quota_logical_size += logical_size;
+        self.quota_unique_size += unique_size;
+        for quota in &self.quotas {
+            quota.check(self.quota_logical_size, self.quota_unique_size)?;
+        }
+        Ok(())
+    }
 }
 
 /// `RpcEnvironmet` implementation for backup service
@@ -133,6 +186,10 @@ impl BackupEnvironment {
             known_chunks: HashMap::new(),
             backup_size: 0,
             backup_stat: UploadStatistic::new(),
+            quotas: Vec::new(),
+            quota_logical_size: 0,
+            quota_unique_size: 0,
+            resume_archives: HashMap::new(),
         };
 
         Self {
@@ -149,6 +206,27 @@ impl BackupEnvironment {
         }
     }
 
+    /// Set the quotas this backup is subject to.
+    pub fn set_quotas(&self, quotas: Vec<ActiveQuota>) {
+        let mut state = self.state.lock().unwrap();
+        state.quotas = quotas;
+    }
+
+    /// Continue the interrupted backup described by `marker`.
+    ///
+    /// Files completed before the interruption count as uploaded, the partial indexes are picked
+    /// up when the client creates the corresponding archive again.
+    pub fn set_resume_marker(&self, marker: ResumeMarker) {
+        let mut state = self.state.lock().unwrap();
+        state.file_counter += marker.file_count;
+        state.backup_size += marker.backup_size;
+        state.resume_archives = marker
+            .archives
+            .into_iter()
+            .map(|archive| (archive.archive_name.clone(), archive))
+            .collect();
+    }
+
     /// Register a Chunk with associated length.
     ///
     /// We do not fully trust clients, so a client may only use registered
@@ -211,6 +289,8 @@ impl BackupEnvironment {
             data.upload_stat.duplicates += 1;
         }
 
+        state.account_quota(0, compressed_size as u64)?;
+
         // register chunk
         state.known_chunks.insert(digest, size);
 
@@ -246,6 +326,8 @@ impl BackupEnvironment {
             data.upload_stat.duplicates += 1;
         }
 
+        state.account_quota(0, compressed_size as u64)?;
+
         // register chunk
         state.known_chunks.insert(digest, size);
 
@@ -297,6 +379,9 @@ impl BackupEnvironment {
 
         state.ensure_unfinished()?;
 
+        // the whole image counts, even if only changed chunks get uploaded
+        state.account_quota(size as u64, 0)?;
+
         let uid = state.next_uid();
 
         state.fixed_writers.insert(
@@ -316,6 +401,44 @@ impl BackupEnvironment {
         Ok(uid)
     }
 
+    fn partial_archive_path(&self, archive_name: &str) -> std::path::PathBuf {
+        let mut path = self.backup_dir.relative_path();
+        path.push(partial_archive_name(archive_name));
+        path
+    }
+
+    /// Pick up the partial index of `archive_name` left by the interrupted backup.
+    ///
+    /// The client appends all chunks of the archive again, so the writer starts empty. The chunks
+    /// of the partial index get registered, so that they need not be uploaded again. Returns
+    /// false if the archive was not interrupted.
+    pub fn resume_archive(&self, archive_name: &str) -> Result<bool, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        state.ensure_unfinished()?;
+
+        if state.resume_archives.remove(archive_name).is_none() {
+            return Ok(false);
+        }
+
+        let path = self.partial_archive_path(archive_name);
+        let index: Box<dyn IndexFile> = match archive_type(archive_name)? {
+            ArchiveType::FixedIndex => Box::new(self.datastore.open_fixed_reader(&path)?),
+            ArchiveType::DynamicIndex => Box::new(self.datastore.open_dynamic_reader(&path)?),
+            ArchiveType::Blob => bail!("no partial index for archive '{}'", archive_name),
+        };
+
+        for pos in 0..index.index_count() {
+            let info = index.chunk_info(pos).unwrap();
+            if info.digest == [0u8; 32] {
+                continue; // not written before the interruption
+            }
+            state.known_chunks.insert(info.digest, info.size() as u32);
+        }
+
+        Ok(true)
+    }
+
     /// Append chunk to dynamic writer
     pub fn dynamic_writer_append_chunk(
         &self,
@@ -347,6 +470,8 @@ impl BackupEnvironment {
 
         data.index.add_chunk(data.offset, digest)?;
 
+        state.account_quota(size as u64, 0)?;
+
         Ok(())
     }
 
@@ -573,9 +698,15 @@ impl BackupEnvironment {
 
         // always verify blob/CRC at server side
         let blob = DataBlob::load_from_reader(&mut &data[..])?;
+        if let Some(id) = blob.zstd_dictionary_id() {
+            // must be readable later on
+            self.datastore.zstd_dictionary(id)?;
+        }
 
-        let raw_data = blob.raw_data();
-        replace_file(&path, raw_data, CreateOptions::new(), false)?;
+        let raw_data = self
+            .datastore
+            .encode_snapshot_file(blob.raw_data(), &path)?;
+        replace_file(&path, &raw_data, CreateOptions::new(), false)?;
 
         self.log(format!(
             "add blob {:?} ({} bytes, comp: {})",
@@ -586,6 +717,7 @@ impl BackupEnvironment {
         state.file_counter += 1;
         state.backup_size += orig_len as u64;
         state.backup_stat.size += blob_len as u64;
+        state.account_quota(orig_len as u64, 0)?;
 
         Ok(())
     }
@@ -623,8 +755,16 @@ impl BackupEnvironment {
             }
         }
 
+        remove_resume_state(&self.backup_dir.full_path())
+            .map_err(|err| format_err!("unable to remove resume state - {}", err))?;
+        state.resume_archives.clear();
+
         self.datastore.try_ensure_sync_level()?;
 
+        self.datastore
+            .upload_snapshot_to_backend(&self.backup_dir)
+            .map_err(|err| format_err!("unable to upload snapshot to backend - {}", err))?;
+
         // marks the backup as successful
         state.finished = true;
 
@@ -707,6 +847,73 @@ impl BackupEnvironment {
         state.finished
     }
 
+    /// Keep the interrupted backup for resuming until `timeout` minutes passed.
+    ///
+    /// Open index writers are stored as partial indexes, the resume marker records their
+    /// progress. The backup counts as finished afterwards, so no further data gets added.
+    pub fn checkpoint_backup(&self, timeout: u64) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        state.finished = true;
+
+        if state.file_counter == 0
+            && state.dynamic_writers.is_empty()
+            && state.fixed_writers.is_empty()
+            && state.resume_archives.is_empty()
+        {
+            bail!("no data written yet");
+        }
+
+        let snapshot_path = self.backup_dir.full_path();
+
+        // an uploaded manifest would mark the snapshot as complete
+        match std::fs::remove_file(snapshot_path.join(MANIFEST_BLOB_NAME)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                bail!("unable to remove manifest - {}", err);
+            }
+            _ => (),
+        }
+
+        // partial indexes of the previous connection which did not get picked up
+        let mut archives: Vec<ResumeArchive> =
+            state.resume_archives.drain().map(|(_, a)| a).collect();
+
+        for (_, mut data) in state.dynamic_writers.drain() {
+            let path = self.partial_archive_path(&data.name);
+            data.index.close_as(&path)?;
+            archives.push(ResumeArchive {
+                archive_name: data.name,
+                size: None,
+                chunk_size: None,
+                incremental: false,
+                offset: data.offset,
+                chunk_count: data.chunk_count,
+            });
+        }
+
+        for (_, mut data) in state.fixed_writers.drain() {
+            let path = self.partial_archive_path(&data.name);
+            data.index.close_as(&path)?;
+            archives.push(ResumeArchive {
+                archive_name: data.name,
+                size: Some(data.size as u64),
+                chunk_size: Some(data.chunk_size),
+                incremental: data.incremental,
+                offset: 0,
+                chunk_count: data.chunk_count,
+            });
+        }
+
+        let marker = ResumeMarker {
+            expires: proxmox_time::epoch_i64() + (timeout as i64) * 60,
+            file_count: state.file_counter,
+            backup_size: state.backup_size,
+            archives,
+        };
+        marker.save(&snapshot_path)?;
+
+        Ok(())
+    }
+
     /// Remove complete backup
     pub fn remove_backup(&self) -> Result<(), Error> {
         let mut state = self.state.lock().unwrap();
@@ -755,3 +962,45 @@ impl AsRef<BackupEnvironment> for Box<dyn RpcEnvironment> {
         self.as_any().downcast_ref::<BackupEnvironment>().unwrap()
     }
 }
+
+#[test]
+fn test_active_quota_check() {
+    let mut quota = ActiveQuota {
+        config: QuotaConfig {
+            id: "test".to_string(),
+            store: "store1".to_string(),
+            ns: None,
+            group: None,
+            max_logical_size: Some(1000.into()),
+            max_unique_size: None,
+            comment: None,
+        },
+        usage: QuotaUsage {
+            logical_size: 600,
+            unique_size: None,
+        },
+    };
+
+    // the limit itself may be reached, but not exceeded
+    assert!(quota.check(0, 0).is_ok());
+    assert!(quota.check(400, 10_000).is_ok());
+    assert!(quota.check(401, 0).is_err());
+
+    // without a calculated unique size, the unique limit can't be checked
+    quota.config.max_unique_size = Some(100.into());
+    assert!(quota.check(0, 10_000).is_ok());
+
+    quota.usage.unique_size = Some(50);
+    assert!(quota.check(0, 50).is_ok());
+    assert!(quota.check(0, 51).is_err());
+    assert!(quota.check(401, 0).is_err());
+
+    // quotas without limits never fail
+    quota.config.max_logical_size = None;
+    quota.config.max_unique_size = None;
+    assert!(quota.check(u64::MAX / 2, u64::MAX / 2).is_ok());
+
+    // an exhausted quota refuses new backups
+    quota.config.max_logical_size = Some(500.into());
+    assert!(quota.check(0, 0).is_err());
+}
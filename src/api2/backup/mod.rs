@@ -1,5 +1,7 @@
 //! Backup protocol (HTTP2 upgrade)
 
+use std::sync::Arc;
+
 use anyhow::{bail, format_err, Error};
 use futures::*;
 use hex::FromHex;
@@ -11,19 +13,27 @@ use serde_json::{json, Value};
 
 use proxmox_router::{http_err, list_subdirs_api_method};
 use proxmox_router::{
-    ApiHandler, ApiMethod, ApiResponseFuture, Permission, Router, RpcEnvironment, SubdirMap,
+    ApiFuture, ApiHandler, ApiMethod, ApiResponseFuture, Permission, Router, RpcEnvironment,
+    SubdirMap,
 };
 use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, Operation, SnapshotVerifyState, VerifyState,
-    BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA, PRIV_DATASTORE_BACKUP,
+    Authid, BackupNamespace, BackupType, ChunkerType, Operation, QuotaConfig, SnapshotVerifyState,
+    VerifyState, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
+    BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA,
+    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ, ZSTD_DICTIONARY_ID_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
+use pbs_datastore::chunker::ChunkerConfig;
+use pbs_datastore::file_formats::{
+    DataBlobHeader, COMPRESSED_BLOB_MAGIC_1_0, DICT_COMPR_BLOB_MAGIC_1_0,
+    UNCOMPRESSED_BLOB_MAGIC_1_0,
+};
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{archive_type, ArchiveType};
+use pbs_datastore::zstd_dictionary::{register_dictionary, ZstdDictionary, MAX_DICTIONARY_SIZE};
 use pbs_datastore::{DataStore, PROXMOX_BACKUP_PROTOCOL_ID_V1};
 use pbs_tools::json::{required_array_param, required_integer_param, required_string_param};
 use proxmox_rest_server::{H2Service, WorkerTask};
@@ -35,6 +45,9 @@ use environment::*;
 mod upload_chunk;
 use upload_chunk::*;
 
+pub(crate) mod resume;
+use resume::{is_partial_archive, partial_archive_name, remove_expired_backups, ResumeMarker};
+
 pub const ROUTER: Router = Router::new().upgrade(&API_METHOD_UPGRADE_BACKUP);
 
 #[sortable]
@@ -50,6 +63,7 @@ pub const API_METHOD_UPGRADE_BACKUP: ApiMethod = ApiMethod::new(
             ("backup-time", false, &BACKUP_TIME_SCHEMA),
             ("debug", true, &BooleanSchema::new("Enable verbose debug logging.").schema()),
             ("benchmark", true, &BooleanSchema::new("Job is a benchmark (do not keep data).").schema()),
+            ("resume", true, &BooleanSchema::new("Resume an interrupted backup with the same backup time, if the datastore kept it.").schema()),
         ]),
     )
 ).access(
@@ -66,6 +80,43 @@ pub(crate) fn optional_ns_param(param: &Value) -> Result<BackupNamespace, Error>
     }
 }
 
+// Collect the quotas a new snapshot in the backup group is subject to, and check whether
+// they are already exhausted.
+fn active_quotas(
+    datastore: &Arc<DataStore>,
+    ns: &BackupNamespace,
+    group: &pbs_api_types::BackupGroup,
+) -> Result<Vec<ActiveQuota>, Error> {
+    let (config, _digest) = pbs_config::quota::config()?;
+    let list: Vec<QuotaConfig> = config.convert_to_typed_array("quota")?;
+
+    let mut quotas = Vec::new();
+    let mut chunk_usage = None;
+    for config in list {
+        if !config.applies_to(datastore.name(), ns, group) {
+            continue;
+        }
+
+        if chunk_usage.is_none() {
+            chunk_usage = Some(datastore.last_chunk_usage()?.unwrap_or_default());
+        }
+        let usage = match datastore.cached_quota_usage(&config, chunk_usage.as_ref().unwrap())? {
+            Some(usage) => usage,
+            None => datastore.calculate_usage(
+                &config.ns.clone().unwrap_or_default(),
+                config.backup_group()?.as_ref(),
+                config.max_unique_size.is_some(),
+            )?,
+        };
+
+        let quota = ActiveQuota { config, usage };
+        quota.check(0, 0)?;
+        quotas.push(quota);
+    }
+
+    Ok(quotas)
+}
+
 fn upgrade_to_backup_protocol(
     parts: Parts,
     req_body: Body,
@@ -76,6 +127,7 @@ fn upgrade_to_backup_protocol(
     async move {
         let debug = param["debug"].as_bool().unwrap_or(false);
         let benchmark = param["benchmark"].as_bool().unwrap_or(false);
+        let resume = param["resume"].as_bool().unwrap_or(false);
 
         let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
@@ -191,11 +243,24 @@ fn upgrade_to_backup_protocol(
             None
         };
 
+        // reads all manifests of the covered snapshots
+        let quotas = tokio::task::block_in_place(|| {
+            active_quotas(&datastore, backup_group.backup_ns(), backup_group.group())
+        })?;
+
         let (path, is_new, snap_guard) =
             datastore.create_locked_backup_dir(backup_dir.backup_ns(), backup_dir.as_ref())?;
-        if !is_new {
+        let resume_marker = if is_new {
+            None
+        } else if resume && !benchmark {
+            match ResumeMarker::load(&backup_dir.full_path())? {
+                Some(marker) if !marker.expired() => Some(marker),
+                Some(_) => bail!("backup directory already exists, resume timeout expired."),
+                None => bail!("backup directory already exists."),
+            }
+        } else {
             bail!("backup directory already exists.");
-        }
+        };
 
         WorkerTask::spawn(
             worker_type,
@@ -213,11 +278,28 @@ fn upgrade_to_backup_protocol(
 
                 env.debug = debug;
                 env.last_backup = last_backup;
+                env.set_quotas(quotas);
+
+                if let Some(marker) = resume_marker {
+                    env.set_resume_marker(marker);
+                    env.log(format!(
+                        "resuming interrupted {} on datastore '{}': {:?}",
+                        worker_type, store, path
+                    ));
+                } else {
+                    env.log(format!(
+                        "starting new {} on datastore '{}': {:?}",
+                        worker_type, store, path
+                    ));
+                }
 
-                env.log(format!(
-                    "starting new {} on datastore '{}': {:?}",
-                    worker_type, store, path
-                ));
+                match remove_expired_backups(&backup_group) {
+                    Ok(0) => (),
+                    Ok(count) => env.log(format!("removed {count} expired interrupted backups")),
+                    Err(err) => env.log(format!(
+                        "removing expired interrupted backups failed: {err}"
+                    )),
+                }
 
                 let service =
                     H2Service::new(env.clone(), worker.clone(), &BACKUP_API_ROUTER, debug);
@@ -284,6 +366,30 @@ fn upgrade_to_backup_protocol(
                         }
                     };
 
+                    // keep the data written so far if the datastore allows resuming
+                    let checkpoint = |env: &BackupEnvironment| {
+                        let timeout = match env.datastore.backup_resume_timeout() {
+                            Some(timeout) => timeout,
+                            None => return false,
+                        };
+                        let res = proxmox_async::runtime::block_in_place(|| {
+                            env.checkpoint_backup(timeout)
+                        });
+                        match res {
+                            Ok(()) => {
+                                env.log(format!(
+                                    "keeping interrupted backup for resuming during {} minutes",
+                                    timeout
+                                ));
+                                true
+                            }
+                            Err(err) => {
+                                env.log(format!("unable to keep interrupted backup: {}", err));
+                                false
+                            }
+                        }
+                    };
+
                     match (res, env.ensure_finished()) {
                         (Ok(_), Ok(())) => {
                             env.log("backup finished successfully");
@@ -298,14 +404,18 @@ fn upgrade_to_backup_protocol(
                         }
                         (Ok(_), Err(err)) => {
                             env.log(format!("backup ended and finish failed: {}", err));
-                            env.log("removing unfinished backup");
-                            proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
+                            if !checkpoint(&env) {
+                                env.log("removing unfinished backup");
+                                proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
+                            }
                             Err(err)
                         }
                         (Err(err), Err(_)) => {
                             env.log(format!("backup failed: {}", err));
-                            env.log("removing failed backup");
-                            proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
+                            if !checkpoint(&env) {
+                                env.log("removing failed backup");
+                                proxmox_async::runtime::block_in_place(|| env.remove_backup())?;
+                            }
                             Err(err)
                         }
                     }
@@ -328,6 +438,10 @@ fn upgrade_to_backup_protocol(
 
 const BACKUP_API_SUBDIRS: SubdirMap = &[
     ("blob", &Router::new().upload(&API_METHOD_UPLOAD_BLOB)),
+    (
+        "compression",
+        &Router::new().get(&API_METHOD_GET_COMPRESSION),
+    ),
     (
         "dynamic_chunk",
         &Router::new().upload(&API_METHOD_UPLOAD_DYNAMIC_CHUNK),
@@ -363,6 +477,10 @@ const BACKUP_API_SUBDIRS: SubdirMap = &[
             .post(&API_METHOD_CREATE_FIXED_INDEX)
             .put(&API_METHOD_FIXED_APPEND),
     ),
+    (
+        "known_chunks",
+        &Router::new().post(&API_METHOD_QUERY_KNOWN_CHUNKS),
+    ),
     (
         "previous",
         &Router::new().download(&API_METHOD_DOWNLOAD_PREVIOUS),
@@ -371,22 +489,44 @@ const BACKUP_API_SUBDIRS: SubdirMap = &[
         "previous_backup_time",
         &Router::new().get(&API_METHOD_GET_PREVIOUS_BACKUP_TIME),
     ),
+    ("resume", &Router::new().get(&API_METHOD_GET_RESUME_INFO)),
+    (
+        "resume_index",
+        &Router::new().download(&API_METHOD_DOWNLOAD_RESUME_INDEX),
+    ),
     (
         "speedtest",
         &Router::new().upload(&API_METHOD_UPLOAD_SPEEDTEST),
     ),
+    (
+        "zstd_dictionary",
+        &Router::new()
+            .download(&API_METHOD_DOWNLOAD_ZSTD_DICTIONARY)
+            .upload(&API_METHOD_UPLOAD_ZSTD_DICTIONARY),
+    ),
 ];
 
 pub const BACKUP_API_ROUTER: Router = Router::new()
     .get(&list_subdirs_api_method!(BACKUP_API_SUBDIRS))
     .subdirs(BACKUP_API_SUBDIRS);
 
+const CHUNKER_SIZE_SCHEMA: Schema = IntegerSchema::new("Chunk size bound in bytes.")
+    .minimum(64)
+    .maximum(16 * 1024 * 1024)
+    .schema();
+
 #[sortable]
 pub const API_METHOD_CREATE_DYNAMIC_INDEX: ApiMethod = ApiMethod::new(
     &ApiHandler::Sync(&create_dynamic_index),
     &ObjectSchema::new(
         "Create dynamic chunk index file.",
-        &sorted!([("archive-name", false, &BACKUP_ARCHIVE_NAME_SCHEMA),]),
+        &sorted!([
+            ("archive-name", false, &BACKUP_ARCHIVE_NAME_SCHEMA),
+            ("chunk-size-avg", true, &CHUNKER_SIZE_SCHEMA),
+            ("chunk-size-max", true, &CHUNKER_SIZE_SCHEMA),
+            ("chunk-size-min", true, &CHUNKER_SIZE_SCHEMA),
+            ("chunker", true, &ChunkerType::API_SCHEMA),
+        ]),
     ),
 );
 
@@ -403,14 +543,41 @@ fn create_dynamic_index(
     if !archive_name.ends_with(".didx") {
         bail!("wrong archive extension: '{}'", archive_name);
     }
+    if is_partial_archive(&archive_name) {
+        bail!("reserved archive name: '{}'", archive_name);
+    }
+
+    // the client records the chunker, so later backups can split the data the same way
+    let chunker = if param["chunker"].is_null() {
+        None
+    } else {
+        let ty: ChunkerType = serde_json::from_value(param["chunker"].clone())?;
+        let avg = required_integer_param(&param, "chunk-size-avg")? as usize;
+        let min = param["chunk-size-min"].as_u64().map(|v| v as usize);
+        let max = param["chunk-size-max"].as_u64().map(|v| v as usize);
+        Some(ChunkerConfig::with_bounds(
+            ty,
+            min.unwrap_or(avg >> 2),
+            avg,
+            max.unwrap_or(avg << 2),
+        )?)
+    };
 
     let mut path = env.backup_dir.relative_path();
     path.push(archive_name);
 
-    let index = env.datastore.create_dynamic_writer(&path)?;
+    let mut index = env.datastore.create_dynamic_writer(&path)?;
+    if let Some(chunker) = chunker {
+        index.set_chunker(chunker);
+    }
+    let resumed = env.resume_archive(&name)?;
     let wid = env.register_dynamic_writer(index, name)?;
 
-    env.log(format!("created new dynamic index {} ({:?})", wid, path));
+    if resumed {
+        env.log(format!("resumed dynamic index {} ({:?})", wid, path));
+    } else {
+        env.log(format!("created new dynamic index {} ({:?})", wid, path));
+    }
 
     Ok(json!(wid))
 }
@@ -455,6 +622,9 @@ fn create_fixed_index(
     if !archive_name.ends_with(".fidx") {
         bail!("wrong archive extension: '{}'", archive_name);
     }
+    if is_partial_archive(&archive_name) {
+        bail!("reserved archive name: '{}'", archive_name);
+    }
 
     let mut path = env.backup_dir.relative_path();
     path.push(&archive_name);
@@ -502,9 +672,14 @@ fn create_fixed_index(
         writer.clone_data_from(&reader)?;
     }
 
+    let resumed = env.resume_archive(&name)?;
     let wid = env.register_fixed_writer(writer, name, size, chunk_size as u32, incremental)?;
 
-    env.log(format!("created new fixed index {} ({:?})", wid, path));
+    if resumed {
+        env.log(format!("resumed fixed index {} ({:?})", wid, path));
+    } else {
+        env.log(format!("created new fixed index {} ({:?})", wid, path));
+    }
 
     Ok(json!(wid))
 }
@@ -657,6 +832,137 @@ fn fixed_append(
     Ok(Value::Null)
 }
 
+/// Check the size of an existing chunk against the size of its blob on disk, as registered
+/// chunks are appended with the given size, without loading the chunk.
+///
+/// Uncompressed blobs must match the size exactly. Compressed blobs are only stored if they are
+/// smaller than the uncompressed blob, so they must not be larger than that. The type of chunks
+/// encrypted at rest is unknown, so only the latter bound is checked for them.
+///
+/// Client-side encrypted chunks are never accepted. Their digests depend on the client's key, so
+/// only clients using the same key could reference them, and the client does not query known
+/// chunks for encrypted backups.
+fn chunk_size_matches(magic: Option<[u8; 8]>, blob_size: u64, size: u64) -> bool {
+    let plain_blob_size = size + std::mem::size_of::<DataBlobHeader>() as u64;
+    match magic {
+        Some(UNCOMPRESSED_BLOB_MAGIC_1_0) => blob_size == plain_blob_size,
+        Some(COMPRESSED_BLOB_MAGIC_1_0) | Some(DICT_COMPR_BLOB_MAGIC_1_0) | None => {
+            blob_size <= plain_blob_size
+        }
+        Some(_) => false,
+    }
+}
+
+/// Returns the chunks which exist in the datastore with a plausible size, and registers them.
+fn register_known_chunks(
+    env: &BackupEnvironment,
+    chunks: &[([u8; 32], u64)],
+) -> Result<Vec<[u8; 32]>, Error> {
+    let mut known = Vec::new();
+    for (digest, size) in chunks {
+        if env.lookup_chunk(digest).is_some() {
+            known.push(*digest);
+        } else if env.datastore.touch_existing_chunk(digest)? {
+            let size_matches = match env.datastore.chunk_blob_header(digest) {
+                Ok((magic, blob_size)) => chunk_size_matches(magic, blob_size, *size),
+                Err(_) => false,
+            };
+            if size_matches {
+                env.register_chunk(*digest, *size as u32)?;
+                known.push(*digest);
+            }
+        }
+    }
+    Ok(known)
+}
+
+#[sortable]
+pub const API_METHOD_QUERY_KNOWN_CHUNKS: ApiMethod = ApiMethod::new(
+    &ApiHandler::Async(&query_known_chunks),
+    &ObjectSchema::new(
+        "Check which chunks already exist in the datastore. Existing chunks are registered, so \
+        they can be appended without uploading them. Chunks encrypted by the client are never \
+        reported. Requires read access on the whole datastore.",
+        &sorted!([
+            (
+                "digest-list",
+                false,
+                &ArraySchema::new("Chunk digest list.", &CHUNK_DIGEST_SCHEMA)
+                    .max_length(4096)
+                    .schema()
+            ),
+            (
+                "size-list",
+                false,
+                &ArraySchema::new(
+                    "Chunk size list.",
+                    &IntegerSchema::new("Corresponding chunk sizes.")
+                        .minimum(1)
+                        .maximum(1024 * 1024 * 16)
+                        .schema()
+                )
+                .max_length(4096)
+                .schema()
+            ),
+        ]),
+    ),
+);
+
+fn query_known_chunks<'a>(
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: &'a mut dyn RpcEnvironment,
+) -> ApiFuture<'a> {
+    async move {
+        let digest_list = required_array_param(&param, "digest-list")?;
+        let size_list = required_array_param(&param, "size-list")?;
+
+        if size_list.len() != digest_list.len() {
+            bail!(
+                "size list has wrong length ({} != {})",
+                size_list.len(),
+                digest_list.len()
+            );
+        }
+
+        let env: &BackupEnvironment = rpcenv.as_ref();
+
+        // answering reveals whether data exists anywhere in the datastore, so only allow it for
+        // users who could read all of it anyway
+        let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+        let user_info = CachedUserInfo::new()?;
+        let privs = user_info.lookup_privs(&auth_id, &["datastore", env.datastore.name()]);
+        if privs & PRIV_DATASTORE_READ == 0 {
+            proxmox_router::http_bail!(
+                FORBIDDEN,
+                "querying known chunks requires read access on the datastore"
+            );
+        }
+
+        let mut chunks = Vec::with_capacity(digest_list.len());
+        for (i, item) in digest_list.iter().enumerate() {
+            let digest = <[u8; 32]>::from_hex(item.as_str().unwrap())?;
+            chunks.push((digest, size_list[i].as_u64().unwrap()));
+        }
+
+        let env = env.clone();
+        let known = tokio::task::spawn_blocking(move || {
+            let known = register_known_chunks(&env, &chunks)?;
+            env.debug(format!(
+                "known_chunks: {} of {} chunks exist",
+                known.len(),
+                chunks.len()
+            ));
+            Ok::<_, Error>(known)
+        })
+        .await??;
+
+        let known: Vec<String> = known.iter().map(hex::encode).collect();
+        Ok(json!(known))
+    }
+    .boxed()
+}
+
 #[sortable]
 pub const API_METHOD_CLOSE_DYNAMIC_INDEX: ApiMethod = ApiMethod::new(
     &ApiHandler::Sync(&close_dynamic_index),
@@ -861,7 +1167,282 @@ fn download_previous(
         }
 
         env.log(format!("download '{}' from previous backup.", archive_name));
-        crate::api2::helpers::create_download_response(path).await
+        crate::api2::helpers::create_snapshot_file_download_response(&env.datastore, path).await
     }
     .boxed()
 }
+
+#[sortable]
+pub const API_METHOD_GET_RESUME_INFO: ApiMethod = ApiMethod::new(
+    &ApiHandler::Sync(&get_resume_info),
+    &ObjectSchema::new(
+        "Get the progress of the interrupted backup this session resumes.",
+        &[],
+    ),
+);
+
+fn get_resume_info(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let env: &BackupEnvironment = rpcenv.as_ref();
+
+    let snapshot_path = env.backup_dir.full_path();
+
+    let marker = match ResumeMarker::load(&snapshot_path)? {
+        Some(marker) => marker,
+        None => return Ok(Value::Null),
+    };
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(&snapshot_path)? {
+        let name = entry?.file_name();
+        let name = match name.to_str() {
+            Some(name) if !name.starts_with('.') && !is_partial_archive(name) => name,
+            _ => continue,
+        };
+
+        let mut path = snapshot_path.clone();
+        path.push(name);
+
+        let index: Option<Box<dyn IndexFile>> = match archive_type(name) {
+            Ok(ArchiveType::FixedIndex) => Some(Box::new(env.datastore.open_fixed_reader(&path)?)),
+            Ok(ArchiveType::DynamicIndex) => {
+                Some(Box::new(env.datastore.open_dynamic_reader(&path)?))
+            }
+            Ok(ArchiveType::Blob) => None,
+            Err(_) => continue,
+        };
+
+        match index {
+            Some(index) => {
+                let (csum, size) = index.compute_csum();
+                files.push(json!({
+                    "filename": name,
+                    "csum": hex::encode(csum),
+                    "size": size,
+                }));
+            }
+            None => files.push(json!({ "filename": name })),
+        }
+    }
+
+    Ok(json!({
+        "expires": marker.expires,
+        "files": files,
+        "archives": marker.archives,
+    }))
+}
+
+#[sortable]
+pub const API_METHOD_DOWNLOAD_RESUME_INDEX: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&download_resume_index),
+    &ObjectSchema::new(
+        "Download the partial index of an interrupted archive, or the index of an archive \
+        completed before the interruption.",
+        &sorted!([("archive-name", false, &BACKUP_ARCHIVE_NAME_SCHEMA)]),
+    ),
+);
+
+fn download_resume_index(
+    _parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let env: &BackupEnvironment = rpcenv.as_ref();
+
+        let archive_name = required_string_param(&param, "archive-name")?.to_owned();
+
+        let mut path = env.backup_dir.full_path();
+        path.push(partial_archive_name(&archive_name));
+        if !path.exists() {
+            // completed before the interruption
+            path.set_file_name(&archive_name);
+        }
+
+        {
+            let index: Box<dyn IndexFile> = match archive_type(&archive_name)? {
+                ArchiveType::FixedIndex => Box::new(env.datastore.open_fixed_reader(&path)?),
+                ArchiveType::DynamicIndex => Box::new(env.datastore.open_dynamic_reader(&path)?),
+                _ => bail!("no index for archive '{}'", archive_name),
+            };
+
+            env.log(format!(
+                "register chunks in interrupted index of '{}'.",
+                archive_name
+            ));
+
+            for pos in 0..index.index_count() {
+                let info = index.chunk_info(pos).unwrap();
+                if info.digest == [0u8; 32] {
+                    continue; // not written before the interruption
+                }
+                let size = info.range.end - info.range.start;
+                env.register_chunk(info.digest, size as u32)?;
+            }
+        }
+
+        env.log(format!("download interrupted index of '{}'.", archive_name));
+        crate::api2::helpers::create_snapshot_file_download_response(&env.datastore, path).await
+    }
+    .boxed()
+}
+
+#[sortable]
+pub const API_METHOD_GET_COMPRESSION: ApiMethod = ApiMethod::new(
+    &ApiHandler::Sync(&get_compression),
+    &ObjectSchema::new(
+        "Get the compression level and zstd dictionary configured for the datastore.",
+        &[],
+    ),
+);
+
+fn get_compression(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let env: &BackupEnvironment = rpcenv.as_ref();
+
+    let dictionary = match env.datastore.zstd_dictionary_id() {
+        Some(id) => match env.datastore.zstd_dictionary(id) {
+            Ok(_) => Some(format!("{id:08x}")),
+            Err(err) => {
+                env.log(format!("configured zstd dictionary not usable - {}", err));
+                None
+            }
+        },
+        None => None,
+    };
+
+    Ok(json!({
+        "level": env.datastore.compression_level(),
+        "zstd-dictionary": dictionary,
+    }))
+}
+
+#[sortable]
+pub const API_METHOD_DOWNLOAD_ZSTD_DICTIONARY: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&download_zstd_dictionary),
+    &ObjectSchema::new(
+        "Download a zstd dictionary stored in the datastore.",
+        &sorted!([("id", false, &ZSTD_DICTIONARY_ID_SCHEMA)]),
+    ),
+);
+
+fn download_zstd_dictionary(
+    _parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let env: &BackupEnvironment = rpcenv.as_ref();
+
+        let id = u32::from_str_radix(required_string_param(&param, "id")?, 16)?;
+
+        // make sure it is a valid dictionary
+        env.datastore.zstd_dictionary(id)?;
+
+        let path = env
+            .datastore
+            .zstd_dictionary_dir()
+            .join(ZstdDictionary::file_name(id));
+
+        env.log(format!("download zstd dictionary {:08x}.", id));
+        crate::api2::helpers::create_snapshot_file_download_response(&env.datastore, path).await
+    }
+    .boxed()
+}
+
+#[sortable]
+pub const API_METHOD_UPLOAD_ZSTD_DICTIONARY: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&upload_zstd_dictionary),
+    &ObjectSchema::new(
+        "Upload a zstd dictionary needed for chunks and blobs of this backup.",
+        &sorted!([("id", false, &ZSTD_DICTIONARY_ID_SCHEMA)]),
+    ),
+);
+
+fn upload_zstd_dictionary(
+    _parts: Parts,
+    req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let env: &BackupEnvironment = rpcenv.as_ref();
+
+        let id = u32::from_str_radix(required_string_param(&param, "id")?, 16)?;
+
+        let data = req_body
+            .map_err(Error::from)
+            .try_fold(Vec::new(), |mut acc, chunk| {
+                acc.extend_from_slice(&chunk);
+                if acc.len() > MAX_DICTIONARY_SIZE {
+                    return future::err(format_err!("zstd dictionary too large"));
+                }
+                future::ok::<_, Error>(acc)
+            })
+            .await?;
+
+        let dictionary = ZstdDictionary::new(data);
+        if dictionary.id() != id {
+            bail!("uploaded zstd dictionary does not match ID {:08x}", id);
+        }
+
+        proxmox_async::runtime::block_in_place(|| {
+            env.datastore.insert_zstd_dictionary(&dictionary)
+        })?;
+        register_dictionary(env.datastore.name(), dictionary)?;
+
+        env.log(format!("upload zstd dictionary {:08x}.", id));
+
+        Ok(env.format_response(Ok(Value::Null)))
+    }
+    .boxed()
+}
+
+#[test]
+fn test_chunk_size_matches() -> Result<(), Error> {
+    use pbs_datastore::DataBlob;
+    use pbs_tools::crypt_config::CryptConfig;
+
+    let blob_header = |blob: &DataBlob| {
+        let mut magic = [0u8; 8];
+        magic.copy_from_slice(&blob.raw_data()[..8]);
+        (Some(magic), blob.raw_size())
+    };
+
+    let data = vec![0u8; 4096];
+    let size = data.len() as u64;
+
+    let (magic, blob_size) = blob_header(&DataBlob::encode(&data, None, false)?);
+    assert!(chunk_size_matches(magic, blob_size, size));
+    assert!(!chunk_size_matches(magic, blob_size, size - 1));
+    assert!(!chunk_size_matches(magic, blob_size, size + 1));
+
+    let (magic, blob_size) = blob_header(&DataBlob::encode(&data, None, true)?);
+    assert_eq!(magic, Some(COMPRESSED_BLOB_MAGIC_1_0));
+    assert!(chunk_size_matches(magic, blob_size, size));
+    // too small for the compressed blob
+    assert!(!chunk_size_matches(magic, blob_size, 1));
+
+    // the type of chunks encrypted at rest is unknown, so only the bound is checked
+    assert!(chunk_size_matches(None, blob_size, size));
+    assert!(!chunk_size_matches(None, size + 13, size));
+
+    let crypt_config = CryptConfig::new([9u8; 32])?;
+    let (magic, blob_size) = blob_header(&DataBlob::encode(&data, Some(&crypt_config), false)?);
+    assert!(!chunk_size_matches(magic, blob_size, size));
+    let (magic, blob_size) = blob_header(&DataBlob::encode(&data, Some(&crypt_config), true)?);
+    assert!(!chunk_size_matches(magic, blob_size, size));
+
+    Ok(())
+}
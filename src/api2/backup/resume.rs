@@ -0,0 +1,160 @@
+//! State of interrupted backups kept for resuming
+//!
+//! When a backup connection fails and the datastore has a `backup-resume-timeout` set, the
+//! indexes written so far are stored as `<name>.partial.<ext>` next to the completed archives,
+//! and a hidden marker records how far each of them got. The partial indexes keep their chunks
+//! alive during garbage collection. A client reconnecting with the same backup time continues
+//! from there, everything is removed once the marker expired.
+
+use std::path::Path;
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+
+use pbs_datastore::backup_info::BackupGroup;
+use pbs_datastore::manifest::{archive_type, ArchiveType};
+
+/// Name of the marker file in the snapshot directory.
+pub const RESUME_MARKER_NAME: &str = ".resume.json";
+
+const PARTIAL_EXTENSION: &str = ".partial";
+
+/// Returns the file name the partial index of `archive_name` is kept under.
+pub fn partial_archive_name(archive_name: &str) -> String {
+    match archive_name.rsplit_once('.') {
+        Some((base, ext)) => format!("{base}{PARTIAL_EXTENSION}.{ext}"),
+        None => format!("{archive_name}{PARTIAL_EXTENSION}"),
+    }
+}
+
+/// Returns true if `file_name` is the partial index of an interrupted archive.
+pub fn is_partial_archive(file_name: &str) -> bool {
+    match file_name.rsplit_once('.') {
+        Some((base, _)) => base.ends_with(PARTIAL_EXTENSION),
+        None => false,
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Progress of an index writer at the time the backup got interrupted.
+pub struct ResumeArchive {
+    pub archive_name: String,
+    /// Image size (fixed indexes only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Chunk size (fixed indexes only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u32>,
+    /// Whether the fixed index got cloned from the previous backup
+    #[serde(default)]
+    pub incremental: bool,
+    /// Bytes appended so far (dynamic indexes only)
+    #[serde(default)]
+    pub offset: u64,
+    /// Number of chunks appended so far
+    pub chunk_count: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Marker of an interrupted, resumable backup.
+pub struct ResumeMarker {
+    /// Epoch after which the backup cannot be resumed anymore
+    pub expires: i64,
+    /// Number of files completed before the interruption
+    pub file_count: usize,
+    /// Size of the files completed before the interruption
+    pub backup_size: u64,
+    pub archives: Vec<ResumeArchive>,
+}
+
+impl ResumeMarker {
+    /// Load the marker of the snapshot at `snapshot_path`, if there is one.
+    pub fn load(snapshot_path: &Path) -> Result<Option<Self>, Error> {
+        let path = snapshot_path.join(RESUME_MARKER_NAME);
+        match file_read_optional_string(&path)? {
+            Some(data) => Ok(Some(serde_json::from_str(&data).map_err(|err| {
+                format_err!("unable to parse resume marker {path:?} - {err}")
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the marker in the snapshot at `snapshot_path`.
+    pub fn save(&self, snapshot_path: &Path) -> Result<(), Error> {
+        let data = serde_json::to_string(self)?;
+        replace_file(
+            snapshot_path.join(RESUME_MARKER_NAME),
+            data.as_bytes(),
+            CreateOptions::new(),
+            false,
+        )
+    }
+
+    pub fn expired(&self) -> bool {
+        self.expires < proxmox_time::epoch_i64()
+    }
+}
+
+/// Remove the marker and all partial indexes from the snapshot at `snapshot_path`.
+pub fn remove_resume_state(snapshot_path: &Path) -> Result<(), Error> {
+    for entry in std::fs::read_dir(snapshot_path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        if name == RESUME_MARKER_NAME
+            || (is_partial_archive(name)
+                && matches!(
+                    archive_type(name),
+                    Ok(ArchiveType::FixedIndex | ArchiveType::DynamicIndex)
+                ))
+        {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the interrupted backups of `group` which can no longer be resumed.
+///
+/// Only call this while holding the group lock, so that no backup can be resumed concurrently.
+/// Snapshots still locked by another operation are skipped. Returns the number of removed
+/// snapshots.
+pub fn remove_expired_backups(group: &BackupGroup) -> Result<usize, Error> {
+    let mut removed = 0;
+    for info in group.list_backups()? {
+        if info.is_finished() {
+            continue;
+        }
+        let expired = match ResumeMarker::load(&info.backup_dir.full_path()) {
+            Ok(Some(marker)) => marker.expired(),
+            Ok(None) => false,
+            Err(_) => true,
+        };
+        if expired && info.backup_dir.destroy(false).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[test]
+fn test_partial_archive_name() {
+    assert_eq!(
+        partial_archive_name("drive-scsi0.img.fidx"),
+        "drive-scsi0.img.partial.fidx"
+    );
+    assert_eq!(
+        partial_archive_name("root.pxar.didx"),
+        "root.pxar.partial.didx"
+    );
+    assert!(is_partial_archive("root.pxar.partial.didx"));
+    assert!(!is_partial_archive("root.pxar.didx"));
+    assert!(!is_partial_archive("partial"));
+}
@@ -77,7 +77,12 @@ impl Future for UploadChunk {
                             let mut chunk = DataBlob::from_raw(raw_data)?;
 
                             proxmox_async::runtime::block_in_place(|| {
-                                chunk.verify_unencrypted(this.size as usize, &this.digest)?;
+                                // also makes sure a zstd dictionary it needs is stored
+                                this.store.verify_unencrypted_chunk(
+                                    &chunk,
+                                    this.size as usize,
+                                    &this.digest,
+                                )?;
 
                                 // always comput CRC at server side
                                 chunk.set_crc(chunk.compute_crc());
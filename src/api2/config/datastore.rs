@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 
 use ::serde::{Deserialize, Serialize};
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use hex::FromHex;
 use serde_json::Value;
 
@@ -11,13 +11,15 @@ use proxmox_section_config::SectionConfigData;
 use proxmox_sys::{task_warn, WorkerTaskContext};
 
 use pbs_api_types::{
-    Authid, DataStoreConfig, DataStoreConfigUpdater, DatastoreNotify, DatastoreTuning,
-    DATASTORE_SCHEMA, PRIV_DATASTORE_ALLOCATE, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY,
-    PROXMOX_CONFIG_DIGEST_SCHEMA, UPID_SCHEMA,
+    Authid, DataStoreConfig, DataStoreConfigUpdater, DatastoreBackendConfig, DatastoreBackendType,
+    DatastoreNotify, DatastoreTuning, DATASTORE_SCHEMA, PRIV_DATASTORE_ALLOCATE,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA, UPID_SCHEMA,
 };
 use pbs_config::BackupLockGuard;
 use pbs_datastore::chunk_store::ChunkStore;
+use pbs_datastore::s3::S3Client;
 
+use crate::api2::admin::datastore::do_mount_device;
 use crate::api2::admin::{
     prune::list_prune_jobs, sync::list_sync_jobs, verify::list_verification_jobs,
 };
@@ -65,6 +67,61 @@ pub fn list_datastores(
     Ok(list.into_iter().filter(filter_by_privs).collect())
 }
 
+// Returns the shards added to ``datastore`` compared to ``old_shards``.
+fn check_chunk_shards(
+    datastore: &DataStoreConfig,
+    old_shards: &[String],
+) -> Result<Vec<String>, Error> {
+    let shards = datastore.chunk_shards()?;
+
+    if shards.is_empty() {
+        if !old_shards.is_empty() {
+            param_bail!("chunk-shards", "removing chunk shards is not supported");
+        }
+        return Ok(shards);
+    }
+
+    let backend_config = DatastoreBackendConfig::parse(datastore.backend.as_deref())?;
+    if let Some(DatastoreBackendType::S3) = backend_config.ty {
+        param_bail!(
+            "chunk-shards",
+            "datastores with S3 backend cannot be sharded"
+        );
+    }
+
+    if let Some(old) = old_shards.iter().find(|old| !shards.contains(old)) {
+        param_bail!(
+            "chunk-shards",
+            "removing chunk shard '{old}' is not supported"
+        );
+    }
+
+    let mut new_shards = Vec::new();
+    for (i, shard) in shards.iter().enumerate() {
+        let path = PathBuf::from(shard);
+        if !path.is_absolute() {
+            param_bail!("chunk-shards", "expected absolute path - got {path:?}");
+        }
+        if path.starts_with(&datastore.path) || PathBuf::from(&datastore.path).starts_with(&path) {
+            param_bail!(
+                "chunk-shards",
+                "shard {path:?} overlaps with the datastore path"
+            );
+        }
+        if shards[..i].contains(shard) {
+            param_bail!("chunk-shards", "duplicate shard {path:?}");
+        }
+        if !old_shards.contains(shard) {
+            if path.join(".chunks").exists() {
+                param_bail!("chunk-shards", "shard {path:?} already contains chunks");
+            }
+            new_shards.push(shard.clone());
+        }
+    }
+
+    Ok(new_shards)
+}
+
 pub(crate) fn do_create_datastore(
     _lock: BackupLockGuard,
     mut config: SectionConfigData,
@@ -78,14 +135,43 @@ pub(crate) fn do_create_datastore(
             .parse_property_string(datastore.tuning.as_deref().unwrap_or(""))?,
     )?;
     let backup_user = pbs_config::backup_user()?;
-    let _store = ChunkStore::create(
-        &datastore.name,
-        path,
-        backup_user.uid,
-        backup_user.gid,
-        worker,
-        tuning.sync_level.unwrap_or_default(),
-    )?;
+    let shards = check_chunk_shards(&datastore, &[])?;
+    let backend_config = DatastoreBackendConfig::parse(datastore.backend.as_deref())?;
+    if let Some(DatastoreBackendType::S3) = backend_config.ty {
+        // unwrap: checked when parsing the backend config
+        let client = S3Client::from_client_config(
+            backend_config.client.as_deref().unwrap(),
+            backend_config.bucket.as_deref().unwrap(),
+        )?;
+        proxmox_async::runtime::block_on(client.head_bucket())
+            .map_err(|err| format_err!("accessing bucket '{}' failed - {err}", client.bucket()))?;
+    }
+
+    if datastore.is_removable() {
+        do_mount_device(&datastore)?;
+    }
+
+    // a removable device might already contain the datastore, e.g. when re-adding it
+    if !(datastore.is_removable() && path.join(".chunks").exists()) {
+        let _store = ChunkStore::create(
+            &datastore.name,
+            path,
+            backup_user.uid,
+            backup_user.gid,
+            worker,
+            tuning.sync_level.unwrap_or_default(),
+        )?;
+    }
+
+    for shard in shards {
+        ChunkStore::create_shard(
+            &datastore.name,
+            shard,
+            backup_user.uid,
+            backup_user.gid,
+            worker,
+        )?;
+    }
 
     config.set_data(&datastore.name, "datastore", &datastore)?;
 
@@ -93,6 +179,7 @@ pub(crate) fn do_create_datastore(
 
     jobstate::create_state_file("prune", &datastore.name)?;
     jobstate::create_state_file("garbage_collection", &datastore.name)?;
+    jobstate::create_state_file("chunk-usage", &datastore.name)?;
 
     Ok(())
 }
@@ -124,6 +211,15 @@ pub fn create_datastore(
         param_bail!("name", "datastore '{}' already exists.", config.name);
     }
 
+    if config.encryption.is_some() {
+        param_bail!(
+            "encryption",
+            "set up at-rest encryption with 'proxmox-backup-manager datastore encryption-init'"
+        );
+    }
+
+    crate::server::check_keep_options(&config.keep)?;
+
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
@@ -171,8 +267,12 @@ pub enum DeletableProperty {
     Comment,
     /// Delete the garbage collection schedule.
     GcSchedule,
+    /// Delete the garbage collection runtime limit.
+    GcMaxRuntime,
     /// Delete the prune job schedule.
     PruneSchedule,
+    /// Delete the chunk usage calculation schedule.
+    ChunkUsageSchedule,
     /// Delete the keep-last property
     KeepLast,
     /// Delete the keep-hourly property
@@ -225,15 +325,23 @@ pub enum DeletableProperty {
     access: {
         permission: &Permission::Privilege(&["datastore", "{name}"], PRIV_DATASTORE_MODIFY, false),
     },
+    returns: {
+        schema: UPID_SCHEMA,
+        optional: true,
+    },
 )]
 /// Update datastore config.
+///
+/// Adding chunk shards creates their chunk directories in a worker task, whose UPID is returned.
+/// The config is saved once the shards are ready.
 pub fn update_datastore(
     update: DataStoreConfigUpdater,
     name: String,
     delete: Option<Vec<DeletableProperty>>,
     digest: Option<String>,
-) -> Result<(), Error> {
-    let _lock = pbs_config::datastore::lock_config()?;
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<String>, Error> {
+    let lock = pbs_config::datastore::lock_config()?;
 
     // pass/compare digest
     let (mut config, expected_digest) = pbs_config::datastore::config()?;
@@ -254,9 +362,15 @@ pub fn update_datastore(
                 DeletableProperty::GcSchedule => {
                     data.gc_schedule = None;
                 }
+                DeletableProperty::GcMaxRuntime => {
+                    data.gc_max_runtime = None;
+                }
                 DeletableProperty::PruneSchedule => {
                     data.prune_schedule = None;
                 }
+                DeletableProperty::ChunkUsageSchedule => {
+                    data.chunk_usage_schedule = None;
+                }
                 DeletableProperty::KeepLast => {
                     data.keep.keep_last = None;
                 }
@@ -309,6 +423,16 @@ pub fn update_datastore(
         data.gc_schedule = update.gc_schedule;
     }
 
+    if update.gc_max_runtime.is_some() {
+        data.gc_max_runtime = update.gc_max_runtime;
+    }
+
+    let mut chunk_usage_schedule_changed = false;
+    if update.chunk_usage_schedule.is_some() {
+        chunk_usage_schedule_changed = data.chunk_usage_schedule != update.chunk_usage_schedule;
+        data.chunk_usage_schedule = update.chunk_usage_schedule;
+    }
+
     macro_rules! prune_disabled {
         ($(($param:literal, $($member:tt)+)),+) => {
             $(
@@ -328,6 +452,11 @@ pub fn update_datastore(
         ("keep-weekly", keep.keep_weekly),
         ("keep-monthly", keep.keep_monthly),
         ("keep-yearly", keep.keep_yearly),
+        ("keep-within", keep.keep_within),
+        ("old-age", keep.old_age),
+        ("max-old", keep.max_old),
+        ("keep-last-verified", keep.keep_last_verified),
+        ("skip-failed-verify", keep.skip_failed_verify),
         ("prune-schedule", prune_schedule)
     }
 
@@ -362,17 +491,61 @@ pub fn update_datastore(
         data.maintenance_mode = update.maintenance_mode;
     }
 
-    config.set_data(&name, "datastore", &data)?;
+    let mut new_shards = Vec::new();
+    if update.chunk_shards.is_some() {
+        let old_shards = data.chunk_shards()?;
+        data.chunk_shards = update.chunk_shards;
+        new_shards = check_chunk_shards(&data, &old_shards)?;
+    }
 
-    pbs_config::datastore::save_config(&config)?;
+    let store = name.clone();
+    let save = move |_lock: BackupLockGuard| -> Result<(), Error> {
+        config.set_data(&store, "datastore", &data)?;
+
+        pbs_config::datastore::save_config(&config)?;
+
+        // we want to reset the statefiles, to avoid an immediate action in some cases
+        // (e.g. going from monthly to weekly in the second week of the month)
+        if gc_schedule_changed {
+            jobstate::update_job_last_run_time("garbage_collection", &store)?;
+        }
+        if chunk_usage_schedule_changed {
+            jobstate::update_job_last_run_time("chunk-usage", &store)?;
+        }
 
-    // we want to reset the statefiles, to avoid an immediate action in some cases
-    // (e.g. going from monthly to weekly in the second week of the month)
-    if gc_schedule_changed {
-        jobstate::update_job_last_run_time("garbage_collection", &name)?;
+        Ok(())
+    };
+
+    if new_shards.is_empty() {
+        save(lock)?;
+        return Ok(None);
     }
 
-    Ok(())
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    // creating the 65536 chunk subdirectories per shard takes a while
+    let upid = WorkerTask::new_thread(
+        "create-chunk-shards",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let backup_user = pbs_config::backup_user()?;
+            for shard in new_shards {
+                ChunkStore::create_shard(
+                    &name,
+                    shard,
+                    backup_user.uid,
+                    backup_user.gid,
+                    Some(&worker),
+                )?;
+            }
+            save(lock)
+        },
+    )?;
+
+    Ok(Some(upid))
 }
 
 #[api(
@@ -466,6 +639,7 @@ pub async fn delete_datastore(
             // ignore errors
             let _ = jobstate::remove_state_file("prune", &name);
             let _ = jobstate::remove_state_file("garbage_collection", &name);
+            let _ = jobstate::remove_state_file("chunk-usage", &name);
 
             if let Err(err) =
                 proxmox_async::runtime::block_on(crate::server::notify_datastore_removed())
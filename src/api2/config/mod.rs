@@ -12,7 +12,9 @@ pub mod drive;
 pub mod media_pool;
 pub mod metrics;
 pub mod prune;
+pub mod quota;
 pub mod remote;
+pub mod s3;
 pub mod sync;
 pub mod tape_backup_job;
 pub mod tape_encryption_keys;
@@ -29,7 +31,9 @@ const SUBDIRS: SubdirMap = &sorted!([
     ("media-pool", &media_pool::ROUTER),
     ("metrics", &metrics::ROUTER),
     ("prune", &prune::ROUTER),
+    ("quota", &quota::ROUTER),
     ("remote", &remote::ROUTER),
+    ("s3", &s3::ROUTER),
     ("sync", &sync::ROUTER),
     ("tape-backup-job", &tape_backup_job::ROUTER),
     ("tape-encryption-keys", &tape_encryption_keys::ROUTER),
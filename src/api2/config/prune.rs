@@ -89,6 +89,8 @@ pub fn create_prune_job(
         param_bail!("id", "job '{}' already exists.", config.id);
     }
 
+    crate::server::check_keep_options(&config.options.keep)?;
+
     section_config.set_data(&config.id, "prune", &config)?;
 
     prune::save_config(&section_config)?;
@@ -157,6 +159,16 @@ pub enum DeletableProperty {
     KeepMonthly,
     /// Delete number of yearly backups to keep.
     KeepYearly,
+    /// Delete the time span to keep all backups in.
+    KeepWithin,
+    /// Delete the age from which backups are limited by max-old.
+    OldAge,
+    /// Delete the maximum number of old backups to keep.
+    MaxOld,
+    /// Delete the keep-last-verified flag.
+    KeepLastVerified,
+    /// Delete the skip-failed-verify flag.
+    SkipFailedVerify,
 }
 
 #[api(
@@ -248,6 +260,21 @@ pub fn update_prune_job(
                 DeletableProperty::KeepYearly => {
                     data.options.keep.keep_yearly = None;
                 }
+                DeletableProperty::KeepWithin => {
+                    data.options.keep.keep_within = None;
+                }
+                DeletableProperty::OldAge => {
+                    data.options.keep.old_age = None;
+                }
+                DeletableProperty::MaxOld => {
+                    data.options.keep.max_old = None;
+                }
+                DeletableProperty::KeepLastVerified => {
+                    data.options.keep.keep_last_verified = None;
+                }
+                DeletableProperty::SkipFailedVerify => {
+                    data.options.keep.skip_failed_verify = None;
+                }
             }
         }
     }
@@ -304,6 +331,23 @@ pub fn update_prune_job(
     if let Some(value) = update.options.keep.keep_yearly {
         data.options.keep.keep_yearly = Some(value);
     }
+    if let Some(value) = update.options.keep.keep_within {
+        data.options.keep.keep_within = Some(value);
+    }
+    if let Some(value) = update.options.keep.old_age {
+        data.options.keep.old_age = Some(value);
+    }
+    if let Some(value) = update.options.keep.max_old {
+        data.options.keep.max_old = Some(value);
+    }
+    if let Some(value) = update.options.keep.keep_last_verified {
+        data.options.keep.keep_last_verified = Some(value);
+    }
+    if let Some(value) = update.options.keep.skip_failed_verify {
+        data.options.keep.skip_failed_verify = Some(value);
+    }
+
+    crate::server::check_keep_options(&data.options.keep)?;
 
     config.set_data(&id, "prune", &data)?;
 
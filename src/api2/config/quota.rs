@@ -0,0 +1,325 @@
+use anyhow::Error;
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, QuotaConfig, QuotaConfigUpdater, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA, QUOTA_ID_SCHEMA,
+};
+use pbs_config::quota;
+
+use pbs_config::CachedUserInfo;
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "List configured quotas.",
+        type: Array,
+        items: { type: QuotaConfig },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Audit on the quota's namespace.",
+    },
+)]
+/// List all quotas.
+pub fn list_quotas(
+    _param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<QuotaConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, digest) = quota::config()?;
+
+    let list = config.convert_to_typed_array("quota")?;
+
+    let list = list
+        .into_iter()
+        .filter(|quota: &QuotaConfig| {
+            let privs = user_info.lookup_privs(&auth_id, &quota.acl_path());
+            privs & PRIV_DATASTORE_AUDIT != 0
+        })
+        .collect();
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: QuotaConfig,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the quota's datastore.",
+    },
+)]
+/// Create a new quota.
+pub fn create_quota(config: QuotaConfig, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    // namespace level privileges are not enough, a tenant must not raise its own quota
+    user_info.check_privs(
+        &auth_id,
+        &["datastore", &config.store],
+        PRIV_DATASTORE_MODIFY,
+        true,
+    )?;
+
+    let _lock = quota::lock_config()?;
+
+    let (mut section_config, _digest) = quota::config()?;
+
+    if section_config.sections.get(&config.id).is_some() {
+        param_bail!("id", "quota '{}' already exists.", config.id);
+    }
+
+    section_config.set_data(&config.id, "quota", &config)?;
+
+    quota::save_config(&section_config)?;
+
+    Ok(())
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: QUOTA_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: QuotaConfig },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Audit on the quota's namespace.",
+    },
+)]
+/// Read a quota configuration.
+pub fn read_quota(id: String, rpcenv: &mut dyn RpcEnvironment) -> Result<QuotaConfig, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, digest) = quota::config()?;
+
+    let quota: QuotaConfig = config.lookup("quota", &id)?;
+
+    user_info.check_privs(&auth_id, &quota.acl_path(), PRIV_DATASTORE_AUDIT, true)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(quota)
+}
+
+#[api]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment.
+    Comment,
+    /// Reset the namespace to the root namespace.
+    Ns,
+    /// Apply the quota to the whole namespace.
+    Group,
+    /// Remove the logical size limit.
+    MaxLogicalSize,
+    /// Remove the unique size limit.
+    MaxUniqueSize,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: QUOTA_ID_SCHEMA,
+            },
+            update: {
+                type: QuotaConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the quota's datastore.",
+    },
+)]
+/// Update a quota configuration.
+pub fn update_quota(
+    id: String,
+    update: QuotaConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let _lock = quota::lock_config()?;
+
+    // pass/compare digest
+    let (mut config, expected_digest) = quota::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: QuotaConfig = config.lookup("quota", &id)?;
+
+    user_info.check_privs(
+        &auth_id,
+        &["datastore", &data.store],
+        PRIV_DATASTORE_MODIFY,
+        true,
+    )?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Ns => {
+                    data.ns = None;
+                }
+                DeletableProperty::Group => {
+                    data.group = None;
+                }
+                DeletableProperty::MaxLogicalSize => {
+                    data.max_logical_size = None;
+                }
+                DeletableProperty::MaxUniqueSize => {
+                    data.max_unique_size = None;
+                }
+            }
+        }
+    }
+
+    if let Some(store) = update.store {
+        // check new store
+        user_info.check_privs(
+            &auth_id,
+            &["datastore", &store],
+            PRIV_DATASTORE_MODIFY,
+            true,
+        )?;
+        data.store = store;
+    }
+
+    if let Some(ns) = update.ns {
+        data.ns = if ns.is_root() { None } else { Some(ns) };
+    }
+
+    if update.group.is_some() {
+        data.group = update.group;
+    }
+    if update.max_logical_size.is_some() {
+        data.max_logical_size = update.max_logical_size;
+    }
+    if update.max_unique_size.is_some() {
+        data.max_unique_size = update.max_unique_size;
+    }
+    if let Some(value) = update.comment {
+        data.comment = Some(value);
+    }
+
+    config.set_data(&id, "quota", &data)?;
+
+    quota::save_config(&config)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: QUOTA_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify on the quota's datastore.",
+    },
+)]
+/// Remove a quota configuration
+pub fn delete_quota(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let _lock = quota::lock_config()?;
+
+    let (mut config, expected_digest) = quota::config()?;
+
+    let quota: QuotaConfig = config.lookup("quota", &id)?;
+
+    user_info.check_privs(
+        &auth_id,
+        &["datastore", &quota.store],
+        PRIV_DATASTORE_MODIFY,
+        true,
+    )?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    if config.sections.remove(&id).is_none() {
+        http_bail!(NOT_FOUND, "quota '{}' does not exist.", id);
+    }
+
+    quota::save_config(&config)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_QUOTA)
+    .put(&API_METHOD_UPDATE_QUOTA)
+    .delete(&API_METHOD_DELETE_QUOTA);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_QUOTAS)
+    .post(&API_METHOD_CREATE_QUOTA)
+    .match_all("id", &ITEM_ROUTER);
@@ -7,9 +7,10 @@ use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
 use proxmox_schema::{api, param_bail};
 
 use pbs_api_types::{
-    Authid, SyncJobConfig, SyncJobConfigUpdater, JOB_ID_SCHEMA, PRIV_DATASTORE_AUDIT,
-    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE, PRIV_REMOTE_AUDIT,
-    PRIV_REMOTE_READ, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    Authid, SyncDirection, SyncJobConfig, SyncJobConfigUpdater, JOB_ID_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
+    PRIV_DATASTORE_READ, PRIV_REMOTE_AUDIT, PRIV_REMOTE_DATASTORE_BACKUP,
+    PRIV_REMOTE_DATASTORE_PRUNE, PRIV_REMOTE_READ, PROXMOX_CONFIG_DIGEST_SCHEMA,
 };
 use pbs_config::sync;
 
@@ -29,37 +30,81 @@ pub fn check_sync_job_read_access(
     remote_privs & PRIV_REMOTE_AUDIT != 0
 }
 
-/// checks whether user can run the corresponding pull job
+/// checks whether user can run the corresponding sync job
 ///
-/// namespace creation/deletion ACL and backup group ownership checks happen in the pull code directly.
-/// remote side checks/filters remote datastore/namespace/group access.
+/// namespace creation/deletion ACL and backup group ownership checks happen in the pull/push code
+/// directly. remote side checks/filters remote datastore/namespace/group access.
 pub fn check_sync_job_modify_access(
     user_info: &CachedUserInfo,
     auth_id: &Authid,
     job: &SyncJobConfig,
 ) -> bool {
+    match job.sync_direction() {
+        SyncDirection::Pull => check_pull_job_modify_access(user_info, auth_id, job),
+        SyncDirection::Push => check_push_job_modify_access(user_info, auth_id, job),
+    }
+}
+
+fn check_sync_job_owner(auth_id: &Authid, job: &SyncJobConfig) -> bool {
+    match job.owner {
+        Some(ref owner) => {
+            owner == auth_id
+                || (owner.is_token() && !auth_id.is_token() && owner.user() == auth_id.user())
+        }
+        // default sync owner
+        None => auth_id == Authid::root_auth_id(),
+    }
+}
+
+fn check_push_job_modify_access(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    job: &SyncJobConfig,
+) -> bool {
+    // Datastore.Backup limits the push to groups owned by the job owner, checked while pushing
     let ns_anchor_privs = user_info.lookup_privs(auth_id, &job.acl_path());
-    if ns_anchor_privs & PRIV_DATASTORE_BACKUP == 0 {
+    if ns_anchor_privs & (PRIV_DATASTORE_READ | PRIV_DATASTORE_BACKUP) == 0 {
+        return false;
+    }
+
+    // the owner's privileges are used for reading, so pushing as somebody else requires
+    // the same permission as changing ownership
+    if !check_sync_job_owner(auth_id, job) && ns_anchor_privs & PRIV_DATASTORE_MODIFY == 0 {
+        return false;
+    }
+
+    let remote_privs = user_info.lookup_privs(auth_id, &["remote", &job.remote, &job.remote_store]);
+    if remote_privs & PRIV_REMOTE_DATASTORE_BACKUP == 0 {
         return false;
     }
 
     if let Some(true) = job.remove_vanished {
-        if ns_anchor_privs & PRIV_DATASTORE_PRUNE == 0 {
+        if remote_privs & PRIV_REMOTE_DATASTORE_PRUNE == 0 {
             return false;
         }
     }
 
-    let correct_owner = match job.owner {
-        Some(ref owner) => {
-            owner == auth_id
-                || (owner.is_token() && !auth_id.is_token() && owner.user() == auth_id.user())
+    true
+}
+
+fn check_pull_job_modify_access(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    job: &SyncJobConfig,
+) -> bool {
+    let ns_anchor_privs = user_info.lookup_privs(auth_id, &job.acl_path());
+    if ns_anchor_privs & PRIV_DATASTORE_BACKUP == 0 {
+        return false;
+    }
+
+    if let Some(true) = job.remove_vanished {
+        if ns_anchor_privs & PRIV_DATASTORE_PRUNE == 0 {
+            return false;
         }
-        // default sync owner
-        None => auth_id == Authid::root_auth_id(),
-    };
+    }
 
     // same permission as changing ownership after syncing
-    if !correct_owner && ns_anchor_privs & PRIV_DATASTORE_MODIFY == 0 {
+    if !check_sync_job_owner(auth_id, job) && ns_anchor_privs & PRIV_DATASTORE_MODIFY == 0 {
         return false;
     }
 
@@ -113,7 +158,7 @@ pub fn list_sync_jobs(
         },
     },
     access: {
-        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves requires Datastore.Modify",
+        description: "For pull jobs, the user needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune. For push jobs, the user needs Datastore.Read or Datastore.Backup on source datastore, and Remote.DatastoreBackup on target remote. Additionally, remove_vanished requires Remote.DatastorePrune. Any owner other than the user themselves requires Datastore.Modify",
         permission: &Permission::Anybody,
     },
 )]
@@ -215,6 +260,10 @@ pub enum DeletableProperty {
     RemoteNs,
     /// Delete the max_depth property,
     MaxDepth,
+    /// Delete the sync_direction property,
+    SyncDirection,
+    /// Delete the run_on_mount property,
+    RunOnMount,
 }
 
 #[api(
@@ -244,7 +293,7 @@ pub enum DeletableProperty {
     },
     access: {
         permission: &Permission::Anybody,
-        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves requires Datastore.Modify",
+        description: "For pull jobs, the user needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune. For push jobs, the user needs Datastore.Read or Datastore.Backup on source datastore, and Remote.DatastoreBackup on target remote. Additionally, remove_vanished requires Remote.DatastorePrune. Any owner other than the user themselves requires Datastore.Modify",
     },
 )]
 /// Update sync job config.
@@ -309,6 +358,12 @@ pub fn update_sync_job(
                 DeletableProperty::MaxDepth => {
                     data.max_depth = None;
                 }
+                DeletableProperty::SyncDirection => {
+                    data.sync_direction = None;
+                }
+                DeletableProperty::RunOnMount => {
+                    data.run_on_mount = None;
+                }
             }
         }
     }
@@ -370,6 +425,12 @@ pub fn update_sync_job(
     if let Some(max_depth) = update.max_depth {
         data.max_depth = Some(max_depth);
     }
+    if let Some(sync_direction) = update.sync_direction {
+        data.sync_direction = Some(sync_direction);
+    }
+    if update.run_on_mount.is_some() {
+        data.run_on_mount = update.run_on_mount;
+    }
 
     if let Some(max_depth) = data.max_depth {
         if let Some(ref ns) = data.ns {
@@ -410,7 +471,7 @@ pub fn update_sync_job(
     },
     access: {
         permission: &Permission::Anybody,
-        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves requires Datastore.Modify",
+        description: "For pull jobs, the user needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune. For push jobs, the user needs Datastore.Read or Datastore.Backup on source datastore, and Remote.DatastoreBackup on target remote. Additionally, remove_vanished requires Remote.DatastorePrune. Any owner other than the user themselves requires Datastore.Modify",
     },
 )]
 /// Remove a sync job configuration
@@ -481,6 +542,8 @@ acl:1:/datastore/localstore2:write@pbs:DatastorePowerUser
 acl:1:/datastore/localstore3:write@pbs:DatastoreAdmin
 acl:1:/remote/remote1:read@pbs,write@pbs:RemoteAudit
 acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
+acl:1:/remote/remote1/remotestore2:write@pbs:RemoteSyncPushOperator
+acl:1:/remote/remote1/remotestore3:write@pbs:RemoteDatastoreBackup
 "###,
     )
     .expect("test acl.cfg is not parsable");
@@ -507,6 +570,8 @@ acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
         group_filter: None,
         schedule: None,
         limit: pbs_api_types::RateLimitConfig::default(), // no limit
+        sync_direction: None,
+        run_on_mount: None,
     };
 
     // should work without ACLs
@@ -636,5 +701,54 @@ acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
         &job
     ));
 
+    // pushing requires Remote.DatastoreBackup on the remote end
+    job.sync_direction = Some(SyncDirection::Push);
+    job.remove_vanished = None;
+    job.owner = Some(write_auth_id.clone());
+    job.store = "localstore1".to_string();
+    assert!(!check_sync_job_modify_access(
+        &user_info,
+        &write_auth_id,
+        &job
+    ));
+    job.remote_store = "remotestore2".to_string();
+    assert!(check_sync_job_modify_access(
+        &user_info,
+        &write_auth_id,
+        &job
+    ));
+
+    // Remote.DatastoreBackup alone is enough for pushing
+    job.remote_store = "remotestore3".to_string();
+    assert!(check_sync_job_modify_access(
+        &user_info,
+        &write_auth_id,
+        &job
+    ));
+
+    // but not for removing vanished snapshots on the remote
+    job.remove_vanished = Some(true);
+    assert!(!check_sync_job_modify_access(
+        &user_info,
+        &write_auth_id,
+        &job
+    ));
+
+    // which requires Remote.DatastorePrune
+    job.remote_store = "remotestore2".to_string();
+    assert!(check_sync_job_modify_access(
+        &user_info,
+        &write_auth_id,
+        &job
+    ));
+
+    // audit permission on the local end is not enough for pushing
+    job.owner = Some(read_auth_id.clone());
+    assert!(!check_sync_job_modify_access(
+        &user_info,
+        &read_auth_id,
+        &job
+    ));
+
     Ok(())
 }
@@ -8,7 +8,8 @@ use proxmox_schema::{api, param_bail};
 
 use pbs_api_types::{
     Authid, VerificationJobConfig, VerificationJobConfigUpdater, JOB_ID_SCHEMA,
-    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_VERIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_VERIFY, PRIV_REMOTE_READ, PRIV_TAPE_READ,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
 };
 use pbs_config::verify;
 
@@ -68,7 +69,7 @@ pub fn list_verification_jobs(
     },
     access: {
         permission: &Permission::Anybody,
-        description: "Requires Datastore.Verify on job's datastore.",
+        description: "Requires Datastore.Verify on job's datastore, and Remote.Read on the repair remote datastore or Tape.Read on the repair drive.",
     },
 )]
 /// Create a new verification job.
@@ -80,6 +81,7 @@ pub fn create_verification_job(
     let user_info = CachedUserInfo::new()?;
 
     user_info.check_privs(&auth_id, &config.acl_path(), PRIV_DATASTORE_VERIFY, false)?;
+    check_repair_source(&config, &auth_id, &user_info)?;
 
     let _lock = verify::lock_config()?;
 
@@ -98,6 +100,33 @@ pub fn create_verification_job(
     Ok(())
 }
 
+fn check_repair_source(
+    config: &VerificationJobConfig,
+    auth_id: &Authid,
+    user_info: &CachedUserInfo,
+) -> Result<(), Error> {
+    if let Some(drive) = &config.repair_drive {
+        if config.repair_remote.is_some() {
+            param_bail!(
+                "repair-drive",
+                "'repair-drive' and 'repair-remote' cannot be used together"
+            );
+        }
+        user_info.check_privs(auth_id, &["tape", "drive", drive], PRIV_TAPE_READ, false)?;
+    }
+
+    match (&config.repair_remote, &config.repair_store) {
+        (Some(remote), Some(store)) => {
+            user_info.check_privs(auth_id, &["remote", remote, store], PRIV_REMOTE_READ, false)
+        }
+        (None, None) => Ok(()),
+        _ => param_bail!(
+            "repair-store",
+            "'repair-remote' and 'repair-store' must be set together"
+        ),
+    }
+}
+
 #[api(
    input: {
         properties: {
@@ -149,6 +178,26 @@ pub enum DeletableProperty {
     Ns,
     /// Delete max-depth property, defaulting to full recursion again
     MaxDepth,
+    /// Delete the number of reader threads.
+    ReadThreads,
+    /// Delete the number of decoder threads.
+    DecodeThreads,
+    /// Delete the I/O priority.
+    IoPriority,
+    /// Delete the read rate limit.
+    ReadRate,
+    /// Delete the repair remote.
+    RepairRemote,
+    /// Delete the datastore on the repair remote.
+    RepairStore,
+    /// Delete the namespace on the repair remote.
+    RepairNs,
+    /// Delete the repair tape drive.
+    RepairDrive,
+    /// Delete the sample percentage.
+    SamplePercent,
+    /// Delete the sample budget.
+    SampleBudget,
 }
 
 #[api(
@@ -178,7 +227,7 @@ pub enum DeletableProperty {
     },
     access: {
         permission: &Permission::Anybody,
-        description: "Requires Datastore.Verify on job's datastore.",
+        description: "Requires Datastore.Verify on job's datastore, and Remote.Read on the repair remote datastore or Tape.Read on the repair drive.",
     },
 )]
 /// Update verification job config.
@@ -229,6 +278,36 @@ pub fn update_verification_job(
                 DeletableProperty::MaxDepth => {
                     data.max_depth = None;
                 }
+                DeletableProperty::ReadThreads => {
+                    data.read_threads = None;
+                }
+                DeletableProperty::DecodeThreads => {
+                    data.decode_threads = None;
+                }
+                DeletableProperty::IoPriority => {
+                    data.io_priority = None;
+                }
+                DeletableProperty::ReadRate => {
+                    data.read_rate = None;
+                }
+                DeletableProperty::RepairRemote => {
+                    data.repair_remote = None;
+                }
+                DeletableProperty::RepairStore => {
+                    data.repair_store = None;
+                }
+                DeletableProperty::RepairNs => {
+                    data.repair_ns = None;
+                }
+                DeletableProperty::RepairDrive => {
+                    data.repair_drive = None;
+                }
+                DeletableProperty::SamplePercent => {
+                    data.sample_percent = None;
+                }
+                DeletableProperty::SampleBudget => {
+                    data.sample_budget = None;
+                }
             }
         }
     }
@@ -266,9 +345,44 @@ pub fn update_verification_job(
             data.max_depth = Some(max_depth);
         }
     }
+    if update.read_threads.is_some() {
+        data.read_threads = update.read_threads;
+    }
+    if update.decode_threads.is_some() {
+        data.decode_threads = update.decode_threads;
+    }
+    if update.io_priority.is_some() {
+        data.io_priority = update.io_priority;
+    }
+    if update.read_rate.is_some() {
+        data.read_rate = update.read_rate;
+    }
+    if update.repair_remote.is_some() {
+        data.repair_remote = update.repair_remote;
+    }
+    if update.repair_store.is_some() {
+        data.repair_store = update.repair_store;
+    }
+    if let Some(repair_ns) = update.repair_ns {
+        if repair_ns.is_root() {
+            data.repair_ns = None;
+        } else {
+            data.repair_ns = Some(repair_ns);
+        }
+    }
+    if update.repair_drive.is_some() {
+        data.repair_drive = update.repair_drive;
+    }
+    if update.sample_percent.is_some() {
+        data.sample_percent = update.sample_percent;
+    }
+    if update.sample_budget.is_some() {
+        data.sample_budget = update.sample_budget;
+    }
 
     // check new store and NS
     user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_VERIFY, true)?;
+    check_repair_source(&data, &auth_id, &user_info)?;
 
     config.set_data(&id, "verification", &data)?;
 
@@ -330,6 +444,7 @@ pub fn delete_verification_job(
     verify::save_config(&config)?;
 
     crate::server::jobstate::remove_state_file("verificationjob", &id)?;
+    crate::server::remove_sample_state(&id)?;
 
     Ok(())
 }
@@ -343,3 +458,89 @@ pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_VERIFICATION_JOBS)
     .post(&API_METHOD_CREATE_VERIFICATION_JOB)
     .match_all("id", &ITEM_ROUTER);
+
+#[test]
+fn verify_job_repair_source_access_test() -> Result<(), Error> {
+    let (user_cfg, _) = pbs_config::user::test_cfg_from_str(
+        r###"
+user: noperm@pbs
+
+user: repair@pbs
+
+"###,
+    )
+    .expect("test user.cfg is not parsable");
+    let acl_tree = pbs_config::acl::AclTree::from_raw(
+        r###"
+acl:1:/remote/remote1:repair@pbs:RemoteAudit
+acl:1:/remote/remote1/remotestore1:repair@pbs:RemoteSyncOperator
+acl:1:/tape/drive/drive1:repair@pbs:TapeReader
+"###,
+    )
+    .expect("test acl.cfg is not parsable");
+
+    let user_info = CachedUserInfo::test_new(user_cfg, acl_tree);
+
+    let no_perm_auth_id: Authid = "noperm@pbs".parse()?;
+    let repair_auth_id: Authid = "repair@pbs".parse()?;
+
+    let job = |repair: Value| -> VerificationJobConfig {
+        let mut config = serde_json::json!({ "id": "job1", "store": "localstore1" });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(repair.as_object().unwrap().clone());
+        serde_json::from_value(config).unwrap()
+    };
+    let check = |auth_id: &Authid, repair: Value| {
+        check_repair_source(&job(repair), auth_id, &user_info).is_ok()
+    };
+
+    // no repair source needs no privileges
+    assert!(check(&no_perm_auth_id, serde_json::json!({})));
+
+    let remote = serde_json::json!({
+        "repair-remote": "remote1",
+        "repair-store": "remotestore1",
+    });
+    assert!(check(&repair_auth_id, remote.clone()));
+    assert!(!check(&no_perm_auth_id, remote));
+
+    // Remote.Audit alone is not enough
+    let other_store = serde_json::json!({
+        "repair-remote": "remote1",
+        "repair-store": "remotestore2",
+    });
+    assert!(!check(&repair_auth_id, other_store));
+
+    // remote and store must be set together
+    assert!(!check(
+        &repair_auth_id,
+        serde_json::json!({ "repair-remote": "remote1" })
+    ));
+
+    assert!(check(
+        &repair_auth_id,
+        serde_json::json!({ "repair-drive": "drive1" })
+    ));
+    assert!(!check(
+        &repair_auth_id,
+        serde_json::json!({ "repair-drive": "drive2" })
+    ));
+    assert!(!check(
+        &no_perm_auth_id,
+        serde_json::json!({ "repair-drive": "drive1" })
+    ));
+
+    // only one repair source at a time
+    assert!(!check(
+        &repair_auth_id,
+        serde_json::json!({
+            "repair-remote": "remote1",
+            "repair-store": "remotestore1",
+            "repair-drive": "drive1",
+        })
+    ));
+
+    Ok(())
+}
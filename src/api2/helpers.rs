@@ -6,6 +6,8 @@ use hyper::{header, Body, Response, StatusCode};
 
 use proxmox_router::http_bail;
 
+use pbs_datastore::DataStore;
+
 pub async fn create_download_response(path: PathBuf) -> Result<Response<Body>, Error> {
     let file = match tokio::fs::File::open(path.clone()).await {
         Ok(file) => file,
@@ -15,15 +17,38 @@ pub async fn create_download_response(path: PathBuf) -> Result<Response<Body>, E
         Err(err) => http_bail!(BAD_REQUEST, "open file {:?} failed: {}", path, err),
     };
 
+    Ok(file_download_response(file))
+}
+
+/// Like [`create_download_response`], for snapshot files which might be encrypted at rest.
+pub async fn create_snapshot_file_download_response(
+    datastore: &DataStore,
+    path: PathBuf,
+) -> Result<Response<Body>, Error> {
+    let file = match proxmox_async::runtime::block_in_place(|| datastore.open_snapshot_file(&path))
+    {
+        Ok(file) => tokio::fs::File::from_std(file),
+        Err(err) => match err.downcast_ref::<std::io::Error>() {
+            Some(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                http_bail!(NOT_FOUND, "open file {:?} failed - not found", path);
+            }
+            _ => http_bail!(BAD_REQUEST, "open file {:?} failed: {}", path, err),
+        },
+    };
+
+    Ok(file_download_response(file))
+}
+
+fn file_download_response(file: tokio::fs::File) -> Response<Body> {
     let payload = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
         .map_ok(|bytes| bytes.freeze());
 
     let body = Body::wrap_stream(payload);
 
     // fixme: set other headers ?
-    Ok(Response::builder()
+    Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/octet-stream")
         .body(body)
-        .unwrap())
+        .unwrap()
 }
@@ -7,8 +7,8 @@ use proxmox_schema::api;
 use proxmox_sys::task_log;
 
 use pbs_api_types::{
-    Authid, BackupNamespace, GroupFilter, RateLimitConfig, SyncJobConfig, DATASTORE_SCHEMA,
-    GROUP_FILTER_LIST_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA, PRIV_DATASTORE_BACKUP,
+    Authid, BackupNamespace, GroupFilter, RateLimitConfig, SyncDirection, SyncJobConfig,
+    DATASTORE_SCHEMA, GROUP_FILTER_LIST_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA, PRIV_DATASTORE_BACKUP,
     PRIV_DATASTORE_PRUNE, PRIV_REMOTE_READ, REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
@@ -16,6 +16,7 @@ use proxmox_rest_server::WorkerTask;
 
 use crate::server::jobstate::Job;
 use crate::server::pull::{pull_store, PullParameters};
+use crate::server::push::{push_store, PushParameters};
 
 pub fn check_pull_privs(
     auth_id: &Authid,
@@ -111,22 +112,41 @@ pub fn do_sync_job(
             let sync_job2 = sync_job.clone();
 
             let worker_future = async move {
-                let pull_params = PullParameters::try_from(&sync_job)?;
-                let client = pull_params.client().await?;
-
                 task_log!(worker, "Starting datastore sync job '{}'", job_id);
                 if let Some(event_str) = schedule {
                     task_log!(worker, "task triggered by schedule '{}'", event_str);
                 }
-                task_log!(
-                    worker,
-                    "sync datastore '{}' from '{}/{}'",
-                    sync_job.store,
-                    sync_job.remote,
-                    sync_job.remote_store,
-                );
-
-                pull_store(&worker, &client, pull_params).await?;
+
+                match sync_job.sync_direction() {
+                    SyncDirection::Pull => {
+                        let pull_params = PullParameters::try_from(&sync_job)?;
+                        let client = pull_params.client().await?;
+
+                        task_log!(
+                            worker,
+                            "sync datastore '{}' from '{}/{}'",
+                            sync_job.store,
+                            sync_job.remote,
+                            sync_job.remote_store,
+                        );
+
+                        pull_store(&worker, &client, pull_params).await?;
+                    }
+                    SyncDirection::Push => {
+                        let push_params = PushParameters::try_from(&sync_job)?;
+                        let client = push_params.client().await?;
+
+                        task_log!(
+                            worker,
+                            "sync datastore '{}' to '{}/{}'",
+                            sync_job.store,
+                            sync_job.remote,
+                            sync_job.remote_store,
+                        );
+
+                        push_store(&worker, &client, push_params).await?;
+                    }
+                }
 
                 task_log!(worker, "sync job '{}' end", &job_id);
 
@@ -19,11 +19,12 @@ use proxmox_sortable_macro::sortable;
 use pbs_api_types::{
     Authid, Operation, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
     BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA,
-    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
+    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ, ZSTD_DICTIONARY_ID_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{archive_type, ArchiveType};
+use pbs_datastore::zstd_dictionary::ZstdDictionary;
 use pbs_datastore::{DataStore, PROXMOX_BACKUP_READER_PROTOCOL_ID_V1};
 use pbs_tools::json::required_string_param;
 use proxmox_rest_server::{H2Service, WorkerTask};
@@ -227,6 +228,10 @@ const READER_API_SUBDIRS: SubdirMap = &[
         &Router::new().download(&API_METHOD_DOWNLOAD_FILE),
     ),
     ("speedtest", &Router::new().download(&API_METHOD_SPEEDTEST)),
+    (
+        "zstd_dictionary",
+        &Router::new().download(&API_METHOD_DOWNLOAD_ZSTD_DICTIONARY),
+    ),
 ];
 
 pub const READER_API_ROUTER: Router = Router::new()
@@ -284,7 +289,7 @@ fn download_file(
             }
         }
 
-        helpers::create_download_response(path).await
+        helpers::create_snapshot_file_download_response(&env.datastore, path).await
     }
     .boxed()
 }
@@ -323,6 +328,9 @@ fn download_chunk(
             ));
         }
 
+        proxmox_async::runtime::block_in_place(|| env.datastore.cache_chunk(&digest))
+            .map_err(|err| http_err!(BAD_REQUEST, "fetching chunk {digest_str} failed: {err}"))?;
+
         let (path, _) = env.datastore.chunk_path(&digest);
         let path2 = path.clone();
 
@@ -332,6 +340,10 @@ fn download_chunk(
             proxmox_async::runtime::block_in_place(|| std::fs::read(path)).map_err(move |err| {
                 http_err!(BAD_REQUEST, "reading file {:?} failed: {}", path2, err)
             })?;
+        let data = proxmox_async::runtime::block_in_place(|| {
+            env.datastore.decode_chunk_file(data, &digest)
+        })
+        .map_err(|err| http_err!(BAD_REQUEST, "decoding chunk {digest_str} failed: {err}"))?;
 
         let body = Body::from(data);
 
@@ -345,6 +357,44 @@ fn download_chunk(
     .boxed()
 }
 
+#[sortable]
+pub const API_METHOD_DOWNLOAD_ZSTD_DICTIONARY: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&download_zstd_dictionary),
+    &ObjectSchema::new(
+        "Download a zstd dictionary needed to decode chunks and blobs.",
+        &sorted!([("id", false, &ZSTD_DICTIONARY_ID_SCHEMA),]),
+    ),
+);
+
+fn download_zstd_dictionary(
+    _parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let env: &ReaderEnvironment = rpcenv.as_ref();
+
+        let id = u32::from_str_radix(required_string_param(&param, "id")?, 16)?;
+
+        // make sure it is a valid dictionary
+        env.datastore
+            .zstd_dictionary(id)
+            .map_err(|err| http_err!(BAD_REQUEST, "{err}"))?;
+
+        let path = env
+            .datastore
+            .zstd_dictionary_dir()
+            .join(ZstdDictionary::file_name(id));
+
+        env.log(format!("download zstd dictionary {:08x}", id));
+
+        helpers::create_snapshot_file_download_response(&env.datastore, path).await
+    }
+    .boxed()
+}
+
 /* this is too slow
 fn download_chunk_old(
     _parts: Parts,
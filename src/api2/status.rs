@@ -8,12 +8,12 @@ use proxmox_router::{ApiMethod, Permission, Router, RpcEnvironment, SubdirMap};
 use proxmox_schema::api;
 
 use pbs_api_types::{
-    Authid, DataStoreStatusListItem, Operation, RRDMode, RRDTimeFrame, PRIV_DATASTORE_AUDIT,
-    PRIV_DATASTORE_BACKUP,
+    Authid, DataStoreConfig, DataStoreMountStatus, DataStoreStatusListItem, Operation, RRDMode,
+    RRDTimeFrame, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP,
 };
 
 use pbs_config::CachedUserInfo;
-use pbs_datastore::DataStore;
+use pbs_datastore::{get_datastore_mount_status, DataStore};
 
 use crate::rrd_cache::extract_rrd_data;
 use crate::tools::statistics::linear_regression;
@@ -45,22 +45,35 @@ pub async fn datastore_status(
 
     let mut list = Vec::new();
 
-    for (store, (_, _)) in &config.sections {
+    for (store, (_, store_config)) in &config.sections {
+        let store_config: DataStoreConfig = serde_json::from_value(store_config.clone())?;
+        let mount_status = get_datastore_mount_status(&store_config);
+
         let user_privs = user_info.lookup_privs(&auth_id, &["datastore", store]);
         let allowed = (user_privs & (PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_BACKUP)) != 0;
         if !allowed {
             if let Ok(datastore) = DataStore::lookup_datastore(store, Some(Operation::Lookup)) {
                 if can_access_any_namespace(datastore, &auth_id, &user_info) {
-                    list.push(DataStoreStatusListItem::empty(store, None));
+                    list.push(DataStoreStatusListItem::empty(store, None, mount_status));
                 }
             }
             continue;
         }
 
+        // an unplugged removable datastore is unavailable, but not broken
+        if mount_status == DataStoreMountStatus::NotMounted {
+            list.push(DataStoreStatusListItem::empty(store, None, mount_status));
+            continue;
+        }
+
         let datastore = match DataStore::lookup_datastore(store, Some(Operation::Read)) {
             Ok(datastore) => datastore,
             Err(err) => {
-                list.push(DataStoreStatusListItem::empty(store, Some(err.to_string())));
+                list.push(DataStoreStatusListItem::empty(
+                    store,
+                    Some(err.to_string()),
+                    mount_status,
+                ));
                 continue;
             }
         };
@@ -77,6 +90,7 @@ pub async fn datastore_status(
             estimated_full_date: None,
             error: None,
             gc_status: Some(datastore.last_gc_status()),
+            mount_status,
         };
 
         let rrd_dir = format!("datastore/{}", store);
@@ -1,3 +1,4 @@
+use std::io::Read;
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
@@ -16,7 +17,8 @@ use pbs_api_types::{
 
 use pbs_config::CachedUserInfo;
 use pbs_datastore::backup_info::{BackupDir, BackupGroup, BackupInfo};
-use pbs_datastore::{DataStore, StoreProgress};
+use pbs_datastore::file_formats::DICT_COMPR_BLOB_MAGIC_1_0;
+use pbs_datastore::{DataStore, SnapshotReader, StoreProgress};
 use proxmox_rest_server::WorkerTask;
 
 use crate::{
@@ -592,6 +594,25 @@ fn update_media_online_status(drive: &str) -> Result<Option<String>, Error> {
     }
 }
 
+/// Returns the name of the first blob of the snapshot compressed with a zstd dictionary, if any.
+///
+/// Dictionaries are not written to tape, so such blobs could not be decoded after a restore.
+fn find_dictionary_compressed_blob(
+    snapshot_reader: &SnapshotReader,
+) -> Result<Option<String>, Error> {
+    for filename in snapshot_reader.file_list() {
+        if !filename.ends_with(".blob") {
+            continue;
+        }
+        let mut file = snapshot_reader.open_file(filename)?;
+        let mut magic = [0u8; 8];
+        if file.read_exact(&mut magic).is_ok() && magic == DICT_COMPR_BLOB_MAGIC_1_0 {
+            return Ok(Some(filename.clone()));
+        }
+    }
+    Ok(None)
+}
+
 fn backup_snapshot(
     worker: &WorkerTask,
     pool_writer: &mut PoolWriter,
@@ -620,6 +641,29 @@ fn backup_snapshot(
         }
     };
 
+    match find_dictionary_compressed_blob(&snapshot_reader) {
+        Ok(None) => (),
+        Ok(Some(filename)) => {
+            task_warn!(
+                worker,
+                "skipping snapshot {:?}: blob '{}' is compressed with a zstd dictionary, which \
+                cannot be restored from tape",
+                snapshot_path,
+                filename,
+            );
+            return Ok(SnapshotBackupResult::Error);
+        }
+        Err(err) => {
+            task_warn!(
+                worker,
+                "failed reading snapshot {:?}: {}",
+                snapshot_path,
+                err
+            );
+            return Ok(SnapshotBackupResult::Error);
+        }
+    }
+
     let snapshot_reader = Arc::new(Mutex::new(snapshot_reader));
 
     let (reader_thread, chunk_iter) =
@@ -23,8 +23,6 @@ use pbs_api_types::{
     TAPE_RESTORE_SNAPSHOT_SCHEMA, UPID_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
-use pbs_datastore::dynamic_index::DynamicIndexReader;
-use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, MANIFEST_BLOB_NAME};
 use pbs_datastore::{DataBlob, DataStore};
@@ -875,7 +873,12 @@ fn restore_list_worker(
                             let entry = entry?;
                             let mut new_path = path.clone();
                             new_path.push(entry.file_name());
-                            std::fs::copy(entry.path(), new_path)?;
+                            // at-rest encrypted files are bound to their path, so they cannot
+                            // be copied as they are
+                            let mut file = datastore.open_snapshot_file(entry.path())?;
+                            let mut new_file = std::fs::File::create(&new_path)?;
+                            std::io::copy(&mut file, &mut new_file)?;
+                            datastore.encrypt_snapshot_file(&new_path, &new_path)?;
                         }
 
                         Ok(())
@@ -1078,8 +1081,12 @@ fn restore_snapshots_to_tmpdir(
                 let chunks = chunks_list
                     .entry(source_datastore)
                     .or_insert_with(HashSet::new);
-                let manifest =
-                    try_restore_snapshot_archive(worker.clone(), &mut decoder, &tmp_path)?;
+                let manifest = try_restore_snapshot_archive(
+                    worker.clone(),
+                    &target_datastore,
+                    &mut decoder,
+                    &tmp_path,
+                )?;
 
                 for item in manifest.files() {
                     let mut archive_path = tmp_path.to_owned();
@@ -1087,9 +1094,11 @@ fn restore_snapshots_to_tmpdir(
 
                     let index: Box<dyn IndexFile> = match archive_type(&item.filename)? {
                         ArchiveType::DynamicIndex => {
-                            Box::new(DynamicIndexReader::open(&archive_path)?)
+                            Box::new(target_datastore.open_dynamic_reader(&archive_path)?)
+                        }
+                        ArchiveType::FixedIndex => {
+                            Box::new(target_datastore.open_fixed_reader(&archive_path)?)
                         }
-                        ArchiveType::FixedIndex => Box::new(FixedIndexReader::open(&archive_path)?),
                         ArchiveType::Blob => continue,
                     };
                     for i in 0..index.index_count() {
@@ -1182,7 +1191,7 @@ fn restore_partial_chunk_archive<'a>(
                 bytes2.fetch_add(chunk.raw_size(), std::sync::atomic::Ordering::SeqCst);
                 chunk.verify_crc()?;
                 if chunk.crypt_mode()? == CryptMode::None {
-                    chunk.decode(None, Some(&digest))?; // verify digest
+                    datastore.decode_blob(&chunk, None, Some(&digest))?; // verify digest
                 }
 
                 datastore.insert_chunk(&chunk, &digest)?;
@@ -1221,6 +1230,114 @@ fn restore_partial_chunk_archive<'a>(
     Ok(count)
 }
 
+/// Read the `wanted` chunks of datastore `store` from the chunk archives on tape.
+///
+/// The chunks are located with the media catalogs, every media containing some of them gets
+/// requested in `drive_name`. `found` is called for every chunk read, the chunks are not
+/// verified. Returns the digests which were not found in any media catalog.
+pub fn read_chunks_from_tape(
+    worker: &dyn WorkerTaskContext,
+    drive_name: &str,
+    store: &str,
+    wanted: &HashSet<[u8; 32]>,
+    mut found: impl FnMut([u8; 32], DataBlob),
+) -> Result<HashSet<[u8; 32]>, Error> {
+    let inventory = Inventory::load(TAPE_STATUS_DIR)?;
+
+    // sorted media_uuid => (sorted file_num => (set of digests)))
+    let mut media_file_chunk_map: BTreeMap<Uuid, BTreeMap<u64, HashSet<[u8; 32]>>> =
+        BTreeMap::new();
+    let mut missing = wanted.clone();
+
+    for media_uuid in MediaCatalog::media_with_catalogs(TAPE_STATUS_DIR)? {
+        if missing.is_empty() {
+            break;
+        }
+        let media_id = match inventory.lookup_media(&media_uuid) {
+            Some(media_id) => media_id,
+            None => continue,
+        };
+        let catalog = MediaCatalog::open(TAPE_STATUS_DIR, media_id, false, false)?;
+        if !catalog.content().contains_key(store) {
+            continue;
+        }
+        missing.retain(|digest| match catalog.lookup_chunk(store, digest) {
+            Some(file_num) => {
+                media_file_chunk_map
+                    .entry(media_uuid.clone())
+                    .or_default()
+                    .entry(file_num)
+                    .or_default()
+                    .insert(*digest);
+                false
+            }
+            None => true,
+        });
+    }
+
+    if media_file_chunk_map.is_empty() {
+        return Ok(missing);
+    }
+
+    let (drive_config, _digest) = pbs_config::drive::config()?;
+    let _drive_lock = lock_tape_device(&drive_config, drive_name)?;
+
+    for (media_uuid, file_chunk_map) in media_file_chunk_map {
+        let media_id = inventory.lookup_media(&media_uuid).unwrap();
+        let (mut drive, info) =
+            request_and_load_media(worker, &drive_config, drive_name, &media_id.label, &None)?;
+
+        let encrypt_fingerprint = info.media_set_label.as_ref().and_then(|set| {
+            set.encryption_key_fingerprint
+                .clone()
+                .map(|fp| (fp, set.uuid.clone()))
+        });
+        drive.set_encryption(encrypt_fingerprint)?;
+
+        for (file_num, mut chunks) in file_chunk_map {
+            drive.move_to_file(file_num)?;
+            let mut reader = drive.read_next_file()?;
+            let header: MediaContentHeader = unsafe { reader.read_le_value()? };
+            if header.magic != PROXMOX_BACKUP_CONTENT_HEADER_MAGIC_1_0 {
+                bail!("file is missing the MediaContentHeader");
+            }
+            if header.content_magic != PROXMOX_BACKUP_CHUNK_ARCHIVE_MAGIC_1_1 {
+                bail!("unexpected content magic {:?}", header.content_magic);
+            }
+            let header_data = reader.read_exact_allocated(header.size as usize)?;
+            let archive_header: ChunkArchiveHeader = serde_json::from_slice(&header_data)
+                .map_err(|err| format_err!("unable to parse chunk archive header - {err}"))?;
+            if archive_header.store != store {
+                bail!(
+                    "unexpected chunk archive for store '{}'",
+                    archive_header.store
+                );
+            }
+
+            task_log!(
+                worker,
+                "reading {} chunks from file {file_num} of {}",
+                chunks.len(),
+                media_id.label.label_text,
+            );
+
+            let mut decoder = ChunkArchiveDecoder::new(reader);
+            while let Some((digest, blob)) = decoder.next_chunk()? {
+                worker.check_abort()?;
+                if chunks.remove(&digest) {
+                    found(digest, blob);
+                }
+                if chunks.is_empty() {
+                    break;
+                }
+            }
+            missing.extend(chunks);
+        }
+    }
+
+    Ok(missing)
+}
+
 /// Request and restore complete media without using existing catalog (create catalog instead)
 #[allow(clippy::too_many_arguments)]
 pub fn request_and_restore_media(
@@ -1411,7 +1528,7 @@ fn restore_archive<'a>(
                     if is_new {
                         task_log!(worker, "restore snapshot {}", backup_dir);
 
-                        match restore_snapshot_archive(worker.clone(), reader, &path) {
+                        match restore_snapshot_archive(worker.clone(), &datastore, reader, &path) {
                             Err(err) => {
                                 std::fs::remove_dir_all(&path)?;
                                 bail!("restore snapshot {} failed - {}", backup_dir, err);
@@ -1607,7 +1724,7 @@ fn restore_chunk_archive<'a>(
                 // println!("verify and write {}", hex::encode(&digest));
                 chunk.verify_crc()?;
                 if chunk.crypt_mode()? == CryptMode::None {
-                    chunk.decode(None, Some(&digest))?; // verify digest
+                    datastore.decode_blob(&chunk, None, Some(&digest))?; // verify digest
                 }
 
                 datastore.insert_chunk(&chunk, &digest)?;
@@ -1670,11 +1787,12 @@ fn restore_chunk_archive<'a>(
 
 fn restore_snapshot_archive<'a>(
     worker: Arc<WorkerTask>,
+    datastore: &DataStore,
     reader: Box<dyn 'a + TapeRead>,
     snapshot_path: &Path,
 ) -> Result<bool, Error> {
     let mut decoder = pxar::decoder::sync::Decoder::from_std(reader)?;
-    match try_restore_snapshot_archive(worker, &mut decoder, snapshot_path) {
+    match try_restore_snapshot_archive(worker, datastore, &mut decoder, snapshot_path) {
         Ok(_) => Ok(true),
         Err(err) => {
             let reader = decoder.input();
@@ -1697,6 +1815,7 @@ fn restore_snapshot_archive<'a>(
 
 fn try_restore_snapshot_archive<R: pxar::decoder::SeqRead>(
     worker: Arc<WorkerTask>,
+    datastore: &DataStore,
     decoder: &mut pxar::decoder::sync::Decoder<R>,
     snapshot_path: &Path,
 ) -> Result<BackupManifest, Error> {
@@ -1765,7 +1884,8 @@ fn try_restore_snapshot_archive<R: pxar::decoder::SeqRead>(
             let blob = DataBlob::encode(old_manifest.as_bytes(), None, true)?;
 
             let options = CreateOptions::new();
-            replace_file(&tmp_path, blob.raw_data(), options, false)?;
+            let raw_data = datastore.encode_snapshot_file(blob.raw_data(), &archive_path)?;
+            replace_file(&tmp_path, &raw_data, options, false)?;
 
             manifest = Some(BackupManifest::try_from(blob)?);
         } else {
@@ -1777,6 +1897,7 @@ fn try_restore_snapshot_archive<R: pxar::decoder::SeqRead>(
                 .map_err(|err| format_err!("restore {:?} failed - {}", tmp_path, err))?;
 
             std::io::copy(&mut contents, &mut tmpfile)?;
+            datastore.encrypt_snapshot_file(&tmp_path, &archive_path)?;
 
             if let Err(err) = std::fs::rename(&tmp_path, &archive_path) {
                 bail!("Atomic rename file {:?} failed - {}", archive_path, err);
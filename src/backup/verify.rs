@@ -1,19 +1,22 @@
 use nix::dir::Dir;
-use std::collections::HashSet;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use anyhow::{bail, format_err, Error};
 
+use proxmox_http::{RateLimit, RateLimiter};
 use proxmox_sys::{task_log, WorkerTaskContext};
 
 use pbs_api_types::{
     print_ns_and_snapshot, print_store_and_ns, Authid, BackupNamespace, BackupType, CryptMode,
-    SnapshotVerifyState, VerifyState, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_VERIFY, UPID,
+    HumanByte, Remote, SnapshotVerifyState, VerificationJobConfig, VerifyIoPriority, VerifyState,
+    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_VERIFY, UPID,
 };
+use pbs_client::BackupReader;
 use pbs_datastore::backup_info::{BackupDir, BackupGroup, BackupInfo};
-use pbs_datastore::index::IndexFile;
+use pbs_datastore::index::{ChunkReadInfo, IndexFile};
 use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, FileInfo};
 use pbs_datastore::{DataBlob, DataStore, StoreProgress};
 use proxmox_sys::fs::lock_dir_noblock_shared;
@@ -29,6 +32,24 @@ pub struct VerifyWorker {
     datastore: Arc<DataStore>,
     verified_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
     corrupt_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    read_threads: usize,
+    decode_threads: usize,
+    io_priority: Option<VerifyIoPriority>,
+    read_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    repair_source: Option<RepairSource>,
+}
+
+/// Where to fetch good copies of corrupt chunks from.
+enum RepairSource {
+    /// A remote datastore, accessed via the reader protocol.
+    Remote {
+        remote: Remote,
+        store: String,
+        local_ns: BackupNamespace,
+        remote_ns: BackupNamespace,
+    },
+    /// The chunk archives on tape, located via the media catalogs.
+    Tape { drive: String },
 }
 
 impl VerifyWorker {
@@ -41,10 +62,80 @@ impl VerifyWorker {
             verified_chunks: Arc::new(Mutex::new(HashSet::with_capacity(16 * 1024))),
             // start with 64 chunks since we assume there are few corrupt ones
             corrupt_chunks: Arc::new(Mutex::new(HashSet::with_capacity(64))),
+            read_threads: 1,
+            decode_threads: 4,
+            io_priority: None,
+            read_limiter: None,
+            repair_source: None,
+        }
+    }
+
+    /// Use the thread counts, I/O priority and read rate limit of a verification job.
+    pub fn set_job_tuning(&mut self, job: &VerificationJobConfig) {
+        if let Some(read_threads) = job.read_threads {
+            self.read_threads = read_threads;
+        }
+        if let Some(decode_threads) = job.decode_threads {
+            self.decode_threads = decode_threads;
         }
+        self.io_priority = job.io_priority;
+        self.read_limiter = job.read_rate.map(|rate| {
+            let rate = rate.as_u64();
+            // allow bursts of one second
+            Arc::new(Mutex::new(RateLimiter::new(rate, rate)))
+        });
+    }
+
+    /// Use the repair remote or tape drive of a verification job to replace corrupt or missing
+    /// chunks.
+    pub fn set_repair_source(&mut self, job: &VerificationJobConfig) -> Result<(), Error> {
+        if let Some(drive) = &job.repair_drive {
+            self.repair_source = Some(RepairSource::Tape {
+                drive: drive.clone(),
+            });
+            return Ok(());
+        }
+
+        let (remote, store) = match (&job.repair_remote, &job.repair_store) {
+            (Some(remote), Some(store)) => (remote, store),
+            _ => return Ok(()),
+        };
+
+        let (config, _digest) = pbs_config::remote::config()?;
+        let remote: Remote = config.lookup("remote", remote)?;
+
+        self.repair_source = Some(RepairSource::Remote {
+            remote,
+            store: store.clone(),
+            local_ns: job.ns.clone().unwrap_or_default(),
+            remote_ns: job.repair_ns.clone().unwrap_or_default(),
+        });
+
+        Ok(())
     }
 }
 
+/// Set the I/O priority of the calling thread, see ioprio_set(2).
+fn set_io_priority(priority: VerifyIoPriority) -> Result<(), Error> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    const IOPRIO_CLASS_BE: libc::c_int = 2;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+
+    let ioprio = match priority {
+        VerifyIoPriority::High => IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT,
+        VerifyIoPriority::Low => (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | 7,
+        VerifyIoPriority::Idle => IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+    };
+
+    // 'who' 0 selects the calling thread
+    let res = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) };
+    if res < 0 {
+        bail!("ioprio_set failed - {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 fn verify_blob(backup_dir: &BackupDir, info: &FileInfo) -> Result<(), Error> {
     let blob = backup_dir.load_blob(&info.filename)?;
 
@@ -62,7 +153,7 @@ fn verify_blob(backup_dir: &BackupDir, info: &FileInfo) -> Result<(), Error> {
         CryptMode::Encrypt => Ok(()),
         CryptMode::None => {
             // digest already verified above
-            blob.decode(None, None)?;
+            backup_dir.datastore().decode_blob(&blob, None, None)?;
             Ok(())
         }
         CryptMode::SignOnly => bail!("Invalid CryptMode for blob"),
@@ -103,30 +194,67 @@ fn rename_corrupted_chunk(
             }
         }
     };
-}
 
-fn verify_index_chunks(
-    verify_worker: &VerifyWorker,
-    index: Box<dyn IndexFile + Send>,
-    crypt_mode: CryptMode,
-) -> Result<(), Error> {
-    let errors = Arc::new(AtomicUsize::new(0));
+    // make sure the corrupted chunk gets uploaded again by the next backup
+    if let Err(err) = datastore.remove_chunk_from_backend(digest) {
+        task_log!(
+            worker,
+            "could not remove corrupted chunk {} from backend - {}",
+            digest_str,
+            err
+        );
+    }
+}
 
-    let start_time = Instant::now();
+fn skip_chunk(verify_worker: &VerifyWorker, digest: &[u8; 32], errors: &AtomicUsize) -> bool {
+    if verify_worker
+        .verified_chunks
+        .lock()
+        .unwrap()
+        .contains(digest)
+    {
+        true
+    } else if verify_worker
+        .corrupt_chunks
+        .lock()
+        .unwrap()
+        .contains(digest)
+    {
+        let digest_str = hex::encode(digest);
+        task_log!(
+            verify_worker.worker,
+            "chunk {} was marked as corrupt",
+            digest_str
+        );
+        errors.fetch_add(1, Ordering::SeqCst);
+        true
+    } else {
+        false
+    }
+}
 
-    let mut read_bytes = 0;
-    let mut decoded_bytes = 0;
+/// Read and check chunks with the reader and decoder thread pools of `verify_worker`.
+///
+/// Chunks already verified or marked as corrupt are skipped, errors are counted in `errors`.
+/// Returns the number of bytes read and decoded.
+fn verify_chunks(
+    verify_worker: &VerifyWorker,
+    chunks: impl Iterator<Item = (ChunkReadInfo, CryptMode)>,
+    errors: &Arc<AtomicUsize>,
+) -> Result<(u64, u64), Error> {
+    let read_bytes = Arc::new(AtomicU64::new(0));
+    let decoded_bytes = Arc::new(AtomicU64::new(0));
 
     let worker2 = Arc::clone(&verify_worker.worker);
     let datastore2 = Arc::clone(&verify_worker.datastore);
     let corrupt_chunks2 = Arc::clone(&verify_worker.corrupt_chunks);
     let verified_chunks2 = Arc::clone(&verify_worker.verified_chunks);
-    let errors2 = Arc::clone(&errors);
+    let errors2 = Arc::clone(errors);
 
     let decoder_pool = ParallelHandler::new(
         "verify chunk decoder",
-        4,
-        move |(chunk, digest, size): (DataBlob, [u8; 32], u64)| {
+        verify_worker.decode_threads,
+        move |(chunk, digest, size, crypt_mode): (DataBlob, [u8; 32], u64, CryptMode)| {
             let chunk_crypt_mode = match chunk.crypt_mode() {
                 Err(err) => {
                     corrupt_chunks2.lock().unwrap().insert(digest);
@@ -147,7 +275,7 @@ fn verify_index_chunks(
                 errors2.fetch_add(1, Ordering::SeqCst);
             }
 
-            if let Err(err) = chunk.verify_unencrypted(size as usize, &digest) {
+            if let Err(err) = datastore2.verify_unencrypted_chunk(&chunk, size as usize, &digest) {
                 corrupt_chunks2.lock().unwrap().insert(digest);
                 task_log!(worker2, "{}", err);
                 errors2.fetch_add(1, Ordering::SeqCst);
@@ -160,87 +288,105 @@ fn verify_index_chunks(
         },
     );
 
-    let skip_chunk = |digest: &[u8; 32]| -> bool {
-        if verify_worker
-            .verified_chunks
-            .lock()
-            .unwrap()
-            .contains(digest)
-        {
-            true
-        } else if verify_worker
-            .corrupt_chunks
-            .lock()
-            .unwrap()
-            .contains(digest)
-        {
-            let digest_str = hex::encode(digest);
-            task_log!(
-                verify_worker.worker,
-                "chunk {} was marked as corrupt",
-                digest_str
-            );
-            errors.fetch_add(1, Ordering::SeqCst);
-            true
-        } else {
-            false
-        }
-    };
+    let decoder = decoder_pool.channel();
+    let worker2 = Arc::clone(&verify_worker.worker);
+    let datastore2 = Arc::clone(&verify_worker.datastore);
+    let corrupt_chunks2 = Arc::clone(&verify_worker.corrupt_chunks);
+    let errors2 = Arc::clone(errors);
+    let read_bytes2 = Arc::clone(&read_bytes);
+    let decoded_bytes2 = Arc::clone(&decoded_bytes);
+    let io_priority = verify_worker.io_priority;
+    let read_limiter = verify_worker.read_limiter.clone();
+
+    let reader_pool = ParallelHandler::new(
+        "verify chunk reader",
+        verify_worker.read_threads,
+        move |(info, crypt_mode): (ChunkReadInfo, CryptMode)| {
+            if let Some(priority) = io_priority {
+                // cheap enough to do per chunk, pool threads have no setup hook
+                set_io_priority(priority)?;
+            }
 
-    let check_abort = |pos: usize| -> Result<(), Error> {
-        if pos & 1023 == 0 {
-            verify_worker.worker.check_abort()?;
-            verify_worker.worker.fail_on_shutdown()?;
-        }
-        Ok(())
-    };
+            match datastore2.load_chunk(&info.digest) {
+                Err(err) => {
+                    corrupt_chunks2.lock().unwrap().insert(info.digest);
+                    task_log!(worker2, "can't verify chunk, load failed - {}", err);
+                    errors2.fetch_add(1, Ordering::SeqCst);
+                    rename_corrupted_chunk(datastore2.clone(), &info.digest, &worker2);
+                }
+                Ok(chunk) => {
+                    let raw_size = chunk.raw_size();
+                    if let Some(limiter) = &read_limiter {
+                        let delay = limiter
+                            .lock()
+                            .unwrap()
+                            .register_traffic(Instant::now(), raw_size);
+                        if !delay.is_zero() {
+                            std::thread::sleep(delay);
+                        }
+                    }
+                    let size = info.size();
+                    read_bytes2.fetch_add(raw_size, Ordering::SeqCst);
+                    decoder.send((chunk, info.digest, size, crypt_mode))?;
+                    decoded_bytes2.fetch_add(size, Ordering::SeqCst);
+                }
+            }
 
-    let chunk_list =
-        verify_worker
-            .datastore
-            .get_chunks_in_order(&*index, skip_chunk, check_abort)?;
+            Ok(())
+        },
+    );
 
-    for (pos, _) in chunk_list {
+    for (info, crypt_mode) in chunks {
         verify_worker.worker.check_abort()?;
         verify_worker.worker.fail_on_shutdown()?;
 
-        let info = index.chunk_info(pos).unwrap();
-
         // we must always recheck this here, the parallel worker below alter it!
-        if skip_chunk(&info.digest) {
+        if skip_chunk(verify_worker, &info.digest, errors) {
             continue; // already verified or marked corrupt
         }
 
-        match verify_worker.datastore.load_chunk(&info.digest) {
-            Err(err) => {
-                verify_worker
-                    .corrupt_chunks
-                    .lock()
-                    .unwrap()
-                    .insert(info.digest);
-                task_log!(
-                    verify_worker.worker,
-                    "can't verify chunk, load failed - {}",
-                    err
-                );
-                errors.fetch_add(1, Ordering::SeqCst);
-                rename_corrupted_chunk(
-                    verify_worker.datastore.clone(),
-                    &info.digest,
-                    &verify_worker.worker,
-                );
-            }
-            Ok(chunk) => {
-                let size = info.size();
-                read_bytes += chunk.raw_size();
-                decoder_pool.send((chunk, info.digest, size))?;
-                decoded_bytes += size;
-            }
-        }
+        reader_pool.send((info, crypt_mode))?;
     }
 
+    // completes the readers first, they feed the decoders
+    reader_pool.complete()?;
     decoder_pool.complete()?;
 
+    Ok((
+        read_bytes.load(Ordering::SeqCst),
+        decoded_bytes.load(Ordering::SeqCst),
+    ))
+}
+
+fn verify_index_chunks(
+    verify_worker: &VerifyWorker,
+    index: Box<dyn IndexFile + Send>,
+    crypt_mode: CryptMode,
+) -> Result<(), Error> {
+    let errors = Arc::new(AtomicUsize::new(0));
+
+    let start_time = Instant::now();
+
+    let check_abort = |pos: usize| -> Result<(), Error> {
+        if pos & 1023 == 0 {
+            verify_worker.worker.check_abort()?;
+            verify_worker.worker.fail_on_shutdown()?;
+        }
+        Ok(())
+    };
+
+    let chunk_list = verify_worker.datastore.get_chunks_in_order(
+        &*index,
+        |digest| skip_chunk(verify_worker, digest, &errors),
+        check_abort,
+    )?;
+
+    let chunks = chunk_list
+        .into_iter()
+        .map(|(pos, _)| (index.chunk_info(pos).unwrap(), crypt_mode));
+
+    let (read_bytes, decoded_bytes) = verify_chunks(verify_worker, chunks, &errors)?;
+
     let elapsed = start_time.elapsed().as_secs_f64();
 
     let read_bytes_mib = (read_bytes as f64) / (1024.0 * 1024.0);
@@ -313,6 +459,186 @@ fn verify_dynamic_index(
     verify_index_chunks(verify_worker, Box::new(index), info.chunk_crypt_mode())
 }
 
+/// Open the index archive `filename` of a snapshot, returns `None` for blobs.
+fn open_index(
+    datastore: &DataStore,
+    backup_dir: &BackupDir,
+    filename: &str,
+) -> Result<Option<Box<dyn IndexFile + Send>>, Error> {
+    let mut path = backup_dir.relative_path();
+    path.push(filename);
+
+    Ok(match archive_type(filename)? {
+        ArchiveType::FixedIndex => Some(Box::new(datastore.open_fixed_reader(&path)?)),
+        ArchiveType::DynamicIndex => Some(Box::new(datastore.open_dynamic_reader(&path)?)),
+        ArchiveType::Blob => None,
+    })
+}
+
+/// Fetch good copies of the corrupt chunks referenced by the archives in `files` from the repair
+/// source and insert them into the datastore.
+///
+/// Returns the number of repaired chunks.
+fn repair_chunks(
+    verify_worker: &VerifyWorker,
+    backup_dir: &BackupDir,
+    files: &[&FileInfo],
+) -> Result<usize, Error> {
+    let source = match verify_worker.repair_source {
+        Some(ref source) => source,
+        None => return Ok(0),
+    };
+
+    // digest => (chunk size, crypt mode of the referencing index)
+    let mut wanted = HashMap::new();
+    let mut index_files = Vec::new();
+    for info in files {
+        let index = match open_index(&verify_worker.datastore, backup_dir, &info.filename)? {
+            Some(index) => index,
+            None => continue,
+        };
+
+        let corrupt_chunks = verify_worker.corrupt_chunks.lock().unwrap();
+        for pos in 0..index.index_count() {
+            let chunk_info = index.chunk_info(pos).unwrap();
+            if corrupt_chunks.contains(&chunk_info.digest) {
+                wanted.insert(
+                    chunk_info.digest,
+                    (chunk_info.size(), info.chunk_crypt_mode()),
+                );
+            }
+        }
+        index_files.push(info.filename.as_str());
+    }
+
+    if wanted.is_empty() {
+        return Ok(0);
+    }
+
+    let mut repaired = 0;
+    let mut insert_chunk = |digest: [u8; 32], chunk: DataBlob| {
+        let result: Result<(), Error> = proxmox_lang::try_block!({
+            // the sources only return wanted chunks
+            let (size, crypt_mode) = wanted[&digest];
+            if chunk.crypt_mode()? != crypt_mode {
+                bail!("chunk CryptMode does not match index CryptMode");
+            }
+            verify_worker
+                .datastore
+                .verify_unencrypted_chunk(&chunk, size as usize, &digest)?;
+
+            verify_worker.datastore.insert_chunk(&chunk, &digest)?;
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => {
+                verify_worker.corrupt_chunks.lock().unwrap().remove(&digest);
+                verify_worker.verified_chunks.lock().unwrap().insert(digest);
+                repaired += 1;
+            }
+            Err(err) => {
+                task_log!(
+                    verify_worker.worker,
+                    "could not repair chunk {} - {}",
+                    hex::encode(digest),
+                    err,
+                );
+            }
+        }
+    };
+
+    match source {
+        RepairSource::Remote {
+            remote,
+            store,
+            local_ns,
+            remote_ns,
+        } => {
+            let remote_ns = backup_dir.backup_ns().map_prefix(local_ns, remote_ns)?;
+
+            task_log!(
+                verify_worker.worker,
+                "trying to repair {} chunks from remote '{}', datastore {}",
+                wanted.len(),
+                remote.name,
+                print_store_and_ns(store, &remote_ns),
+            );
+
+            proxmox_async::runtime::block_on(async {
+                let client = crate::api2::config::remote::remote_client(remote, None).await?;
+                let reader = BackupReader::start(
+                    client,
+                    None,
+                    store,
+                    &remote_ns,
+                    backup_dir.as_ref(),
+                    false,
+                )
+                .await?;
+
+                // the reader protocol only serves chunks referenced by an already downloaded
+                // index
+                for filename in index_files {
+                    reader.download(filename, std::io::sink()).await?;
+                }
+
+                let digests: Vec<([u8; 32], u64)> = wanted
+                    .iter()
+                    .map(|(digest, (size, _))| (*digest, *size))
+                    .collect();
+                for (digest, size) in digests {
+                    verify_worker.worker.check_abort()?;
+
+                    let mut raw_data = Vec::with_capacity(size as usize);
+                    let result = match reader.download_chunk(&digest, &mut raw_data).await {
+                        Ok(()) => DataBlob::load_from_reader(&mut &raw_data[..]),
+                        Err(err) => Err(err),
+                    };
+                    match result {
+                        Ok(chunk) => insert_chunk(digest, chunk),
+                        Err(err) => task_log!(
+                            verify_worker.worker,
+                            "could not repair chunk {} - {}",
+                            hex::encode(digest),
+                            err,
+                        ),
+                    }
+                }
+
+                Ok::<_, Error>(())
+            })?;
+        }
+        RepairSource::Tape { drive } => {
+            task_log!(
+                verify_worker.worker,
+                "trying to repair {} chunks from tape drive '{}'",
+                wanted.len(),
+                drive,
+            );
+
+            // media catalogs record the datastore name at the time of the tape backup
+            let digests: HashSet<[u8; 32]> = wanted.keys().copied().collect();
+            let missing = crate::api2::tape::restore::read_chunks_from_tape(
+                &*verify_worker.worker,
+                drive,
+                verify_worker.datastore.name(),
+                &digests,
+                &mut insert_chunk,
+            )?;
+            if !missing.is_empty() {
+                task_log!(
+                    verify_worker.worker,
+                    "{} chunks not found on tape",
+                    missing.len()
+                );
+            }
+        }
+    }
+
+    Ok(repaired)
+}
+
 /// Verify a single backup snapshot
 ///
 /// This checks all archives inside a backup snapshot.
@@ -401,10 +727,7 @@ pub fn verify_backup_dir_with_lock(
         backup_dir.dir()
     );
 
-    let mut error_count = 0;
-
-    let mut verify_result = VerifyState::Ok;
-    for info in manifest.files() {
+    let verify_file = |info: &FileInfo| -> Result<bool, Error> {
         let result = proxmox_lang::try_block!({
             task_log!(verify_worker.worker, "  check {}", info.filename);
             match archive_type(&info.filename)? {
@@ -426,11 +749,54 @@ pub fn verify_backup_dir_with_lock(
                 info.filename,
                 err,
             );
-            error_count += 1;
-            verify_result = VerifyState::Failed;
+            return Ok(false);
+        }
+        Ok(true)
+    };
+
+    let mut failed_files = Vec::new();
+    for info in manifest.files() {
+        if !verify_file(info)? {
+            failed_files.push(info);
+        }
+    }
+
+    if !failed_files.is_empty() && verify_worker.repair_source.is_some() {
+        match repair_chunks(verify_worker, backup_dir, &failed_files) {
+            Ok(0) => (),
+            Ok(count) => {
+                task_log!(
+                    verify_worker.worker,
+                    "repaired {} chunks, verifying failed archives again",
+                    count
+                );
+                let mut still_failed = Vec::new();
+                for info in failed_files {
+                    if !verify_file(info)? {
+                        still_failed.push(info);
+                    }
+                }
+                failed_files = still_failed;
+            }
+            Err(err) => {
+                task_log!(
+                    verify_worker.worker,
+                    "repair of {}:{} failed: {}",
+                    verify_worker.datastore.name(),
+                    backup_dir.dir(),
+                    err,
+                );
+            }
         }
     }
 
+    let error_count = failed_files.len();
+    let verify_result = if error_count == 0 {
+        VerifyState::Ok
+    } else {
+        VerifyState::Failed
+    };
+
     let verify_state = SnapshotVerifyState {
         state: verify_result,
         upid,
@@ -500,6 +866,56 @@ pub fn verify_backup_group(
     Ok(errors)
 }
 
+/// List the groups to verify, errors on iterating single groups are logged and added to
+/// `errors`.
+fn list_verify_groups(
+    verify_worker: &VerifyWorker,
+    ns: &BackupNamespace,
+    max_depth: Option<usize>,
+    owner: Option<&Authid>,
+    errors: &mut Vec<String>,
+) -> Result<Vec<BackupGroup>, Error> {
+    let worker = &verify_worker.worker;
+
+    let owner_filtered = if let Some(owner) = &owner {
+        task_log!(worker, "limiting to backups owned by {}", owner);
+        true
+    } else {
+        false
+    };
+
+    // FIXME: This should probably simply enable recursion (or the call have a recursion parameter)
+    let store = &verify_worker.datastore;
+    let max_depth = max_depth.unwrap_or(pbs_api_types::MAX_NAMESPACE_DEPTH);
+
+    let list = ListAccessibleBackupGroups::new_with_privs(
+        store,
+        ns.clone(),
+        max_depth,
+        Some(PRIV_DATASTORE_VERIFY),
+        Some(PRIV_DATASTORE_BACKUP),
+        owner,
+    )?
+    .filter_map(|group| match group {
+        Ok(group) => Some(group),
+        Err(err) if owner_filtered => {
+            // intentionally not in task log, the user might not see this group!
+            println!("error on iterating groups in ns '{ns}' - {err}");
+            None
+        }
+        Err(err) => {
+            // we don't filter by owner, but we want to log the error
+            task_log!(worker, "error on iterating groups in ns '{ns}' - {err}");
+            errors.push(err.to_string());
+            None
+        }
+    })
+    .filter(|group| !(group.backup_type() == BackupType::Host && group.backup_id() == "benchmark"))
+    .collect();
+
+    Ok(list)
+}
+
 /// Verify all (owned) backups inside a datastore
 ///
 /// Errors are logged to the worker log.
@@ -524,44 +940,8 @@ pub fn verify_all_backups(
         verify_worker.datastore.name()
     );
 
-    let owner_filtered = if let Some(owner) = &owner {
-        task_log!(worker, "limiting to backups owned by {}", owner);
-        true
-    } else {
-        false
-    };
-
-    // FIXME: This should probably simply enable recursion (or the call have a recursion parameter)
-    let store = &verify_worker.datastore;
-    let max_depth = max_depth.unwrap_or(pbs_api_types::MAX_NAMESPACE_DEPTH);
-
-    let mut list = match ListAccessibleBackupGroups::new_with_privs(
-        store,
-        ns.clone(),
-        max_depth,
-        Some(PRIV_DATASTORE_VERIFY),
-        Some(PRIV_DATASTORE_BACKUP),
-        owner,
-    ) {
-        Ok(list) => list
-            .filter_map(|group| match group {
-                Ok(group) => Some(group),
-                Err(err) if owner_filtered => {
-                    // intentionally not in task log, the user might not see this group!
-                    println!("error on iterating groups in ns '{ns}' - {err}");
-                    None
-                }
-                Err(err) => {
-                    // we don't filter by owner, but we want to log the error
-                    task_log!(worker, "error on iterating groups in ns '{ns}' - {err}");
-                    errors.push(err.to_string());
-                    None
-                }
-            })
-            .filter(|group| {
-                !(group.backup_type() == BackupType::Host && group.backup_id() == "benchmark")
-            })
-            .collect::<Vec<BackupGroup>>(),
+    let mut list = match list_verify_groups(verify_worker, &ns, max_depth, owner, &mut errors) {
+        Ok(list) => list,
         Err(err) => {
             task_log!(worker, "unable to list backups: {}", err,);
             return Ok(errors);
@@ -588,6 +968,343 @@ pub fn verify_all_backups(
     Ok(errors)
 }
 
+/// Call `callback` for every index archive of the `snapshots`, together with the position of
+/// the snapshot in the list and whether its manifest passes `filter`. Each snapshot is read while
+/// holding its shared lock, locked snapshots are skipped. Snapshots which cannot be read are
+/// logged and added to `errors`.
+fn for_each_index<F>(
+    verify_worker: &VerifyWorker,
+    snapshots: &[BackupDir],
+    filter: Option<&dyn Fn(&BackupManifest) -> bool>,
+    errors: &mut Vec<String>,
+    mut callback: F,
+) -> Result<(), Error>
+where
+    F: FnMut(usize, bool, &FileInfo, &dyn IndexFile),
+{
+    for (pos, backup_dir) in snapshots.iter().enumerate() {
+        verify_worker.worker.check_abort()?;
+        verify_worker.worker.fail_on_shutdown()?;
+
+        if !backup_dir.full_path().exists() {
+            continue; // pruned in the meantime
+        }
+
+        let _snap_lock = match lock_dir_noblock_shared(
+            &backup_dir.full_path(),
+            "snapshot",
+            "locked by another operation",
+        ) {
+            Ok(lock) => lock,
+            Err(err) => {
+                task_log!(
+                    verify_worker.worker,
+                    "SKIPPED: verify {}:{} - could not acquire snapshot lock: {}",
+                    verify_worker.datastore.name(),
+                    backup_dir.dir(),
+                    err,
+                );
+                continue;
+            }
+        };
+
+        let result: Result<(), Error> = proxmox_lang::try_block!({
+            let (manifest, _) = backup_dir.load_manifest()?;
+            let selected = filter.map(|filter| filter(&manifest)).unwrap_or(true);
+            for info in manifest.files() {
+                if let Some(index) =
+                    open_index(&verify_worker.datastore, backup_dir, &info.filename)?
+                {
+                    callback(pos, selected, info, &*index);
+                }
+            }
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            task_log!(
+                verify_worker.worker,
+                "verify {}:{} - unable to read indexes: {}",
+                verify_worker.datastore.name(),
+                backup_dir.dir(),
+                err,
+            );
+            errors.push(print_ns_and_snapshot(
+                backup_dir.backup_ns(),
+                backup_dir.as_ref(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify a sample of the chunks used by the backups inside a datastore
+///
+/// Chunks are grouped into buckets by the first two bytes of their digest, so every bucket holds
+/// a random sample of about 1/65536 of all chunks. The `buckets` are verified in the given order
+/// until `budget` bytes were read. Only chunks of snapshots passing `filter` are sampled.
+///
+/// Corrupt chunks are fetched from the repair source, if any. Snapshots still using corrupt or
+/// missing chunks are marked as failed, the verify state of all others is left untouched.
+///
+/// Returns
+/// - Ok((checked, failed_dirs)) with the completely checked buckets and the bytes read for
+///   each, and the snapshots using corrupt chunks
+/// - Err(_) if task was aborted
+pub fn verify_chunk_sample(
+    verify_worker: &VerifyWorker,
+    upid: &UPID,
+    ns: BackupNamespace,
+    max_depth: Option<usize>,
+    buckets: &[u16],
+    budget: Option<u64>,
+    filter: Option<&dyn Fn(&BackupManifest) -> bool>,
+) -> Result<(Vec<(u16, u64)>, Vec<String>), Error> {
+    let mut errors = Vec::new();
+    let worker = Arc::clone(&verify_worker.worker);
+
+    task_log!(
+        worker,
+        "verify sample of {} chunk buckets of datastore {}",
+        buckets.len(),
+        verify_worker.datastore.name()
+    );
+
+    let groups = match list_verify_groups(verify_worker, &ns, max_depth, None, &mut errors) {
+        Ok(list) => list,
+        Err(err) => {
+            task_log!(worker, "unable to list backups: {}", err);
+            return Ok((Vec::new(), errors));
+        }
+    };
+
+    let mut snapshots = Vec::new();
+    for group in groups {
+        match group.list_backups() {
+            Ok(list) => snapshots.extend(list.into_iter().map(|info| info.backup_dir)),
+            Err(err) => {
+                task_log!(
+                    worker,
+                    "verify {}, group {} - unable to list backups: {}",
+                    print_store_and_ns(verify_worker.datastore.name(), group.backup_ns()),
+                    group.group(),
+                    err,
+                );
+            }
+        }
+    }
+
+    let bucket_of = |digest: &[u8; 32]| u16::from_be_bytes([digest[0], digest[1]]);
+
+    let mut selected = vec![false; 0x10000];
+    for bucket in buckets {
+        selected[*bucket as usize] = true;
+    }
+
+    struct SampledChunk {
+        size: u64,
+        crypt_mode: CryptMode,
+        /// used by a snapshot passing the filter
+        sampled: bool,
+        /// positions of all snapshots using the chunk
+        users: Vec<usize>,
+    }
+
+    let mut chunks: HashMap<[u8; 32], SampledChunk> = HashMap::new();
+    for_each_index(
+        verify_worker,
+        &snapshots,
+        filter,
+        &mut errors,
+        |pos, selected_snapshot, info, index| {
+            for i in 0..index.index_count() {
+                let chunk_info = index.chunk_info(i).unwrap();
+                if !selected[bucket_of(&chunk_info.digest) as usize] {
+                    continue;
+                }
+                let chunk = chunks
+                    .entry(chunk_info.digest)
+                    .or_insert_with(|| SampledChunk {
+                        size: chunk_info.size(),
+                        crypt_mode: info.chunk_crypt_mode(),
+                        sampled: false,
+                        users: Vec::new(),
+                    });
+                if selected_snapshot && !chunk.sampled {
+                    chunk.size = chunk_info.size();
+                    chunk.crypt_mode = info.chunk_crypt_mode();
+                    chunk.sampled = true;
+                }
+                if chunk.users.last() != Some(&pos) {
+                    chunk.users.push(pos);
+                }
+            }
+        },
+    )?;
+
+    let mut users: HashMap<[u8; 32], Vec<usize>> = HashMap::new();
+    let mut sample: Vec<_> = chunks
+        .into_iter()
+        .filter_map(|(digest, chunk)| {
+            users.insert(digest, chunk.users);
+            chunk
+                .sampled
+                .then_some((digest, (chunk.size, chunk.crypt_mode)))
+        })
+        .collect();
+    sample.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    task_log!(
+        worker,
+        "found {} chunks in {} snapshots",
+        sample.len(),
+        snapshots.len()
+    );
+
+    let chunk_errors = Arc::new(AtomicUsize::new(0));
+    let mut checked = Vec::with_capacity(buckets.len());
+    let mut read_total = 0;
+    let mut last_percentage = 0;
+    for (pos, bucket) in buckets.iter().enumerate() {
+        if let Some(budget) = budget {
+            if read_total >= budget {
+                task_log!(
+                    worker,
+                    "sample budget of {} used up",
+                    HumanByte::from(budget)
+                );
+                break;
+            }
+        }
+
+        let start = sample.partition_point(|(digest, _)| bucket_of(digest) < *bucket);
+        let end = sample.partition_point(|(digest, _)| bucket_of(digest) <= *bucket);
+        let chunks = sample[start..end]
+            .iter()
+            .map(|(digest, (size, crypt_mode))| {
+                let info = ChunkReadInfo {
+                    range: 0..*size,
+                    digest: *digest,
+                };
+                (info, *crypt_mode)
+            });
+
+        let (read_bytes, _) = verify_chunks(verify_worker, chunks, &chunk_errors)?;
+        read_total += read_bytes;
+        checked.push((*bucket, read_bytes));
+
+        let percentage = (pos + 1) * 100 / buckets.len();
+        if percentage != last_percentage {
+            task_log!(worker, "percentage done: {}%", percentage);
+            last_percentage = percentage;
+        }
+    }
+
+    task_log!(
+        worker,
+        "verified {} chunk buckets, read {} ({} errors)",
+        checked.len(),
+        HumanByte::from(read_total),
+        chunk_errors.load(Ordering::SeqCst),
+    );
+
+    let corrupt_chunks = verify_worker.corrupt_chunks.lock().unwrap().clone();
+    if corrupt_chunks.is_empty() {
+        return Ok((checked, errors));
+    }
+
+    // only sampled chunks were verified, so the sample knows all snapshots using corrupt ones
+    let mut failed: Vec<usize> = corrupt_chunks
+        .iter()
+        .filter_map(|digest| users.get(digest))
+        .flatten()
+        .copied()
+        .collect();
+    failed.sort_unstable();
+    failed.dedup();
+
+    let verify_state = serde_json::to_value(SnapshotVerifyState {
+        state: VerifyState::Failed,
+        upid: upid.clone(),
+    })?;
+
+    for pos in failed {
+        let backup_dir = &snapshots[pos];
+        if verify_worker.repair_source.is_some() {
+            match repair_snapshot_chunks(verify_worker, backup_dir) {
+                Ok(true) => continue,
+                Ok(false) => (),
+                Err(err) => {
+                    task_log!(
+                        worker,
+                        "repair of {}:{} failed: {}",
+                        verify_worker.datastore.name(),
+                        backup_dir.dir(),
+                        err,
+                    );
+                }
+            }
+        }
+
+        let verify_state = verify_state.clone();
+        if let Err(err) = backup_dir.update_manifest(|manifest| {
+            manifest.unprotected["verify_state"] = verify_state;
+        }) {
+            task_log!(
+                worker,
+                "verify {}:{} - unable to update manifest blob - {}",
+                verify_worker.datastore.name(),
+                backup_dir.dir(),
+                err,
+            );
+        }
+        errors.push(print_ns_and_snapshot(
+            backup_dir.backup_ns(),
+            backup_dir.as_ref(),
+        ));
+    }
+
+    Ok((checked, errors))
+}
+
+/// Fetch good copies of the corrupt chunks used by a snapshot from the repair source.
+///
+/// Returns whether the snapshot does not use any corrupt chunks anymore.
+fn repair_snapshot_chunks(
+    verify_worker: &VerifyWorker,
+    backup_dir: &BackupDir,
+) -> Result<bool, Error> {
+    let (manifest, _) = backup_dir.load_manifest()?;
+    let files: Vec<&FileInfo> = manifest.files().iter().collect();
+
+    if repair_chunks(verify_worker, backup_dir, &files)? == 0 {
+        return Ok(false);
+    }
+
+    let corrupt_chunks = verify_worker.corrupt_chunks.lock().unwrap();
+    for info in files {
+        if let Some(index) = open_index(&verify_worker.datastore, backup_dir, &info.filename)? {
+            if (0..index.index_count())
+                .any(|pos| corrupt_chunks.contains(index.index_digest(pos).unwrap()))
+            {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Check whether the last verification of a snapshot failed.
+pub fn last_verify_failed(manifest: &BackupManifest) -> bool {
+    let raw_verify_state = manifest.unprotected["verify_state"].clone();
+    match serde_json::from_value::<SnapshotVerifyState>(raw_verify_state) {
+        Ok(last_verify) => last_verify.state == VerifyState::Failed,
+        Err(_) => false,
+    }
+}
+
 /// Filter out any snapshot from being (re-)verified where this fn returns false.
 pub fn verify_filter(
     ignore_verified_snapshots: bool,
@@ -93,6 +93,94 @@ async fn garbage_collection_status(param: Value) -> Result<Value, Error> {
     Ok(Value::Null)
 }
 
+#[api(
+   input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+   }
+)]
+/// Start the chunk usage calculation for a specific datastore.
+async fn start_chunk_usage_calculation(param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let store = required_string_param(&param, "store")?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{}/chunk-usage", store);
+
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+   input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+   }
+)]
+/// Show the result of the last chunk usage calculation for a specific datastore.
+async fn chunk_usage_status(param: Value) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let store = required_string_param(&param, "store")?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{}/chunk-usage", store);
+
+    let args = param.get("ns").map(|ns| json!({ "ns": ns }));
+
+    let mut result = client.get(&path, args).await?;
+    let mut data = result["data"].take();
+    let return_type = &api2::admin::datastore::API_METHOD_GET_CHUNK_USAGE.returns;
+
+    let options = default_table_format_options();
+
+    format_and_print_result_full(&mut data, return_type, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+fn chunk_usage_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert(
+            "status",
+            CliCommand::new(&API_METHOD_CHUNK_USAGE_STATUS)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "start",
+            CliCommand::new(&API_METHOD_START_CHUNK_USAGE_CALCULATION)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        );
+
+    cmd_def.into()
+}
+
 fn garbage_collection_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert(
@@ -433,14 +521,17 @@ async fn run() -> Result<(), Error> {
         .insert("user", user_commands())
         .insert("openid", openid_commands())
         .insert("remote", remote_commands())
+        .insert("s3", s3_commands())
         .insert("traffic-control", traffic_control_commands())
         .insert("garbage-collection", garbage_collection_commands())
+        .insert("chunk-usage", chunk_usage_commands())
         .insert("acme", acme_mgmt_cli())
         .insert("cert", cert_mgmt_cli())
         .insert("subscription", subscription_commands())
         .insert("sync-job", sync_job_commands())
         .insert("verify-job", verify_job_commands())
         .insert("prune-job", prune_job_commands())
+        .insert("quota", quota_commands())
         .insert("task", task_mgmt_cli())
         .insert(
             "pull",
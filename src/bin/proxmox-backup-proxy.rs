@@ -20,7 +20,7 @@ use proxmox_sys::linux::procfs::{Loadavg, ProcFsMemInfo, ProcFsNetDev, ProcFsSta
 use proxmox_sys::logrotate::LogRotate;
 use proxmox_sys::{task_log, task_warn};
 
-use pbs_datastore::DataStore;
+use pbs_datastore::{get_datastore_mount_status, DataStore};
 
 use proxmox_rest_server::{
     cleanup_old_tasks, cookie_from_header, rotate_task_log_archive, ApiConfig, RestEnvironment,
@@ -43,8 +43,8 @@ use pbs_buildcfg::configdir;
 use proxmox_time::CalendarEvent;
 
 use pbs_api_types::{
-    Authid, DataStoreConfig, Operation, PruneJobConfig, SyncJobConfig, TapeBackupJobConfig,
-    VerificationJobConfig,
+    Authid, DataStoreConfig, DataStoreMountStatus, Operation, PruneJobConfig, SyncJobConfig,
+    TapeBackupJobConfig, VerificationJobConfig,
 };
 
 use proxmox_rest_server::daemon;
@@ -268,6 +268,15 @@ async fn run() -> Result<(), Error> {
         Ok(Value::Null)
     })?;
 
+    // to run the sync jobs of a removable datastore which just got mounted
+    commando_sock.register_command("datastore-mounted".to_string(), |value| {
+        match value.and_then(|args| args["store"].as_str()) {
+            Some(store) => run_sync_jobs_on_mount(store),
+            None => log::error!("datastore-mounted command without store"),
+        }
+        Ok(Value::Null)
+    })?;
+
     let connections = proxmox_rest_server::connection::AcceptBuilder::with_acceptor(acceptor)
         .debug(debug)
         .rate_limiter_lookup(Arc::new(lookup_rate_limiter))
@@ -404,6 +413,7 @@ async fn run_task_scheduler() {
 
 async fn schedule_tasks() -> Result<(), Error> {
     schedule_datastore_garbage_collection().await;
+    schedule_datastore_chunk_usage().await;
     schedule_datastore_prune_jobs().await;
     schedule_datastore_sync_jobs().await;
     schedule_datastore_verify_jobs().await;
@@ -431,6 +441,10 @@ async fn schedule_datastore_garbage_collection() {
             }
         };
 
+        if get_datastore_mount_status(&store_config) == DataStoreMountStatus::NotMounted {
+            continue; // unplugged removable datastore
+        }
+
         let event_str = match store_config.gc_schedule {
             Some(event_str) => event_str,
             None => continue,
@@ -489,7 +503,7 @@ async fn schedule_datastore_garbage_collection() {
             Err(_) => continue, // could not get lock
         };
 
-        let datastore = match DataStore::lookup_datastore(&store, Some(Operation::Write)) {
+        let datastore = match DataStore::lookup_datastore(&store, Some(Operation::Delete)) {
             Ok(datastore) => datastore,
             Err(err) => {
                 log::warn!("skipping scheduled GC on {store}, could look it up - {err}");
@@ -511,6 +525,102 @@ async fn schedule_datastore_garbage_collection() {
     }
 }
 
+async fn schedule_datastore_chunk_usage() {
+    let config = match pbs_config::datastore::config() {
+        Err(err) => {
+            eprintln!("unable to read datastore config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    for (store, (_, store_config)) in config.sections {
+        let store_config: DataStoreConfig = match serde_json::from_value(store_config) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("datastore config from_value failed - {err}");
+                continue;
+            }
+        };
+
+        if get_datastore_mount_status(&store_config) == DataStoreMountStatus::NotMounted {
+            continue; // unplugged removable datastore
+        }
+
+        let event_str = match store_config.chunk_usage_schedule {
+            Some(event_str) => event_str,
+            None => continue,
+        };
+
+        let event: CalendarEvent = match event_str.parse() {
+            Ok(event) => event,
+            Err(err) => {
+                eprintln!("unable to parse schedule '{event_str}' - {err}");
+                continue;
+            }
+        };
+
+        let worker_type = "chunk-usage";
+
+        let last = match jobstate::last_run_time(worker_type, &store) {
+            Ok(time) => time,
+            Err(err) => {
+                eprintln!("could not get last run time of {worker_type} {store}: {err}");
+                continue;
+            }
+        };
+
+        let next = match event.compute_next_event(last) {
+            Ok(Some(next)) => next,
+            Ok(None) => continue,
+            Err(err) => {
+                eprintln!("compute_next_event for '{event_str}' failed - {err}");
+                continue;
+            }
+        };
+
+        let now = proxmox_time::epoch_i64();
+
+        if next > now {
+            continue;
+        }
+
+        let job = match Job::new(worker_type, &store) {
+            Ok(job) => job,
+            Err(_) => continue, // could not get lock
+        };
+
+        let datastore = match DataStore::lookup_datastore(&store, Some(Operation::Read)) {
+            Ok(datastore) => datastore,
+            Err(err) => {
+                log::warn!("skipping scheduled chunk usage calculation on {store} - {err}");
+                continue;
+            }
+        };
+
+        let auth_id = Authid::root_auth_id();
+
+        if let Err(err) =
+            crate::server::do_chunk_usage_job(job, datastore, auth_id, Some(event_str), false)
+        {
+            eprintln!("unable to start chunk usage calculation on datastore {store} - {err}");
+        }
+    }
+}
+
+/// Returns true if `store` is a removable datastore whose device is currently unplugged.
+///
+/// Jobs on such datastores are skipped instead of failing, and catch up once it is mounted again.
+fn is_unmounted_datastore(store: &str) -> bool {
+    let config: DataStoreConfig = match pbs_config::datastore::config()
+        .and_then(|(config, _digest)| config.lookup("datastore", store))
+    {
+        Ok(config) => config,
+        Err(_) => return false, // let the job report the actual error
+    };
+    get_datastore_mount_status(&config) == DataStoreMountStatus::NotMounted
+}
+
 async fn schedule_datastore_prune_jobs() {
     let config = match pbs_config::prune::config() {
         Err(err) => {
@@ -536,6 +646,10 @@ async fn schedule_datastore_prune_jobs() {
             continue; // no 'keep' values set, keep all
         }
 
+        if is_unmounted_datastore(&job_config.store) {
+            continue;
+        }
+
         let worker_type = "prunejob";
         let auth_id = Authid::root_auth_id().clone();
         if check_schedule(worker_type, &job_config.schedule, &job_id) {
@@ -579,6 +693,10 @@ async fn schedule_datastore_sync_jobs() {
             None => continue,
         };
 
+        if is_unmounted_datastore(&job_config.store) {
+            continue;
+        }
+
         let worker_type = "syncjob";
         if check_schedule(worker_type, &event_str, &job_id) {
             let job = match Job::new(worker_type, &job_id) {
@@ -594,6 +712,40 @@ async fn schedule_datastore_sync_jobs() {
     }
 }
 
+fn run_sync_jobs_on_mount(store: &str) {
+    let config = match pbs_config::sync::config() {
+        Err(err) => {
+            log::error!("unable to read sync job config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    for (job_id, (_, job_config)) in config.sections {
+        let job_config: SyncJobConfig = match serde_json::from_value(job_config) {
+            Ok(c) => c,
+            Err(err) => {
+                log::error!("sync job config from_value failed - {err}");
+                continue;
+            }
+        };
+
+        if job_config.store != store || !job_config.run_on_mount.unwrap_or(false) {
+            continue;
+        }
+
+        let job = match Job::new("syncjob", &job_id) {
+            Ok(job) => job,
+            Err(_) => continue, // could not get lock
+        };
+
+        let auth_id = Authid::root_auth_id().clone();
+        if let Err(err) = do_sync_job(job, job_config, &auth_id, None, false) {
+            log::error!("unable to start datastore sync job {job_id} on mount - {err}");
+        }
+    }
+}
+
 async fn schedule_datastore_verify_jobs() {
     let config = match pbs_config::verify::config() {
         Err(err) => {
@@ -615,6 +767,10 @@ async fn schedule_datastore_verify_jobs() {
             None => continue,
         };
 
+        if is_unmounted_datastore(&job_config.store) {
+            continue;
+        }
+
         let worker_type = "verificationjob";
         let auth_id = Authid::root_auth_id().clone();
         if check_schedule(worker_type, &event_str, &job_id) {
@@ -651,6 +807,10 @@ async fn schedule_tape_backup_jobs() {
             None => continue,
         };
 
+        if is_unmounted_datastore(&job_config.setup.store) {
+            continue;
+        }
+
         let worker_type = "tape-backup-job";
         let auth_id = Authid::root_auth_id().clone();
         if check_schedule(worker_type, &event_str, &job_id) {
@@ -1088,6 +1248,9 @@ fn collect_disk_stats_sync() -> (DiskStat, Vec<DiskStat>) {
                 {
                     continue;
                 }
+                if get_datastore_mount_status(&config) == DataStoreMountStatus::NotMounted {
+                    continue;
+                }
                 let path = std::path::Path::new(&config.path);
                 datastores.push(gather_disk_stats(disk_manager.clone(), path, &config.name));
             }
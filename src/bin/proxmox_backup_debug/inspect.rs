@@ -17,15 +17,22 @@ use proxmox_schema::api;
 use pbs_client::tools::key_source::get_encryption_key_password;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::file_formats::{
-    COMPRESSED_BLOB_MAGIC_1_0, DYNAMIC_SIZED_CHUNK_INDEX_1_0, ENCRYPTED_BLOB_MAGIC_1_0,
-    ENCR_COMPR_BLOB_MAGIC_1_0, FIXED_SIZED_CHUNK_INDEX_1_0, UNCOMPRESSED_BLOB_MAGIC_1_0,
+    COMPRESSED_BLOB_MAGIC_1_0, DICT_COMPR_BLOB_MAGIC_1_0, DYNAMIC_SIZED_CHUNK_INDEX_1_0,
+    ENCRYPTED_BLOB_MAGIC_1_0, ENCR_COMPR_BLOB_MAGIC_1_0, FIXED_SIZED_CHUNK_INDEX_1_0,
+    UNCOMPRESSED_BLOB_MAGIC_1_0,
 };
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
+use pbs_datastore::zstd_dictionary::{
+    lookup_dictionary, register_dictionary, ZstdDictionary, DICTIONARY_DIR,
+};
 use pbs_datastore::DataBlob;
 use pbs_key_config::load_and_decrypt_key;
 use pbs_tools::crypt_config::CryptConfig;
 
+/// Name the zstd dictionaries of the inspected datastore are registered with.
+const DICTIONARY_STORE: &str = "inspect";
+
 /// Decodes a blob and writes its content either to stdout or into a file
 fn decode_blob(
     mut output_path: Option<&Path>,
@@ -48,8 +55,42 @@ fn decode_blob(
         _ => output_path,
     };
 
-    crate::outfile_or_stdout(output_path)?
-        .write_all(blob.decode(crypt_conf_opt, digest)?.as_slice())?;
+    crate::outfile_or_stdout(output_path)?.write_all(
+        blob.decode_for_store(DICTIONARY_STORE, crypt_conf_opt, digest)?
+            .as_slice(),
+    )?;
+    Ok(())
+}
+
+/// Registers the zstd dictionary `blob` was compressed with, if any, so that it can be decoded.
+///
+/// Without `dictionary_dir`, the dictionary directory of the datastore containing `path` is used.
+fn register_blob_dictionary(
+    blob: &DataBlob,
+    path: &Path,
+    dictionary_dir: Option<&Path>,
+) -> Result<(), Error> {
+    let id = match blob.zstd_dictionary_id() {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    if lookup_dictionary(DICTIONARY_STORE, id).is_some() {
+        return Ok(());
+    }
+
+    let dir = match dictionary_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => path
+            .canonicalize()?
+            .ancestors()
+            .map(|dir| dir.join(DICTIONARY_DIR))
+            .find(|dir| dir.is_dir())
+            .ok_or_else(|| {
+                format_err!("no zstd dictionary directory found for {path:?}, please specify one")
+            })?,
+    };
+
+    register_dictionary(DICTIONARY_STORE, ZstdDictionary::load(&dir, id)?)?;
     Ok(())
 }
 
@@ -86,6 +127,11 @@ fn decode_blob(
                 optional: true,
                 default: true,
             },
+            "zstd-dictionary-dir": {
+                description: "Directory containing the zstd dictionaries, defaults to the one of the datastore containing the file.",
+                type: String,
+                optional: true,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -101,6 +147,7 @@ fn inspect_chunk(
     decode: Option<String>,
     keyfile: Option<String>,
     use_filename_as_digest: bool,
+    zstd_dictionary_dir: Option<String>,
     param: Value,
 ) -> Result<(), Error> {
     let output_format = get_output_format(&param);
@@ -172,6 +219,11 @@ fn inspect_chunk(
     };
 
     if decode_output_path.is_some() {
+        register_blob_dictionary(
+            &blob,
+            chunk_path,
+            zstd_dictionary_dir.as_ref().map(Path::new),
+        )?;
         decode_blob(
             decode_output_path,
             key_file_path,
@@ -186,7 +238,7 @@ fn inspect_chunk(
         blob.verify_crc().map_or("BAD", |_| "OK")
     );
 
-    let val = match referenced_by {
+    let mut val = match referenced_by {
         Some(references) => json!({
             "crc": crc_status,
             "encryption": blob.crypt_mode()?,
@@ -197,10 +249,16 @@ fn inspect_chunk(
              "encryption": blob.crypt_mode()?,
         }),
     };
+    if let Some(id) = blob.zstd_dictionary_id() {
+        val["zstd-dictionary"] = format!("{id:08x}").into();
+    }
 
     if output_format == "text" {
         println!("CRC: {}", val["crc"]);
         println!("encryption: {}", val["encryption"]);
+        if let Some(id) = val["zstd-dictionary"].as_str() {
+            println!("zstd dictionary: {}", id);
+        }
         if let Some(refs) = val["referenced-by"].as_array() {
             println!("referenced by:");
             for reference in refs {
@@ -230,6 +288,11 @@ fn inspect_chunk(
                 type: String,
                 optional: true,
             },
+            "zstd-dictionary-dir": {
+                description: "Directory containing the zstd dictionaries, defaults to the one of the datastore containing the file.",
+                type: String,
+                optional: true,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -242,11 +305,13 @@ fn inspect_file(
     file: String,
     decode: Option<String>,
     keyfile: Option<String>,
+    zstd_dictionary_dir: Option<String>,
     param: Value,
 ) -> Result<(), Error> {
     let output_format = get_output_format(&param);
 
-    let mut file = File::open(Path::new(&file))?;
+    let path = Path::new(&file);
+    let mut file = File::open(path)?;
     let mut magic = [0; 8];
     file.read_exact(&mut magic)?;
     file.seek(SeekFrom::Start(0))?;
@@ -254,29 +319,42 @@ fn inspect_file(
         UNCOMPRESSED_BLOB_MAGIC_1_0
         | COMPRESSED_BLOB_MAGIC_1_0
         | ENCRYPTED_BLOB_MAGIC_1_0
-        | ENCR_COMPR_BLOB_MAGIC_1_0 => {
+        | ENCR_COMPR_BLOB_MAGIC_1_0
+        | DICT_COMPR_BLOB_MAGIC_1_0 => {
             let data_blob = DataBlob::load_from_reader(&mut file)?;
             let key_file_path = keyfile.as_ref().map(Path::new);
 
             let decode_output_path = decode.as_ref().map(Path::new);
 
             if decode_output_path.is_some() {
+                register_blob_dictionary(
+                    &data_blob,
+                    path,
+                    zstd_dictionary_dir.as_ref().map(Path::new),
+                )?;
                 decode_blob(decode_output_path, key_file_path, None, &data_blob)?;
             }
 
             let crypt_mode = data_blob.crypt_mode()?;
-            json!({
+            let mut val = json!({
                 "encryption": crypt_mode,
                 "size": data_blob.raw_size(),
-            })
+            });
+            if let Some(id) = data_blob.zstd_dictionary_id() {
+                val["zstd-dictionary"] = format!("{id:08x}").into();
+            }
+            val
         }
         FIXED_SIZED_CHUNK_INDEX_1_0 | DYNAMIC_SIZED_CHUNK_INDEX_1_0 => {
+            let mut chunker = None;
             let index: Box<dyn IndexFile> = match magic {
                 FIXED_SIZED_CHUNK_INDEX_1_0 => {
                     Box::new(FixedIndexReader::new(file)?) as Box<dyn IndexFile>
                 }
                 DYNAMIC_SIZED_CHUNK_INDEX_1_0 => {
-                    Box::new(DynamicIndexReader::new(file)?) as Box<dyn IndexFile>
+                    let index = DynamicIndexReader::new(file)?;
+                    chunker = index.chunker;
+                    Box::new(index) as Box<dyn IndexFile>
                 }
                 _ => bail!(format_err!("This is technically not possible")),
             };
@@ -293,11 +371,21 @@ fn inspect_file(
                 chunk_digests.insert(hex::encode(digest));
             }
 
-            json!({
+            let mut val = json!({
                 "size": index.index_size(),
                 "ctime": ctime_str,
                 "chunk-digests": chunk_digests
-            })
+            });
+
+            if let Some(chunker) = chunker {
+                val["chunker"] = json!({
+                    "type": chunker.ty,
+                    "min": chunker.min,
+                    "avg": chunker.avg,
+                    "max": chunker.max,
+                });
+            }
+            val
         }
         _ => bail!(format_err!(
             "Only .blob, .fidx and .didx files may be inspected"
@@ -309,9 +397,21 @@ fn inspect_file(
         if let Some(encryption) = val["encryption"].as_str() {
             println!("encryption: {}", encryption);
         }
+        if let Some(id) = val["zstd-dictionary"].as_str() {
+            println!("zstd dictionary: {}", id);
+        }
         if let Some(ctime) = val["ctime"].as_str() {
             println!("creation time: {}", ctime);
         }
+        if let Some(chunker) = val["chunker"].as_object() {
+            println!(
+                "chunker: {} (min {}, avg {}, max {})",
+                chunker["type"].as_str().unwrap_or("unknown"),
+                chunker["min"],
+                chunker["avg"],
+                chunker["max"]
+            );
+        }
         if let Some(chunks) = val["chunk-digests"].as_array() {
             println!("chunks:");
             for chunk in chunks {
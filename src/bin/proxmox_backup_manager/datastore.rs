@@ -1,11 +1,21 @@
-use anyhow::Error;
+use std::path::Path;
+
+use anyhow::{bail, format_err, Error};
 use serde_json::Value;
 
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
+use proxmox_sys::fs::file_get_contents;
+use proxmox_sys::linux::tty;
 
-use pbs_api_types::{DataStoreConfig, DATASTORE_SCHEMA, PROXMOX_CONFIG_DIGEST_SCHEMA};
+use pbs_api_types::{
+    DataStoreConfig, DataStoreConfigUpdater, DatastoreEncryptionConfig, DatastoreKeySealing,
+    BACKING_DEVICE_SCHEMA, DATASTORE_SCHEMA, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    ZSTD_DICTIONARY_SIZE_SCHEMA,
+};
 use pbs_client::view_task_result;
+use pbs_datastore::at_rest;
+use pbs_tools::json::required_string_param;
 
 use proxmox_backup::api2;
 use proxmox_backup::client_helpers::connect_to_localhost;
@@ -99,6 +109,48 @@ async fn create_datastore(mut param: Value) -> Result<Value, Error> {
     Ok(Value::Null)
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: DATASTORE_SCHEMA,
+            },
+            update: {
+                type: DataStoreConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: api2::config::datastore::DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+)]
+/// Update datastore config.
+async fn update_datastore(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let info = &api2::config::datastore::API_METHOD_UPDATE_DATASTORE;
+    let result = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    // adding chunk shards runs in a worker
+    if let Some(upid) = result.as_str() {
+        crate::wait_for_local_worker(upid).await?;
+    }
+
+    Ok(())
+}
+
 #[api(
     protected: true,
     input: {
@@ -139,6 +191,334 @@ async fn delete_datastore(mut param: Value, rpcenv: &mut dyn RpcEnvironment) ->
     Ok(())
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Move all chunks of a sharded datastore to the shard they belong to.
+async fn rebalance_chunks(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = required_string_param(&param, "store")?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/rebalance-chunks");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "max-size": {
+                schema: ZSTD_DICTIONARY_SIZE_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Train a zstd dictionary on the small unencrypted chunks and blobs of a datastore.
+async fn train_zstd_dictionary(store: String, mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/train-zstd-dictionary");
+    let result = client.post(&path, Some(param)).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Encrypt the chunks and snapshot files a datastore stored before at-rest encryption got set
+/// up.
+async fn encrypt_existing_data(store: String, mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/encrypt-existing-data");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            keyfile: {
+                description: "Seal the datastore key with this key file instead of a passphrase.",
+                type: String,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Set up at-rest encryption of the chunks and snapshot files of a datastore. Data already
+/// stored stays unencrypted until it gets encrypted with 'encrypt-existing-data'.
+fn encryption_init(store: String, keyfile: Option<String>) -> Result<(), Error> {
+    let _lock = pbs_config::datastore::lock_config()?;
+
+    let (mut config, _digest) = pbs_config::datastore::config()?;
+    let mut store_config: DataStoreConfig = config.lookup("datastore", &store)?;
+
+    if store_config.encryption.is_some() {
+        bail!("datastore '{store}' already has at-rest encryption set up");
+    }
+
+    let (secret, encryption) = match keyfile {
+        Some(keyfile) => {
+            let keyfile = std::fs::canonicalize(keyfile)?;
+            let secret = file_get_contents(&keyfile)?;
+            if secret.len() < 32 {
+                bail!("key file {keyfile:?} needs to contain at least 32 bytes");
+            }
+            let keyfile = keyfile
+                .to_str()
+                .ok_or_else(|| format_err!("non-utf8 paths not supported"))?;
+            (secret, format!("sealing=keyfile,keyfile={keyfile}"))
+        }
+        None => {
+            if !tty::stdin_isatty() {
+                bail!("no password input mechanism available");
+            }
+            let passphrase = tty::read_and_verify_password("Datastore Key Passphrase: ")?;
+            (passphrase, "sealing=passphrase".to_string())
+        }
+    };
+    let sealing = DatastoreEncryptionConfig::parse(&encryption)?
+        .sealing
+        .unwrap_or_default();
+
+    let base = Path::new(&store_config.path);
+    let fingerprint = at_rest::create_sealed_key(base, &secret)?;
+    if sealing == DatastoreKeySealing::Passphrase {
+        at_rest::unlock(&store, base, &secret)?;
+    }
+
+    store_config.encryption = Some(encryption);
+    config.set_data(&store, "datastore", &store_config)?;
+    pbs_config::datastore::save_config(&config)?;
+
+    println!("Datastore key fingerprint: {fingerprint}");
+    println!(
+        "Data already stored in the datastore is still unencrypted, encrypt it with \
+        'proxmox-backup-manager datastore encrypt-existing-data {store}'"
+    );
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+)]
+/// Unlock a datastore whose at-rest encryption key is sealed with a passphrase. Required after
+/// every boot.
+fn unlock_datastore(store: String) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let store_config: DataStoreConfig = config.lookup("datastore", &store)?;
+
+    let encryption = match store_config.encryption.as_deref() {
+        Some(encryption) => DatastoreEncryptionConfig::parse(encryption)?,
+        None => bail!("datastore '{store}' has no at-rest encryption set up"),
+    };
+    if encryption.sealing.unwrap_or_default() != DatastoreKeySealing::Passphrase {
+        bail!("datastore key of '{store}' is sealed with a key file, no need to unlock it");
+    }
+
+    if !tty::stdin_isatty() {
+        bail!("no password input mechanism available");
+    }
+    let passphrase = tty::read_password("Datastore Key Passphrase: ")?;
+
+    let fingerprint = at_rest::unlock(&store, Path::new(&store_config.path), &passphrase)?;
+
+    println!("Unlocked datastore '{store}', key fingerprint: {fingerprint}");
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Refresh the local cache of a datastore with S3 backend from the bucket.
+async fn s3_refresh(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = required_string_param(&param, "store")?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/s3-refresh");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Mount a removable datastore.
+async fn mount_datastore(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = required_string_param(&param, "store")?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/mount");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Unmount a removable datastore.
+async fn unmount_datastore(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let store = required_string_param(&param, "store")?;
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/unmount");
+    let result = client.post(&path, None).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            uuid: {
+                schema: BACKING_DEVICE_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Mount all removable datastores backed by the device with the given filesystem UUID.
+///
+/// This gets called by udev when a device appears.
+async fn uuid_mount(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let uuid = required_string_param(&param, "uuid")?;
+
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let datastores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+
+    let client = connect_to_localhost()?;
+
+    for datastore in datastores {
+        if datastore.backing_device.as_deref() != Some(uuid) {
+            continue;
+        }
+
+        let path = format!("api2/json/admin/datastore/{}/mount", datastore.name);
+        let result = client.post(&path, None).await?;
+
+        view_task_result(&client, result, &output_format).await?;
+    }
+
+    Ok(Value::Null)
+}
+
 pub fn datastore_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_LIST_DATASTORES))
@@ -154,7 +534,7 @@ pub fn datastore_commands() -> CommandLineInterface {
         )
         .insert(
             "update",
-            CliCommand::new(&api2::config::datastore::API_METHOD_UPDATE_DATASTORE)
+            CliCommand::new(&API_METHOD_UPDATE_DATASTORE)
                 .arg_param(&["name"])
                 .completion_cb("name", pbs_config::datastore::complete_datastore_name)
                 .completion_cb(
@@ -164,6 +544,10 @@ pub fn datastore_commands() -> CommandLineInterface {
                 .completion_cb(
                     "prune-schedule",
                     pbs_config::datastore::complete_calendar_event,
+                )
+                .completion_cb(
+                    "chunk-usage-schedule",
+                    pbs_config::datastore::complete_calendar_event,
                 ),
         )
         .insert(
@@ -171,6 +555,59 @@ pub fn datastore_commands() -> CommandLineInterface {
             CliCommand::new(&API_METHOD_DELETE_DATASTORE)
                 .arg_param(&["name"])
                 .completion_cb("name", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "mount",
+            CliCommand::new(&API_METHOD_MOUNT_DATASTORE)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "unmount",
+            CliCommand::new(&API_METHOD_UNMOUNT_DATASTORE)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "uuid-mount",
+            CliCommand::new(&API_METHOD_UUID_MOUNT).arg_param(&["uuid"]),
+        )
+        .insert(
+            "s3-refresh",
+            CliCommand::new(&API_METHOD_S3_REFRESH)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "rebalance-chunks",
+            CliCommand::new(&API_METHOD_REBALANCE_CHUNKS)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "train-zstd-dictionary",
+            CliCommand::new(&API_METHOD_TRAIN_ZSTD_DICTIONARY)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "encryption-init",
+            CliCommand::new(&API_METHOD_ENCRYPTION_INIT)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("keyfile", complete_file_name),
+        )
+        .insert(
+            "encrypt-existing-data",
+            CliCommand::new(&API_METHOD_ENCRYPT_EXISTING_DATA)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "unlock",
+            CliCommand::new(&API_METHOD_UNLOCK_DATASTORE)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
         );
 
     cmd_def.into()
@@ -14,6 +14,8 @@ mod network;
 pub use network::*;
 mod prune;
 pub use prune::*;
+mod quota;
+pub use quota::*;
 mod remote;
 pub use remote::*;
 mod sync;
@@ -32,3 +34,5 @@ mod openid;
 pub use openid::*;
 mod traffic_control;
 pub use traffic_control::*;
+mod s3;
+pub use s3::*;
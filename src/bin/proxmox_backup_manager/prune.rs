@@ -44,7 +44,8 @@ fn list_prune_jobs(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Valu
         .column(ColumnConfig::new("keep-daily"))
         .column(ColumnConfig::new("keep-weekly"))
         .column(ColumnConfig::new("keep-monthly"))
-        .column(ColumnConfig::new("keep-yearly"));
+        .column(ColumnConfig::new("keep-yearly"))
+        .column(ColumnConfig::new("keep-within"));
 
     format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
 
@@ -240,6 +241,11 @@ pub(crate) fn update_to_prune_jobs_config() -> Result<(), Error> {
             continue;
         }
 
+        if let Err(err) = proxmox_backup::server::check_keep_options(&options.keep) {
+            eprintln!("dropping invalid prune job of datastore '{store}' in datastore.cfg - {err}");
+            continue;
+        }
+
         let prune_config = PruneJobConfig {
             id: id.clone(),
             store: store.clone(),
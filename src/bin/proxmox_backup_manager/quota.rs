@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{QuotaConfig, QUOTA_ID_SCHEMA};
+use pbs_config::quota;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List all quotas
+fn list_quotas(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::quota::API_METHOD_LIST_QUOTAS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("group"))
+        .column(ColumnConfig::new("max-logical-size"))
+        .column(ColumnConfig::new("max-unique-size"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: QUOTA_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show quota configuration
+fn show_quota(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::quota::API_METHOD_READ_QUOTA;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn quota_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_QUOTAS))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_QUOTA)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::quota::complete_quota_id),
+        )
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::quota::API_METHOD_CREATE_QUOTA)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::quota::complete_quota_id)
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", complete_quota_local_datastore_namespace),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::quota::API_METHOD_UPDATE_QUOTA)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::quota::complete_quota_id)
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", complete_quota_local_datastore_namespace),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::quota::API_METHOD_DELETE_QUOTA)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::quota::complete_quota_id),
+        );
+
+    cmd_def.into()
+}
+
+// shell completion helper
+fn complete_quota_local_datastore_namespace(
+    _arg: &str,
+    param: &HashMap<String, String>,
+) -> Vec<String> {
+    let mut list = Vec::new();
+    let mut rpcenv = CliEnvironment::new();
+    rpcenv.set_auth_id(Some(String::from("root@pam")));
+
+    let store = param.get("store").map(|r| r.to_owned()).or_else(|| {
+        let id = param.get("id")?;
+        get_quota(id).ok().map(|quota| quota.store)
+    });
+
+    if let Some(store) = store {
+        if let Ok(data) =
+            crate::api2::admin::namespace::list_namespaces(store, None, None, &mut rpcenv)
+        {
+            for item in data {
+                list.push(item.ns.name());
+            }
+        }
+    }
+
+    list
+}
+
+fn get_quota(id: &str) -> Result<QuotaConfig, Error> {
+    let (config, _digest) = quota::config()?;
+
+    config.lookup("quota", id)
+}
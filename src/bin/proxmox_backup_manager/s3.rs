@@ -0,0 +1,100 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::S3_CLIENT_ID_SCHEMA;
+
+use proxmox_backup::api2;
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List configured S3 clients.
+fn list_s3_clients(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::s3::API_METHOD_LIST_S3_CLIENTS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("id"))
+        .column(ColumnConfig::new("endpoint"))
+        .column(ColumnConfig::new("region"))
+        .column(ColumnConfig::new("access-key"))
+        .column(ColumnConfig::new("fingerprint"))
+        .column(ColumnConfig::new("comment"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: S3_CLIENT_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show S3 client configuration
+fn show_s3_client(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::s3::API_METHOD_READ_S3_CLIENT;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn s3_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_S3_CLIENTS))
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_S3_CLIENT)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::s3::complete_s3_client_id),
+        )
+        .insert(
+            "create",
+            CliCommand::new(&api2::config::s3::API_METHOD_CREATE_S3_CLIENT).arg_param(&["id"]),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&api2::config::s3::API_METHOD_UPDATE_S3_CLIENT)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::s3::complete_s3_client_id),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::s3::API_METHOD_DELETE_S3_CLIENT)
+                .arg_param(&["id"])
+                .completion_cb("id", pbs_config::s3::complete_s3_client_id),
+        );
+
+    cmd_def.into()
+}
@@ -103,7 +103,9 @@ pub fn verify_job_commands() -> CommandLineInterface {
                 .arg_param(&["id"])
                 .completion_cb("id", pbs_config::verify::complete_verification_job_id)
                 .completion_cb("schedule", pbs_config::datastore::complete_calendar_event)
-                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("repair-remote", pbs_config::remote::complete_remote_name)
+                .completion_cb("repair-drive", pbs_config::drive::complete_drive_name),
         )
         .insert(
             "update",
@@ -112,7 +114,9 @@ pub fn verify_job_commands() -> CommandLineInterface {
                 .completion_cb("id", pbs_config::verify::complete_verification_job_id)
                 .completion_cb("schedule", pbs_config::datastore::complete_calendar_event)
                 .completion_cb("store", pbs_config::datastore::complete_datastore_name)
-                .completion_cb("remote-store", crate::complete_remote_datastore_name),
+                .completion_cb("remote-store", crate::complete_remote_datastore_name)
+                .completion_cb("repair-remote", pbs_config::remote::complete_remote_name)
+                .completion_cb("repair-drive", pbs_config::drive::complete_drive_name),
         )
         .insert(
             "run",
@@ -10,6 +10,7 @@ use pbs_api_types::{
     TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
 };
 
+use pbs_client::tools::key_provider::KeyProvider;
 use pbs_datastore::paperkey::{generate_paper_key, PaperkeyFormat};
 use pbs_key_config::KeyConfig;
 
@@ -190,6 +191,12 @@ fn change_passphrase(
                 type: String,
                 optional: true,
             },
+            "key-provider": {
+                description: "Import key from the key provider configured via PBS_KEY_PROVIDER.",
+                type: Boolean,
+                optional: true,
+                default: false,
+            },
         },
     },
 )]
@@ -198,6 +205,7 @@ async fn restore_key(
     mut param: Value,
     key: Option<String>,
     key_file: Option<String>,
+    key_provider: bool,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
     let drive_passed = param.get("drive").is_some();
@@ -205,7 +213,9 @@ async fn restore_key(
         bail!("cannot have both 'drive' and 'key(-file)' parameter set!");
     } else if key.is_some() && key_file.is_some() {
         bail!("cannot have both 'key' and 'key-file' parameter set!");
-    } else if !drive_passed && key.is_none() && key_file.is_none() {
+    } else if key_provider && (drive_passed || key.is_some() || key_file.is_some()) {
+        bail!("cannot have 'key-provider' together with 'drive' or 'key(-file)' parameter set!");
+    } else if !drive_passed && key.is_none() && key_file.is_none() && !key_provider {
         bail!("one of either 'drive' or 'key' parameter must be set!");
     }
     if !tty::stdin_isatty() {
@@ -220,9 +230,13 @@ async fn restore_key(
         }
     }
 
-    let key = match key_file {
-        Some(key_file) => Some(proxmox_sys::fs::file_read_string(key_file)?),
-        None => key,
+    let key = match (key_file, key_provider) {
+        (Some(key_file), _) => Some(proxmox_sys::fs::file_read_string(key_file)?),
+        (None, true) => match KeyProvider::from_env()? {
+            Some(provider) => Some(String::from_utf8(provider.get_key()?)?),
+            None => bail!("no key provider configured, set PBS_KEY_PROVIDER"),
+        },
+        (None, false) => key,
     };
     if let Some(data) = key {
         let key = if serde_json::from_str::<KeyConfig>(&data).is_ok() {
@@ -0,0 +1,54 @@
+use anyhow::Error;
+use std::sync::Arc;
+
+use proxmox_sys::task_log;
+
+use pbs_api_types::Authid;
+use pbs_datastore::DataStore;
+use proxmox_rest_server::WorkerTask;
+
+use crate::server::jobstate::Job;
+
+/// Runs a chunk usage calculation job.
+pub fn do_chunk_usage_job(
+    mut job: Job,
+    datastore: Arc<DataStore>,
+    auth_id: &Authid,
+    schedule: Option<String>,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let store = datastore.name().to_string();
+
+    let worker_type = job.jobtype().to_string();
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        Some(store.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "calculating chunk usage of store {}", store);
+            if let Some(event_str) = schedule {
+                task_log!(worker, "task triggered by schedule '{}'", event_str);
+            }
+
+            // quota usage is refreshed as well, so listings don't have to read every index
+            let result = pbs_config::quota::config()
+                .and_then(|(config, _digest)| config.convert_to_typed_array("quota"))
+                .and_then(|quotas| {
+                    datastore.calculate_chunk_usage(&*worker, worker.upid(), quotas)
+                });
+
+            let status = worker.create_state(&result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
+            }
+
+            result
+        },
+    )?;
+
+    Ok(upid_str)
+}
@@ -99,6 +99,13 @@ fn get_path(jobtype: &str, jobname: &str) -> PathBuf {
     path
 }
 
+/// Path of an additional, job type specific state file kept next to the statefile of a job
+pub fn get_extra_state_path(jobtype: &str, jobname: &str, extension: &str) -> PathBuf {
+    let mut path = get_path(jobtype, jobname);
+    path.set_extension(extension);
+    path
+}
+
 fn get_lock<P>(path: P) -> Result<BackupLockGuard, Error>
 where
     P: AsRef<Path>,
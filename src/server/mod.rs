@@ -5,7 +5,7 @@
 //! tokio/hyper.
 
 use anyhow::{format_err, Error};
-use serde_json::Value;
+use serde_json::{json, Value};
 
 use proxmox_sys::fs::{create_path, CreateOptions};
 
@@ -22,6 +22,9 @@ pub use prune_job::*;
 mod gc_job;
 pub use gc_job::*;
 
+mod chunk_usage_job;
+pub use chunk_usage_job::*;
+
 mod realm_sync_job;
 pub use realm_sync_job::*;
 
@@ -37,6 +40,8 @@ pub mod auth;
 
 pub(crate) mod pull;
 
+pub(crate) mod push;
+
 pub(crate) async fn reload_proxy_certificate() -> Result<(), Error> {
     let proxy_pid = proxmox_rest_server::read_pid(pbs_buildcfg::PROXMOX_BACKUP_PROXY_PID_FN)?;
     let sock = proxmox_rest_server::ctrl_sock_from_pid(proxy_pid);
@@ -55,6 +60,14 @@ pub(crate) async fn notify_datastore_removed() -> Result<(), Error> {
     Ok(())
 }
 
+pub(crate) async fn notify_datastore_mounted(store: &str) -> Result<(), Error> {
+    let proxy_pid = proxmox_rest_server::read_pid(pbs_buildcfg::PROXMOX_BACKUP_PROXY_PID_FN)?;
+    let sock = proxmox_rest_server::ctrl_sock_from_pid(proxy_pid);
+    let command = json!({ "command": "datastore-mounted", "args": { "store": store } });
+    let _: Value = proxmox_rest_server::send_raw_command(sock, &format!("{command}\n")).await?;
+    Ok(())
+}
+
 /// Create the base run-directory.
 ///
 /// This exists to fixate the permissions for the run *base* directory while allowing intermediate
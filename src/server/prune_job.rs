@@ -2,16 +2,20 @@ use std::sync::Arc;
 
 use anyhow::Error;
 
+use proxmox_schema::param_bail;
+use proxmox_sys::fs::lock_dir_noblock;
 use proxmox_sys::{task_log, task_warn};
 
 use pbs_api_types::{
-    print_store_and_ns, Authid, KeepOptions, Operation, PruneJobOptions, MAX_NAMESPACE_DEPTH,
-    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
+    print_store_and_ns, Authid, HumanByte, KeepOptions, Operation, PruneJobOptions,
+    MAX_NAMESPACE_DEPTH, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
 };
-use pbs_datastore::prune::compute_prune_info;
-use pbs_datastore::DataStore;
+use pbs_datastore::backup_info::BackupGroup;
+use pbs_datastore::prune::{compute_prune_info, load_verify_states};
+use pbs_datastore::{BackupInfo, DataStore};
 use proxmox_rest_server::WorkerTask;
 
+use crate::api2::backup::resume::remove_expired_backups;
 use crate::backup::ListAccessibleBackupGroups;
 use crate::server::jobstate::Job;
 
@@ -46,6 +50,9 @@ pub fn prune_datastore(
         task_log!(worker, "retention options: {rendered_options}");
     }
 
+    // snapshots a dry run would remove, per group, to estimate the reclaimable space
+    let mut removed_groups = Vec::new();
+
     for group in ListAccessibleBackupGroups::new_with_privs(
         &datastore,
         ns,
@@ -56,7 +63,13 @@ pub fn prune_datastore(
     )? {
         let group = group?;
         let ns = group.backup_ns();
-        let list = group.list_backups()?;
+
+        if !dry_run {
+            remove_expired_interrupted_backups(&worker, &group);
+        }
+
+        let mut list = group.list_backups()?;
+        load_verify_states(&mut list, &prune_options.keep);
 
         let mut prune_info = compute_prune_info(list, &prune_options.keep)?;
         prune_info.reverse(); // delete older snapshots first
@@ -68,6 +81,8 @@ pub fn prune_datastore(
             group.backup_id()
         );
 
+        let mut removed = Vec::new();
+
         for (info, mark) in prune_info {
             let keep = keep_all || mark.keep();
             task_log!(
@@ -85,9 +100,70 @@ pub fn prune_datastore(
                     task_warn!(worker, "failed to remove dir {path:?}: {err}");
                 }
             }
+            if !keep && dry_run {
+                removed.push(info);
+            }
+        }
+
+        if !removed.is_empty() {
+            removed_groups.push((format!("{ns}:\"{}\"", group.group()), removed));
         }
     }
 
+    if dry_run && !removed_groups.is_empty() {
+        log_reclaim_estimate(&worker, &datastore, removed_groups)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the interrupted backups of `group` which were kept for resuming, but expired.
+///
+/// Groups with a running backup are skipped, the backup removes them itself.
+fn remove_expired_interrupted_backups(worker: &WorkerTask, group: &BackupGroup) {
+    let _guard = match lock_dir_noblock(
+        &group.full_group_path(),
+        "backup group",
+        "possible running backup",
+    ) {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+
+    match remove_expired_backups(group) {
+        Ok(0) => (),
+        Ok(count) => task_log!(worker, "removed {count} expired interrupted backups"),
+        Err(err) => task_warn!(worker, "removing expired interrupted backups failed: {err}"),
+    }
+}
+
+/// Log how much space a garbage collection would free after a prune, per group and in total.
+pub(crate) fn log_reclaim_estimate(
+    worker: &WorkerTask,
+    datastore: &DataStore,
+    removed_groups: Vec<(String, Vec<BackupInfo>)>,
+) -> Result<(), Error> {
+    task_log!(
+        worker,
+        "Estimating reclaimable space (reading all index files)"
+    );
+
+    let (names, removed): (Vec<String>, Vec<Vec<BackupInfo>>) = removed_groups.into_iter().unzip();
+    let (total, per_group) = datastore.estimate_reclaimable_bytes(&removed, worker)?;
+
+    for (name, bytes) in names.iter().zip(per_group) {
+        task_log!(worker, "group {name} would free {}", HumanByte::from(bytes));
+    }
+    task_log!(
+        worker,
+        "a garbage collection would free about {} in total",
+        HumanByte::from(total)
+    );
+    task_log!(
+        worker,
+        "chunks shared between groups are only included in the total"
+    );
+
     Ok(())
 }
 
@@ -109,6 +185,20 @@ pub(crate) fn cli_prune_options_string(options: &PruneJobOptions) -> String {
     opts.join(" ")
 }
 
+/// Checks combinations of keep options which their schema cannot express.
+pub fn check_keep_options(options: &KeepOptions) -> Result<(), Error> {
+    if options.max_old.is_some() && options.old_age.is_none() {
+        param_bail!("max-old", "'max-old' requires 'old-age' to be set");
+    }
+    if options.keep_last_verified.unwrap_or(false) && !options.keeps_something() {
+        param_bail!(
+            "keep-last-verified",
+            "'keep-last-verified' can only be used in addition to another keep option"
+        );
+    }
+    Ok(())
+}
+
 pub(crate) fn cli_keep_options(opts: &mut Vec<String>, options: &KeepOptions) {
     for (key, keep) in [
         ("last", options.keep_last),
@@ -123,6 +213,21 @@ pub(crate) fn cli_keep_options(opts: &mut Vec<String>, options: &KeepOptions) {
             _ => {}
         };
     }
+    if let Some(keep_within) = &options.keep_within {
+        opts.push(format!("--keep-within {keep_within}"));
+    }
+    if let Some(old_age) = &options.old_age {
+        opts.push(format!("--old-age {old_age}"));
+    }
+    if let Some(max_old) = options.max_old {
+        opts.push(format!("--max-old {max_old}"));
+    }
+    if options.keep_last_verified.unwrap_or(false) {
+        opts.push("--keep-last-verified".to_string());
+    }
+    if options.skip_failed_verify.unwrap_or(false) {
+        opts.push("--skip-failed-verify".to_string());
+    }
 }
 
 pub fn do_prune_job(
@@ -132,7 +237,7 @@ pub fn do_prune_job(
     auth_id: &Authid,
     schedule: Option<String>,
 ) -> Result<String, Error> {
-    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Delete))?;
 
     let worker_type = job.jobtype().to_string();
     let auth_id = auth_id.clone();
@@ -172,3 +277,26 @@ pub fn do_prune_job(
     )?;
     Ok(upid_str)
 }
+
+#[test]
+fn test_check_keep_options() {
+    let mut options = KeepOptions {
+        keep_last_verified: Some(true),
+        ..Default::default()
+    };
+    assert!(!options.keeps_something());
+    assert!(check_keep_options(&options).is_err());
+
+    options.keep_within = Some("14d".to_string());
+    assert!(check_keep_options(&options).is_ok());
+
+    options.keep_within = None;
+    options.keep_daily = Some(7);
+    assert!(check_keep_options(&options).is_ok());
+
+    options.max_old = Some(1);
+    assert!(check_keep_options(&options).is_err());
+
+    options.old_age = Some("1y".to_string());
+    assert!(check_keep_options(&options).is_ok());
+}
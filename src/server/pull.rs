@@ -30,6 +30,7 @@ use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{
     archive_type, ArchiveType, BackupManifest, FileInfo, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME,
 };
+use pbs_datastore::zstd_dictionary::lookup_dictionary;
 use pbs_datastore::{check_backup_owner, DataStore, StoreProgress};
 use pbs_tools::sha::sha256;
 use proxmox_rest_server::WorkerTask;
@@ -145,12 +146,19 @@ async fn pull_index_chunks<I: IndexFile>(
     );
 
     let target2 = target.clone();
+    let source_store = chunk_reader.dictionary_store().to_string();
     let verify_pool = ParallelHandler::new(
         "sync chunk writer",
         4,
         move |(chunk, digest, size): (DataBlob, [u8; 32], u64)| {
             // println!("verify and write {}", hex::encode(&digest));
-            chunk.verify_unencrypted(size as usize, &digest)?;
+            chunk.verify_unencrypted_for_store(&source_store, size as usize, &digest)?;
+            if let Some(id) = chunk.zstd_dictionary_id() {
+                // fetched by the chunk reader for decoding, keep a local copy for reading it
+                let dictionary = lookup_dictionary(&source_store, id)
+                    .ok_or_else(|| format_err!("zstd dictionary {id:08x} not available"))?;
+                target2.insert_zstd_dictionary(&dictionary)?;
+            }
             target2.insert_chunk(&chunk, &digest)?;
             Ok(())
         },
@@ -318,8 +326,18 @@ async fn pull_single_archive(
             tmpfile.seek(SeekFrom::Start(0))?;
             let (csum, size) = sha256(&mut tmpfile)?;
             verify_archive(archive_info, &csum, size)?;
+
+            tmpfile.seek(SeekFrom::Start(0))?;
+            let blob = DataBlob::load_from_reader(&mut tmpfile)?;
+            if let Some(id) = blob.zstd_dictionary_id() {
+                let dictionary = reader.download_zstd_dictionary(id).await?;
+                snapshot.datastore().insert_zstd_dictionary(&dictionary)?;
+            }
         }
     }
+    snapshot
+        .datastore()
+        .encrypt_snapshot_file(&tmp_path, &path)?;
     if let Err(err) = std::fs::rename(&tmp_path, &path) {
         bail!("Atomic rename file {:?} failed - {}", path, err);
     }
@@ -331,6 +349,7 @@ async fn pull_single_archive(
 async fn try_client_log_download(
     worker: &WorkerTask,
     reader: Arc<BackupReader>,
+    datastore: &DataStore,
     path: &std::path::Path,
 ) -> Result<(), Error> {
     let mut tmp_path = path.to_owned();
@@ -344,6 +363,7 @@ async fn try_client_log_download(
 
     // Note: be silent if there is no log - only log successful download
     if let Ok(()) = reader.download(CLIENT_LOG_BLOB_NAME, tmpfile).await {
+        datastore.encrypt_snapshot_file(&tmp_path, path)?;
         if let Err(err) = std::fs::rename(&tmp_path, path) {
             bail!("Atomic rename file {:?} failed - {}", path, err);
         }
@@ -405,9 +425,12 @@ async fn pull_snapshot(
 
     if manifest_name.exists() {
         let manifest_blob = proxmox_lang::try_block!({
-            let mut manifest_file = std::fs::File::open(&manifest_name).map_err(|err| {
-                format_err!("unable to open local manifest {manifest_name:?} - {err}")
-            })?;
+            let mut manifest_file = snapshot
+                .datastore()
+                .open_snapshot_file(&manifest_name)
+                .map_err(|err| {
+                    format_err!("unable to open local manifest {manifest_name:?} - {err}")
+                })?;
 
             let manifest_blob = DataBlob::load_from_reader(&mut manifest_file)?;
             Ok(manifest_blob)
@@ -418,7 +441,8 @@ async fn pull_snapshot(
 
         if manifest_blob.raw_data() == tmp_manifest_blob.raw_data() {
             if !client_log_name.exists() {
-                try_client_log_download(worker, reader, &client_log_name).await?;
+                try_client_log_download(worker, reader, snapshot.datastore(), &client_log_name)
+                    .await?;
             }
             task_log!(worker, "no data changes");
             let _ = std::fs::remove_file(&tmp_manifest_name);
@@ -435,7 +459,7 @@ async fn pull_snapshot(
         if path.exists() {
             match archive_type(&item.filename)? {
                 ArchiveType::DynamicIndex => {
-                    let index = DynamicIndexReader::open(&path)?;
+                    let index = snapshot.datastore().open_dynamic_reader(&path)?;
                     let (csum, size) = index.compute_csum();
                     match manifest.verify_file(&item.filename, &csum, size) {
                         Ok(_) => continue,
@@ -445,7 +469,7 @@ async fn pull_snapshot(
                     }
                 }
                 ArchiveType::FixedIndex => {
-                    let index = FixedIndexReader::open(&path)?;
+                    let index = snapshot.datastore().open_fixed_reader(&path)?;
                     let (csum, size) = index.compute_csum();
                     match manifest.verify_file(&item.filename, &csum, size) {
                         Ok(_) => continue,
@@ -455,7 +479,7 @@ async fn pull_snapshot(
                     }
                 }
                 ArchiveType::Blob => {
-                    let mut tmpfile = std::fs::File::open(&path)?;
+                    let mut tmpfile = snapshot.datastore().open_snapshot_file(&path)?;
                     let (csum, size) = sha256(&mut tmpfile)?;
                     match manifest.verify_file(&item.filename, &csum, size) {
                         Ok(_) => continue,
@@ -485,18 +509,26 @@ async fn pull_snapshot(
         .await?;
     }
 
+    snapshot
+        .datastore()
+        .encrypt_snapshot_file(&tmp_manifest_name, &manifest_name)?;
     if let Err(err) = std::fs::rename(&tmp_manifest_name, &manifest_name) {
         bail!("Atomic rename file {:?} failed - {}", manifest_name, err);
     }
 
     if !client_log_name.exists() {
-        try_client_log_download(worker, reader, &client_log_name).await?;
+        try_client_log_download(worker, reader, snapshot.datastore(), &client_log_name).await?;
     }
 
     snapshot
         .cleanup_unreferenced_files(&manifest)
         .map_err(|err| format_err!("failed to cleanup unreferenced files - {err}"))?;
 
+    proxmox_async::runtime::block_in_place(|| {
+        snapshot.datastore().upload_snapshot_to_backend(snapshot)
+    })
+    .map_err(|err| format_err!("failed to upload snapshot to backend - {err}"))?;
+
     Ok(())
 }
 
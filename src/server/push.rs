@@ -0,0 +1,924 @@
+//! Sync datastore to remote server
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use futures::stream::{self, StreamExt};
+use http::StatusCode;
+use serde_json::json;
+
+use proxmox_router::HttpError;
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    print_store_and_ns, Authid, BackupNamespace, GroupFilter, GroupListItem, NamespaceListItem,
+    Operation, RateLimitConfig, Remote, SnapshotListItem, MAX_NAMESPACE_DEPTH,
+    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
+};
+
+use pbs_client::{
+    BackupRepository, BackupWriter, HttpClient, HttpClientOptions, MergedChunkInfo, UploadOptions,
+};
+use pbs_datastore::data_blob::ChunkInfo;
+use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{
+    archive_type, ArchiveType, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME,
+};
+use pbs_datastore::{BackupInfo, DataStore, SnapshotReader, StoreProgress};
+use proxmox_rest_server::WorkerTask;
+
+use crate::backup::check_ns_privs_full;
+
+/// Parameters for a push operation.
+pub(crate) struct PushParameters {
+    /// Remote that is pushed to
+    remote: Remote,
+    /// Full specification of remote datastore
+    target: BackupRepository,
+    /// Local store that is pushed from
+    store: Arc<DataStore>,
+    /// Local namespace (anchor)
+    ns: BackupNamespace,
+    /// Remote namespace (anchor)
+    remote_ns: BackupNamespace,
+    /// Local user whose privileges are used to access the local datastore
+    local_user: Authid,
+    /// Whether to remove groups and snapshots which exist on the remote, but not locally
+    remove_vanished: bool,
+    /// How many levels of sub-namespaces to push (0 == no recursion, None == maximum recursion)
+    max_depth: Option<usize>,
+    /// Filters for reducing the push scope
+    group_filter: Option<Vec<GroupFilter>>,
+    /// Rate limits for all transfers to `remote`
+    limit: RateLimitConfig,
+    /// zstd dictionaries already uploaded to `remote`
+    uploaded_dictionaries: Mutex<HashSet<u32>>,
+}
+
+impl PushParameters {
+    /// Creates a new instance of `PushParameters`.
+    ///
+    /// `remote` will be dereferenced via [pbs_api_types::RemoteConfig], and combined into a
+    /// [BackupRepository] with `remote_store`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        store: &str,
+        ns: BackupNamespace,
+        remote: &str,
+        remote_store: &str,
+        remote_ns: BackupNamespace,
+        local_user: Authid,
+        remove_vanished: Option<bool>,
+        max_depth: Option<usize>,
+        group_filter: Option<Vec<GroupFilter>>,
+        limit: RateLimitConfig,
+    ) -> Result<Self, Error> {
+        let store = DataStore::lookup_datastore(store, Some(Operation::Read))?;
+
+        if let Some(max_depth) = max_depth {
+            ns.check_max_depth(max_depth)?;
+            remote_ns.check_max_depth(max_depth)?;
+        }
+
+        let (remote_config, _digest) = pbs_config::remote::config()?;
+        let remote: Remote = remote_config.lookup("remote", remote)?;
+
+        let remove_vanished = remove_vanished.unwrap_or(false);
+
+        let target = BackupRepository::new(
+            Some(remote.config.auth_id.clone()),
+            Some(remote.config.host.clone()),
+            remote.config.port,
+            remote_store.to_string(),
+        );
+
+        Ok(Self {
+            remote,
+            target,
+            store,
+            ns,
+            remote_ns,
+            local_user,
+            remove_vanished,
+            max_depth,
+            group_filter,
+            limit,
+            uploaded_dictionaries: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Creates a new [HttpClient] for accessing the [Remote] that is pushed to.
+    pub async fn client(&self) -> Result<HttpClient, Error> {
+        crate::api2::config::remote::remote_client(&self.remote, Some(self.limit.clone())).await
+    }
+
+    fn matches_group_filter(&self, group: &pbs_api_types::BackupGroup) -> bool {
+        match &self.group_filter {
+            Some(filters) => filters.iter().any(|filter| group.matches(filter)),
+            None => true,
+        }
+    }
+}
+
+/// Builds the stream of chunks referenced by `index` for uploading.
+///
+/// Chunks contained in `known_chunks` are only referenced, all others are loaded from the local
+/// datastore and uploaded as they are, so encrypted chunks stay encrypted.
+fn index_chunk_stream<I: IndexFile + Send + 'static>(
+    datastore: Arc<DataStore>,
+    index: I,
+    known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+) -> impl futures::Stream<Item = Result<MergedChunkInfo, Error>> {
+    stream::iter(0..index.index_count()).map(move |pos| {
+        let info = index
+            .chunk_info(pos)
+            .ok_or_else(|| format_err!("index position {pos} out of range"))?;
+        let offset = info.range.start;
+
+        if !known_chunks.lock().unwrap().insert(info.digest) {
+            return Ok(MergedChunkInfo::Known(vec![(offset, info.digest)]));
+        }
+
+        let chunk = proxmox_async::runtime::block_in_place(|| datastore.load_chunk(&info.digest))?;
+
+        Ok(MergedChunkInfo::New(ChunkInfo {
+            chunk,
+            digest: info.digest,
+            chunk_len: info.size(),
+            offset,
+        }))
+    })
+}
+
+/// Uploads the zstd dictionaries of the local datastore not yet sent to the remote.
+///
+/// The remote needs them to accept chunks and blobs compressed with a dictionary.
+async fn upload_zstd_dictionaries(
+    writer: &BackupWriter,
+    params: &PushParameters,
+) -> Result<(), Error> {
+    for id in params.store.list_zstd_dictionaries()? {
+        if params.uploaded_dictionaries.lock().unwrap().contains(&id) {
+            continue;
+        }
+        let dictionary = params.store.zstd_dictionary(id)?;
+        writer
+            .upload_zstd_dictionary(&dictionary)
+            .await
+            .map_err(|err| format_err!("uploading zstd dictionary {id:08x} failed - {err}"))?;
+        params.uploaded_dictionaries.lock().unwrap().insert(id);
+    }
+    Ok(())
+}
+
+/// Registers the chunks of the matching archive of the previous remote snapshot as known.
+///
+/// Errors are ignored, as they only mean that more chunks need to be uploaded.
+async fn register_previous_index(
+    writer: &BackupWriter,
+    archive_name: &str,
+    archive_type: ArchiveType,
+    previous_manifest: Option<&pbs_datastore::manifest::BackupManifest>,
+    known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+) {
+    let manifest = match previous_manifest {
+        Some(manifest) => manifest,
+        None => return,
+    };
+    if manifest.lookup_file_info(archive_name).is_err() {
+        return;
+    }
+
+    let _ = match archive_type {
+        ArchiveType::DynamicIndex => writer
+            .download_previous_dynamic_index(archive_name, manifest, known_chunks)
+            .await
+            .map(|_| ()),
+        ArchiveType::FixedIndex => writer
+            .download_previous_fixed_index(archive_name, manifest, known_chunks)
+            .await
+            .map(|_| ()),
+        ArchiveType::Blob => Ok(()),
+    };
+}
+
+/// Pushes a single snapshot to the remote.
+///
+/// Pushing a snapshot consists of the following steps:
+/// - Open the local snapshot via a [SnapshotReader] (holding a shared lock on it) and start a new
+///   backup on the remote
+/// - Upload the zstd dictionaries not yet sent to the remote
+/// - Register the chunks of the previous remote snapshot as known
+/// - Upload all archives referenced by the manifest, only sending chunks unknown to the remote
+/// - Upload the manifest and client log (if any) and finish the backup
+async fn push_snapshot(
+    worker: &WorkerTask,
+    client: HttpClient,
+    params: &PushParameters,
+    target_ns: &BackupNamespace,
+    snapshot: &pbs_datastore::BackupDir,
+) -> Result<(), Error> {
+    let snapshot_reader = SnapshotReader::new(
+        params.store.clone(),
+        snapshot.backup_ns().clone(),
+        snapshot.dir().clone(),
+    )?;
+    let (manifest, _) = snapshot.load_manifest()?;
+
+    let writer = BackupWriter::start(
+        client,
+        None,
+        params.target.store(),
+        target_ns,
+        snapshot.dir(),
+        false,
+        false,
+        false,
+    )
+    .await?;
+
+    upload_zstd_dictionaries(&writer, params).await?;
+
+    // the manifest is signed or plain, so no crypt config is required to read it
+    let previous_manifest = writer.download_previous_manifest().await.ok();
+
+    let known_chunks = Arc::new(Mutex::new(HashSet::new()));
+
+    for item in manifest.files() {
+        let archive_name = &item.filename;
+        let archive_type = archive_type(archive_name)?;
+
+        register_previous_index(
+            &writer,
+            archive_name,
+            archive_type,
+            previous_manifest.as_ref(),
+            known_chunks.clone(),
+        )
+        .await;
+
+        task_log!(worker, "push archive {}", archive_name);
+
+        let file = snapshot_reader.open_file(archive_name)?;
+        let stats = match archive_type {
+            ArchiveType::DynamicIndex => {
+                let index = DynamicIndexReader::new(file).map_err(|err| {
+                    format_err!("unable to read dynamic index {archive_name:?} - {err}")
+                })?;
+                let (csum, size) = index.compute_csum();
+                let options = UploadOptions {
+                    chunker: index.chunker,
+                    ..UploadOptions::default()
+                };
+                let stream = index_chunk_stream(params.store.clone(), index, known_chunks.clone());
+                writer
+                    .upload_index_chunk_info(archive_name, stream, csum, size, options)
+                    .await?
+            }
+            ArchiveType::FixedIndex => {
+                let index = FixedIndexReader::new(file).map_err(|err| {
+                    format_err!("unable to read fixed index {archive_name:?} - {err}")
+                })?;
+                let (csum, size) = index.compute_csum();
+                let options = UploadOptions {
+                    fixed_size: Some(index.index_bytes()),
+                    ..UploadOptions::default()
+                };
+                let stream = index_chunk_stream(params.store.clone(), index, known_chunks.clone());
+                writer
+                    .upload_index_chunk_info(archive_name, stream, csum, size, options)
+                    .await?
+            }
+            ArchiveType::Blob => writer.upload_blob(file, archive_name).await?,
+        };
+
+        if stats.size != item.size || stats.csum != item.csum {
+            bail!("archive {archive_name:?} does not match its manifest entry");
+        }
+    }
+
+    let manifest_file = snapshot_reader.open_file(MANIFEST_BLOB_NAME)?;
+    writer
+        .upload_blob(manifest_file, MANIFEST_BLOB_NAME)
+        .await?;
+
+    if snapshot_reader
+        .file_list()
+        .iter()
+        .any(|name| name == CLIENT_LOG_BLOB_NAME)
+    {
+        let log_file = snapshot_reader.open_file(CLIENT_LOG_BLOB_NAME)?;
+        writer.upload_blob(log_file, CLIENT_LOG_BLOB_NAME).await?;
+    }
+
+    writer.finish().await?;
+
+    Ok(())
+}
+
+/// Pushes a group according to `params`.
+///
+/// Pushing a group consists of the following steps:
+/// - Query the list of snapshots of this group in the target namespace on the remote
+/// - List all finished local snapshots, sorted by snapshot time
+/// - Iterate over the local snapshots
+/// -- Recreate client/BackupWriter
+/// -- push snapshot, unless it's not newer than the last remote snapshot
+/// - (remove_vanished) remove remote snapshots which don't exist locally anymore
+///
+/// Permission checks:
+/// - local group access is already checked by push_ns
+/// - remote snapshot access is checked by remote (query and opening the backup writer)
+async fn push_group(
+    worker: &WorkerTask,
+    client: &HttpClient,
+    params: &PushParameters,
+    group: &pbs_datastore::BackupGroup,
+    target_ns: &BackupNamespace,
+    progress: &mut StoreProgress,
+) -> Result<(), Error> {
+    let path = format!(
+        "api2/json/admin/datastore/{}/snapshots",
+        params.target.store()
+    );
+
+    let mut args = json!({
+        "backup-type": group.backup_type(),
+        "backup-id": group.backup_id(),
+    });
+    if !target_ns.is_root() {
+        args["ns"] = serde_json::to_value(target_ns)?;
+    }
+
+    let remote_list: Vec<SnapshotListItem> = match client.get(&path, Some(args)).await {
+        Ok(mut result) => serde_json::from_value(result["data"].take())?,
+        Err(err) => match err.downcast_ref::<HttpError>() {
+            // group does not exist on the remote yet
+            Some(HttpError { code, .. }) if *code == StatusCode::NOT_FOUND => Vec::new(),
+            _ => return Err(err),
+        },
+    };
+
+    let last_remote_time = last_remote_time(&remote_list);
+
+    let mut local_list = group.list_backups()?;
+    BackupInfo::sort_list(&mut local_list, true);
+
+    let local_snapshots: HashSet<i64> = local_list
+        .iter()
+        .map(|info| info.backup_dir.backup_time())
+        .collect();
+
+    progress.group_snapshots = local_list.len() as u64;
+
+    client.login().await?; // make sure auth is complete
+    let fingerprint = client.fingerprint();
+
+    let mut skipped = 0;
+    for (pos, info) in local_list.into_iter().enumerate() {
+        let snapshot = info.backup_dir;
+
+        if !info.files.iter().any(|name| name == MANIFEST_BLOB_NAME) {
+            task_log!(
+                worker,
+                "skipping snapshot {} - in-progress backup",
+                snapshot.dir()
+            );
+            continue;
+        }
+
+        if !is_newer_than_remote(snapshot.backup_time(), last_remote_time) {
+            skipped += 1;
+            continue;
+        }
+
+        // get updated auth_info (new tickets)
+        let auth_info = client.login().await?;
+
+        let options =
+            HttpClientOptions::new_non_interactive(auth_info.ticket.clone(), fingerprint.clone())
+                .rate_limit(params.limit.clone());
+
+        let new_client = HttpClient::new(
+            params.target.host(),
+            params.target.port(),
+            params.target.auth_id(),
+            options,
+        )?;
+
+        task_log!(worker, "push snapshot {}", snapshot.dir());
+        let result = push_snapshot(worker, new_client, params, target_ns, &snapshot).await;
+
+        progress.done_snapshots = pos as u64 + 1;
+        task_log!(worker, "percentage done: {}", progress);
+
+        result?; // stop on error
+        task_log!(worker, "push snapshot {} done", snapshot.dir());
+    }
+
+    if skipped > 0 {
+        task_log!(
+            worker,
+            "skipped: {} snapshot(s) not newer than the last remote snapshot",
+            skipped
+        );
+    }
+
+    if params.remove_vanished {
+        for item in vanished_remote_snapshots(remote_list, &local_snapshots) {
+            if item.protected {
+                task_log!(
+                    worker,
+                    "don't delete vanished remote snapshot {} (protected)",
+                    item.backup
+                );
+                continue;
+            }
+            task_log!(worker, "delete vanished remote snapshot {}", item.backup);
+
+            let mut args = json!({
+                "backup-type": item.backup.ty(),
+                "backup-id": item.backup.id(),
+                "backup-time": item.backup.time,
+            });
+            if !target_ns.is_root() {
+                args["ns"] = serde_json::to_value(target_ns)?;
+            }
+            client.delete(&path, Some(args)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the backup time of the most recent snapshot in `remote_list`.
+fn last_remote_time(remote_list: &[SnapshotListItem]) -> Option<i64> {
+    remote_list.iter().map(|item| item.backup.time).max()
+}
+
+/// Checks whether a local snapshot is newer than the last snapshot on the remote.
+///
+/// Older snapshots are never pushed, as the remote only accepts new snapshots at the end of a
+/// group.
+fn is_newer_than_remote(backup_time: i64, last_remote_time: Option<i64>) -> bool {
+    match last_remote_time {
+        Some(last_remote_time) => backup_time > last_remote_time,
+        None => true,
+    }
+}
+
+/// Returns the snapshots in `remote_list` which don't exist (anymore) in `local_snapshots`.
+fn vanished_remote_snapshots(
+    remote_list: Vec<SnapshotListItem>,
+    local_snapshots: &HashSet<i64>,
+) -> Vec<SnapshotListItem> {
+    remote_list
+        .into_iter()
+        .filter(|item| !local_snapshots.contains(&item.backup.time))
+        .collect()
+}
+
+/// Queries the existing namespaces below the remote anchor namespace.
+async fn query_remote_namespaces(
+    client: &HttpClient,
+    params: &PushParameters,
+) -> Result<Vec<BackupNamespace>, Error> {
+    let path = format!(
+        "api2/json/admin/datastore/{}/namespace",
+        params.target.store()
+    );
+    let mut data = json!({});
+    if let Some(max_depth) = params.max_depth {
+        data["max-depth"] = json!(max_depth);
+    }
+    if !params.remote_ns.is_root() {
+        data["parent"] = json!(params.remote_ns);
+    }
+
+    let mut result = client
+        .get(&path, Some(data))
+        .await
+        .map_err(|err| format_err!("Querying remote namespaces failed - {err}"))?;
+    let list: Vec<NamespaceListItem> = serde_json::from_value(result["data"].take())?;
+
+    Ok(list.into_iter().map(|item| item.ns).collect())
+}
+
+async fn check_and_create_remote_ns(
+    client: &HttpClient,
+    params: &PushParameters,
+    remote_namespaces: &[BackupNamespace],
+    ns: &BackupNamespace,
+) -> Result<bool, Error> {
+    if ns.is_root() || remote_namespaces.contains(ns) {
+        return Ok(false);
+    }
+
+    let name = match ns.components().last() {
+        Some(name) => name.to_owned(),
+        None => {
+            bail!("Failed to determine last component of namespace.");
+        }
+    };
+
+    let path = format!(
+        "api2/json/admin/datastore/{}/namespace",
+        params.target.store()
+    );
+    let mut args = json!({ "name": name });
+    let parent = ns.parent();
+    if !parent.is_root() {
+        args["parent"] = json!(parent);
+    }
+
+    client
+        .post(&path, Some(args))
+        .await
+        .map_err(|err| format_err!("namespace creation failed - {err}"))?;
+
+    Ok(true)
+}
+
+async fn remove_vanished_remote_ns(
+    worker: &WorkerTask,
+    client: &HttpClient,
+    params: &PushParameters,
+    remote_namespaces: Vec<BackupNamespace>,
+    synced_ns: &HashSet<BackupNamespace>,
+) -> bool {
+    let mut errors = false;
+
+    let path = format!(
+        "api2/json/admin/datastore/{}/namespace",
+        params.target.store()
+    );
+
+    for remote_ns in vanished_remote_namespaces(&params.remote_ns, remote_namespaces, synced_ns) {
+        // only empty namespaces get removed, remaining groups might not be ours
+        let args = json!({ "ns": remote_ns, "delete-groups": false });
+        match client.delete(&path, Some(args)).await {
+            Ok(_) => task_log!(worker, "Removed remote namespace {}", remote_ns),
+            Err(err) => {
+                task_log!(
+                    worker,
+                    "Failed to remove remote namespace {} - {}",
+                    remote_ns,
+                    err
+                );
+                errors = true;
+            }
+        }
+    }
+
+    errors
+}
+
+/// Returns the remote namespaces below `remote_anchor` which were not synced, children first.
+fn vanished_remote_namespaces(
+    remote_anchor: &BackupNamespace,
+    mut remote_namespaces: Vec<BackupNamespace>,
+    synced_ns: &HashSet<BackupNamespace>,
+) -> Vec<BackupNamespace> {
+    remote_namespaces.retain(|ns| !ns.is_root() && ns != remote_anchor && !synced_ns.contains(ns));
+
+    // children first!
+    remote_namespaces.sort_unstable_by_key(|ns| std::cmp::Reverse(ns.name_len()));
+
+    remote_namespaces
+}
+
+/// Pushes a store according to `params`.
+///
+/// Pushing a store consists of the following steps:
+/// - Query list of namespaces below the local anchor and the existing remote namespaces
+/// - Iterate local list
+/// -- create remote sub-NS if needed
+/// -- attempt to push each NS in turn
+/// - (remove_vanished) remove empty remote sub-NS which are no longer available locally
+///
+/// Permission checks:
+/// - access to local datastore, namespace anchor and remote entry need to be checked at call site
+/// - local namespace access of `local_user` checked here
+/// - remote namespace creation/removal and group access are checked by the remote
+pub(crate) async fn push_store(
+    worker: &WorkerTask,
+    client: &HttpClient,
+    params: PushParameters,
+) -> Result<(), Error> {
+    let mut errors = false;
+
+    let max_depth = params
+        .max_depth
+        .unwrap_or_else(|| MAX_NAMESPACE_DEPTH - params.ns.depth());
+
+    let mut namespaces: Vec<BackupNamespace> = params
+        .store
+        .recursive_iter_backup_ns_ok(params.ns.clone(), Some(max_depth))?
+        .collect();
+    // parents first
+    namespaces.sort_unstable_by_key(|ns| ns.name_len());
+
+    let remote_namespaces = query_remote_namespaces(client, &params).await?;
+
+    let (mut groups, mut snapshots) = (0, 0);
+    let mut synced_ns = HashSet::with_capacity(namespaces.len());
+
+    for namespace in namespaces {
+        let source_store_ns_str = print_store_and_ns(params.store.name(), &namespace);
+
+        let target_ns = namespace.map_prefix(&params.ns, &params.remote_ns)?;
+        let target_store_ns_str = print_store_and_ns(params.target.store(), &target_ns);
+
+        let owner_check_required = match check_ns_privs_full(
+            params.store.name(),
+            &namespace,
+            &params.local_user,
+            PRIV_DATASTORE_READ,
+            PRIV_DATASTORE_BACKUP,
+        ) {
+            Ok(owner_check_required) => owner_check_required,
+            Err(_) => continue, // silently skip namespaces the local user cannot access
+        };
+
+        task_log!(worker, "----");
+        task_log!(
+            worker,
+            "Syncing {} into {}",
+            source_store_ns_str,
+            target_store_ns_str
+        );
+
+        synced_ns.insert(target_ns.clone());
+
+        match check_and_create_remote_ns(client, &params, &remote_namespaces, &target_ns).await {
+            Ok(true) => task_log!(worker, "Created remote namespace {}", target_ns),
+            Ok(false) => {}
+            Err(err) => {
+                task_log!(
+                    worker,
+                    "Cannot sync {} into {} - {}",
+                    source_store_ns_str,
+                    target_store_ns_str,
+                    err,
+                );
+                errors = true;
+                continue;
+            }
+        }
+
+        match push_ns(
+            worker,
+            client,
+            &params,
+            &namespace,
+            &target_ns,
+            owner_check_required,
+        )
+        .await
+        {
+            Ok((ns_progress, ns_errors)) => {
+                errors |= ns_errors;
+
+                if params.max_depth != Some(0) {
+                    groups += ns_progress.done_groups;
+                    snapshots += ns_progress.done_snapshots;
+                    task_log!(
+                        worker,
+                        "Finished syncing namespace {}, current progress: {} groups, {} snapshots",
+                        namespace,
+                        groups,
+                        snapshots,
+                    );
+                }
+            }
+            Err(err) => {
+                errors = true;
+                task_log!(
+                    worker,
+                    "Encountered errors while syncing namespace {} - {}",
+                    namespace,
+                    err,
+                );
+            }
+        }
+    }
+
+    if params.remove_vanished {
+        errors |=
+            remove_vanished_remote_ns(worker, client, &params, remote_namespaces, &synced_ns).await;
+    }
+
+    if errors {
+        bail!("sync failed with some errors.");
+    }
+
+    Ok(())
+}
+
+/// Pushes a namespace according to `params`.
+///
+/// Pushing a namespace consists of the following steps:
+/// - List local groups in `source_ns` which `local_user` may access
+/// - Filter list according to configured group filters
+/// - Iterate list and attempt to push each group in turn
+/// - (remove_vanished) remove remote groups owned by the remote user and matching the configured
+///   group filters which are not or no longer available locally
+///
+/// Permission checks:
+/// - local group ownership is checked here if `owner_check_required` is set
+/// - remote group ownership is checked by the remote
+async fn push_ns(
+    worker: &WorkerTask,
+    client: &HttpClient,
+    params: &PushParameters,
+    source_ns: &BackupNamespace,
+    target_ns: &BackupNamespace,
+    owner_check_required: bool,
+) -> Result<(StoreProgress, bool), Error> {
+    let mut list: Vec<pbs_datastore::BackupGroup> = params
+        .store
+        .iter_backup_groups_ok(source_ns.clone())?
+        .collect();
+
+    list.sort_unstable_by(|a, b| {
+        let type_order = a.backup_type().cmp(&b.backup_type());
+        if type_order == std::cmp::Ordering::Equal {
+            a.backup_id().cmp(b.backup_id())
+        } else {
+            type_order
+        }
+    });
+
+    // groups the local user cannot access must not be removed on the remote
+    let local_groups: HashSet<pbs_api_types::BackupGroup> =
+        list.iter().map(|group| group.group().clone()).collect();
+
+    let total_count = list.len();
+    let list: Vec<pbs_datastore::BackupGroup> = list
+        .into_iter()
+        .filter(|group| params.matches_group_filter(group.group()))
+        .filter(|group| {
+            if !owner_check_required {
+                return true;
+            }
+            match group.get_owner() {
+                Ok(owner) => pbs_datastore::check_backup_owner(&owner, &params.local_user).is_ok(),
+                Err(_) => false,
+            }
+        })
+        .collect();
+
+    if params.group_filter.is_some() || owner_check_required {
+        task_log!(
+            worker,
+            "found {} groups to sync (out of {} total)",
+            list.len(),
+            total_count
+        );
+    } else {
+        task_log!(worker, "found {} groups to sync", total_count);
+    }
+
+    let mut errors = false;
+    let mut progress = StoreProgress::new(list.len() as u64);
+
+    for (done, group) in list.into_iter().enumerate() {
+        progress.done_groups = done as u64;
+        progress.done_snapshots = 0;
+        progress.group_snapshots = 0;
+
+        if let Err(err) = push_group(worker, client, params, &group, target_ns, &mut progress).await
+        {
+            task_log!(worker, "sync group {} failed - {}", group.group(), err);
+            errors = true; // do not stop here, instead continue
+        }
+    }
+
+    if params.remove_vanished {
+        let result: Result<(), Error> = async {
+            let path = format!("api2/json/admin/datastore/{}/groups", params.target.store());
+            let args = if !target_ns.is_root() {
+                Some(json!({ "ns": target_ns }))
+            } else {
+                None
+            };
+
+            let mut result = client.get(&path, args).await?;
+            let remote_groups: Vec<GroupListItem> = serde_json::from_value(result["data"].take())?;
+
+            for remote_group in remote_groups {
+                if local_groups.contains(&remote_group.backup) {
+                    continue;
+                }
+                if remote_group.owner.as_ref() != Some(params.target.auth_id()) {
+                    continue;
+                }
+                if !params.matches_group_filter(&remote_group.backup) {
+                    continue;
+                }
+
+                task_log!(
+                    worker,
+                    "delete vanished remote group '{}'",
+                    remote_group.backup
+                );
+
+                let mut args = json!({
+                    "backup-type": remote_group.backup.ty,
+                    "backup-id": remote_group.backup.id,
+                });
+                if !target_ns.is_root() {
+                    args["ns"] = serde_json::to_value(target_ns)?;
+                }
+                if let Err(err) = client.delete(&path, Some(args)).await {
+                    task_log!(worker, "{}", err);
+                    errors = true;
+                }
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(err) = result {
+            task_log!(worker, "error during cleanup: {}", err);
+            errors = true;
+        };
+    }
+
+    Ok((progress, errors))
+}
+
+#[cfg(test)]
+fn test_snapshot(time: i64, protected: bool) -> SnapshotListItem {
+    serde_json::from_value(json!({
+        "backup-type": "vm",
+        "backup-id": "100",
+        "backup-time": time,
+        "files": [],
+        "protected": protected,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_push_snapshot_selection() {
+    let remote_list = vec![test_snapshot(100, false), test_snapshot(300, false)];
+    let last = last_remote_time(&remote_list);
+    assert_eq!(last, Some(300));
+
+    // only snapshots after the last remote one are pushed
+    assert!(!is_newer_than_remote(100, last));
+    assert!(!is_newer_than_remote(200, last));
+    assert!(!is_newer_than_remote(300, last));
+    assert!(is_newer_than_remote(301, last));
+
+    // everything is pushed into an empty group
+    assert_eq!(last_remote_time(&[]), None);
+    assert!(is_newer_than_remote(0, None));
+}
+
+#[test]
+fn test_vanished_remote_snapshots() {
+    let remote_list = vec![
+        test_snapshot(100, false),
+        test_snapshot(200, true),
+        test_snapshot(300, false),
+    ];
+    let local_snapshots: HashSet<i64> = [100, 400].into_iter().collect();
+
+    // protected snapshots are still returned, the caller decides whether to keep them
+    let vanished = vanished_remote_snapshots(remote_list, &local_snapshots);
+    let times: Vec<i64> = vanished.iter().map(|item| item.backup.time).collect();
+    assert_eq!(times, vec![200, 300]);
+    assert!(vanished[0].protected);
+
+    let remote_list = vec![test_snapshot(100, false)];
+    assert!(vanished_remote_snapshots(remote_list, &local_snapshots).is_empty());
+}
+
+#[test]
+fn test_vanished_remote_namespaces() -> Result<(), Error> {
+    let anchor: BackupNamespace = "a".parse()?;
+    let remote_namespaces: Vec<BackupNamespace> = ["", "a", "a/b", "a/b/c", "a/d", "a/d/e"]
+        .into_iter()
+        .map(|ns| ns.parse())
+        .collect::<Result<_, _>>()?;
+    let synced_ns: HashSet<BackupNamespace> = ["a", "a/d"]
+        .into_iter()
+        .map(|ns| ns.parse())
+        .collect::<Result<_, _>>()?;
+
+    // neither root, the anchor, nor synced namespaces are removed, children go first
+    let vanished = vanished_remote_namespaces(&anchor, remote_namespaces, &synced_ns);
+    assert_eq!(vanished.len(), 3);
+    assert!(vanished[0].depth() >= vanished[1].depth());
+    assert!(vanished[1].depth() >= vanished[2].depth());
+    assert_eq!(vanished[2], BackupNamespace::new("a/b")?);
+    assert!(!vanished
+        .iter()
+        .any(|ns| synced_ns.contains(ns) || *ns == anchor));
+
+    Ok(())
+}
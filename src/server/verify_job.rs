@@ -1,15 +1,191 @@
-use anyhow::{format_err, Error};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
 
 use pbs_api_types::{Authid, Operation, VerificationJobConfig};
+use pbs_datastore::manifest::BackupManifest;
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
-use proxmox_sys::task_log;
+use proxmox_sys::fs::{file_get_optional_contents, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn};
 
 use crate::{
-    backup::{verify_all_backups, verify_filter},
-    server::jobstate::Job,
+    backup::{
+        last_verify_failed, verify_all_backups, verify_chunk_sample, verify_filter, VerifyWorker,
+    },
+    server::jobstate::{self, Job},
 };
 
+/// Number of chunk buckets in sampling mode, one per chunk directory.
+const SAMPLE_BUCKETS: usize = 0x10000;
+
+/// Sampling mode state of a verification job.
+///
+/// For every bucket of chunks, the time it was last checked and the amount of chunk data read.
+struct SampleState {
+    last_check: Vec<i64>,
+    bytes: Vec<u64>,
+}
+
+impl SampleState {
+    fn path(jobname: &str) -> PathBuf {
+        jobstate::get_extra_state_path("verificationjob", jobname, "sample")
+    }
+
+    fn new() -> Self {
+        Self {
+            last_check: vec![0; SAMPLE_BUCKETS],
+            bytes: vec![0; SAMPLE_BUCKETS],
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self, Error> {
+        let data = match file_get_optional_contents(path)? {
+            Some(data) => data,
+            None => return Ok(Self::new()),
+        };
+
+        if data.len() != SAMPLE_BUCKETS * 16 {
+            bail!("sample state {path:?} has unexpected size {}", data.len());
+        }
+
+        let (last_check, bytes) = data.split_at(SAMPLE_BUCKETS * 8);
+        Ok(Self {
+            last_check: last_check
+                .chunks_exact(8)
+                .map(|v| i64::from_le_bytes(v.try_into().unwrap()))
+                .collect(),
+            bytes: bytes
+                .chunks_exact(8)
+                .map(|v| u64::from_le_bytes(v.try_into().unwrap()))
+                .collect(),
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut data = Vec::with_capacity(SAMPLE_BUCKETS * 16);
+        for last_check in &self.last_check {
+            data.extend_from_slice(&last_check.to_le_bytes());
+        }
+        for bytes in &self.bytes {
+            data.extend_from_slice(&bytes.to_le_bytes());
+        }
+
+        let backup_user = pbs_config::backup_user()?;
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+        let options = CreateOptions::new()
+            .perm(mode)
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        replace_file(path, &data, options, false)
+    }
+
+    /// Select the least recently checked buckets, as many as needed for `percent` of all
+    /// chunks, or to read about `budget` bytes.
+    fn select(&self, percent: Option<f64>, budget: Option<u64>) -> Vec<u16> {
+        let count = match (percent, budget) {
+            (Some(percent), _) => (SAMPLE_BUCKETS as f64 * percent / 100.0).ceil() as usize,
+            (None, Some(budget)) => {
+                let (checked, bytes) = self
+                    .last_check
+                    .iter()
+                    .zip(&self.bytes)
+                    .filter(|(last_check, _)| **last_check != 0)
+                    .fold((0u64, 0u64), |(count, sum), (_, bytes)| {
+                        (count + 1, sum + bytes)
+                    });
+                if checked == 0 {
+                    // nothing known yet, start with one percent
+                    SAMPLE_BUCKETS / 100
+                } else {
+                    let average = (bytes / checked).max(1);
+                    (budget / average + 1) as usize
+                }
+            }
+            (None, None) => SAMPLE_BUCKETS,
+        };
+
+        // stable sort, so buckets with equal check time are taken in digest order
+        let mut buckets: Vec<u16> = (0..=u16::MAX).collect();
+        buckets.sort_by_key(|bucket| self.last_check[*bucket as usize]);
+        buckets.truncate(count.clamp(1, SAMPLE_BUCKETS));
+        buckets
+    }
+}
+
+/// Removes the sampling mode state of a verification job.
+pub fn remove_sample_state(jobname: &str) -> Result<(), Error> {
+    let path = SampleState::path(jobname);
+    if let Err(err) = std::fs::remove_file(&path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            bail!("cannot remove sample state of verification job {jobname}: {err}");
+        }
+    }
+    Ok(())
+}
+
+fn verify_sample(
+    verify_worker: &VerifyWorker,
+    worker: &WorkerTask,
+    jobname: &str,
+    verification_job: &VerificationJobConfig,
+    filter: &dyn Fn(&BackupManifest) -> bool,
+) -> Result<Vec<String>, Error> {
+    let path = SampleState::path(jobname);
+    let mut state = match SampleState::load(&path) {
+        Ok(state) => state,
+        Err(err) => {
+            task_warn!(
+                worker,
+                "unable to load sample state, starting over - {}",
+                err
+            );
+            SampleState::new()
+        }
+    };
+
+    let budget = verification_job.sample_budget.map(|budget| budget.as_u64());
+    let buckets = state.select(verification_job.sample_percent, budget);
+
+    let (checked, failed_dirs) = verify_chunk_sample(
+        verify_worker,
+        worker.upid(),
+        verification_job.ns.clone().unwrap_or_default(),
+        verification_job.max_depth,
+        &buckets,
+        budget,
+        Some(filter),
+    )?;
+
+    let now = proxmox_time::epoch_i64();
+    for (bucket, bytes) in checked {
+        state.last_check[bucket as usize] = now;
+        state.bytes[bucket as usize] = bytes;
+    }
+    if let Err(err) = state.save(&path) {
+        task_warn!(worker, "unable to save sample state - {}", err);
+    }
+
+    let never_checked = state.last_check.iter().filter(|time| **time == 0).count();
+    if never_checked > 0 {
+        task_log!(
+            worker,
+            "{} of {} chunk buckets not checked yet",
+            never_checked,
+            SAMPLE_BUCKETS
+        );
+    } else if let Some(oldest) = state.last_check.iter().min() {
+        task_log!(
+            worker,
+            "all chunks were checked since {}",
+            proxmox_time::epoch_to_rfc3339_utc(*oldest)?
+        );
+    }
+
+    Ok(failed_dirs)
+}
+
 /// Runs a verification job.
 pub fn do_verification_job(
     mut job: Job,
@@ -22,11 +198,14 @@ pub fn do_verification_job(
 
     let outdated_after = verification_job.outdated_after;
     let ignore_verified_snapshots = verification_job.ignore_verified.unwrap_or(true);
+    // failed snapshots might be repairable now, so always include them with a repair source
+    let retry_failed = verification_job.has_repair_source();
 
     let (email, notify) = crate::server::lookup_datastore_notify_settings(&verification_job.store);
 
     // FIXME encode namespace here for filter/ACL check?
-    let job_id = format!("{}:{}", &verification_job.store, job.jobname());
+    let jobname = job.jobname().to_string();
+    let job_id = format!("{}:{}", &verification_job.store, jobname);
     let worker_type = job.jobtype().to_string();
     let upid_str = WorkerTask::new_thread(
         &worker_type,
@@ -46,17 +225,33 @@ pub fn do_verification_job(
                 None => Default::default(),
             };
 
-            let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
-            let result = verify_all_backups(
-                &verify_worker,
-                worker.upid(),
-                ns,
-                verification_job.max_depth,
-                None,
-                Some(&move |manifest| {
-                    verify_filter(ignore_verified_snapshots, outdated_after, manifest)
-                }),
-            );
+            let mut verify_worker = VerifyWorker::new(worker.clone(), datastore);
+            verify_worker.set_job_tuning(&verification_job);
+            if let Err(err) = verify_worker.set_repair_source(&verification_job) {
+                task_warn!(worker, "unable to set up chunk repair - {}", err);
+            }
+            let filter = move |manifest: &BackupManifest| {
+                (retry_failed && last_verify_failed(manifest))
+                    || verify_filter(ignore_verified_snapshots, outdated_after, manifest)
+            };
+            let result = if verification_job.is_sampling() {
+                verify_sample(
+                    &verify_worker,
+                    &worker,
+                    &jobname,
+                    &verification_job,
+                    &filter,
+                )
+            } else {
+                verify_all_backups(
+                    &verify_worker,
+                    worker.upid(),
+                    ns,
+                    verification_job.max_depth,
+                    None,
+                    Some(&filter),
+                )
+            };
             let job_result = match result {
                 Ok(ref failed_dirs) if failed_dirs.is_empty() => Ok(()),
                 Ok(ref failed_dirs) => {
@@ -91,3 +286,29 @@ pub fn do_verification_job(
     )?;
     Ok(upid_str)
 }
+
+#[test]
+fn test_sample_selection() {
+    let mut state = SampleState::new();
+
+    // one percent of 65536 buckets, rounded up
+    let buckets = state.select(Some(1.0), None);
+    assert_eq!(buckets.len(), 656);
+    assert_eq!(buckets[0], 0);
+    assert_eq!(buckets[655], 655);
+
+    // checked buckets are picked last
+    for bucket in buckets {
+        state.last_check[bucket as usize] = 1000;
+        state.bytes[bucket as usize] = 1024;
+    }
+    let buckets = state.select(Some(1.0), None);
+    assert_eq!(buckets[0], 656);
+
+    // the budget is spread using the average bucket size
+    let buckets = state.select(None, Some(10 * 1024));
+    assert_eq!(buckets.len(), 11);
+    assert_eq!(buckets[0], 656);
+
+    assert_eq!(state.select(Some(100.0), None).len(), SAMPLE_BUCKETS);
+}
@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 
 use pbs_datastore::{DataBlob, DataStore, SnapshotReader};
 
@@ -56,6 +56,14 @@ impl NewChunksIterator {
                     }
 
                     let blob = datastore.load_chunk(&digest)?;
+                    if let Some(id) = blob.zstd_dictionary_id() {
+                        // dictionaries are not written to tape
+                        bail!(
+                            "chunk {} is compressed with zstd dictionary {id:08x}, which cannot \
+                            be restored from tape",
+                            hex::encode(digest),
+                        );
+                    }
                     //println!("LOAD CHUNK {}", hex::encode(&digest));
                     match tx.send(Ok(Some((digest, blob)))) {
                         Ok(()) => {}
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use anyhow::Error;
 
-use pbs_api_types::PruneJobOptions;
+use pbs_api_types::{PruneJobOptions, VerifyState};
 use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
 use pbs_datastore::prune::compute_prune_info;
 use pbs_datastore::{BackupDir, BackupInfo};
@@ -41,9 +41,25 @@ fn create_info(snapshot: &str, partial: bool) -> BackupInfo {
         backup_dir,
         files,
         protected: false,
+        verify_state: None,
     }
 }
 
+fn create_info_days_ago(days: i64) -> BackupInfo {
+    let backup_time = proxmox_time::epoch_i64() - days * 24 * 3600;
+    let snapshot = format!(
+        "host/elsa/{}",
+        proxmox_time::epoch_to_rfc3339_utc(backup_time).unwrap()
+    );
+    create_info(&snapshot, false)
+}
+
+fn relative_paths(list: &[BackupInfo]) -> Vec<PathBuf> {
+    list.iter()
+        .map(|info| info.backup_dir.relative_path())
+        .collect()
+}
+
 fn create_info_protected(snapshot: &str, partial: bool) -> BackupInfo {
     let mut info = create_info(snapshot, partial);
     info.protected = true;
@@ -335,3 +351,124 @@ fn test_prune_simple() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn test_prune_keep_within() -> Result<(), Error> {
+    let orig_list = vec![
+        create_info_days_ago(1),
+        create_info_days_ago(5),
+        create_info_days_ago(13),
+        create_info_days_ago(15),
+        create_info_days_ago(20),
+    ];
+
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_within = Some("14d".to_string());
+    let remove_list = get_prune_list(orig_list.clone(), false, &options);
+    let expect = relative_paths(&[orig_list[4].clone(), orig_list[3].clone()]);
+    assert_eq!(remove_list, expect);
+
+    // count based options only consider backups outside of the time span
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_within = Some("14d".to_string());
+    options.keep.keep_last = Some(1);
+    let remove_list = get_prune_list(orig_list.clone(), false, &options);
+    let expect = relative_paths(&[orig_list[4].clone()]);
+    assert_eq!(remove_list, expect);
+
+    Ok(())
+}
+
+#[test]
+fn test_prune_max_old() -> Result<(), Error> {
+    let orig_list = vec![
+        create_info_days_ago(10),
+        create_info_days_ago(400),
+        create_info_days_ago(500),
+        create_info_days_ago(600),
+    ];
+
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_last = Some(10);
+    options.keep.old_age = Some("1y".to_string());
+    options.keep.max_old = Some(1);
+    let remove_list = get_prune_list(orig_list.clone(), false, &options);
+    let expect = relative_paths(&[orig_list[3].clone(), orig_list[2].clone()]);
+    assert_eq!(remove_list, expect);
+
+    // protected backups are neither limited nor counted
+    let mut list = orig_list.clone();
+    list[1].protected = true;
+    let remove_list = get_prune_list(list, false, &options);
+    let expect = relative_paths(&[orig_list[3].clone()]);
+    assert_eq!(remove_list, expect);
+
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_last = Some(10);
+    options.keep.max_old = Some(1);
+    assert!(compute_prune_info(orig_list, &options.keep).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_prune_keep_last_verified() -> Result<(), Error> {
+    let mut orig_list = vec![
+        create_info_days_ago(1),
+        create_info_days_ago(2),
+        create_info_days_ago(3),
+        create_info_days_ago(4),
+    ];
+    orig_list[0].verify_state = Some(VerifyState::Failed);
+    orig_list[2].verify_state = Some(VerifyState::Ok);
+    orig_list[3].verify_state = Some(VerifyState::Ok);
+
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_last = Some(1);
+    options.keep.keep_last_verified = Some(true);
+    let remove_list = get_prune_list(orig_list.clone(), false, &options);
+    let expect = relative_paths(&[orig_list[3].clone(), orig_list[1].clone()]);
+    assert_eq!(remove_list, expect);
+
+    // failed backups are kept, but do not use up the keep-last slot
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_last = Some(1);
+    options.keep.skip_failed_verify = Some(true);
+    let remove_list = get_prune_list(orig_list.clone(), false, &options);
+    let expect = relative_paths(&[orig_list[3].clone(), orig_list[2].clone()]);
+    assert_eq!(remove_list, expect);
+
+    // older failed backups outside of the kept slots are removed as usual
+    let mut failed_list = orig_list.clone();
+    failed_list[2].verify_state = Some(VerifyState::Failed);
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_last = Some(1);
+    options.keep.skip_failed_verify = Some(true);
+    let remove_list = get_prune_list(failed_list.clone(), false, &options);
+    let expect = relative_paths(&[failed_list[3].clone(), failed_list[2].clone()]);
+    assert_eq!(remove_list, expect);
+
+    // failed backups outside of the first periods are not kept, even if slots are left
+    let mut all_failed = orig_list.clone();
+    for info in all_failed.iter_mut() {
+        info.verify_state = Some(VerifyState::Failed);
+    }
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_last = Some(2);
+    options.keep.skip_failed_verify = Some(true);
+    let remove_list = get_prune_list(all_failed.clone(), false, &options);
+    let expect = relative_paths(&[all_failed[3].clone(), all_failed[2].clone()]);
+    assert_eq!(remove_list, expect);
+
+    // the last verified backup overrides the limit on old backups
+    let mut options = PruneJobOptions::default();
+    options.keep.keep_last = Some(1);
+    options.keep.old_age = Some("1d 12h".to_string());
+    options.keep.max_old = Some(0);
+    options.keep.keep_last_verified = Some(true);
+    let remove_list = get_prune_list(orig_list.clone(), false, &options);
+    let expect = relative_paths(&[orig_list[3].clone(), orig_list[1].clone()]);
+    assert_eq!(remove_list, expect);
+
+    Ok(())
+}
@@ -413,6 +413,7 @@ Ext.define('PBS.Utils', {
 	    logrotate: [null, gettext('Log Rotation')],
 	    prune: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Prune')),
 	    prunejob: (type, id) => PBS.Utils.render_prune_job_worker_id(id, gettext('Prune Job')),
+	    'rebalance-chunks': ['Datastore', gettext('Rebalance Chunks')],
 	    reader: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Read Objects')),
 	    'rewind-media': [gettext('Drive'), gettext('Rewind Media')],
 	    sync: ['Datastore', gettext('Remote Sync')],
@@ -682,7 +683,13 @@ Ext.define('PBS.Utils', {
 	let extra = '';
 
 	if (activeTasks !== undefined) {
-	    const conflictingTasks = activeTasks.write + (type === 'offline' ? activeTasks.read : 0);
+	    let conflictingTasks = activeTasks.write;
+	    if (type !== 'delete-only') {
+		conflictingTasks += activeTasks.delete;
+	    }
+	    if (type === 'offline') {
+		conflictingTasks += activeTasks.read;
+	    }
 
 	    if (conflictingTasks > 0) {
 		extra += '| <i class="fa fa-spinner fa-pulse fa-fw"></i> ';
@@ -700,6 +707,8 @@ Ext.define('PBS.Utils', {
 	switch (type) {
 	    case 'read-only': modeText = gettext("Read-only");
 		break;
+	    case 'delete-only': modeText = gettext("Delete-only");
+		break;
 	    case 'offline': modeText = gettext("Offline");
 		break;
 	}
@@ -723,6 +732,11 @@ Ext.define('PBS.Utils', {
 	    file: gettext('File'),
 	    filesystem: gettext('Filesystem'),
 	},
+	'gc-mark-mode': {
+	    '__default__': Proxmox.Utils.defaultText + ` (${gettext('Access Time')})`,
+	    atime: gettext('Access Time'),
+	    bitmap: gettext('Bitmap'),
+	},
     },
 
     render_tuning_options: function(tuning) {
@@ -737,6 +751,25 @@ Ext.define('PBS.Utils', {
 	sync = PBS.Utils.tuningOptions['sync-level'][sync ?? '__default__'];
 	options.push(`${gettext('Sync Level')}: ${sync}`);
 
+	let markMode = tuning['gc-mark-mode'];
+	delete tuning['gc-mark-mode'];
+	markMode = PBS.Utils.tuningOptions['gc-mark-mode'][markMode ?? '__default__'];
+	options.push(`${gettext('GC Mark Mode')}: ${markMode}`);
+
+	let resumeTimeout = tuning['backup-resume-timeout'];
+	delete tuning['backup-resume-timeout'];
+	resumeTimeout = resumeTimeout ? `${resumeTimeout} min` : Proxmox.Utils.disabledText;
+	options.push(`${gettext('Backup Resume Timeout')}: ${resumeTimeout}`);
+
+	let level = tuning['compression-level'];
+	delete tuning['compression-level'];
+	level = level ?? `${Proxmox.Utils.defaultText} (1)`;
+	options.push(`${gettext('Compression Level')}: ${level}`);
+
+	let dictionary = tuning['zstd-dictionary'];
+	delete tuning['zstd-dictionary'];
+	options.push(`${gettext('zstd Dictionary')}: ${dictionary ?? Proxmox.Utils.noneText}`);
+
 	for (const [k, v] of Object.entries(tuning)) {
 	    options.push(`${k}: ${v}`);
 	}
@@ -3,6 +3,7 @@ Ext.define('pbs-prune-jobs-status', {
     fields: [
 	'id', 'disable', 'store', 'ns', 'max-depth', 'schedule',
 	'keep-last', 'keep-hourly', 'keep-daily', 'keep-weekly', 'keep-monthly', 'keep-yearly',
+	'keep-within',
 	'next-run', 'last-run-upid', 'last-run-state', 'last-run-endtime',
 	{
 	    name: 'duration',
@@ -221,6 +222,7 @@ Ext.define('PBS.config.PruneJobView', {
 		['weekly', gettext('Weekly')],
 		['monthly', gettext('Monthly')],
 		['yearly', gettext('Yearly')],
+		['within', gettext('Within')],
 	    ].map(([data, header]) => ({
 		header: header,
 		dataIndex: `keep-${data}`,
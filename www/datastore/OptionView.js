@@ -66,6 +66,7 @@ Ext.define('PBS.Datastore.Options', {
 	me.maintenanceActiveTasks = {
 	    read: 0,
 	    write: 0,
+	    delete: 0,
 	};
 	me.datastore = encodeURIComponent(me.datastore);
 	me.url = `/api2/json/config/datastore/${me.datastore}`;
@@ -90,8 +91,9 @@ Ext.define('PBS.Datastore.Options', {
 
 	    view.mon(me.activeOperationsRstore, 'load', (store, data, success) => {
 		let activeTasks = me.getView().maintenanceActiveTasks;
-		activeTasks.read = data?.[0]?.data.value ?? 0;
-		activeTasks.write = data?.[1]?.data.value ?? 0;
+		activeTasks.read = store.getById('read')?.data.value ?? 0;
+		activeTasks.write = store.getById('write')?.data.value ?? 0;
+		activeTasks.delete = store.getById('delete')?.data.value ?? 0;
 	    });
 	},
 
@@ -244,6 +246,39 @@ Ext.define('PBS.Datastore.Options', {
 			    deleteEmpty: true,
 			    value: '__default__',
 			},
+			{
+			    xtype: 'proxmoxKVComboBox',
+			    name: 'gc-mark-mode',
+			    fieldLabel: gettext('GC Mark Mode'),
+			    comboItems: Object.entries(PBS.Utils.tuningOptions['gc-mark-mode']),
+			    deleteEmpty: true,
+			    value: '__default__',
+			},
+			{
+			    xtype: 'proxmoxintegerfield',
+			    name: 'backup-resume-timeout',
+			    fieldLabel: gettext('Backup Resume Timeout') + ' (min)',
+			    emptyText: Proxmox.Utils.disabledText,
+			    minValue: 1,
+			    deleteEmpty: true,
+			},
+			{
+			    xtype: 'proxmoxintegerfield',
+			    name: 'compression-level',
+			    fieldLabel: gettext('Compression Level'),
+			    emptyText: Proxmox.Utils.defaultText + ' (1)',
+			    minValue: 1,
+			    maxValue: 19,
+			    deleteEmpty: true,
+			},
+			{
+			    xtype: 'proxmoxtextfield',
+			    name: 'zstd-dictionary',
+			    fieldLabel: gettext('zstd Dictionary'),
+			    emptyText: Proxmox.Utils.noneText,
+			    regex: /^[0-9a-f]{8}$/,
+			    deleteEmpty: true,
+			},
 		    ],
 		},
 	    },
@@ -92,6 +92,17 @@ Ext.define('PBS.Datastore.PruneInputPanel', {
 		    return b["backup-time"] - a["backup-time"];
 		});
 
+		// time and verification based rules cannot be attributed to single backups here
+		if (params['keep-within'] || params['max-old'] !== undefined ||
+		    params['keep-last-verified'] || params['skip-failed-verify']) {
+		    for (let backup of backups) {
+			if (backup.keep) {
+			    backup.keepReason = backup.protected ? 'protected' : 'retention rules';
+			}
+		    }
+		    return;
+		}
+
 		let ruleIndex = -1;
 		let nextRule = function() {
 		    let rule;
@@ -181,6 +192,35 @@ Ext.define('PBS.Datastore.PruneInputPanel', {
 	    name: 'keep-yearly',
 	    fieldLabel: gettext('keep-yearly'),
 	},
+	{
+	    xtype: 'proxmoxtextfield',
+	    name: 'keep-within',
+	    fieldLabel: gettext('keep-within'),
+	    emptyText: gettext('e.g. 14d'),
+	},
+	{
+	    xtype: 'proxmoxtextfield',
+	    name: 'old-age',
+	    fieldLabel: gettext('old-age'),
+	    emptyText: gettext('e.g. 1y'),
+	},
+	{
+	    xtype: 'proxmoxintegerfield',
+	    name: 'max-old',
+	    fieldLabel: gettext('max-old'),
+	    minValue: 0,
+	    allowBlank: true,
+	},
+	{
+	    xtype: 'proxmoxcheckbox',
+	    name: 'keep-last-verified',
+	    fieldLabel: gettext('keep-last-verified'),
+	},
+	{
+	    xtype: 'proxmoxcheckbox',
+	    name: 'skip-failed-verify',
+	    fieldLabel: gettext('skip-failed-verify'),
+	},
     ],
 
 
@@ -80,6 +80,25 @@ Ext.define('PBS.Datastore.GCOptions', {
 		},
 	    },
 	},
+	"gc-max-runtime": {
+	    required: true,
+	    defaultValue: Proxmox.Utils.unlimitedText,
+	    header: gettext('GC Maximum Runtime'),
+	    renderer: v => v === Proxmox.Utils.unlimitedText ? v : `${v} min`,
+	    editor: {
+		xtype: 'proxmoxWindowEdit',
+		title: gettext('GC Maximum Runtime'),
+		onlineHelp: 'maintenance_gc',
+		items: {
+		    xtype: 'proxmoxintegerfield',
+		    name: 'gc-max-runtime',
+		    fieldLabel: gettext('Maximum Runtime') + ' (min)',
+		    emptyText: Proxmox.Utils.unlimitedText,
+		    minValue: 1,
+		    deleteEmpty: true,
+		},
+	    },
+	},
     },
 });
 
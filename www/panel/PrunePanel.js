@@ -59,6 +59,32 @@ Ext.define('PBS.panel.PruneInputPanel', {
 		deleteEmpty: '{!isCreate}',
 	    },
 	},
+	{
+	    xtype: 'proxmoxtextfield',
+	    name: 'keep-within',
+	    fieldLabel: gettext('Keep Within'),
+	    emptyText: gettext('e.g. 14d'),
+	    cbind: {
+		deleteEmpty: '{!isCreate}',
+	    },
+	},
+	{
+	    xtype: 'proxmoxtextfield',
+	    name: 'old-age',
+	    fieldLabel: gettext('Old Age'),
+	    emptyText: gettext('e.g. 1y'),
+	    cbind: {
+		deleteEmpty: '{!isCreate}',
+	    },
+	},
+	{
+	    xtype: 'proxmoxcheckbox',
+	    name: 'skip-failed-verify',
+	    fieldLabel: gettext('Skip Failed Verify'),
+	    cbind: {
+		deleteDefaultValue: '{!isCreate}',
+	    },
+	},
 	{
 	    xtype: 'proxmoxcheckbox',
 	    name: 'dry-run',
@@ -94,6 +120,24 @@ Ext.define('PBS.panel.PruneInputPanel', {
 		deleteEmpty: '{!isCreate}',
 	    },
 	},
+	{
+	    xtype: 'proxmoxcheckbox',
+	    name: 'keep-last-verified',
+	    fieldLabel: gettext('Keep Last Verified'),
+	    cbind: {
+		deleteDefaultValue: '{!isCreate}',
+	    },
+	},
+	{
+	    xtype: 'proxmoxintegerfield',
+	    name: 'max-old',
+	    fieldLabel: gettext('Max. Old'),
+	    minValue: 0,
+	    allowBlank: true,
+	    cbind: {
+		deleteEmpty: '{!isCreate}',
+	    },
+	},
 	{
 	    xtype: 'fieldcontainer',
 	    layout: 'hbox',
@@ -57,6 +57,7 @@ Ext.define('PBS.window.VerifyJobEdit', {
 	    if (!values.id && me.up('pbsVerifyJobEdit').isCreate) {
 		values.id = 'v-' + Ext.data.identifier.Uuid.Global.generate().slice(0, 13);
 	    }
+	    PBS.Utils.delete_if_default(values, 'sample-percent', undefined, me.isCreate);
 	    return values;
 	},
 	column1: [
@@ -163,6 +164,124 @@ Ext.define('PBS.window.VerifyJobEdit', {
 		    editable: '{isCreate}',
 		},
 	    },
+	    {
+		xtype: 'proxmoxintegerfield',
+		name: 'read-threads',
+		fieldLabel: gettext('Read Threads'),
+		emptyText: '1',
+		minValue: 1,
+		maxValue: 32,
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
+	    {
+		xtype: 'proxmoxintegerfield',
+		name: 'decode-threads',
+		fieldLabel: gettext('Decode Threads'),
+		emptyText: '4',
+		minValue: 1,
+		maxValue: 32,
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
+	    {
+		xtype: 'numberfield',
+		name: 'sample-percent',
+		fieldLabel: gettext('Sample (%)'),
+		emptyText: gettext('All chunks'),
+		minValue: 0.01,
+		maxValue: 100,
+		decimalPrecision: 2,
+		allowBlank: true,
+		// NOTE: deleteEmpty handled in onGetValues
+	    },
+	    {
+		xtype: 'pmxSizeField',
+		name: 'sample-budget',
+		fieldLabel: gettext('Sample Budget'),
+		emptyText: gettext('Unlimited'),
+		submitAutoScaledSizeUnit: true,
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
+	],
+	advancedColumn2: [
+	    {
+		xtype: 'proxmoxKVComboBox',
+		name: 'io-priority',
+		fieldLabel: gettext('I/O Priority'),
+		value: '__default__',
+		comboItems: [
+		    ['__default__', Proxmox.Utils.defaultText],
+		    ['high', gettext('High')],
+		    ['low', gettext('Low')],
+		    ['idle', gettext('Idle')],
+		],
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
+	    {
+		xtype: 'pmxBandwidthField',
+		name: 'read-rate',
+		fieldLabel: gettext('Read Rate'),
+		emptyText: gettext('Unlimited'),
+		submitAutoScaledSizeUnit: true,
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
+	    {
+		xtype: 'pbsRemoteSelector',
+		name: 'repair-remote',
+		fieldLabel: gettext('Repair Remote'),
+		emptyText: gettext('None'),
+		allowBlank: true,
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+		listeners: {
+		    change: function(field, value) {
+			let view = field.up('pbsVerifyJobEdit');
+			view.down('field[name=repair-store]').setRemote(value);
+			view.down('field[name=repair-ns]').setRemote(value);
+		    },
+		},
+	    },
+	    {
+		xtype: 'pbsRemoteStoreSelector',
+		name: 'repair-store',
+		fieldLabel: gettext('Repair Datastore'),
+		allowBlank: true,
+		autoSelect: false,
+		disabled: true,
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+		listeners: {
+		    change: function(field, value) {
+			let view = field.up('pbsVerifyJobEdit');
+			let remote = view.down('field[name=repair-remote]').getValue();
+			let nsField = view.down('field[name=repair-ns]');
+			nsField.setRemote(remote);
+			nsField.setRemoteStore(value);
+		    },
+		},
+	    },
+	    {
+		xtype: 'pbsRemoteNamespaceSelector',
+		name: 'repair-ns',
+		fieldLabel: gettext('Repair Namespace'),
+		allowBlank: true,
+		autoSelect: false,
+		disabled: true,
+		cbind: {
+		    deleteEmpty: '{!isCreate}',
+		},
+	    },
 	],
     },
 });